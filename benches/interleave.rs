@@ -0,0 +1,91 @@
+//! Times interleaving layers into the texel data, against packing every texel at its computed storage index.
+//! Run with `cargo bench --bench interleave`.
+
+use std::hint::black_box;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use image::{DynamicImage, Rgba32FImage};
+
+use nsdgen::format::{AttributeFormat, AttributeType, TexelOrder};
+use nsdgen::layer::{Layer, LayerDimensions, LayerSource};
+use nsdgen::manifest::LayerConfig;
+use nsdgen::texels::TexelBuffer;
+use nsdgen::writer::{make_raw_data, DataPacker};
+
+const FORMATS: [(AttributeType, u8); 6] = [
+    (AttributeType::Byte, 1), (AttributeType::Byte, 4), (AttributeType::UInt, 2),
+    (AttributeType::Float, 4), (AttributeType::Byte, 3), (AttributeType::Int, 1),
+];
+const RUNS: u32 = 3;
+
+fn make_layers(dimensions: &LayerDimensions, count: usize) -> Vec<Layer> {
+    let image = DynamicImage::ImageRgba32F(Rgba32FImage::from_fn(dimensions.width, dimensions.height, |x, y| {
+        image::Rgba([(x % 97) as f32 / 97.0, (y % 89) as f32 / 89.0, ((x ^ y) % 7) as f32 / 7.0, 1.0])
+    }));
+    (0..count)
+        .map(|index| {
+            let (attribute_type, size) = FORMATS[index % FORMATS.len()];
+            let config = LayerConfig { format: AttributeFormat::new(attribute_type, size).unwrap(), ..LayerConfig::default() };
+            Layer {
+                name: format!("layer{index}"),
+                texels: TexelBuffer::from_image(&image, &config, None),
+                format: config.format,
+                source: LayerSource::image(PathBuf::from(format!("layer{index}.png"))),
+            }
+        })
+        .collect()
+}
+
+/// Packing every texel at its storage index, as the packer did before it walked the texels in order.
+fn pack_per_texel(layers: &[Layer], dimensions: &LayerDimensions, order: TexelOrder) -> Vec<u8> {
+    let LayerDimensions { width, height } = *dimensions;
+    let stride: usize = layers.iter().map(|layer| layer.format.size as usize).sum();
+    let mut raw_data = vec![0; stride * dimensions.get_texel_count()];
+    let mut offset = 0;
+    for layer in layers {
+        let size = layer.format.size as usize;
+        for (index, value) in layer.encode_texels().chunks_exact(size).enumerate() {
+            let (x, y) = ((index % width as usize) as u32, (index / width as usize) as u32);
+            let start = order.storage_index(x, y, width, height) * stride + offset;
+            raw_data[start..start + size].copy_from_slice(value);
+        }
+        offset += size;
+    }
+    raw_data
+}
+
+fn pack_incrementally(layers: &[Layer], dimensions: &LayerDimensions, order: TexelOrder) -> Vec<u8> {
+    let mut packer = DataPacker::new(layers.iter().map(|layer| layer.format).collect(), dimensions, order);
+    for (index, layer) in layers.iter().enumerate() {
+        packer.pack(index, layer);
+    }
+    packer.finish()
+}
+
+/// Average duration of the runs, after checking the result against the expected texel data.
+fn time(expected: &[u8], pack: impl Fn() -> Vec<u8>) -> Duration {
+    assert!(pack() == expected, "The texel data differs from packing per texel");
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        black_box(pack());
+    }
+    start.elapsed() / RUNS
+}
+
+fn main() {
+    for (side, count) in [(1024, 8), (1024, 48), (2048, 16)] {
+        let dimensions = LayerDimensions { width: side, height: side };
+        let layers = make_layers(&dimensions, count);
+        for order in [TexelOrder::RowMajor, TexelOrder::ColumnMajor] {
+            let expected = pack_per_texel(&layers, &dimensions, order);
+            let per_texel = time(&expected, || pack_per_texel(&layers, &dimensions, order));
+            let incremental = time(&expected, || pack_incrementally(&layers, &dimensions, order));
+            let all = time(&expected, || make_raw_data(&layers, &dimensions, order));
+            println!(
+                "{side}x{side}, {count} layers, {order:?}: per texel {per_texel:.2?}, DataPacker::pack {incremental:.2?}, \
+                 make_raw_data {all:.2?}"
+            );
+        }
+    }
+}
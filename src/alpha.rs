@@ -0,0 +1,71 @@
+//! Handling of the alpha channel of RGBA (and gray + alpha) sources.
+
+use image::{DynamicImage, ImageBuffer, Pixel, Primitive};
+
+use crate::resample::Sample;
+
+/// What transparent texels of a layer mean.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum AlphaMode {
+    /// Alpha is not taken into account, transparent texels keep whatever color they have.
+    #[default]
+    Ignore,
+    /// Colors are multiplied by alpha before resizing, so transparent texels become zero.
+    Premultiply,
+}
+
+impl AlphaMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            AlphaMode::Ignore => "ignore",
+            AlphaMode::Premultiply => "premultiply",
+        }
+    }
+}
+
+/// Multiplies the color channels by alpha, for images which have an alpha channel.
+pub fn premultiply(image: DynamicImage) -> DynamicImage {
+    match image {
+        DynamicImage::ImageLumaA8(buffer) => DynamicImage::ImageLumaA8(premultiply_buffer(buffer)),
+        DynamicImage::ImageRgba8(buffer) => DynamicImage::ImageRgba8(premultiply_buffer(buffer)),
+        DynamicImage::ImageLumaA16(buffer) => DynamicImage::ImageLumaA16(premultiply_buffer(buffer)),
+        DynamicImage::ImageRgba16(buffer) => DynamicImage::ImageRgba16(premultiply_buffer(buffer)),
+        DynamicImage::ImageRgba32F(buffer) => DynamicImage::ImageRgba32F(premultiply_buffer(buffer)),
+        image => image,
+    }
+}
+
+/// Premultiplies a buffer whose last channel is alpha.
+fn premultiply_buffer<P>(mut buffer: ImageBuffer<P, Vec<P::Subpixel>>) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel,
+    P::Subpixel: Sample,
+{
+    let max = P::Subpixel::DEFAULT_MAX_VALUE.to_f64();
+    for pixel in buffer.pixels_mut() {
+        let (alpha, colors) = pixel.channels_mut().split_last_mut().unwrap();
+        let alpha = alpha.to_f64() / max;
+        for color in colors {
+            *color = P::Subpixel::from_f64(color.to_f64() * alpha);
+        }
+    }
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn multiplies_the_colors_by_alpha() {
+        let image = RgbaImage::from_fn(3, 1, |x, _| Rgba([200, 100, 50, [255, 51, 0][x as usize]]));
+        let premultiplied = premultiply(DynamicImage::ImageRgba8(image)).to_rgba8();
+        assert_eq!(premultiplied.into_raw(), [200, 100, 50, 255, 40, 20, 10, 51, 0, 0, 0, 0]);
+
+        // Images without an alpha channel are left as they are.
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(2, 1, vec![7, 9]).unwrap());
+        assert_eq!(premultiply(image).to_luma8().into_raw(), [7, 9]);
+    }
+}
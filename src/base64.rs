@@ -0,0 +1,77 @@
+//! Standard base64 (with `+` and `/`), for binary data embedded in text formats.
+
+const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Encodes the bytes with padding.
+pub fn encode(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let buffer = chunk.iter().enumerate().fold(0u32, |buffer, (index, &byte)| buffer | (byte as u32) << (16 - 8 * index));
+        for index in 0..4 {
+            match index <= chunk.len() {
+                true => text.push(ALPHABET[(buffer >> (18 - 6 * index) & 63) as usize] as char),
+                false => text.push('='),
+            }
+        }
+    }
+    text
+}
+
+/// Decodes the text, ignoring whitespace and padding.
+pub fn decode(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![];
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for char in text.chars().filter(|char| !char.is_whitespace() && *char != '=') {
+        let value = match char {
+            'A'..='Z' => char as u32 - 'A' as u32,
+            'a'..='z' => char as u32 - 'a' as u32 + 26,
+            '0'..='9' => char as u32 - '0' as u32 + 52,
+            '+' => 62,
+            '/' => 63,
+            _ => return Err(format!("invalid base64 character `{char}`")),
+        };
+        buffer = (buffer << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_with_padding() {
+        let encoded = ["", "Zg==", "Zm8=", "Zm9v", "Zm9vYg==", "Zm9vYmE=", "Zm9vYmFy"];
+        for (length, expected) in encoded.iter().enumerate() {
+            assert_eq!(encode(&b"foobar"[..length]), *expected);
+        }
+        assert_eq!(encode(&[0xFB, 0xFF, 0xBF]), "+/+/");
+    }
+
+    #[test]
+    fn round_trips_every_byte() {
+        let bytes: Vec<u8> = (0..=255).chain((0..=255).rev()).collect();
+        for length in [0, 1, 2, 3, 4, 255, bytes.len()] {
+            assert_eq!(decode(&encode(&bytes[..length])), Ok(bytes[..length].to_vec()));
+        }
+    }
+
+    #[test]
+    fn ignores_whitespace_and_padding() {
+        assert_eq!(decode(" Zm9v\nYmE=\r\n"), Ok(b"fooba".to_vec()));
+        assert_eq!(decode("Zm8"), Ok(b"fo".to_vec()));
+    }
+
+    #[test]
+    fn rejects_invalid_characters() {
+        for text in ["Zm9v-mFy", "Zm9v_mFy", "Zm9v.", "Zm9vé"] {
+            assert!(decode(text).is_err(), "{text:?} was accepted");
+        }
+    }
+}
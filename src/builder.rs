@@ -0,0 +1,139 @@
+//! Construction of spatial data files from texel buffers, for programs producing their data without images.
+
+use std::io::{self, Seek, Write};
+
+use crate::format::{AttributeFormat, NsdExtensions, NsdHeader, TexelOrder};
+use crate::layer::LayerDimensions;
+use crate::reader::NsdAttribute;
+use crate::writer::write_attributes_binary;
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Builds a structurally valid file one attribute at a time, checking every step, e.g.
+/// `NsdBuilder::new(dimensions).add_layer("height", format, &texels)?.write(&mut output)?`.
+pub struct NsdBuilder {
+    header: NsdHeader,
+    dimensions: LayerDimensions,
+    texel_order: TexelOrder,
+    attributes: Vec<NsdAttribute>,
+    /// Texels of every attribute, little-endian in row-major order.
+    buffers: Vec<Vec<u8>>,
+}
+
+impl NsdBuilder {
+    pub fn new(dimensions: LayerDimensions) -> NsdBuilder {
+        NsdBuilder {
+            header: NsdHeader::default(),
+            dimensions,
+            texel_order: TexelOrder::default(),
+            attributes: vec![],
+            buffers: vec![],
+        }
+    }
+
+    /// Header written instead of the default NSG / Spatial one.
+    pub fn header(self, header: NsdHeader) -> NsdBuilder {
+        NsdBuilder { header, ..self }
+    }
+
+    /// Order the texels are stored in. The buffers passed to `add_layer` are always row-major.
+    pub fn texel_order(self, texel_order: TexelOrder) -> NsdBuilder {
+        NsdBuilder { texel_order, ..self }
+    }
+
+    /// Adds an attribute with its texels encoded like in the DATA chunk (little-endian, `format.size` bytes per
+    /// texel), row by row from the top left. Fails on empty or duplicate names, names the ATR record can't store,
+    /// and buffers whose length doesn't match the dimensions.
+    pub fn add_layer(mut self, name: &str, format: AttributeFormat, data: &[u8]) -> io::Result<NsdBuilder> {
+        let LayerDimensions { width, height } = self.dimensions;
+        if width == 0 || height == 0 {
+            return Err(invalid_input(format!("Invalid dimensions {width}x{height}, both sides must be at least 1")));
+        }
+        if name.is_empty() {
+            return Err(invalid_input("Attribute names must not be empty".into()));
+        }
+        if name.contains('\0') {
+            return Err(invalid_input(format!("Attribute name {name:?} contains a null character")));
+        }
+        if self.attributes.iter().any(|attribute| attribute.name == name) {
+            return Err(invalid_input(format!("Attribute {name} has already been added")));
+        }
+        // Validated again, as the fields of the format are public.
+        let format = AttributeFormat::new(format.attribute_type, format.size)?;
+        let expected = self.dimensions.get_texel_count() * format.size as usize;
+        if data.len() != expected {
+            return Err(invalid_input(format!(
+                "Attribute {name} has {} bytes of texels, expected {expected} ({width}x{height} texels of {} bytes)",
+                data.len(), format.size
+            )));
+        }
+        self.attributes.push(NsdAttribute { name: name.to_string(), format });
+        self.buffers.push(data.to_vec());
+        Ok(self)
+    }
+
+    /// Interleaves the attributes and writes the file. Fails if no attribute has been added.
+    pub fn write<W: Write + Seek>(self, output: &mut W) -> io::Result<()> {
+        if self.attributes.is_empty() {
+            return Err(invalid_input("A spatial data file requires at least one attribute".into()));
+        }
+        let stride: usize = self.attributes.iter().map(|attribute| attribute.format.size as usize).sum();
+        let LayerDimensions { width, height } = self.dimensions;
+        let mut raw_data = vec![0; stride * self.dimensions.get_texel_count()];
+        let mut offset = 0;
+        for (attribute, buffer) in self.attributes.iter().zip(&self.buffers) {
+            let size = attribute.format.size as usize;
+            for (row_major_index, value) in buffer.chunks_exact(size).enumerate() {
+                let x = (row_major_index % width as usize) as u32;
+                let y = (row_major_index / width as usize) as u32;
+                let start = self.texel_order.storage_index(x, y, width, height) * stride + offset;
+                raw_data[start..start + size].copy_from_slice(value);
+            }
+            offset += size;
+        }
+
+        let extensions = NsdExtensions { texel_order: self.texel_order, ..NsdExtensions::default() };
+        write_attributes_binary(output, &self.header, &self.attributes, &self.dimensions, &extensions, &raw_data, &[])?;
+        output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::format::AttributeType;
+    use crate::reader::NsdReader;
+
+    #[test]
+    fn interleaves_the_attributes_in_the_texel_order() {
+        let uint16 = AttributeFormat::new(AttributeType::UInt, 2).unwrap();
+        let builder = NsdBuilder::new(LayerDimensions { width: 2, height: 2 })
+            .texel_order(TexelOrder::ColumnMajor)
+            .add_layer("grass", AttributeFormat::default(), &[1, 2, 3, 4]).unwrap()
+            .add_layer("height", uint16, &[10, 0, 20, 0, 30, 0, 40, 0]).unwrap();
+        let mut output = Cursor::new(vec![]);
+        builder.write(&mut output).unwrap();
+
+        let reader = NsdReader::from_bytes(output.get_ref()).unwrap();
+        assert_eq!(reader.texel_order(), TexelOrder::ColumnMajor);
+        assert_eq!(reader.data(), [1, 10, 0, 3, 30, 0, 2, 20, 0, 4, 40, 0]);
+    }
+
+    #[test]
+    fn rejects_invalid_attributes() {
+        let builder = || NsdBuilder::new(LayerDimensions { width: 2, height: 1 });
+        let error = |result: io::Result<NsdBuilder>| result.err().unwrap().to_string();
+        assert_eq!(error(builder().add_layer("", AttributeFormat::default(), &[0, 0])), "Attribute names must not be empty");
+        assert_eq!(
+            error(builder().add_layer("grass", AttributeFormat::default(), &[0])),
+            "Attribute grass has 1 bytes of texels, expected 2 (2x1 texels of 1 bytes)"
+        );
+        let twice = builder().add_layer("grass", AttributeFormat::default(), &[0, 0]).unwrap();
+        assert_eq!(error(twice.add_layer("grass", AttributeFormat::default(), &[0, 0])), "Attribute grass has already been added");
+        assert!(builder().write(&mut Cursor::new(vec![])).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use image::{DynamicImage, ImageBuffer};
+
+use crate::layer::{LayerDimensions, LayerRegion, LayerSource};
+
+/// Cache directory created inside the input directory.
+pub const DEFAULT_CACHE_DIRECTORY_NAME: &str = ".nsdcache";
+/// Directory inside the input directory keeping the loaded layers of `--resume` runs until they succeed.
+pub const CHECKPOINT_DIRECTORY_NAME: &str = ".nsdcheckpoint";
+
+const CACHE_MAGIC: [u8; 8] = *b"NSDCACHE";
+/// Bumped whenever the cached representation or the resizing changes.
+const CACHE_VERSION: u32 = 1;
+
+/// Entries kept of every layer. A layer is resized differently by the outputs of a manifest with several outputs, or
+/// once for every size it's generated at, and those entries shouldn't evict each other.
+const KEPT_ENTRIES: usize = 4;
+
+/// Latest entries of every layer by the stem, the most recently used last.
+type MemoryEntries = HashMap<String, Vec<(u64, DynamicImage)>>;
+
+/// Stores resized layer images, keyed by the source contents, region, target dimensions and resize filter,
+/// so unchanged layers don't need to be decoded and resized again.
+#[derive(Clone)]
+pub struct LayerCache {
+    directory: PathBuf,
+    /// Entries kept decoded in memory, shared by the clones. Used by long-running modes.
+    memory: Option<Arc<Mutex<MemoryEntries>>>,
+}
+
+/// 64-bit FNV-1a, which is stable across builds unlike `DefaultHasher`.
+pub(crate) fn fnv1a(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+pub(crate) const FNV_OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+
+fn invalid_entry() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Invalid cache entry")
+}
+
+impl LayerCache {
+    pub fn new(directory: PathBuf) -> io::Result<LayerCache> {
+        fs::create_dir_all(&directory)?;
+        Ok(LayerCache { directory, memory: None })
+    }
+
+    /// Also keeps the entries in memory, so they don't need to be read from the disk again.
+    pub fn keep_in_memory(self) -> LayerCache {
+        LayerCache { memory: Some(Arc::default()), ..self }
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Key of a layer: hash of everything that affects the resized image.
+    pub fn key(
+        source: &LayerSource,
+        dimensions: &LayerDimensions,
+        region: Option<LayerRegion>,
+        filter: &str
+    ) -> io::Result<u64> {
+        let mut hash = fnv1a(FNV_OFFSET_BASIS, &CACHE_VERSION.to_le_bytes());
+        for file in source.files() {
+            hash = fnv1a(hash, &fs::read(file)?);
+        }
+        if let Some((tile_layer, tiled_config)) = &source.tile_layer {
+            hash = fnv1a(hash, format!("{tile_layer}{tiled_config:?}").as_bytes());
+        }
+        if source.alpha_channel {
+            hash = fnv1a(hash, b"alpha");
+        }
+        if let Some(channels) = &source.channels {
+            hash = fnv1a(hash, format!("channels{channels:?}").as_bytes());
+        }
+        if let Some(region) = region {
+            for value in [region.x, region.y, region.width, region.height] {
+                hash = fnv1a(hash, &value.to_le_bytes());
+            }
+        }
+        hash = fnv1a(hash, &dimensions.width.to_le_bytes());
+        hash = fnv1a(hash, &dimensions.height.to_le_bytes());
+        hash = fnv1a(hash, filter.as_bytes());
+        Ok(hash)
+    }
+
+    fn entry_path(&self, stem: &str, key: u64) -> PathBuf {
+        self.directory.join(format!("{stem}-{key:016x}.bin"))
+    }
+
+    pub fn load(&self, stem: &str, key: u64) -> Option<DynamicImage> {
+        if let Some(memory) = &self.memory {
+            let memory = memory.lock().unwrap();
+            if let Some((_, image)) = memory.get(stem).and_then(|entries| entries.iter().find(|(entry_key, _)| *entry_key == key)) {
+                let image = image.clone();
+                drop(memory);
+                self.remember(stem, key, &image);
+                return Some(image);
+            }
+        }
+        let path = self.entry_path(stem, key);
+        let image = decode_entry(&fs::read(&path).ok()?).ok()?;
+        // The modification time tells the entries used recently apart from the ones to evict.
+        let _ = File::options().write(true).open(&path).and_then(|file| file.set_modified(SystemTime::now()));
+        self.remember(stem, key, &image);
+        Some(image)
+    }
+
+    /// Keeps the image in memory as the most recently used entry of the layer.
+    fn remember(&self, stem: &str, key: u64, image: &DynamicImage) {
+        if let Some(memory) = &self.memory {
+            let mut memory = memory.lock().unwrap();
+            let entries = memory.entry(stem.to_string()).or_default();
+            entries.retain(|(entry_key, _)| *entry_key != key);
+            entries.push((key, image.clone()));
+            if entries.len() > KEPT_ENTRIES {
+                entries.remove(0);
+            }
+        }
+    }
+
+    /// Stores the image, removing the least recently used entries of the same layer beyond `KEPT_ENTRIES`.
+    pub fn store(&self, stem: &str, key: u64, image: &DynamicImage) -> io::Result<()> {
+        self.remember(stem, key, image);
+        let path = self.entry_path(stem, key);
+        fs::write(&path, encode_entry(image)?)?;
+
+        let prefix = format!("{stem}-");
+        let mut entries = vec![];
+        for entry in fs::read_dir(&self.directory)?.filter_map(|entry| entry.ok()) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let is_entry = name.strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".bin"))
+                .is_some_and(|hash| hash.len() == 16 && hash.chars().all(|char| char.is_ascii_hexdigit()));
+            if is_entry && entry.path() != path {
+                entries.push((entry.metadata()?.modified()?, entry.path()));
+            }
+        }
+        entries.sort_by(|lhs, rhs| rhs.cmp(lhs));
+        for (_, path) in entries.iter().skip(KEPT_ENTRIES - 1) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn encode_entry(image: &DynamicImage) -> io::Result<Vec<u8>> {
+    let color_code: u8 = match image {
+        DynamicImage::ImageLuma8(_) => 0,
+        DynamicImage::ImageLumaA8(_) => 1,
+        DynamicImage::ImageRgb8(_) => 2,
+        DynamicImage::ImageRgba8(_) => 3,
+        DynamicImage::ImageLuma16(_) => 4,
+        DynamicImage::ImageLumaA16(_) => 5,
+        DynamicImage::ImageRgb16(_) => 6,
+        DynamicImage::ImageRgba16(_) => 7,
+        DynamicImage::ImageRgb32F(_) => 8,
+        DynamicImage::ImageRgba32F(_) => 9,
+        _ => return Err(io::Error::new(io::ErrorKind::Unsupported, "Unsupported color type")),
+    };
+    let mut bytes = Vec::with_capacity(17 + image.as_bytes().len());
+    bytes.extend_from_slice(&CACHE_MAGIC);
+    bytes.push(color_code);
+    bytes.extend_from_slice(&image.width().to_le_bytes());
+    bytes.extend_from_slice(&image.height().to_le_bytes());
+    // Samples are stored in native byte order, the cache isn't meant to be shared between machines.
+    bytes.extend_from_slice(image.as_bytes());
+    Ok(bytes)
+}
+
+fn decode_entry(bytes: &[u8]) -> io::Result<DynamicImage> {
+    if bytes.len() < 17 || bytes[..8] != CACHE_MAGIC {
+        return Err(invalid_entry());
+    }
+    let width = u32::from_le_bytes(bytes[9..13].try_into().unwrap());
+    let height = u32::from_le_bytes(bytes[13..17].try_into().unwrap());
+    let data = &bytes[17..];
+    let u16_samples = || -> Vec<u16> {
+        data.chunks_exact(2).map(|sample| u16::from_ne_bytes(sample.try_into().unwrap())).collect()
+    };
+    let f32_samples = || -> Vec<f32> {
+        data.chunks_exact(4).map(|sample| f32::from_ne_bytes(sample.try_into().unwrap())).collect()
+    };
+
+    let image = match bytes[8] {
+        0 => ImageBuffer::from_raw(width, height, data.to_vec()).map(DynamicImage::ImageLuma8),
+        1 => ImageBuffer::from_raw(width, height, data.to_vec()).map(DynamicImage::ImageLumaA8),
+        2 => ImageBuffer::from_raw(width, height, data.to_vec()).map(DynamicImage::ImageRgb8),
+        3 => ImageBuffer::from_raw(width, height, data.to_vec()).map(DynamicImage::ImageRgba8),
+        4 => ImageBuffer::from_raw(width, height, u16_samples()).map(DynamicImage::ImageLuma16),
+        5 => ImageBuffer::from_raw(width, height, u16_samples()).map(DynamicImage::ImageLumaA16),
+        6 => ImageBuffer::from_raw(width, height, u16_samples()).map(DynamicImage::ImageRgb16),
+        7 => ImageBuffer::from_raw(width, height, u16_samples()).map(DynamicImage::ImageRgba16),
+        8 => ImageBuffer::from_raw(width, height, f32_samples()).map(DynamicImage::ImageRgb32F),
+        9 => ImageBuffer::from_raw(width, height, f32_samples()).map(DynamicImage::ImageRgba32F),
+        _ => None,
+    };
+    image.ok_or_else(invalid_entry)
+}
+
+
+/// Decoded sources kept in memory for the duration of a run, so the outputs of a manifest with several outputs
+/// decode every source once, even though each resizes it differently. Keyed by the source and its decoding settings.
+#[derive(Clone, Default)]
+pub struct DecodedSources {
+    images: Arc<Mutex<HashMap<String, DynamicImage>>>,
+}
+
+impl DecodedSources {
+    /// The image decoded earlier under this key, or the one decoded now.
+    pub fn get_or_decode(&self, key: String, decode: impl FnOnce() -> io::Result<DynamicImage>) -> io::Result<DynamicImage> {
+        if let Some(image) = self.images.lock().unwrap().get(&key) {
+            return Ok(image.clone());
+        }
+        // Not locked while decoding, other layers are decoded in parallel.
+        let image = decode()?;
+        self.images.lock().unwrap().insert(key, image.clone());
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use image::{GrayImage, Rgb32FImage, RgbaImage};
+
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    fn image(value: u8) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(3, 2, |x, y| image::Luma([value + (x + y * 3) as u8])))
+    }
+
+    #[test]
+    fn round_trips_entries() {
+        let images = [
+            image(1),
+            DynamicImage::ImageRgba8(RgbaImage::from_fn(2, 5, |x, y| image::Rgba([x as u8, y as u8, 7, 255]))),
+            DynamicImage::ImageRgb32F(Rgb32FImage::from_fn(4, 1, |x, _| image::Rgb([x as f32 / 3.0, -1.5, 1e9]))),
+        ];
+        for image in images {
+            assert_eq!(decode_entry(&encode_entry(&image).unwrap()).unwrap(), image);
+        }
+        assert!(decode_entry(b"NSDCACHE\x00").is_err());
+        assert!(decode_entry(&encode_entry(&image(1)).unwrap()[..20]).is_err());
+    }
+
+    #[test]
+    fn decodes_every_source_once() {
+        let decoded = DecodedSources::default();
+        let mut decode_count = 0;
+        for _ in 0..2 {
+            let height = decoded.clone().get_or_decode("height.png".into(), || {
+                decode_count += 1;
+                Ok(image(1))
+            });
+            assert_eq!(height.unwrap(), image(1));
+        }
+        assert_eq!(decode_count, 1);
+        assert_eq!(decoded.get_or_decode("grass.png".into(), || Ok(image(2))).unwrap(), image(2));
+        assert!(decoded.get_or_decode("rivers.png".into(), || Err(io::ErrorKind::NotFound.into())).is_err());
+    }
+
+    #[test]
+    fn keeps_the_latest_entries_of_every_layer() {
+        let directory = TempDirectory::new();
+        let cache = LayerCache::new(directory.path().to_path_buf()).unwrap();
+        // Several sizes or filters of a layer don't evict each other, nor do layers with similar names.
+        for key in 0..KEPT_ENTRIES as u64 {
+            cache.store("height", key, &image(key as u8)).unwrap();
+            // Leaves the file system time to tell the modification times apart.
+            thread::sleep(Duration::from_millis(20));
+        }
+        cache.store("height-2", 0, &image(9)).unwrap();
+        assert_eq!(fs::read_dir(directory.path()).unwrap().count(), KEPT_ENTRIES + 1);
+
+        // Loading the first entry makes it the most recently used one, so the second is evicted.
+        assert_eq!(cache.load("height", 0), Some(image(0)));
+        thread::sleep(Duration::from_millis(20));
+        cache.store("height", 100, &image(100)).unwrap();
+        assert_eq!(cache.load("height", 1), None);
+        for key in (2..KEPT_ENTRIES as u64).chain([0, 100]) {
+            assert!(cache.load("height", key).is_some(), "Entry {key} was evicted");
+        }
+        assert_eq!(cache.load("height-2", 0), Some(image(9)));
+    }
+
+    #[test]
+    fn keeps_entries_in_memory() {
+        let directory = TempDirectory::new();
+        let cache = LayerCache::new(directory.path().to_path_buf()).unwrap().keep_in_memory();
+        cache.store("height", 1, &image(1)).unwrap();
+        cache.store("height", 2, &image(2)).unwrap();
+        for entry in fs::read_dir(directory.path()).unwrap() {
+            fs::remove_file(entry.unwrap().path()).unwrap();
+        }
+        assert_eq!(cache.clone().load("height", 1), Some(image(1)));
+        assert_eq!(cache.load("height", 2), Some(image(2)));
+        assert_eq!(cache.load("height", 3), None);
+    }
+}
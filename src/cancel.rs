@@ -0,0 +1,157 @@
+//! Cancellation of running generations, by host applications or by Ctrl+C.
+
+use std::io::{self, Seek, SeekFrom, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, OnceLock};
+
+/// Shared flag stopping a generation. Clones refer to the same flag.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Error to stop with, if the token has been cancelled.
+    pub fn check(&self) -> io::Result<()> {
+        match self.is_cancelled() {
+            true => Err(cancelled_error()),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Error of operations stopped by a cancellation. Not `ErrorKind::Interrupted`, which `write_all` retries.
+pub fn cancelled_error() -> io::Error {
+    io::Error::other("Cancelled")
+}
+
+/// Writer failing as soon as the token is cancelled, so large outputs stop in the middle of compression.
+pub struct CancellableWriter<W> {
+    inner: W,
+    token: CancellationToken,
+}
+
+impl<W> CancellableWriter<W> {
+    pub fn new(inner: W, token: CancellationToken) -> CancellableWriter<W> {
+        CancellableWriter { inner, token }
+    }
+}
+
+impl<W: Write> Write for CancellableWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.token.check()?;
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.token.check()?;
+        self.inner.flush()
+    }
+}
+
+impl<W: Seek> Seek for CancellableWriter<W> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.inner.seek(position)
+    }
+}
+
+static INTERRUPT_TOKEN: OnceLock<CancellationToken> = OnceLock::new();
+
+/// Cancels the token on Ctrl+C instead of killing the process. Interrupting again kills it.
+/// Only the first token registered is cancelled.
+pub fn cancel_on_interrupt(token: &CancellationToken) {
+    if INTERRUPT_TOKEN.set(token.clone()).is_ok() {
+        signal::install();
+    }
+}
+
+/// Called by the signal handler, returns whether the interrupt has been handled.
+fn interrupt() -> bool {
+    match INTERRUPT_TOKEN.get() {
+        Some(token) if !token.is_cancelled() => {
+            token.cancel();
+            true
+        }
+        _ => false,
+    }
+}
+
+#[cfg(unix)]
+mod signal {
+    const SIGINT: i32 = 2;
+    const SIGTERM: i32 = 15;
+
+    extern "C" {
+        fn signal(signum: i32, handler: extern "C" fn(i32)) -> usize;
+        fn _exit(status: i32) -> !;
+    }
+
+    extern "C" fn handle(signum: i32) {
+        if !super::interrupt() {
+            unsafe { _exit(128 + signum) }
+        }
+    }
+
+    pub fn install() {
+        unsafe {
+            signal(SIGINT, handle);
+            signal(SIGTERM, handle);
+        }
+    }
+}
+
+#[cfg(windows)]
+mod signal {
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn SetConsoleCtrlHandler(handler: extern "system" fn(u32) -> i32, add: i32) -> i32;
+    }
+
+    /// Returning 0 passes the event on to the default handler, which ends the process.
+    extern "system" fn handle(_event: u32) -> i32 {
+        super::interrupt() as i32
+    }
+
+    pub fn install() {
+        unsafe {
+            SetConsoleCtrlHandler(handle, 1);
+        }
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+mod signal {
+    pub fn install() {}
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn stops_writing_once_cancelled() {
+        let token = CancellationToken::new();
+        let mut writer = CancellableWriter::new(Cursor::new(vec![]), token.clone());
+        writer.write_all(&[1, 2]).unwrap();
+        assert!(token.check().is_ok());
+
+        // Clones share the flag, so the writer sees the cancellation of the host's token.
+        token.clone().cancel();
+        let error = writer.write_all(&[3]).unwrap_err();
+        assert_eq!(error.to_string(), "Cancelled");
+        assert!(writer.flush().is_err());
+        assert_eq!(writer.inner.into_inner(), [1, 2]);
+    }
+}
@@ -0,0 +1,164 @@
+//! Channel maps, declaring which channels of a source image become which attributes, for exports packing several
+//! layers into the channels of one file under names the studio can't change.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::{DynamicImage, Rgb, Rgb32FImage, Rgba32FImage};
+
+use crate::toml::{self, Value};
+
+/// Channel map picked up from the input directory when none is given explicitly.
+pub const DEFAULT_CHANNEL_MAP_NAME: &str = "channels.toml";
+
+const CHANNEL_NAMES: [char; 4] = ['r', 'g', 'b', 'a'];
+
+/// Attribute made of channels of a source image.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ChannelMapping {
+    /// Name of the layer, which is also the key of its manifest entry.
+    pub stem: String,
+    /// Channels in the order they're stored, 0 to 3 for r, g, b and a.
+    pub channels: Vec<usize>,
+}
+
+/// Attributes of the source files, keyed by the file name.
+#[derive(Clone, Default, Debug)]
+pub struct ChannelMap {
+    pub files: BTreeMap<String, Vec<ChannelMapping>>,
+}
+
+fn invalid_channel_map(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Parses channel names like `r` or `rgb` into channel numbers.
+fn parse_channels(name: &str) -> Option<Vec<usize>> {
+    let channels: Vec<usize> = name.chars()
+        .map(|char| CHANNEL_NAMES.iter().position(|channel| *channel == char))
+        .collect::<Option<_>>()?;
+    let unique = channels.iter().enumerate().all(|(index, channel)| !channels[..index].contains(channel));
+    (!channels.is_empty() && unique).then_some(channels)
+}
+
+/// Channel names of the channel numbers, like `rgb`.
+pub fn channel_names(channels: &[usize]) -> String {
+    channels.iter().map(|&channel| CHANNEL_NAMES[channel]).collect()
+}
+
+impl ChannelMap {
+    /// Returns the channel map path to use: the explicit one, or the default one if it exists in the input directory.
+    pub fn find(explicit_path: Option<&Path>, directory: &Path) -> Option<PathBuf> {
+        match explicit_path {
+            Some(path) => Some(path.to_path_buf()),
+            None => Some(directory.join(DEFAULT_CHANNEL_MAP_NAME)).filter(|path| path.is_file()),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<ChannelMap> {
+        let source = fs::read_to_string(path)?;
+        ChannelMap::parse(&source)
+            .map_err(|error| invalid_channel_map(format!("{}: {error}", path.display())))
+    }
+
+    /// Parses a table per file, mapping channel names (`r`, `g`, `b`, `a` or several of them, like `rgb`) to layer
+    /// names.
+    pub fn parse(source: &str) -> io::Result<ChannelMap> {
+        let root = toml::parse(source).map_err(invalid_channel_map)?;
+        let mut map = ChannelMap::default();
+        for (file_name, table) in &root {
+            let table = table.as_table()
+                .ok_or_else(|| invalid_channel_map(format!("[\"{file_name}\"] must be a table of channels")))?;
+            let mut mappings = vec![];
+            for (channels, stem) in table {
+                let channels = parse_channels(channels).ok_or_else(|| invalid_channel_map(format!(
+                    "Invalid channels `{channels}` of {file_name}, expected r, g, b or a, or several of them like rgb"
+                )))?;
+                let stem = match stem {
+                    Value::String(stem) if !stem.is_empty() => stem.clone(),
+                    _ => return Err(invalid_channel_map(format!(
+                        "Channels {} of {file_name} must be mapped to a layer name", channel_names(&channels)
+                    ))),
+                };
+                mappings.push(ChannelMapping { stem, channels });
+            }
+            map.files.insert(file_name.clone(), mappings);
+        }
+
+        let mut stems: Vec<&str> = map.files.values().flatten().map(|mapping| mapping.stem.as_str()).collect();
+        stems.sort_unstable();
+        if let Some(stem) = stems.windows(2).find(|pair| pair[0] == pair[1]) {
+            return Err(invalid_channel_map(format!("Layer {} is mapped more than once", stem[0])));
+        }
+        Ok(map)
+    }
+
+    pub fn mappings(&self, file_name: &str) -> Option<&[ChannelMapping]> {
+        self.files.get(file_name).map(Vec::as_slice)
+    }
+}
+
+/// Image of the channels in order: grayscale for a single channel, RGB (missing channels are zero) for two or three
+/// and RGBA for four. Keeps the bit depth of the source.
+pub fn select_channels(image: &DynamicImage, channels: &[usize]) -> DynamicImage {
+    let rgba = image.to_rgba32f();
+    let (width, height) = rgba.dimensions();
+    let pick = |x: u32, y: u32, index: usize| channels.get(index).map_or(0.0, |&channel| rgba.get_pixel(x, y).0[channel]);
+    let selected = match channels.len() {
+        4 => DynamicImage::ImageRgba32F(Rgba32FImage::from_fn(width, height, |x, y| {
+            image::Rgba([pick(x, y, 0), pick(x, y, 1), pick(x, y, 2), pick(x, y, 3)])
+        })),
+        1 => DynamicImage::ImageRgb32F(Rgb32FImage::from_fn(width, height, |x, y| Rgb([pick(x, y, 0); 3]))),
+        _ => DynamicImage::ImageRgb32F(Rgb32FImage::from_fn(width, height, |x, y| {
+            Rgb([pick(x, y, 0), pick(x, y, 1), pick(x, y, 2)])
+        })),
+    };
+    match (image.color().bytes_per_pixel() / image.color().channel_count(), channels.len()) {
+        (1, 1) => DynamicImage::ImageLuma8(selected.to_luma8()),
+        (1, 4) => DynamicImage::ImageRgba8(selected.to_rgba8()),
+        (1, _) => DynamicImage::ImageRgb8(selected.to_rgb8()),
+        (2, 1) => DynamicImage::ImageLuma16(selected.to_luma16()),
+        (2, 4) => DynamicImage::ImageRgba16(selected.to_rgba16()),
+        (2, _) => DynamicImage::ImageRgb16(selected.to_rgb16()),
+        _ => selected,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn maps_the_channels_of_files_to_layers() {
+        let map = ChannelMap::parse(r#"
+            ["packed.png"]
+            r = "height"
+            gba = "splat"
+        "#).unwrap();
+        assert_eq!(map.mappings("packed.png").unwrap(), [
+            ChannelMapping { stem: "splat".into(), channels: vec![1, 2, 3] },
+            ChannelMapping { stem: "height".into(), channels: vec![0] },
+        ]);
+        assert!(map.mappings("height.png").is_none());
+
+        let error = |source: &str| ChannelMap::parse(source).unwrap_err().to_string();
+        assert!(error("[\"a.png\"]\nrr = \"height\"").contains("Invalid channels `rr` of a.png"));
+        assert!(error("[\"a.png\"]\nx = \"height\"").contains("Invalid channels `x`"));
+        assert!(error("[\"a.png\"]\nr = 1").contains("must be mapped to a layer name"));
+        assert!(error("[\"a.png\"]\nr = \"height\"\n[\"b.png\"]\ng = \"height\"").contains("Layer height is mapped more than once"));
+    }
+
+    #[test]
+    fn selects_the_channels_in_order() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([10, 20, 30, 40])));
+        assert_eq!(select_channels(&image, &[3]), DynamicImage::ImageLuma8(image::GrayImage::from_pixel(1, 1, image::Luma([40]))));
+        assert_eq!(select_channels(&image, &[2, 0]).to_rgb8().into_raw(), [30, 10, 0]);
+        assert_eq!(select_channels(&image, &[3, 2, 1, 0]).to_rgba8().into_raw(), [40, 30, 20, 10]);
+        let words = DynamicImage::ImageRgba16(image.to_rgba16());
+        assert!(matches!(select_channels(&words, &[1]), DynamicImage::ImageLuma16(_)));
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use image::{DynamicImage, GrayImage, Luma};
+
+use crate::manifest::Manifest;
+use crate::resample::ValueKind;
+use crate::toml::{self, Table};
+
+/// Color to ID mapping of a single layer.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ColorClasses {
+    /// Colors of the source image with the value stored for them.
+    pub colors: Vec<([u8; 3], u8)>,
+    /// Largest (euclidean RGB) distance at which a texel still matches its nearest color.
+    pub tolerance: f64,
+    /// Value stored for texels which don't match any color.
+    pub unmatched: u8,
+}
+
+/// Mappings of color-coded layers (e.g. hand-painted biome maps) to ID layers, keyed by the layer file name
+/// without extension.
+#[derive(Default)]
+pub struct ColorClassification {
+    pub layers: BTreeMap<String, ColorClasses>,
+}
+
+fn invalid_classification(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub(crate) fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 || !hex.chars().all(|char| char.is_ascii_hexdigit()) {
+        return None;
+    }
+    let channel = |index: usize| u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok();
+    Some([channel(0)?, channel(1)?, channel(2)?])
+}
+
+fn get_byte(table: &Table, key: &str, context: &str) -> io::Result<Option<u8>> {
+    table.get(key)
+        .map(|value| value.as_integer().and_then(|integer| u8::try_from(integer).ok())
+            .ok_or_else(|| invalid_classification(format!("`{key}` in {context} must be an integer from 0 to 255"))))
+        .transpose()
+}
+
+impl ColorClassification {
+    pub fn load(path: &Path) -> io::Result<ColorClassification> {
+        let source = fs::read_to_string(path)?;
+        ColorClassification::parse(&source)
+            .map_err(|error| invalid_classification(format!("{}: {error}", path.display())))
+    }
+
+    pub fn parse(source: &str) -> io::Result<ColorClassification> {
+        let root = toml::parse(source).map_err(invalid_classification)?;
+        if let Some(key) = root.keys().find(|key| !["tolerance", "unmatched", "layers"].contains(&key.as_str())) {
+            return Err(invalid_classification(format!("Unknown key `{key}` in the classification")));
+        }
+
+        let tolerance = match root.get("tolerance") {
+            Some(value) => value.as_float().or(value.as_integer().map(|integer| integer as f64))
+                .filter(|tolerance| *tolerance >= 0.0)
+                .ok_or_else(|| invalid_classification("`tolerance` must be a non-negative number".into()))?,
+            None => 0.0,
+        };
+        let unmatched = get_byte(&root, "unmatched", "the classification")?.unwrap_or_default();
+
+        let mut classification = ColorClassification::default();
+        let Some(layers) = root.get("layers") else {
+            return Ok(classification);
+        };
+        let layers = layers.as_table()
+            .ok_or_else(|| invalid_classification("`layers` must be a table".into()))?;
+        for (stem, colors) in layers {
+            let context = format!("[layers.{stem}]");
+            let colors = colors.as_table()
+                .ok_or_else(|| invalid_classification(format!("{context} must be a table")))?;
+            let colors = colors.keys()
+                .map(|color| {
+                    let rgb = parse_color(color)
+                        .ok_or_else(|| invalid_classification(format!("Invalid color `{color}` in {context}, expected #rrggbb")))?;
+                    Ok((rgb, get_byte(colors, color, &context)?.unwrap()))
+                })
+                .collect::<io::Result<_>>()?;
+            classification.layers.insert(stem.clone(), ColorClasses { colors, tolerance, unmatched });
+        }
+        Ok(classification)
+    }
+}
+
+impl ColorClasses {
+    /// Value of the nearest color within the tolerance.
+    fn classify_color(&self, color: [u8; 3]) -> Option<u8> {
+        self.colors.iter()
+            .map(|(class_color, value)| {
+                let distance_squared: f64 = (0..3)
+                    .map(|channel| (color[channel] as f64 - class_color[channel] as f64).powi(2))
+                    .sum();
+                (distance_squared, *value)
+            })
+            .filter(|(distance_squared, _)| distance_squared.sqrt() <= self.tolerance)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .map(|(_, value)| value)
+    }
+
+    /// Replaces the colors of the image with their IDs, returning the image and the number of unmatched texels.
+    pub fn classify(&self, image: &DynamicImage) -> (GrayImage, usize) {
+        let rgb = image.to_rgb8();
+        let mut classified: BTreeMap<[u8; 3], Option<u8>> = BTreeMap::new();
+        let mut unmatched_count = 0;
+        let ids = GrayImage::from_fn(rgb.width(), rgb.height(), |x, y| {
+            let color = rgb.get_pixel(x, y).0;
+            let id = *classified.entry(color).or_insert_with(|| self.classify_color(color));
+            Luma([id.unwrap_or_else(|| {
+                unmatched_count += 1;
+                self.unmatched
+            })])
+        });
+        (ids, unmatched_count)
+    }
+}
+
+impl Manifest {
+    /// Makes the layers of the classification store IDs in place of their colors.
+    pub fn apply_classification(&mut self, classification: ColorClassification) {
+        for (stem, classes) in classification.layers {
+            let config = self.layers.entry(stem).or_default();
+            config.classes = Some(classes);
+            config.kind = ValueKind::Categorical;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Rgb, RgbImage};
+
+    use super::*;
+
+    #[test]
+    fn replaces_the_colors_with_the_nearest_class() {
+        let classification = ColorClassification::parse(r##"
+            tolerance = 10
+            unmatched = 255
+            [layers.biomes]
+            "#00ff00" = 1
+            "#0000ff" = 2
+        "##).unwrap();
+        let classes = &classification.layers["biomes"];
+        let colors = [[0, 255, 0], [5, 0, 250], [128, 128, 128], [0, 250, 3]];
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(4, 1, |x, _| Rgb(colors[x as usize])));
+        let (ids, unmatched) = classes.classify(&image);
+        assert_eq!(ids.into_raw(), [1, 2, 255, 1]);
+        assert_eq!(unmatched, 1);
+
+        for invalid in ["tolerance = -1", "[layers.biomes]\n\"green\" = 1", "[layers.biomes]\n\"#00ff00\" = 256", "colors = 1"] {
+            assert!(ColorClassification::parse(invalid).is_err(), "{invalid:?} was accepted");
+        }
+    }
+}
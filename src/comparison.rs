@@ -0,0 +1,184 @@
+//! Contact sheets of `--save-resized`, putting a crop of every source next to the same area of its resized layer,
+//! both at 100% zoom, so the effect of the resize filters on thin features can be checked at a glance, and the
+//! measurements of `--validate-resize`.
+
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use image::imageops::FilterType;
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+
+use crate::resample::ValueKind;
+
+/// Largest side of the crops of the sources.
+const CROP_SIZE: u32 = 256;
+const PADDING: u32 = 8;
+const BACKGROUND: Rgba<u8> = Rgba([128, 128, 128, 255]);
+
+/// Crops of the center of a source and of the same area of its resized layer.
+pub struct ResizeComparison {
+    pub stem: String,
+    pub original: RgbaImage,
+    pub resized: RgbaImage,
+}
+
+impl ResizeComparison {
+    pub fn new(stem: &str, original: &DynamicImage, resized: &DynamicImage) -> ResizeComparison {
+        let (width, height) = (CROP_SIZE.min(original.width()), CROP_SIZE.min(original.height()));
+        let (x, y) = ((original.width() - width) / 2, (original.height() - height) / 2);
+        let scale_x = resized.width() as f64 / original.width() as f64;
+        let scale_y = resized.height() as f64 / original.height() as f64;
+        let resized_x = (x as f64 * scale_x).floor() as u32;
+        let resized_y = (y as f64 * scale_y).floor() as u32;
+        let resized_width = ((width as f64 * scale_x).round() as u32).clamp(1, resized.width() - resized_x);
+        let resized_height = ((height as f64 * scale_y).round() as u32).clamp(1, resized.height() - resized_y);
+        ResizeComparison {
+            stem: stem.to_string(),
+            original: original.crop_imm(x, y, width, height).to_rgba8(),
+            resized: resized.crop_imm(resized_x, resized_y, resized_width, resized_height).to_rgba8(),
+        }
+    }
+}
+
+/// Comparisons collected from every layer while they're loaded, possibly on several threads.
+#[derive(Clone, Default)]
+pub struct ComparisonSheet {
+    comparisons: Arc<Mutex<Vec<ResizeComparison>>>,
+}
+
+impl ComparisonSheet {
+    pub fn add(&self, comparison: ResizeComparison) {
+        self.comparisons.lock().unwrap().push(comparison);
+    }
+
+    /// Writes a row per layer in the order of the stems, the source on the left and the resized layer on the right.
+    /// Returns the stems of the rows.
+    pub fn write(&self, path: &Path, order: &[&str]) -> io::Result<Vec<String>> {
+        let mut comparisons = std::mem::take(&mut *self.comparisons.lock().unwrap());
+        comparisons.sort_by_key(|comparison| order.iter().position(|stem| *stem == comparison.stem));
+        let original_width = comparisons.iter().map(|comparison| comparison.original.width()).max().unwrap_or(0);
+        let resized_width = comparisons.iter().map(|comparison| comparison.resized.width()).max().unwrap_or(0);
+        let row_heights: Vec<u32> = comparisons.iter()
+            .map(|comparison| comparison.original.height().max(comparison.resized.height()))
+            .collect();
+
+        let width = PADDING * 3 + original_width + resized_width;
+        let height = PADDING + row_heights.iter().map(|height| height + PADDING).sum::<u32>();
+        let mut sheet = RgbaImage::from_pixel(width, height, BACKGROUND);
+        let mut top = PADDING;
+        for (comparison, row_height) in comparisons.iter().zip(row_heights) {
+            imageops::replace(&mut sheet, &comparison.original, PADDING as i64, top as i64);
+            imageops::replace(&mut sheet, &comparison.resized, (PADDING * 2 + original_width) as i64, top as i64);
+            top += row_height + PADDING;
+        }
+        sheet.save(path).map_err(io::Error::other)?;
+        Ok(comparisons.into_iter().map(|comparison| comparison.stem).collect())
+    }
+}
+
+/// Information a layer lost by being downscaled, measured by upscaling it back to the size of its source.
+/// Differences are fractions of the sample range, over the color channels.
+pub struct ResizeLoss {
+    pub max_difference: f64,
+    pub mean_difference: f64,
+    /// Fraction of the source texels whose value changed, for categorical and mask layers.
+    pub changed: Option<f64>,
+}
+
+impl ResizeLoss {
+    /// Upscales the resized layer back with the nearest filter for categorical and mask layers, which must keep
+    /// their values, and the triangle filter otherwise.
+    pub fn measure(original: &DynamicImage, resized: &DynamicImage, kind: ValueKind) -> ResizeLoss {
+        let filter = match kind {
+            ValueKind::Continuous => FilterType::Triangle,
+            _ => FilterType::Nearest,
+        };
+        let restored = resized.resize_exact(original.width(), original.height(), filter).to_rgba32f();
+        let original = original.to_rgba32f();
+        let (mut max_difference, mut total_difference, mut changed_count) = (0.0f64, 0.0, 0usize);
+        for (original, restored) in original.pixels().zip(restored.pixels()) {
+            let difference = original.0[..3].iter().zip(&restored.0[..3])
+                .map(|(original, restored)| (original - restored).abs() as f64)
+                .fold(0.0, f64::max);
+            max_difference = max_difference.max(difference);
+            total_difference += difference;
+            if difference > 0.0 {
+                changed_count += 1;
+            }
+        }
+        let texel_count = (original.width() as usize * original.height() as usize).max(1) as f64;
+        ResizeLoss {
+            max_difference,
+            mean_difference: total_difference / texel_count,
+            changed: (kind != ValueKind::Continuous).then_some(changed_count as f64 / texel_count),
+        }
+    }
+
+    /// The measure compared to the threshold: the changed fraction of categorical and mask layers, otherwise
+    /// the mean difference.
+    pub fn loss(&self) -> f64 {
+        self.changed.unwrap_or(self.mean_difference)
+    }
+}
+
+impl std::fmt::Display for ResizeLoss {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            formatter, "max difference {:.2}%, mean difference {:.2}%",
+            self.max_difference * 100.0, self.mean_difference * 100.0
+        )?;
+        if let Some(changed) = self.changed {
+            write!(formatter, ", {:.2}% of the texels changed", changed * 100.0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    fn gradient(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageLuma8(image::GrayImage::from_fn(width, height, |x, _| image::Luma([(x % 256) as u8])))
+    }
+
+    #[test]
+    fn puts_the_center_of_the_sources_next_to_their_resized_layers() {
+        let comparison = ResizeComparison::new("height", &gradient(512, 8), &gradient(128, 2));
+        assert_eq!(comparison.original.dimensions(), (256, 8));
+        assert_eq!(comparison.original.get_pixel(0, 0), &Rgba([128, 128, 128, 255]));
+        assert_eq!(comparison.resized.dimensions(), (64, 2));
+        assert_eq!(comparison.resized.get_pixel(0, 0), &Rgba([32, 32, 32, 255]));
+
+        let sheet = ComparisonSheet::default();
+        sheet.add(ResizeComparison::new("grass", &gradient(4, 4), &gradient(2, 2)));
+        sheet.clone().add(comparison);
+        let directory = TempDirectory::new();
+        let path = directory.join("comparison.png");
+        assert_eq!(sheet.write(&path, &["height", "grass"]).unwrap(), ["height", "grass"]);
+
+        let written = image::open(&path).unwrap().to_rgba8();
+        assert_eq!(written.dimensions(), (PADDING * 3 + 256 + 64, PADDING + (8 + PADDING) + (4 + PADDING)));
+        assert_eq!(written.get_pixel(PADDING + 1, PADDING), &Rgba([129, 129, 129, 255]));
+        assert_eq!(written.get_pixel(PADDING * 2 + 256 + 1, PADDING), &Rgba([33, 33, 33, 255]));
+        assert_eq!(written.get_pixel(PADDING + 1, PADDING * 2 + 8), &Rgba([1, 1, 1, 255]));
+        assert_eq!(written.get_pixel(0, 0), &BACKGROUND);
+    }
+
+    #[test]
+    fn measures_what_downscaling_loses() {
+        let gray = |width, texels: Vec<u8>| DynamicImage::ImageLuma8(image::GrayImage::from_raw(width, 1, texels).unwrap());
+        let flat = ResizeLoss::measure(&gray(4, vec![80; 4]), &gray(2, vec![80; 2]), ValueKind::Continuous);
+        assert_eq!((flat.max_difference, flat.mean_difference, flat.changed), (0.0, 0.0, None));
+
+        let stripes = ResizeLoss::measure(&gray(2, vec![0, 255]), &gray(1, vec![128]), ValueKind::Continuous);
+        assert!((stripes.loss() - 0.5).abs() < 0.01, "{stripes}");
+
+        let ids = ResizeLoss::measure(&gray(4, vec![1, 1, 2, 3]), &gray(2, vec![1, 2]), ValueKind::Categorical);
+        assert_eq!(ids.changed, Some(0.25));
+        assert_eq!(ids.loss(), 0.25);
+        assert!(ids.to_string().ends_with(", 25.00% of the texels changed"), "{ids}");
+    }
+}
@@ -0,0 +1,265 @@
+//! Chunk-level comparison of two spatial data files: which chunks were added, removed, moved or changed.
+
+use std::io;
+
+use crate::format::*;
+use crate::reader::{NsdReader, NsdSection};
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A chunk of a file, identified by its kind and, for ATR and SEC chunks, by the name it stores.
+pub struct NsdChunk<'a> {
+    /// e.g. `DIM`, `ATR height`, `DATA` or `SEC coarse`. Repeated unknown chunks are numbered, e.g. `XYZ #2`.
+    pub key: String,
+    pub offset: usize,
+    /// All bytes of the chunk, including its magic.
+    pub bytes: &'a [u8],
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> io::Result<u32> {
+    bytes.get(offset..offset + 4)
+        .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| invalid_data(format!("Unexpected end of file at offset {offset:#x}")))
+}
+
+fn read_string(bytes: &[u8], offset: usize) -> io::Result<String> {
+    let length = bytes.get(offset..).and_then(|rest| rest.iter().position(|&byte| byte == 0))
+        .ok_or_else(|| invalid_data(format!("Unterminated string at offset {offset:#x}")))?;
+    Ok(String::from_utf8_lossy(&bytes[offset..offset + length]).into_owned())
+}
+
+/// Splits a file into its chunks, in the order they're stored.
+pub fn chunk_layout(bytes: &[u8]) -> io::Result<Vec<NsdChunk<'_>>> {
+    let mut chunks = vec![];
+    let mut push = |key: String, offset: usize, end: usize| -> io::Result<usize> {
+        let chunk = bytes.get(offset..end).ok_or_else(|| invalid_data(format!("Unexpected end of the {key} chunk")))?;
+        chunks.push(NsdChunk { key, offset, bytes: chunk });
+        Ok(end)
+    };
+
+    let mut offset = push("HEADER".into(), 0, NSD_HEADER.len())?;
+    offset = push("DIM".into(), offset, offset + 20)?;
+    while bytes.get(offset..offset + 4) == Some(NSD_ATTR_HEADER.as_slice()) {
+        let name = read_string(bytes, offset + 4)?;
+        offset = push(format!("ATR {name}"), offset, offset + 4 + name.len() + 3)?;
+    }
+    if bytes.get(offset..offset + 4) != Some(NSD_DATA_HEADER.as_slice()) {
+        return Err(invalid_data(format!("Expected a DATA chunk at offset {offset:#x}")));
+    }
+    let compressed_size = read_u32(bytes, offset + 8)? as usize;
+    offset = push("DATA".into(), offset, offset + 12 + compressed_size)?;
+
+    while offset < bytes.len() {
+        let magic = bytes.get(offset..offset + 4)
+            .ok_or_else(|| invalid_data(format!("Truncated chunk at offset {offset:#x}")))?;
+        let size = read_u32(bytes, offset + 4)? as usize;
+        let name = String::from_utf8_lossy(&magic[..3]).into_owned();
+        let key = match magic == NSD_SECTION_HEADER.as_slice() {
+            true => format!("SEC {}", read_string(bytes, offset + 8)?),
+            false => name,
+        };
+        offset = push(key, offset, offset + 8 + size)?;
+    }
+
+    // Repeated keys, e.g. of unknown chunks, are numbered to stay unique.
+    for index in 1..chunks.len() {
+        let key = &chunks[index].key;
+        let repeats = chunks[..index].iter()
+            .filter(|chunk| chunk.key == *key || chunk.key.starts_with(&format!("{key} #")))
+            .count();
+        if repeats > 0 {
+            chunks[index].key = format!("{} #{}", chunks[index].key, repeats + 1);
+        }
+    }
+    Ok(chunks)
+}
+
+/// What changed in the decoded contents of a chunk present in both files.
+fn describe_change(key: &str, old: &NsdReader, new: &NsdReader) -> Option<String> {
+    let kind = key.split(' ').next().unwrap_or_default();
+    match kind {
+        "HEADER" => Some(format!(
+            "{}/{} -> {}/{}", old.header().vendor(), old.header().identifier(), new.header().vendor(), new.header().identifier()
+        )),
+        "DIM" => {
+            let mut changes = vec![];
+            if old.dimensions() != new.dimensions() {
+                changes.push(format!(
+                    "{}x{} -> {}x{}", old.dimensions().width, old.dimensions().height, new.dimensions().width, new.dimensions().height
+                ));
+            }
+            if old.texel_order() != new.texel_order() {
+                changes.push(format!("{} -> {}", old.texel_order().name(), new.texel_order().name()));
+            }
+            if old.extensions().row_stride != new.extensions().row_stride {
+                changes.push(format!(
+                    "row stride {} -> {}", old.extensions().row_stride.unwrap_or(0), new.extensions().row_stride.unwrap_or(0)
+                ));
+            }
+            (!changes.is_empty()).then(|| changes.join(", "))
+        }
+        "ATR" => {
+            let name = &key[4..];
+            let (old_index, old_attribute) = old.attributes().iter().enumerate().find(|(_, attribute)| attribute.name == name)?;
+            let (new_index, new_attribute) = new.attributes().iter().enumerate().find(|(_, attribute)| attribute.name == name)?;
+            let mut changes = vec![];
+            if old_attribute.format != new_attribute.format {
+                changes.push(format!(
+                    "{} ({} bytes) -> {} ({} bytes)",
+                    old_attribute.format.attribute_type.name(), old_attribute.format.size,
+                    new_attribute.format.attribute_type.name(), new_attribute.format.size
+                ));
+            }
+            if old_index != new_index {
+                changes.push(format!("index {old_index} -> {new_index}"));
+            }
+            (!changes.is_empty()).then(|| changes.join(", "))
+        }
+        "WLD" => {
+            let describe = |reader: &NsdReader| match reader.world() {
+                Some(world) => format!(
+                    "origin ({}, {}), {} x {} units per texel{}",
+                    world.origin[0], world.origin[1], world.units_per_texel[0], world.units_per_texel[1],
+                    world.epsg.map_or(String::new(), |epsg| format!(" in EPSG:{epsg}"))
+                ),
+                None => "none".to_string(),
+            };
+            Some(format!("{} -> {}", describe(old), describe(new)))
+        }
+        "GRP" => {
+            let names = |reader: &NsdReader| -> Vec<String> {
+                reader.groups().iter().map(|group| {
+                    let members: Vec<&str> = group.attributes.iter()
+                        .map(|&index| reader.attributes()[index as usize].name.as_str())
+                        .collect();
+                    format!("{} [{}]", group.name, members.join(", "))
+                }).collect()
+            };
+            let (old_groups, new_groups) = (names(old), names(new));
+            let mut changes: Vec<String> = old_groups.iter().filter(|group| !new_groups.contains(group))
+                .map(|group| format!("removed {group}"))
+                .collect();
+            changes.extend(new_groups.iter().filter(|group| !old_groups.contains(group)).map(|group| format!("added {group}")));
+            (!changes.is_empty()).then(|| changes.join(", "))
+        }
+        "MET" => {
+            let mut changed: Vec<&str> = vec![];
+            for (index, attribute) in new.attributes().iter().enumerate() {
+                let old_metadata = old.attributes().iter().position(|old_attribute| old_attribute.name == attribute.name)
+                    .and_then(|old_index| old.attribute_metadata(old_index));
+                let new_metadata = new.attribute_metadata(index);
+                let same = match (old_metadata, new_metadata) {
+                    (Some(old_metadata), Some(new_metadata)) => old_metadata.description == new_metadata.description
+                        && old_metadata.tags == new_metadata.tags
+                        && old_metadata.nodata == new_metadata.nodata
+                        && old_metadata.unit == new_metadata.unit
+                        && old_metadata.kind == new_metadata.kind
+                        && old_metadata.priority == new_metadata.priority
+                        && old_metadata.weight == new_metadata.weight,
+                    (old_metadata, new_metadata) => old_metadata.is_none() && new_metadata.is_none(),
+                };
+                if !same {
+                    changed.push(&attribute.name);
+                }
+            }
+            (!changed.is_empty()).then(|| format!("metadata of {} changed", changed.join(", ")))
+        }
+        "SEC" => {
+            let name = &key[4..];
+            let old_section = old.sections().iter().find(|section| section.name == name)?;
+            let new_section = new.sections().iter().find(|section| section.name == name)?;
+            let mut changes = vec![];
+            if old_section.dimensions != new_section.dimensions {
+                changes.push(format!(
+                    "{}x{} -> {}x{}", old_section.dimensions.width, old_section.dimensions.height,
+                    new_section.dimensions.width, new_section.dimensions.height
+                ));
+            }
+            let names = |section: &NsdSection| -> Vec<String> {
+                section.attributes.iter().map(|attribute| attribute.name.clone()).collect()
+            };
+            if names(old_section) != names(new_section) {
+                changes.push(format!("attributes [{}] -> [{}]", names(old_section).join(", "), names(new_section).join(", ")));
+            }
+            if changes.is_empty() && old_section.data != new_section.data {
+                changes.push("texel data changed".into());
+            }
+            (!changes.is_empty()).then(|| changes.join(", "))
+        }
+        "DATA" => match old.data() == new.data() {
+            // e.g. after a change of the zlib version.
+            true => Some("same texels, compressed differently".into()),
+            false => Some("texel data changed".into()),
+        },
+        _ => None,
+    }
+}
+
+/// Chunks only in one of the files, chunks which moved to another offset or changed their size, and what changed
+/// in the chunks whose bytes differ, e.g. `DATA: moved from 0x49 to 0x55, 151 -> 160 bytes, texel data changed`.
+pub fn diff_structure(old_bytes: &[u8], new_bytes: &[u8]) -> io::Result<Vec<String>> {
+    let (old, new) = (NsdReader::from_bytes(old_bytes)?, NsdReader::from_bytes(new_bytes)?);
+    let (old_chunks, new_chunks) = (chunk_layout(old_bytes)?, chunk_layout(new_bytes)?);
+
+    let mut differences = vec![];
+    for old_chunk in &old_chunks {
+        if !new_chunks.iter().any(|chunk| chunk.key == old_chunk.key) {
+            differences.push(format!("{}: removed ({} bytes at {:#x})", old_chunk.key, old_chunk.bytes.len(), old_chunk.offset));
+        }
+    }
+    for new_chunk in &new_chunks {
+        let Some(old_chunk) = old_chunks.iter().find(|chunk| chunk.key == new_chunk.key) else {
+            differences.push(format!("{}: added ({} bytes at {:#x})", new_chunk.key, new_chunk.bytes.len(), new_chunk.offset));
+            continue;
+        };
+        let mut changes = vec![];
+        if old_chunk.offset != new_chunk.offset {
+            changes.push(format!("moved from {:#x} to {:#x}", old_chunk.offset, new_chunk.offset));
+        }
+        if old_chunk.bytes.len() != new_chunk.bytes.len() {
+            changes.push(format!("{} -> {} bytes", old_chunk.bytes.len(), new_chunk.bytes.len()));
+        }
+        if old_chunk.bytes != new_chunk.bytes {
+            changes.push(describe_change(&new_chunk.key, &old, &new).unwrap_or_else(|| "content changed".into()));
+        }
+        if !changes.is_empty() {
+            differences.push(format!("{}: {}", new_chunk.key, changes.join(", ")));
+        }
+    }
+    Ok(differences)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builder::NsdBuilder;
+    use crate::format::{AttributeFormat, TexelOrder};
+    use crate::layer::LayerDimensions;
+
+    fn file_bytes(layers: &[(&str, [u8; 2])], order: TexelOrder) -> Vec<u8> {
+        let mut builder = NsdBuilder::new(LayerDimensions { width: 2, height: 1 }).texel_order(order);
+        for (name, texels) in layers {
+            builder = builder.add_layer(name, AttributeFormat::default(), texels).unwrap();
+        }
+        let mut bytes = Cursor::new(vec![]);
+        builder.write(&mut bytes).unwrap();
+        bytes.into_inner()
+    }
+
+    #[test]
+    fn describes_the_changed_chunks() {
+        let old = file_bytes(&[("grass", [1, 2])], TexelOrder::RowMajor);
+        assert!(diff_structure(&old, &old).unwrap().is_empty());
+
+        let new = file_bytes(&[("grass", [1, 3]), ("height", [0, 0])], TexelOrder::ColumnMajor);
+        assert_eq!(diff_structure(&old, &new).unwrap(), [
+            "DIM: row-major -> column-major",
+            "ATR height: added (13 bytes at 0x30)",
+            "DATA: moved from 0x30 to 0x3d, 22 -> 24 bytes, texel data changed",
+        ]);
+    }
+}
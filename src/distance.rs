@@ -0,0 +1,147 @@
+//! Distance fields of mask layers, since runtime queries like the distance to water want the distance rather than
+//! the mask itself.
+
+use image::{DynamicImage, Rgb, Rgb32FImage};
+
+/// Squared distance standing in for infinity, large enough to lose against any real distance.
+const FAR: f64 = 1e30;
+
+/// How a mask is turned into distances, measured in texels from texel center to texel center.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DistanceField {
+    /// Distance to the nearest texel inside the mask, 0 inside, mapped from 0..max_distance to the attribute range.
+    Unsigned { max_distance: f32 },
+    /// Distance to the edge of the mask, negative inside, mapped from -max_distance..max_distance to the attribute
+    /// range, so the edge is at its middle.
+    Signed { max_distance: f32 },
+}
+
+impl DistanceField {
+    pub fn from_name(name: &str, max_distance: f32) -> Option<DistanceField> {
+        match name {
+            "unsigned" => Some(DistanceField::Unsigned { max_distance }),
+            "signed" => Some(DistanceField::Signed { max_distance }),
+            _ => None,
+        }
+    }
+
+    /// Distance field of the mask in the first channel of the image, where texels at or above half of the range are
+    /// inside. The values are normalized from 0 to 1.
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        let mask = image.to_rgb32f();
+        let (width, height) = mask.dimensions();
+        let inside: Vec<bool> = mask.pixels().map(|pixel| pixel.0[0] >= 0.5).collect();
+        let to_inside = distance_transform(&inside, width as usize, height as usize);
+        let values: Vec<f32> = match *self {
+            DistanceField::Unsigned { max_distance } => to_inside.iter()
+                .map(|distance| (distance / max_distance as f64).min(1.0) as f32)
+                .collect(),
+            DistanceField::Signed { max_distance } => {
+                let outside: Vec<bool> = inside.iter().map(|inside| !inside).collect();
+                let to_outside = distance_transform(&outside, width as usize, height as usize);
+                // Texel centers next to the edge are half a texel away from it.
+                inside.iter().zip(to_inside.iter().zip(&to_outside))
+                    .map(|(&inside, (to_inside, to_outside))| {
+                        let distance = if inside { 0.5 - to_outside } else { to_inside - 0.5 };
+                        (0.5 + 0.5 * (distance / max_distance as f64).clamp(-1.0, 1.0)) as f32
+                    })
+                    .collect()
+            }
+        };
+        DynamicImage::ImageRgb32F(Rgb32FImage::from_fn(width, height, |x, y| {
+            Rgb([values[y as usize * width as usize + x as usize]; 3])
+        }))
+    }
+}
+
+/// Euclidean distance of every texel to the nearest feature texel, by the separable algorithm of Felzenszwalb and
+/// Huttenlocher: squared distances along the rows, then along the columns.
+fn distance_transform(features: &[bool], width: usize, height: usize) -> Vec<f64> {
+    let mut squared: Vec<f64> = features.iter().map(|&feature| if feature { 0.0 } else { FAR }).collect();
+    let mut line = vec![0.0; width.max(height)];
+    let mut transformed = vec![0.0; width.max(height)];
+    for y in 0..height {
+        let row = &mut squared[y * width..(y + 1) * width];
+        line[..width].copy_from_slice(row);
+        transform_line(&line[..width], &mut transformed[..width]);
+        row.copy_from_slice(&transformed[..width]);
+    }
+    for x in 0..width {
+        for y in 0..height {
+            line[y] = squared[y * width + x];
+        }
+        transform_line(&line[..height], &mut transformed[..height]);
+        for y in 0..height {
+            squared[y * width + x] = transformed[y];
+        }
+    }
+    squared.into_iter().map(f64::sqrt).collect()
+}
+
+/// Squared distance transform of a line: the lower envelope of the parabolas rooted at every sample.
+fn transform_line(samples: &[f64], output: &mut [f64]) {
+    let count = samples.len();
+    // Samples whose parabolas form the envelope, and the boundaries between them.
+    let mut roots = vec![0usize; count];
+    let mut boundaries = vec![0.0; count + 1];
+    let mut envelope = 0;
+    boundaries[0] = f64::NEG_INFINITY;
+    boundaries[1] = f64::INFINITY;
+    let intersection = |q: usize, p: usize| {
+        ((samples[q] + (q * q) as f64) - (samples[p] + (p * p) as f64)) / (2 * q - 2 * p) as f64
+    };
+    for q in 1..count {
+        let mut boundary = intersection(q, roots[envelope]);
+        while boundary <= boundaries[envelope] {
+            envelope -= 1;
+            boundary = intersection(q, roots[envelope]);
+        }
+        envelope += 1;
+        roots[envelope] = q;
+        boundaries[envelope] = boundary;
+        boundaries[envelope + 1] = f64::INFINITY;
+    }
+    envelope = 0;
+    for (q, output) in output.iter_mut().enumerate() {
+        while boundaries[envelope + 1] < q as f64 {
+            envelope += 1;
+        }
+        let p = roots[envelope];
+        *output = (q as f64 - p as f64).powi(2) + samples[p];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GrayImage;
+
+    use super::*;
+
+    fn values(image: DynamicImage) -> Vec<f32> {
+        image.to_rgb32f().pixels().map(|pixel| pixel.0[0]).collect()
+    }
+
+    #[test]
+    fn measures_the_distance_to_the_mask() {
+        let mask = DynamicImage::ImageLuma8(GrayImage::from_raw(5, 1, vec![0, 0, 255, 0, 0]).unwrap());
+        assert_eq!(values(DistanceField::Unsigned { max_distance: 4.0 }.apply(&mask)), [0.5, 0.25, 0.0, 0.25, 0.5]);
+        assert_eq!(values(DistanceField::Signed { max_distance: 2.0 }.apply(&mask)), [0.875, 0.625, 0.375, 0.625, 0.875]);
+        let empty = DynamicImage::ImageLuma8(GrayImage::new(2, 2));
+        assert_eq!(values(DistanceField::Unsigned { max_distance: 4.0 }.apply(&empty)), [1.0; 4]);
+    }
+
+    #[test]
+    fn matches_the_brute_force_distances() {
+        let (width, height) = (7, 5);
+        let features: Vec<bool> = (0..width * height).map(|index| index * 7 % 11 == 0).collect();
+        let distances = distance_transform(&features, width, height);
+        for (index, distance) in distances.iter().enumerate() {
+            let (x, y) = ((index % width) as f64, (index / width) as f64);
+            let nearest = features.iter().enumerate()
+                .filter(|(_, &feature)| feature)
+                .map(|(feature, _)| ((feature % width) as f64 - x).hypot((feature / width) as f64 - y))
+                .fold(f64::INFINITY, f64::min);
+            assert!((distance - nearest).abs() < 1e-9, "texel {index}: {distance} instead of {nearest}");
+        }
+    }
+}
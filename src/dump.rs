@@ -0,0 +1,294 @@
+//! Annotated dump of the chunks of a spatial data file, for debugging readers of the format.
+//! Unlike `NsdReader`, it shows everything up to the first malformed byte.
+
+use std::fmt::Write as _;
+use std::io::{self, Read};
+
+use flate2::read::ZlibDecoder;
+
+use crate::format::*;
+use crate::resample::ValueKind;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Dumper<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// Number of bytes shown of every payload.
+    preview: usize,
+    output: &'a mut String,
+}
+
+impl<'a> Dumper<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() - self.position < count {
+            return Err(invalid_data(format!(
+                "Unexpected end of file at offset {:#010x}, {count} more bytes expected", self.position
+            )));
+        }
+        let slice = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(slice)
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn read_string(&mut self) -> io::Result<String> {
+        let length = self.bytes[self.position..].iter().position(|&byte| byte == 0)
+            .ok_or_else(|| invalid_data(format!("Unterminated string at offset {:#010x}", self.position)))?;
+        let string = String::from_utf8_lossy(self.take(length)?).into_owned();
+        self.take(1)?;
+        Ok(string)
+    }
+
+    fn peek_magic(&self) -> Option<&'a [u8]> {
+        self.bytes.get(self.position..self.position + 4)
+    }
+
+    /// Starts the entry of a chunk at the given offset.
+    fn chunk(&mut self, offset: usize, depth: usize, name: &str, summary: &str) {
+        let indent = "  ".repeat(depth);
+        let _ = writeln!(self.output, "{offset:#010x}  {indent}{name}  {summary}");
+    }
+
+    /// Adds a decoded field to the current chunk.
+    fn field(&mut self, depth: usize, text: &str) {
+        let indent = "  ".repeat(depth);
+        let _ = writeln!(self.output, "            {indent}  {text}");
+    }
+
+    /// Hex and ASCII view of the first bytes, 16 per line.
+    fn hex(&mut self, depth: usize, label: &str, bytes: &[u8]) {
+        let shown = &bytes[..bytes.len().min(self.preview)];
+        if shown.is_empty() {
+            return;
+        }
+        let more = match bytes.len() > shown.len() {
+            true => format!(" (first {} of {})", shown.len(), bytes.len()),
+            false => String::new(),
+        };
+        self.field(depth, &format!("{label}{more}:"));
+        for line in shown.chunks(16) {
+            let hex: Vec<String> = line.iter().map(|byte| format!("{byte:02X}")).collect();
+            let ascii: String = line.iter().map(|&byte| if byte.is_ascii_graphic() { byte as char } else { '.' }).collect();
+            self.field(depth + 1, &format!("{:<47}  {ascii}", hex.join(" ")));
+        }
+    }
+
+    fn dump_header(&mut self) -> io::Result<()> {
+        let bytes = self.take(NSD_HEADER.len())?;
+        let summary = match NsdHeader::from_bytes(bytes.try_into().unwrap()) {
+            Ok(header) => format!("vendor {}, identifier {}", header.vendor(), header.identifier()),
+            Err(error) => format!("invalid: {error}"),
+        };
+        self.chunk(0, 0, "HEADER", &format!("{} bytes, {summary}", NSD_HEADER.len()));
+        self.hex(0, "bytes", bytes);
+        Ok(())
+    }
+
+    /// DIM, ATR and DATA chunks of the main section or of a SEC chunk.
+    fn dump_section(&mut self, depth: usize) -> io::Result<()> {
+        let offset = self.position;
+        if self.take(4)? != NSD_DIM_HEADER.as_slice() {
+            return Err(invalid_data(format!("Expected a DIM chunk at offset {offset:#010x}")));
+        }
+        let (width, height) = (self.read_u32()?, self.read_u32()?);
+        let code = self.read_u32()?;
+        let row_stride = self.read_u32()?;
+        self.chunk(offset, depth, "DIM", &format!("{} bytes, {width}x{height} texels", self.position - offset));
+        let order = TexelOrder::from_code(code).map_or("unknown", |order| order.name());
+        self.field(depth, &format!("texel order {code} ({order})"));
+        match row_stride {
+            1 => self.field(depth, "tightly packed rows"),
+            row_stride => self.field(depth, &format!("row stride {row_stride} bytes")),
+        }
+
+        let mut stride = 0;
+        while self.peek_magic() == Some(NSD_ATTR_HEADER.as_slice()) {
+            let offset = self.position;
+            self.take(4)?;
+            let name = self.read_string()?;
+            let size = self.read_u8()?;
+            let type_code = self.read_u8()?;
+            let type_name = AttributeType::from_code(type_code).map_or("unknown", |attribute_type| attribute_type.name());
+            stride += size as usize;
+            self.chunk(offset, depth, "ATR", &format!(
+                "{} bytes, {name}: {type_name} (type {type_code}), {size} bytes per texel", self.position - offset
+            ));
+        }
+
+        let offset = self.position;
+        if self.take(4)? != NSD_DATA_HEADER.as_slice() {
+            return Err(invalid_data(format!("Expected an ATR or DATA chunk at offset {offset:#010x}")));
+        }
+        let combined_size = self.read_u32()? as usize;
+        let compressed_size = self.read_u32()? as usize;
+        let compressed = self.take(compressed_size)?;
+        self.chunk(offset, depth, "DATA", &format!(
+            "{} bytes, {combined_size} bytes of texels compressed to {compressed_size}", self.position - offset
+        ));
+        let texel_count = width as usize * height as usize;
+        if stride * texel_count != combined_size {
+            self.field(depth, &format!(
+                "note: {texel_count} texels of {stride} bytes are {} bytes, the size includes row padding or is wrong",
+                stride * texel_count
+            ));
+        }
+        self.hex(depth, "compressed", compressed);
+        let mut texels = vec![];
+        let decoder = ZlibDecoder::new(compressed).take(self.preview as u64).read_to_end(&mut texels);
+        match decoder {
+            Ok(_) => self.hex(depth, "texels", &texels),
+            Err(error) => self.field(depth, &format!("invalid compressed data: {error}")),
+        }
+        Ok(())
+    }
+
+    fn dump_optional_chunk(&mut self) -> io::Result<()> {
+        let offset = self.position;
+        let magic = self.take(4)?;
+        let size = self.read_u32()? as usize;
+        let payload_offset = self.position;
+        let payload = self.take(size)?;
+        let name = String::from_utf8_lossy(&magic[..3]).into_owned();
+        let known = [
+            NSD_GROUP_HEADER, NSD_METADATA_HEADER, NSD_SECTION_HEADER,
+            NSD_WORLD_HEADER
+        ];
+        let summary = match known.iter().any(|header| header.as_slice() == magic) {
+            true => format!("{} bytes, payload of {size}", size + 8),
+            false => format!("{} bytes, payload of {size}, unknown chunk skipped by readers", size + 8),
+        };
+        self.chunk(offset, 0, &name, &summary);
+
+        // The payload is decoded in place, then the position continues after the chunk regardless.
+        let end = self.position;
+        self.position = payload_offset;
+        let decoded = self.dump_payload(magic, end);
+        self.position = end;
+        if let Err(error) = decoded {
+            self.field(0, &format!("invalid payload: {error}"));
+            self.hex(0, "payload", payload);
+        }
+        Ok(())
+    }
+
+    fn dump_payload(&mut self, magic: &[u8], end: usize) -> io::Result<()> {
+        // Reads past the payload into the next chunk are errors of the payload.
+        let bytes = self.bytes;
+        self.bytes = &bytes[..end];
+        let result = self.dump_payload_fields(magic);
+        self.bytes = bytes;
+        result
+    }
+
+    fn dump_payload_fields(&mut self, magic: &[u8]) -> io::Result<()> {
+        if magic == NSD_GROUP_HEADER.as_slice() {
+            let count = self.read_u32()?;
+            for _ in 0..count {
+                let name = self.read_string()?;
+                let members = self.read_u32()?;
+                let attributes = (0..members).map(|_| self.read_u32().map(|index| index.to_string()))
+                    .collect::<io::Result<Vec<_>>>()?;
+                self.field(0, &format!("group {name}: attributes [{}]", attributes.join(", ")));
+            }
+        } else if magic == NSD_METADATA_HEADER.as_slice() {
+            let count = self.read_u32()?;
+            for _ in 0..count {
+                let attribute = self.read_u32()?;
+                let description = self.read_string()?;
+                let tag_count = self.read_u32()?;
+                let tags = (0..tag_count).map(|_| Ok(format!("{}={}", self.read_string()?, self.read_string()?)))
+                    .collect::<io::Result<Vec<_>>>()?;
+                self.field(0, &format!("attribute {attribute}: description {description:?}, tags [{}]", tags.join(", ")));
+            }
+            if self.position < self.bytes.len() {
+                let mut nodata = vec![];
+                for _ in 0..count {
+                    nodata.push(match self.read_u8()? {
+                        0 => "none".to_string(),
+                        _ => f64::from_le_bytes(self.take(8)?.try_into().unwrap()).to_string(),
+                    });
+                }
+                self.field(0, &format!("nodata: [{}]", nodata.join(", ")));
+            }
+            if self.position < self.bytes.len() {
+                let mut units = vec![];
+                for _ in 0..count {
+                    let unit = self.read_string()?;
+                    let code = self.read_u8()?;
+                    let kind = ValueKind::from_code(code).map_or("unknown", |kind| kind.name());
+                    units.push(format!("{unit:?} {kind}"));
+                }
+                self.field(0, &format!("units: [{}]", units.join(", ")));
+            }
+        } else if magic == NSD_WORLD_HEADER.as_slice() {
+            let mut values = [0.0; 4];
+            for value in &mut values {
+                *value = f64::from_le_bytes(self.take(8)?.try_into().unwrap());
+            }
+            self.field(0, &format!("world origin ({}, {})", values[0], values[1]));
+            self.field(0, &format!("{} x {} units per texel", values[2], values[3]));
+            if self.position < self.bytes.len() {
+                let epsg = self.read_u32()?;
+                self.field(0, &format!("EPSG:{epsg}"));
+            }
+        } else if magic == NSD_SECTION_HEADER.as_slice() {
+            let name = self.read_string()?;
+            self.field(0, &format!("section {name}"));
+            self.dump_section(1)?;
+        } else {
+            let bytes = self.bytes;
+            self.hex(0, "payload", &bytes[self.position..]);
+        }
+        Ok(())
+    }
+}
+
+/// Writes every chunk with its offset, size and decoded fields, and the first `preview` bytes of the texel data
+/// (compressed and decompressed) and of unknown chunks. Fails at the first malformed chunk, with everything
+/// before it already written to the output.
+pub fn dump(bytes: &[u8], preview: usize, output: &mut String) -> io::Result<()> {
+    let mut dumper = Dumper { bytes, position: 0, preview, output };
+    dumper.dump_header()?;
+    dumper.dump_section(0)?;
+    while dumper.position < bytes.len() {
+        dumper.dump_optional_chunk()?;
+    }
+    let _ = writeln!(dumper.output, "{:#010x}  end of file", bytes.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::reader::tests::{all_extensions, sample_file};
+    use crate::writer::write_parsed_file;
+
+    #[test]
+    fn dumps_every_chunk_until_the_first_malformed_one() {
+        let mut bytes = Cursor::new(vec![]);
+        write_parsed_file(&mut bytes, &sample_file(all_extensions())).unwrap();
+        let bytes = bytes.into_inner();
+
+        let mut output = String::new();
+        dump(&bytes, 4, &mut output).unwrap();
+        for expected in ["HEADER  16 bytes", "DIM  20 bytes, 3x2 texels", "height: uint", "section coarse", "end of file"] {
+            assert!(output.contains(expected), "{expected} is not in:\n{output}");
+        }
+
+        let mut output = String::new();
+        assert!(dump(&bytes[..bytes.len() - 3], 4, &mut output).is_err());
+        assert!(output.contains("DIM  20 bytes") && !output.contains("end of file"), "{output}");
+    }
+}
@@ -0,0 +1,220 @@
+//! Quick fixes to the header, DIM and ATR chunks of existing files. The DATA chunk and everything after it are
+//! copied as they are, so even huge files are edited without decompressing or repacking anything.
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::format::*;
+use crate::layer::LayerDimensions;
+use crate::reader::NsdAttribute;
+use crate::writer::{make_attribute_table_bytes, make_dimensions_bytes};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EditOperation {
+    Vendor(String),
+    Identifier(String),
+    /// New dimensions with the same number of texels, e.g. to fix swapped sides.
+    Dimensions(LayerDimensions),
+    RenameAttribute { from: String, to: String },
+    /// Reinterprets the stored bytes of an attribute as another type.
+    AttributeType { name: String, attribute_type: AttributeType },
+    AttributeSize { name: String, size: u8 },
+}
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_array<const N: usize>(input: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+/// Header, DIM and ATR chunks of a file, read up to the magic of its DATA chunk.
+struct Prefix {
+    header: NsdHeader,
+    dimensions: LayerDimensions,
+    texel_order: TexelOrder,
+    row_stride: Option<u32>,
+    attributes: Vec<NsdAttribute>,
+}
+
+fn read_prefix(input: &mut impl Read) -> io::Result<Prefix> {
+    let header = NsdHeader::from_bytes(&read_array(input)?)?;
+    if read_array::<4>(input)? != NSD_DIM_HEADER {
+        return Err(invalid_data("Expected a DIM chunk after the header".into()));
+    }
+    let dimensions = LayerDimensions {
+        width: u32::from_le_bytes(read_array(input)?),
+        height: u32::from_le_bytes(read_array(input)?),
+    };
+    let code = u32::from_le_bytes(read_array(input)?);
+    let texel_order = TexelOrder::from_code(code)
+        .ok_or_else(|| invalid_data(format!("Unknown texel order {code}")))?;
+    let row_stride = match u32::from_le_bytes(read_array(input)?) {
+        1 => None,
+        row_stride => Some(row_stride),
+    };
+
+    let mut attributes = vec![];
+    loop {
+        let magic = read_array::<4>(input)?;
+        if magic == NSD_DATA_HEADER {
+            return Ok(Prefix { header, dimensions, texel_order, row_stride, attributes });
+        }
+        if magic != NSD_ATTR_HEADER {
+            return Err(invalid_data("Expected an ATR or DATA chunk".into()));
+        }
+        let mut name = vec![];
+        loop {
+            match read_array::<1>(input)?[0] {
+                0 => break,
+                byte => name.push(byte),
+            }
+        }
+        let [size, type_code] = read_array(input)?;
+        let name = String::from_utf8_lossy(&name).into_owned();
+        let attribute_type = AttributeType::from_code(type_code)
+            .ok_or_else(|| invalid_data(format!("Attribute {name} has an unknown type {type_code}")))?;
+        let format = AttributeFormat::new(attribute_type, size)
+            .map_err(|error| invalid_data(format!("Attribute {name}: {error}")))?;
+        attributes.push(NsdAttribute { name, format });
+    }
+}
+
+fn find_attribute<'a>(attributes: &'a mut [NsdAttribute], name: &str) -> io::Result<&'a mut NsdAttribute> {
+    attributes.iter_mut().find(|attribute| attribute.name == name)
+        .ok_or_else(|| invalid_input(format!("The file has no attribute {name}")))
+}
+
+fn apply(prefix: &mut Prefix, operation: &EditOperation) -> io::Result<()> {
+    match operation {
+        EditOperation::Vendor(vendor) => prefix.header = NsdHeader::new(vendor, &prefix.header.identifier())?,
+        EditOperation::Identifier(identifier) => prefix.header = NsdHeader::new(&prefix.header.vendor(), identifier)?,
+        EditOperation::Dimensions(dimensions) => {
+            if dimensions.get_texel_count() != prefix.dimensions.get_texel_count() {
+                return Err(invalid_input(format!(
+                    "{}x{} has a different number of texels than {}x{}, the texel data would have to be repacked",
+                    dimensions.width, dimensions.height, prefix.dimensions.width, prefix.dimensions.height
+                )));
+            }
+            prefix.dimensions = dimensions.clone();
+        }
+        EditOperation::RenameAttribute { from, to } => {
+            if to.is_empty() || to.contains('\0') {
+                return Err(invalid_input(format!("Invalid attribute name {to:?}")));
+            }
+            if prefix.attributes.iter().any(|attribute| attribute.name == *to) {
+                return Err(invalid_input(format!("The file already has an attribute {to}")));
+            }
+            find_attribute(&mut prefix.attributes, from)?.name = to.clone();
+        }
+        EditOperation::AttributeType { name, attribute_type } => {
+            find_attribute(&mut prefix.attributes, name)?.format.attribute_type = *attribute_type;
+        }
+        EditOperation::AttributeSize { name, size } => {
+            find_attribute(&mut prefix.attributes, name)?.format.size = *size;
+        }
+    }
+    Ok(())
+}
+
+/// Applies the operations in order and writes the edited file to the output, which may be the input itself.
+/// The combined size of the attributes must stay the same, as the texel data isn't touched.
+pub fn edit_file(input: &Path, output: &Path, operations: &[EditOperation]) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(input)?);
+    let mut prefix = read_prefix(&mut reader)?;
+    let combined_size = read_array::<4>(&mut reader)?;
+    let stride = |attributes: &[NsdAttribute]| attributes.iter().map(|attribute| attribute.format.size as usize).sum::<usize>();
+    let original_stride = stride(&prefix.attributes);
+    let padded = u32::from_le_bytes(combined_size) as usize != original_stride * prefix.dimensions.get_texel_count();
+    if operations.iter().any(|operation| matches!(operation, EditOperation::Dimensions(_))) {
+        if padded {
+            return Err(invalid_input(
+                "The rows of the texel data are padded, so the dimensions can't change without repacking it".into()
+            ));
+        }
+        if prefix.row_stride.is_some() {
+            return Err(invalid_input("The file stores a row stride, so the dimensions can't change without rewriting it".into()));
+        }
+    }
+
+    for operation in operations {
+        apply(&mut prefix, operation)?;
+    }
+    for attribute in &prefix.attributes {
+        AttributeFormat::new(attribute.format.attribute_type, attribute.format.size)
+            .map_err(|error| invalid_input(format!("Attribute {}: {error}", attribute.name)))?;
+    }
+    if stride(&prefix.attributes) != original_stride {
+        return Err(invalid_input(format!(
+            "The attributes would take {} bytes per texel instead of {original_stride}, \
+            the texel data would have to be repacked",
+            stride(&prefix.attributes)
+        )));
+    }
+
+    // Written next to the output first, so the input can be the output and a failed edit leaves it intact.
+    let mut partial_path = output.as_os_str().to_owned();
+    partial_path.push(".partial");
+    let written = File::create(&partial_path).and_then(|file| {
+        let mut writer = BufWriter::new(file);
+        writer.write_all(&prefix.header.to_bytes())?;
+        writer.write_all(&make_dimensions_bytes(&prefix.dimensions, prefix.texel_order, prefix.row_stride))?;
+        writer.write_all(&make_attribute_table_bytes(&prefix.attributes))?;
+        writer.write_all(&NSD_DATA_HEADER)?;
+        writer.write_all(&combined_size)?;
+        io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&partial_path, output)
+    });
+    if written.is_err() {
+        let _ = fs::remove_file(&partial_path);
+    }
+    written
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::tests::sample_file;
+    use crate::reader::NsdReader;
+    use crate::testing::TempDirectory;
+    use crate::writer::write_parsed_file;
+
+    #[test]
+    fn edits_the_file_in_place_without_touching_the_texels() {
+        let directory = TempDirectory::new();
+        let path = directory.join("terrain.nsd");
+        let original = sample_file(NsdExtensions::default());
+        write_parsed_file(&mut File::create(&path).unwrap(), &original).unwrap();
+
+        edit_file(&path, &path, &[
+            EditOperation::Identifier("Terrain".into()),
+            EditOperation::Dimensions(LayerDimensions { width: 6, height: 1 }),
+            EditOperation::RenameAttribute { from: "grass".into(), to: "grass_density".into() },
+            EditOperation::AttributeType { name: "height".into(), attribute_type: AttributeType::Int },
+        ]).unwrap();
+        let edited = NsdReader::open(&path).unwrap();
+        assert_eq!(edited.header().identifier(), "Terrain");
+        assert_eq!(edited.dimensions(), &LayerDimensions { width: 6, height: 1 });
+        let attributes: Vec<(&str, AttributeType)> = edited.attributes().iter()
+            .map(|attribute| (attribute.name.as_str(), attribute.format.attribute_type))
+            .collect();
+        assert_eq!(attributes, [("height", AttributeType::Int), ("grass_density", AttributeType::Byte)]);
+        assert_eq!(edited.data(), original.data());
+
+        // A failed edit leaves the file as it was.
+        let error = edit_file(&path, &path, &[EditOperation::AttributeSize { name: "height".into(), size: 1 }]).unwrap_err();
+        assert!(error.to_string().contains("would take 2 bytes per texel instead of 3"), "{error}");
+        assert_eq!(NsdReader::open(&path).unwrap().attributes()[0].format.size, 2);
+        assert!(!directory.join("terrain.nsd.partial").exists());
+    }
+}
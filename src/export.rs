@@ -0,0 +1,66 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::{DynamicImage, Rgb32FImage};
+
+use crate::reader::NsdReader;
+
+/// Writes every attribute as a separate EXR named `<stem>.<attribute>.exr`, which Houdini's
+/// Heightfield File node can load as a layer of the same name.
+///
+/// Values are stored as-is (byte attributes keep their 0-255 range); vector attributes
+/// put their components into the color channels.
+pub fn export_houdini(reader: &NsdReader, stem: &str, directory: &Path) -> io::Result<Vec<PathBuf>> {
+    let dimensions = reader.dimensions();
+    let mut paths = vec![];
+
+    for (index, attribute) in reader.attributes().iter().enumerate() {
+        let component_count = attribute.format.component_count();
+
+        let image = Rgb32FImage::from_fn(dimensions.width, dimensions.height, |x, y| {
+            let texel = y as usize * dimensions.width as usize + x as usize;
+            let bytes = reader.attribute_bytes(index, texel);
+            let mut pixel = [0f32; 3];
+            for (channel, value) in pixel.iter_mut().enumerate() {
+                // Scalars are replicated so the layer reads the same from any channel.
+                let component = if component_count == 1 { 0 } else { channel };
+                if component < component_count {
+                    *value = attribute.format.decode_component(bytes, component) as f32;
+                }
+            }
+            image::Rgb(pixel)
+        });
+
+        let path = directory.join(format!("{stem}.{}.exr", attribute.name));
+        DynamicImage::ImageRgb32F(image)
+            .save(&path)
+            .map_err(|error| io::Error::other(format!("Could not write {}: {error}", path.display())))?;
+        paths.push(path);
+    }
+
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::NsdExtensions;
+    use crate::reader::tests::sample_file;
+    use crate::testing::TempDirectory;
+
+    #[test]
+    fn exports_every_attribute_with_its_values() {
+        let directory = TempDirectory::new();
+        let file = sample_file(NsdExtensions::default());
+        let paths = export_houdini(&file, "Terrain", directory.path()).unwrap();
+        assert_eq!(paths, [directory.join("Terrain.height.exr"), directory.join("Terrain.grass.exr")]);
+
+        for (index, path) in paths.iter().enumerate() {
+            let image = image::open(path).unwrap().to_rgb32f();
+            for (x, y, pixel) in image.enumerate_pixels() {
+                let value = file.sample(index, x, y).unwrap()[0] as f32;
+                assert_eq!(pixel.0, [value; 3], "{} at ({x}, {y})", path.display());
+            }
+        }
+    }
+}
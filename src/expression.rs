@@ -0,0 +1,436 @@
+//! Small expression language for per-texel math and conditions in the manifest.
+//!
+//! Expressions are made of numbers, variables, the operators `+ - * / % ^`, comparisons (`< <= > >= == !=`),
+//! `&& || !`, the conditional `condition ? a : b` and the functions listed in `Function`. Conditions are 1 when
+//! true and 0 when false, and any value other than 0 counts as true.
+
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum UnaryOperator {
+    Negate,
+    Not,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum BinaryOperator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum Function {
+    Min,
+    Max,
+    Clamp,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Sqrt,
+    Pow,
+    Exp,
+    Log,
+    Sin,
+    Cos,
+    Lerp,
+    Step,
+    Smoothstep,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<(Function, usize)> {
+        Some(match name {
+            "min" => (Function::Min, 2),
+            "max" => (Function::Max, 2),
+            "clamp" => (Function::Clamp, 3),
+            "abs" => (Function::Abs, 1),
+            "floor" => (Function::Floor, 1),
+            "ceil" => (Function::Ceil, 1),
+            "round" => (Function::Round, 1),
+            "sqrt" => (Function::Sqrt, 1),
+            "pow" => (Function::Pow, 2),
+            "exp" => (Function::Exp, 1),
+            "log" => (Function::Log, 1),
+            "sin" => (Function::Sin, 1),
+            "cos" => (Function::Cos, 1),
+            "lerp" => (Function::Lerp, 3),
+            "step" => (Function::Step, 2),
+            "smoothstep" => (Function::Smoothstep, 3),
+            _ => return None,
+        })
+    }
+
+    fn call(&self, arguments: &[f64]) -> f64 {
+        match self {
+            Function::Min => arguments[0].min(arguments[1]),
+            Function::Max => arguments[0].max(arguments[1]),
+            Function::Clamp => arguments[0].max(arguments[1]).min(arguments[2]),
+            Function::Abs => arguments[0].abs(),
+            Function::Floor => arguments[0].floor(),
+            Function::Ceil => arguments[0].ceil(),
+            Function::Round => arguments[0].round(),
+            Function::Sqrt => arguments[0].sqrt(),
+            Function::Pow => arguments[0].powf(arguments[1]),
+            Function::Exp => arguments[0].exp(),
+            Function::Log => arguments[0].ln(),
+            Function::Sin => arguments[0].sin(),
+            Function::Cos => arguments[0].cos(),
+            Function::Lerp => arguments[0] + (arguments[1] - arguments[0]) * arguments[2],
+            Function::Step => if arguments[1] >= arguments[0] { 1.0 } else { 0.0 },
+            Function::Smoothstep => {
+                let t = ((arguments[2] - arguments[0]) / (arguments[1] - arguments[0])).clamp(0.0, 1.0);
+                t * t * (3.0 - 2.0 * t)
+            }
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Node {
+    Constant(f64),
+    /// Index into the variables of the expression.
+    Variable(usize),
+    Unary(UnaryOperator, Box<Node>),
+    Binary(BinaryOperator, Box<Node>, Box<Node>),
+    Conditional(Box<Node>, Box<Node>, Box<Node>),
+    Call(Function, Vec<Node>),
+}
+
+/// Parsed expression, evaluated with the values of its variables in the order of `variables()`.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Expression {
+    source: String,
+    root: Node,
+    variables: Vec<String>,
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Number(f64),
+    Identifier(String),
+    Symbol(&'static str),
+}
+
+/// Longer symbols come first, so `<=` isn't read as `<` followed by `=`.
+const SYMBOLS: [&str; 20] = [
+    "<=", ">=", "==", "!=", "&&", "||", "+", "-", "*", "/", "%", "^", "<", ">", "!", "?", ":", "(", ")", ",",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = source.trim_start();
+    while let Some(char) = rest.chars().next() {
+        if char.is_ascii_digit() || char == '.' {
+            let length = rest.find(|char: char| !(char.is_ascii_alphanumeric() || char == '.')).unwrap_or(rest.len());
+            let number = rest[..length].parse::<f64>().map_err(|_| format!("Invalid number `{}`", &rest[..length]))?;
+            tokens.push(Token::Number(number));
+            rest = &rest[length..];
+        } else if char.is_alphabetic() || char == '_' {
+            let length = rest.find(|char: char| !(char.is_alphanumeric() || char == '_')).unwrap_or(rest.len());
+            tokens.push(Token::Identifier(rest[..length].to_string()));
+            rest = &rest[length..];
+        } else {
+            let symbol = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol))
+                .ok_or_else(|| format!("Unexpected `{char}`"))?;
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+/// Deepest nesting of parentheses, calls, conditionals and unary operators accepted.
+const MAX_NESTING: usize = 64;
+/// Most tokens of an expression, which bounds the depth of chained binary operators.
+const MAX_TOKENS: usize = 4096;
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+    variables: Vec<String>,
+    /// Nested subexpressions being parsed.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek_symbol(&self) -> Option<&'static str> {
+        match self.tokens.get(self.position) {
+            Some(Token::Symbol(symbol)) => Some(symbol),
+            _ => None,
+        }
+    }
+
+    fn eat(&mut self, symbol: &str) -> bool {
+        let matches = self.peek_symbol() == Some(symbol);
+        if matches {
+            self.position += 1;
+        }
+        matches
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        if self.eat(symbol) { Ok(()) } else { Err(format!("Expected `{symbol}`")) }
+    }
+
+    /// Parses a subexpression, failing once they're nested deeper than `MAX_NESTING` instead of overflowing the stack.
+    fn nested(&mut self, parse: fn(&mut Parser) -> Result<Node, String>) -> Result<Node, String> {
+        if self.depth == MAX_NESTING {
+            return Err("Expression is nested too deeply".into());
+        }
+        self.depth += 1;
+        let node = parse(self);
+        self.depth -= 1;
+        node
+    }
+
+    fn conditional(&mut self) -> Result<Node, String> {
+        let condition = self.binary(0)?;
+        if !self.eat("?") {
+            return Ok(condition);
+        }
+        let then = self.nested(Parser::conditional)?;
+        self.expect(":")?;
+        let otherwise = self.nested(Parser::conditional)?;
+        Ok(Node::Conditional(Box::new(condition), Box::new(then), Box::new(otherwise)))
+    }
+
+    /// Binary operators from the lowest precedence level, all of which are left-associative except `^`.
+    fn binary(&mut self, level: usize) -> Result<Node, String> {
+        const LEVELS: [&[(&str, BinaryOperator)]; 5] = [
+            &[("||", BinaryOperator::Or)],
+            &[("&&", BinaryOperator::And)],
+            &[
+                ("<=", BinaryOperator::LessOrEqual), (">=", BinaryOperator::GreaterOrEqual), ("<", BinaryOperator::Less),
+                (">", BinaryOperator::Greater), ("==", BinaryOperator::Equal), ("!=", BinaryOperator::NotEqual),
+            ],
+            &[("+", BinaryOperator::Add), ("-", BinaryOperator::Subtract)],
+            &[("*", BinaryOperator::Multiply), ("/", BinaryOperator::Divide), ("%", BinaryOperator::Remainder)],
+        ];
+        let Some(operators) = LEVELS.get(level) else {
+            return self.unary();
+        };
+        let mut node = self.binary(level + 1)?;
+        while let Some(&(_, operator)) = operators.iter().find(|(symbol, _)| self.peek_symbol() == Some(symbol)) {
+            self.position += 1;
+            node = Node::Binary(operator, Box::new(node), Box::new(self.binary(level + 1)?));
+        }
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        if self.eat("-") {
+            return Ok(Node::Unary(UnaryOperator::Negate, Box::new(self.nested(Parser::unary)?)));
+        }
+        if self.eat("!") {
+            return Ok(Node::Unary(UnaryOperator::Not, Box::new(self.nested(Parser::unary)?)));
+        }
+        let base = self.primary()?;
+        if self.eat("^") {
+            return Ok(Node::Binary(BinaryOperator::Power, Box::new(base), Box::new(self.nested(Parser::unary)?)));
+        }
+        Ok(base)
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self.tokens.get(self.position).cloned().ok_or("Unexpected end of expression")?;
+        self.position += 1;
+        match token {
+            Token::Number(number) => Ok(Node::Constant(number)),
+            Token::Identifier(name) if self.eat("(") => {
+                let (function, arity) = Function::from_name(&name).ok_or_else(|| format!("Unknown function `{name}`"))?;
+                let mut arguments = vec![];
+                if !self.eat(")") {
+                    loop {
+                        arguments.push(self.nested(Parser::conditional)?);
+                        if self.eat(")") {
+                            break;
+                        }
+                        self.expect(",")?;
+                    }
+                }
+                if arguments.len() != arity {
+                    return Err(format!("`{name}` takes {arity} arguments, got {}", arguments.len()));
+                }
+                Ok(Node::Call(function, arguments))
+            }
+            Token::Identifier(name) => {
+                let index = match self.variables.iter().position(|variable| *variable == name) {
+                    Some(index) => index,
+                    None => {
+                        self.variables.push(name);
+                        self.variables.len() - 1
+                    }
+                };
+                Ok(Node::Variable(index))
+            }
+            Token::Symbol("(") => {
+                let node = self.nested(Parser::conditional)?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Token::Symbol(symbol) => Err(format!("Unexpected `{symbol}`")),
+        }
+    }
+}
+
+fn truth(value: bool) -> f64 {
+    if value { 1.0 } else { 0.0 }
+}
+
+impl Node {
+    fn evaluate(&self, variables: &[f64]) -> f64 {
+        match self {
+            Node::Constant(value) => *value,
+            Node::Variable(index) => variables[*index],
+            Node::Unary(UnaryOperator::Negate, operand) => -operand.evaluate(variables),
+            Node::Unary(UnaryOperator::Not, operand) => truth(operand.evaluate(variables) == 0.0),
+            Node::Binary(BinaryOperator::And, lhs, rhs) => {
+                truth(lhs.evaluate(variables) != 0.0 && rhs.evaluate(variables) != 0.0)
+            }
+            Node::Binary(BinaryOperator::Or, lhs, rhs) => {
+                truth(lhs.evaluate(variables) != 0.0 || rhs.evaluate(variables) != 0.0)
+            }
+            Node::Binary(operator, lhs, rhs) => {
+                let (lhs, rhs) = (lhs.evaluate(variables), rhs.evaluate(variables));
+                match operator {
+                    BinaryOperator::Add => lhs + rhs,
+                    BinaryOperator::Subtract => lhs - rhs,
+                    BinaryOperator::Multiply => lhs * rhs,
+                    BinaryOperator::Divide => lhs / rhs,
+                    BinaryOperator::Remainder => lhs % rhs,
+                    BinaryOperator::Power => lhs.powf(rhs),
+                    BinaryOperator::Less => truth(lhs < rhs),
+                    BinaryOperator::LessOrEqual => truth(lhs <= rhs),
+                    BinaryOperator::Greater => truth(lhs > rhs),
+                    BinaryOperator::GreaterOrEqual => truth(lhs >= rhs),
+                    BinaryOperator::Equal => truth(lhs == rhs),
+                    BinaryOperator::NotEqual => truth(lhs != rhs),
+                    BinaryOperator::And | BinaryOperator::Or => unreachable!("Handled above to short-circuit."),
+                }
+            }
+            Node::Conditional(condition, then, otherwise) => {
+                if condition.evaluate(variables) != 0.0 { then.evaluate(variables) } else { otherwise.evaluate(variables) }
+            }
+            Node::Call(function, arguments) => {
+                let arguments: Vec<f64> = arguments.iter().map(|argument| argument.evaluate(variables)).collect();
+                function.call(&arguments)
+            }
+        }
+    }
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Expression, String> {
+        let tokens = tokenize(source)?;
+        if tokens.len() > MAX_TOKENS {
+            return Err(format!("Expression is longer than {MAX_TOKENS} tokens"));
+        }
+        let mut parser = Parser { tokens, position: 0, variables: vec![], depth: 0 };
+        let root = parser.conditional()
+            .map_err(|error| format!("{error} in expression `{source}`"))?;
+        if parser.position != parser.tokens.len() {
+            return Err(format!("Unexpected trailing input in expression `{source}`"));
+        }
+        Ok(Expression { source: source.to_string(), root, variables: parser.variables })
+    }
+
+    /// Names of the variables used by the expression, in the order their values are passed to `evaluate`.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    pub fn evaluate(&self, variables: &[f64]) -> f64 {
+        self.root.evaluate(variables)
+    }
+
+    /// Evaluates the expression with variables looked up by name, failing if one is missing.
+    pub fn evaluate_with(&self, lookup: impl Fn(&str) -> Option<f64>) -> Result<f64, String> {
+        let values = self.variables.iter()
+            .map(|name| lookup(name).ok_or_else(|| format!("Unknown variable `{name}` in expression `{self}`")))
+            .collect::<Result<Vec<f64>, String>>()?;
+        Ok(self.evaluate(&values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn evaluate(source: &str) -> f64 {
+        Expression::parse(source).unwrap().evaluate(&[])
+    }
+
+    #[test]
+    fn follows_the_operator_precedence() {
+        let expressions = [
+            ("1 + 2 * 3", 7.0),
+            ("(1 + 2) * 3", 9.0),
+            ("10 - 4 - 3", 3.0),
+            ("2 * 3 % 4", 2.0),
+            ("2 ^ 3 ^ 2", 512.0),
+            ("-2 ^ 2", -4.0),
+            ("2 ^ -1", 0.5),
+            ("!0 + 1", 2.0),
+            ("1 + 2 < 4", 1.0),
+            ("1 < 2 == 1", 1.0),
+            ("1 < 2 && 2 < 1 || 1", 1.0),
+            ("0 || 1 && 0", 0.0),
+            ("0 || 1 ? 5 : 6", 5.0),
+            ("0 ? 2 : 0 ? 3 : 4", 4.0),
+            ("1 ? 2 : 0 ? 3 : 4", 2.0),
+            ("min(2, 3) * max(1, 4) + clamp(5, 0, 1)", 9.0),
+            ("lerp(10, 20, 0.25) - step(0.5, 0.4)", 12.5),
+            ("1e3 / .5", 2000.0),
+        ];
+        for (source, expected) in expressions {
+            assert_eq!(evaluate(source), expected, "{source}");
+        }
+    }
+
+    #[test]
+    fn evaluates_variables_in_the_order_they_appear() {
+        let expression = Expression::parse("height * scale + height").unwrap();
+        assert_eq!(expression.variables(), ["height", "scale"]);
+        assert_eq!(expression.evaluate(&[2.0, 3.0]), 8.0);
+        assert_eq!(expression.evaluate_with(|name| (name == "height").then_some(2.0).or(Some(0.5))), Ok(3.0));
+        assert!(expression.evaluate_with(|name| (name == "height").then_some(2.0)).unwrap_err().contains("`scale`"));
+        assert_eq!(expression.to_string(), "height * scale + height");
+    }
+
+    #[test]
+    fn rejects_malformed_expressions() {
+        let malformed = ["", "1 +", "(1", "1)", "1 2", "min(1)", "min(1, 2", "foo(1)", "1 ? 2", "1 $ 2", "1..2", ",", "* 2"];
+        for source in malformed {
+            assert!(Expression::parse(source).is_err(), "{source:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn limits_the_nesting_and_length() {
+        let parenthesized = |depth: usize| "(".repeat(depth) + "1" + &")".repeat(depth);
+        assert_eq!(evaluate(&parenthesized(MAX_NESTING)), 1.0);
+        assert!(Expression::parse(&parenthesized(MAX_NESTING + 1)).unwrap_err().contains("nested too deeply"));
+        assert!(Expression::parse(&("-".repeat(MAX_TOKENS - 1) + "1")).unwrap_err().contains("nested too deeply"));
+        assert!(Expression::parse(&("1".to_string() + &" + 1".repeat(MAX_TOKENS / 2))).unwrap_err().contains("longer than"));
+    }
+}
@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+use std::io;
+
+use half::f16;
+
+use crate::resample::ValueKind;
+
+pub const NSD_HEADER: [u8; 16] = [
+    0x4E, 0x53, 0x47, 0xFF, 0x53, 0x70, 0x61, 0x74, 0x69, 0x61, 0x6C, 0x00, 0x00, 0x00, 0x00, 0x00
+];
+pub const NSD_DIM_HEADER: [u8; 4] = [
+    0x44, 0x49, 0x4D, 0xFA
+];
+pub const NSD_ATTR_HEADER: [u8; 4] = [
+    0x41, 0x54, 0x52, 0xFA
+];
+pub const NSD_DATA_HEADER: [u8; 4] = [
+    0x44, 0x41, 0x54, 0xFA
+];
+pub const NSD_GROUP_HEADER: [u8; 4] = [
+    0x47, 0x52, 0x50, 0xFA
+];
+pub const NSD_METADATA_HEADER: [u8; 4] = [
+    0x4D, 0x45, 0x54, 0xFA
+];
+pub const NSD_SECTION_HEADER: [u8; 4] = [
+    0x53, 0x45, 0x43, 0xFA
+];
+pub const NSD_WORLD_HEADER: [u8; 4] = [
+    0x57, 0x4C, 0x44, 0xFA
+];
+
+const NSD_HEADER_SEPARATOR: u8 = 0xFF;
+const NSD_VENDOR_LENGTH: usize = 3;
+const NSD_IDENTIFIER_LENGTH: usize = 12;
+
+/// The 16-byte file header: a 3-byte vendor, a 0xFF separator and a zero-padded sub-identifier.
+#[derive(Clone, PartialEq, Debug)]
+pub struct NsdHeader {
+    vendor: [u8; NSD_VENDOR_LENGTH],
+    identifier: [u8; NSD_IDENTIFIER_LENGTH],
+}
+
+impl NsdHeader {
+    pub fn new(vendor: &str, identifier: &str) -> io::Result<NsdHeader> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+
+        if vendor.len() != NSD_VENDOR_LENGTH || !vendor.bytes().all(|byte| byte.is_ascii_graphic()) {
+            return Err(invalid(format!(
+                "Header vendor must be exactly {NSD_VENDOR_LENGTH} printable ASCII characters, got \"{vendor}\""
+            )));
+        }
+        if identifier.is_empty() || identifier.len() > NSD_IDENTIFIER_LENGTH
+            || !identifier.bytes().all(|byte| byte.is_ascii_graphic()) {
+            return Err(invalid(format!(
+                "Header identifier must be 1 to {NSD_IDENTIFIER_LENGTH} printable ASCII characters, got \"{identifier}\""
+            )));
+        }
+
+        let mut header = NsdHeader {
+            vendor: [0; NSD_VENDOR_LENGTH],
+            identifier: [0; NSD_IDENTIFIER_LENGTH],
+        };
+        header.vendor.copy_from_slice(vendor.as_bytes());
+        header.identifier[..identifier.len()].copy_from_slice(identifier.as_bytes());
+        Ok(header)
+    }
+
+    pub fn from_bytes(bytes: &[u8; 16]) -> io::Result<NsdHeader> {
+        if bytes[NSD_VENDOR_LENGTH] != NSD_HEADER_SEPARATOR {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not a spatial data file (invalid header)"));
+        }
+        let mut header = NsdHeader {
+            vendor: [0; NSD_VENDOR_LENGTH],
+            identifier: [0; NSD_IDENTIFIER_LENGTH],
+        };
+        header.vendor.copy_from_slice(&bytes[..NSD_VENDOR_LENGTH]);
+        header.identifier.copy_from_slice(&bytes[NSD_VENDOR_LENGTH + 1..]);
+        Ok(header)
+    }
+
+    pub fn to_bytes(&self) -> [u8; 16] {
+        let mut bytes = [0u8; 16];
+        bytes[..NSD_VENDOR_LENGTH].copy_from_slice(&self.vendor);
+        bytes[NSD_VENDOR_LENGTH] = NSD_HEADER_SEPARATOR;
+        bytes[NSD_VENDOR_LENGTH + 1..].copy_from_slice(&self.identifier);
+        bytes
+    }
+
+    pub fn vendor(&self) -> String {
+        String::from_utf8_lossy(&self.vendor).into_owned()
+    }
+
+    pub fn identifier(&self) -> String {
+        let length = self.identifier.iter().position(|&byte| byte == 0).unwrap_or(NSD_IDENTIFIER_LENGTH);
+        String::from_utf8_lossy(&self.identifier[..length]).into_owned()
+    }
+}
+
+impl Default for NsdHeader {
+    fn default() -> Self {
+        NsdHeader::from_bytes(&NSD_HEADER).unwrap()
+    }
+}
+
+/// Mirrors ESpatialDataTexelAttributeType.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AttributeType {
+    Float = 0,
+    Int = 1,
+    UInt = 2,
+    Byte = 3,
+}
+
+impl AttributeType {
+    pub fn from_code(code: u8) -> Option<AttributeType> {
+        match code {
+            0 => Some(AttributeType::Float),
+            1 => Some(AttributeType::Int),
+            2 => Some(AttributeType::UInt),
+            3 => Some(AttributeType::Byte),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<AttributeType> {
+        match name {
+            "float" => Some(AttributeType::Float),
+            "int" => Some(AttributeType::Int),
+            "uint" => Some(AttributeType::UInt),
+            "byte" => Some(AttributeType::Byte),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            AttributeType::Float => "float",
+            AttributeType::Int => "int",
+            AttributeType::UInt => "uint",
+            AttributeType::Byte => "byte",
+        }
+    }
+
+    /// Size used when a layer only specifies the type.
+    pub fn default_size(&self) -> u8 {
+        match self {
+            AttributeType::Float => 4,
+            AttributeType::Int => 2,
+            AttributeType::UInt => 2,
+            AttributeType::Byte => 1,
+        }
+    }
+}
+
+/// Order of the texels in the DATA chunk, stored as the first reserved word of the DIM chunk. Files written before
+/// the order was recorded have a 1 there, so they're row-major.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum TexelOrder {
+    #[default]
+    RowMajor = 1,
+    ColumnMajor = 2,
+}
+
+impl TexelOrder {
+    pub fn from_code(code: u32) -> Option<TexelOrder> {
+        match code {
+            1 => Some(TexelOrder::RowMajor),
+            2 => Some(TexelOrder::ColumnMajor),
+            _ => None,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<TexelOrder> {
+        match name {
+            "row-major" => Some(TexelOrder::RowMajor),
+            "column-major" => Some(TexelOrder::ColumnMajor),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TexelOrder::RowMajor => "row-major",
+            TexelOrder::ColumnMajor => "column-major",
+        }
+    }
+
+    /// Position in the DATA chunk of the texel at (x, y).
+    pub fn storage_index(&self, x: u32, y: u32, width: u32, height: u32) -> usize {
+        match self {
+            TexelOrder::RowMajor => y as usize * width as usize + x as usize,
+            TexelOrder::ColumnMajor => x as usize * height as usize + y as usize,
+        }
+    }
+
+    /// Number of texels in a single row of the DATA chunk (a column when column-major) and the number of rows.
+    pub fn rows(&self, width: u32, height: u32) -> (usize, usize) {
+        match self {
+            TexelOrder::RowMajor => (width as usize, height as usize),
+            TexelOrder::ColumnMajor => (height as usize, width as usize),
+        }
+    }
+
+    /// Coordinates of the texel at the given position in the DATA chunk.
+    pub fn coordinates(&self, index: usize, width: u32, height: u32) -> (u32, u32) {
+        match self {
+            TexelOrder::RowMajor => ((index % width as usize) as u32, (index / width as usize) as u32),
+            TexelOrder::ColumnMajor => ((index / height as usize) as u32, (index % height as usize) as u32),
+        }
+    }
+}
+
+/// Type and per-texel size (in bytes) of a single attribute, as stored in its ATR record.
+///
+/// Byte attributes store the first `size` channels of the source (so a size of 4 is a RGBA vector),
+/// the other types store a single scalar read from the first channel. Int attributes are signed,
+/// storing the source value minus a zero point. Float attributes of size 2 are half-precision.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct AttributeFormat {
+    pub attribute_type: AttributeType,
+    pub size: u8,
+}
+
+impl AttributeFormat {
+    pub fn new(attribute_type: AttributeType, size: u8) -> io::Result<AttributeFormat> {
+        let supported_sizes: &[u8] = match attribute_type {
+            AttributeType::Byte => &[1, 2, 3, 4],
+            AttributeType::UInt => &[2, 4],
+            AttributeType::Float => &[2, 4],
+            AttributeType::Int => &[1, 2],
+        };
+        if !supported_sizes.contains(&size) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                "Unsupported size {size} for attribute type {} (supported: {supported_sizes:?})", attribute_type.name()
+            )));
+        }
+        Ok(AttributeFormat { attribute_type, size })
+    }
+
+    /// Number of values stored per texel (channels of a byte vector, otherwise a single scalar).
+    pub fn component_count(&self) -> usize {
+        match self.attribute_type {
+            AttributeType::Byte => self.size as usize,
+            _ => 1,
+        }
+    }
+
+    /// Decodes a single component from the bytes of one texel of this attribute.
+    pub fn decode_component(&self, bytes: &[u8], component: usize) -> f64 {
+        match (self.attribute_type, self.size) {
+            (AttributeType::Byte, _) => bytes[component] as f64,
+            (AttributeType::UInt, 2) => u16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+            (AttributeType::UInt, _) => u32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+            (AttributeType::Float, 2) => f16::from_le_bytes([bytes[0], bytes[1]]).to_f64(),
+            (AttributeType::Float, _) => f32::from_le_bytes(bytes[..4].try_into().unwrap()) as f64,
+            (AttributeType::Int, 1) => bytes[0] as i8 as f64,
+            (AttributeType::Int, _) => i16::from_le_bytes([bytes[0], bytes[1]]) as f64,
+        }
+    }
+}
+
+impl Default for AttributeFormat {
+    fn default() -> Self {
+        AttributeFormat {
+            attribute_type: AttributeType::Byte,
+            size: 1,
+        }
+    }
+}
+
+/// Named category of attributes, referenced by their index in the attribute table.
+#[derive(Clone, PartialEq, Debug)]
+pub struct NsdGroup {
+    pub name: String,
+    pub attributes: Vec<u32>,
+}
+
+/// Human-readable description and key/value tags of an attribute.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct NsdAttributeMetadata {
+    pub attribute: u32,
+    pub description: String,
+    pub tags: BTreeMap<String, String>,
+    /// Value stored by texels without valid data, if the attribute has any.
+    pub nodata: Option<f64>,
+    /// Unit of the values, e.g. meters, empty if they have none.
+    pub unit: String,
+    pub kind: ValueKind,
+    /// Rank of the attribute when overlapping systems conflict, higher first.
+    pub priority: Option<i32>,
+    /// Weight of the attribute when the values of overlapping systems are blended.
+    pub weight: Option<f64>,
+}
+
+/// Placement of the main section in world space. Texel coordinates are measured from the top left corner of the
+/// first texel, so texel (x, y) covers the world from `origin + (x, y) * units_per_texel` to the next texel.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NsdWorldScale {
+    pub origin: [f64; 2],
+    /// World units covered by one texel along x and y, negative if the axis points against the rows or columns.
+    pub units_per_texel: [f64; 2],
+    /// EPSG code of the coordinate reference system of georeferenced files.
+    pub epsg: Option<u32>,
+}
+
+impl NsdWorldScale {
+    pub fn texel_to_world(&self, x: f64, y: f64) -> [f64; 2] {
+        [self.origin[0] + x * self.units_per_texel[0], self.origin[1] + y * self.units_per_texel[1]]
+    }
+
+    pub fn world_to_texel(&self, x: f64, y: f64) -> [f64; 2] {
+        [(x - self.origin[0]) / self.units_per_texel[0], (y - self.origin[1]) / self.units_per_texel[1]]
+    }
+}
+
+/// Contents of the optional chunks following the DATA chunk.
+///
+/// Every optional chunk starts with its magic and the size of the rest of the chunk,
+/// so readers can skip chunks they don't understand.
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct NsdExtensions {
+    pub groups: Vec<NsdGroup>,
+    /// Only attributes with a description or tags have an entry.
+    pub attribute_metadata: Vec<NsdAttributeMetadata>,
+    /// Written into the DIM chunk of the main section instead of an optional chunk, as the texel data can't be read
+    /// without it.
+    pub texel_order: TexelOrder,
+    /// Size in bytes of each row of the DATA chunk including padding, written into the DIM chunk of the main section
+    /// like the texel order. Rows are tightly packed if not set.
+    pub row_stride: Option<u32>,
+    /// Written as a WLD chunk.
+    pub world: Option<NsdWorldScale>,
+}
@@ -0,0 +1,204 @@
+//! Georeferencing of GeoTIFF sources, so the output can record where it lies in their coordinate reference system.
+//! Only north-up images placed by a tie point and a pixel scale are supported, not arbitrary transformations.
+
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+
+use tiff::decoder::{Decoder, Limits};
+use tiff::tags::Tag;
+
+use crate::format::NsdWorldScale;
+use crate::layer::{DecodeLimits, LayerDimensions, LayerRegion, LayerSource};
+
+const RASTER_TYPE_KEY: u16 = 1025;
+const GEOGRAPHIC_TYPE_KEY: u16 = 2048;
+const PROJECTED_TYPE_KEY: u16 = 3072;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+const USER_DEFINED: u16 = 32767;
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Extent of a GeoTIFF in its coordinate reference system.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct GeoReference {
+    /// EPSG code of the coordinate reference system, unless it's user-defined or not given.
+    pub epsg: Option<u32>,
+    /// `[min_x, min_y, max_x, max_y]` covered by the whole image, y pointing north.
+    pub bounds: [f64; 4],
+    pub width: u32,
+    pub height: u32,
+}
+
+pub fn is_geotiff_path(path: &Path) -> bool {
+    let extension = path.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+    extension == "tif" || extension == "tiff"
+}
+
+impl GeoReference {
+    /// Reads the georeferencing tags of a TIFF, `None` if it has none. Fails if the image or its tags exceed the
+    /// limits.
+    pub fn read(path: &Path, limits: &DecodeLimits) -> io::Result<Option<GeoReference>> {
+        let tiff_error = |error: tiff::TiffError| match error {
+            tiff::TiffError::LimitsExceeded => limits.exceeded(format_args!("{}", path.display())),
+            error => invalid_data(format!("{}: {error}", path.display())),
+        };
+        let memory = usize::try_from(limits.max_memory).unwrap_or(usize::MAX);
+        let mut tiff_limits = Limits::default();
+        tiff_limits.decoding_buffer_size = memory;
+        tiff_limits.intermediate_buffer_size = memory;
+        tiff_limits.ifd_value_size = tiff_limits.ifd_value_size.min(memory);
+        let mut decoder = Decoder::new(BufReader::new(File::open(path)?)).map_err(tiff_error)?.with_limits(tiff_limits);
+        let (width, height) = decoder.dimensions().map_err(tiff_error)?;
+        let tie_point = decoder.find_tag(Tag::ModelTiepointTag).map_err(tiff_error)?;
+        let pixel_scale = decoder.find_tag(Tag::ModelPixelScaleTag).map_err(tiff_error)?;
+        let (tie_point, pixel_scale) = match (tie_point, pixel_scale) {
+            (Some(tie_point), Some(pixel_scale)) => (
+                tie_point.into_f64_vec().map_err(tiff_error)?,
+                pixel_scale.into_f64_vec().map_err(tiff_error)?,
+            ),
+            _ if decoder.find_tag(Tag::ModelTransformationTag).map_err(tiff_error)?.is_some() => {
+                return Err(invalid_data(format!(
+                    "{} is placed by a model transformation, only tie points with a pixel scale are supported",
+                    path.display()
+                )));
+            }
+            _ => return Ok(None),
+        };
+        let ([column, row, _, x, y, ..], [scale_x, scale_y, ..]) = (&tie_point[..], &pixel_scale[..]) else {
+            return Err(invalid_data(format!("{} has an incomplete tie point or pixel scale", path.display())));
+        };
+
+        let keys = match decoder.find_tag(Tag::GeoKeyDirectoryTag).map_err(tiff_error)? {
+            Some(keys) => keys.into_u16_vec().map_err(tiff_error)?,
+            None => vec![],
+        };
+        // Entries of 4 values after the header, with the value itself stored in the entry if it has no location.
+        let key = |id: u16| keys.get(4..).unwrap_or_default().chunks_exact(4)
+            .find(|entry| entry[0] == id && entry[1] == 0)
+            .map(|entry| entry[3]);
+        let epsg = [PROJECTED_TYPE_KEY, GEOGRAPHIC_TYPE_KEY].into_iter()
+            .find_map(&key)
+            .filter(|&code| code != 0 && code != USER_DEFINED)
+            .map(u32::from);
+        // Tie points of point rasters are at the center of the pixel instead of its corner.
+        let offset = match key(RASTER_TYPE_KEY) {
+            Some(RASTER_PIXEL_IS_POINT) => 0.5,
+            _ => 0.0,
+        };
+
+        let left = x - (column + offset) * scale_x;
+        let top = y + (row + offset) * scale_y;
+        Ok(Some(GeoReference {
+            epsg,
+            bounds: [left, top - height as f64 * scale_y, left + width as f64 * scale_x, top],
+            width,
+            height,
+        }))
+    }
+
+    pub fn pixel_size(&self) -> [f64; 2] {
+        [
+            (self.bounds[2] - self.bounds[0]) / self.width as f64,
+            (self.bounds[3] - self.bounds[1]) / self.height as f64,
+        ]
+    }
+
+    /// Whether both cover the same extent, up to half of the larger pixel.
+    pub fn same_extent(&self, other: &GeoReference) -> bool {
+        let [width, height] = self.pixel_size();
+        let [other_width, other_height] = other.pixel_size();
+        let tolerance = [width.max(other_width) / 2.0, height.max(other_height) / 2.0];
+        self.bounds.iter().zip(&other.bounds).enumerate()
+            .all(|(index, (bound, other_bound))| (bound - other_bound).abs() <= tolerance[index % 2])
+    }
+
+    /// Placement of the output in the coordinate reference system, with y pointing down like the rows. The region is
+    /// in pixels of this image.
+    pub fn world_scale(&self, region: Option<LayerRegion>, dimensions: &LayerDimensions) -> NsdWorldScale {
+        let region = region.unwrap_or(LayerRegion { x: 0, y: 0, width: self.width, height: self.height });
+        // Regions are clipped to the image like the sources are.
+        let right = region.x.checked_add(region.width).map_or(self.width, |right| right.min(self.width));
+        let bottom = region.y.checked_add(region.height).map_or(self.height, |bottom| bottom.min(self.height));
+        let [pixel_width, pixel_height] = self.pixel_size();
+        NsdWorldScale {
+            origin: [self.bounds[0] + region.x as f64 * pixel_width, self.bounds[3] - region.y as f64 * pixel_height],
+            units_per_texel: [
+                (right.saturating_sub(region.x)) as f64 * pixel_width / dimensions.width as f64,
+                -((bottom.saturating_sub(region.y)) as f64 * pixel_height) / dimensions.height as f64,
+            ],
+            epsg: self.epsg,
+        }
+    }
+}
+
+/// Georeferencing shared by all GeoTIFF sources, failing if they differ in their coordinate reference system or
+/// extent. `None` if none of the sources is georeferenced.
+pub fn common_georeference<'a>(
+    sources: impl IntoIterator<Item = &'a LayerSource>,
+    limits: &DecodeLimits
+) -> io::Result<Option<GeoReference>> {
+    let mut common: Option<(&Path, GeoReference)> = None;
+    let files = sources.into_iter()
+        .filter(|source| source.tile_layer.is_none() && source.mosaic.is_none() && is_geotiff_path(&source.path));
+    for source in files {
+        let Some(reference) = GeoReference::read(&source.path, limits)? else {
+            continue;
+        };
+        let Some((first_path, first)) = common else {
+            common = Some((&source.path, reference));
+            continue;
+        };
+        let describe = |reference: &GeoReference| {
+            let crs = reference.epsg.map_or("an unknown CRS".to_string(), |epsg| format!("EPSG:{epsg}"));
+            format!("{:?} in {crs}", reference.bounds)
+        };
+        if reference.epsg != first.epsg || !reference.same_extent(&first) {
+            return Err(invalid_data(format!(
+                "{} covers {}, but {} covers {}. Reproject or crop the sources to the same extent.",
+                source.path.display(), describe(&reference), first_path.display(), describe(&first)
+            )));
+        }
+    }
+    Ok(common.map(|(_, reference)| reference))
+}
+
+#[cfg(test)]
+mod tests {
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    /// Writes a 4x2 GeoTIFF whose top left corner is at (1000, 2000), with pixels 10 units wide and 5 high.
+    fn write_geotiff(path: &Path, epsg: u16) {
+        let mut encoder = TiffEncoder::new(File::create(path).unwrap()).unwrap();
+        let mut image = encoder.new_image::<colortype::Gray8>(4, 2).unwrap();
+        image.encoder().write_tag(Tag::ModelTiepointTag, &[0.0, 0.0, 0.0, 1000.0, 2000.0, 0.0][..]).unwrap();
+        image.encoder().write_tag(Tag::ModelPixelScaleTag, &[10.0, 5.0, 0.0][..]).unwrap();
+        image.encoder().write_tag(Tag::GeoKeyDirectoryTag, &[1, 1, 0, 1, PROJECTED_TYPE_KEY, 0, 1, epsg][..]).unwrap();
+        image.write_data(&[0; 8]).unwrap();
+    }
+
+    #[test]
+    fn places_the_output_in_the_crs_of_the_sources() {
+        let directory = TempDirectory::new();
+        write_geotiff(&directory.join("height.tif"), 32633);
+        let limits = DecodeLimits::default();
+        let reference = GeoReference::read(&directory.join("height.tif"), &limits).unwrap().unwrap();
+        assert_eq!(reference, GeoReference { epsg: Some(32633), bounds: [1000.0, 1990.0, 1040.0, 2000.0], width: 4, height: 2 });
+
+        // The right half of the image, stretched over 4x4 texels.
+        let region = LayerRegion { x: 2, y: 0, width: 2, height: 2 };
+        let scale = reference.world_scale(Some(region), &LayerDimensions { width: 4, height: 4 });
+        assert_eq!(scale, NsdWorldScale { origin: [1020.0, 2000.0], units_per_texel: [5.0, -2.5], epsg: Some(32633) });
+
+        write_geotiff(&directory.join("grass.tif"), 4326);
+        let sources = [LayerSource::image(directory.join("height.tif")), LayerSource::image(directory.join("grass.tif"))];
+        let error = common_georeference(&sources, &limits).unwrap_err();
+        assert!(error.to_string().contains("EPSG:4326"), "{error}");
+        assert_eq!(common_georeference(&sources[..1], &limits).unwrap(), Some(reference));
+    }
+}
@@ -0,0 +1,49 @@
+//! Commands run after a generation, e.g. to copy the output into the content directory of an engine or to trigger
+//! a reimport.
+
+use std::io;
+use std::process::Stdio;
+
+use crate::preprocess::shell;
+
+/// Runs the command through the shell, waiting for it to finish. Every field is passed as the `NSDGEN_<NAME>`
+/// environment variable and never pasted into the command, so values like paths and error messages can't be
+/// interpreted by the shell. The output of the command goes to stderr, keeping stdout free for the protocol of the
+/// editor integration.
+pub fn run_hook(command: &str, fields: &[(&str, &str)]) -> io::Result<()> {
+    let mut shell = shell(command);
+    for (name, value) in fields {
+        shell.env(format!("NSDGEN_{}", name.to_uppercase()), value);
+    }
+    let status = shell.stdin(Stdio::null()).stdout(io::stderr()).status()?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(io::Error::other(format!("`{command}` failed with {status}"))),
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    #[test]
+    fn passes_fields_as_environment_variables() {
+        let directory = TempDirectory::new();
+        let error = "Layer \"x\" failed; $(touch injected) `touch injected` {dir}";
+        let command = format!("printf %s \"$NSDGEN_ERROR|$NSDGEN_DIR\" > {}/fields", directory.path().display());
+        run_hook(&command, &[("dir", "{error}"), ("error", error)]).unwrap();
+
+        assert_eq!(fs::read_to_string(directory.join("fields")).unwrap(), format!("{error}|{{error}}"));
+        assert!(!directory.join("injected").exists() && !Path::new("injected").exists());
+    }
+
+    #[test]
+    fn fails_with_the_command() {
+        let error = run_hook("exit 2", &[]).unwrap_err();
+        assert!(error.to_string().starts_with("`exit 2` failed"), "{error}");
+    }
+}
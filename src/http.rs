@@ -0,0 +1,138 @@
+//! Minimal HTTP/1.1 handling for the local API of `nsdgen serve` and for calling webhooks.
+//! Every connection carries a single request.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// Largest request body accepted, manifests and job descriptions are far smaller.
+const MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Most headers of a request.
+const MAX_HEADERS: usize = 100;
+
+/// How long webhooks may take to connect and to answer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub struct Request {
+    pub method: String,
+    /// Path without the query string.
+    pub path: String,
+    /// Names and values of the headers, in the order they were sent.
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    /// Value of the first header with the name, which is case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    /// Non-empty segments of the path, e.g. `["jobs", "3"]` for `/jobs/3`.
+    pub fn segments(&self) -> Vec<&str> {
+        self.path.split('/').filter(|segment| !segment.is_empty()).collect()
+    }
+}
+
+fn bad_request(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+pub fn read_request(stream: &mut impl BufRead) -> io::Result<Request> {
+    let mut request_line = String::new();
+    stream.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(bad_request("Malformed request line"));
+    };
+    let path = target.split('?').next().unwrap_or_default().to_string();
+    let method = method.to_string();
+
+    let mut content_length = 0;
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        if stream.read_line(&mut line)? == 0 {
+            return Err(bad_request("Unexpected end of the headers"));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse::<usize>().map_err(|_| bad_request("Invalid Content-Length"))?;
+            }
+            if headers.len() == MAX_HEADERS {
+                return Err(bad_request("Too many headers"));
+            }
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    if content_length > MAX_BODY_SIZE {
+        return Err(bad_request("Request body too large"));
+    }
+
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body)?;
+    Ok(Request { method, path, headers, body })
+}
+
+fn reason_phrase(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        415 => "Unsupported Media Type",
+        _ => "Internal Server Error",
+    }
+}
+
+pub fn write_response(stream: &mut impl Write, status: u16, content_type: &str, body: &[u8]) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status} {}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        reason_phrase(status), body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+/// Sends a POST request to an `http://` URL and returns the status code of the response.
+pub fn post(url: &str, content_type: &str, body: &[u8]) -> io::Result<u16> {
+    let invalid_url = || io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid URL {url}, expected http://host[:port]/path"));
+    let rest = url.strip_prefix("http://").ok_or_else(invalid_url)?;
+    let (host, path) = match rest.find('/') {
+        Some(index) => rest.split_at(index),
+        None => (rest, "/"),
+    };
+    if host.is_empty() {
+        return Err(invalid_url());
+    }
+    let address = match host.rsplit_once(':') {
+        Some((_, port)) if !port.contains(']') => host.to_string(),
+        _ => format!("{host}:80"),
+    };
+
+    let address = address.to_socket_addrs()?.next().ok_or_else(invalid_url)?;
+    let mut stream = TcpStream::connect_timeout(&address, REQUEST_TIMEOUT)?;
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body)?;
+    stream.flush()?;
+
+    let mut status_line = String::new();
+    BufReader::new(stream).read_line(&mut status_line)?;
+    status_line.split_whitespace().nth(1)
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Malformed response"))
+}
@@ -0,0 +1,166 @@
+//! Ignore files excluding files of the input directory from discovery, so reference images and work in progress can
+//! live next to the layers. Patterns follow the gitignore syntax.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Ignore file read from the input directory.
+pub const IGNORE_FILE_NAME: &str = ".nsdignore";
+
+#[derive(Clone, Debug)]
+struct IgnorePattern {
+    glob: Vec<char>,
+    /// Re-includes the files matched by earlier patterns (`!pattern`).
+    negated: bool,
+    /// Only matches directories (`pattern/`).
+    directory_only: bool,
+    /// Matches the whole relative path instead of the file name, for patterns containing a slash.
+    anchored: bool,
+}
+
+#[derive(Clone, Default, Debug)]
+pub struct IgnoreFile {
+    patterns: Vec<IgnorePattern>,
+}
+
+impl IgnoreFile {
+    /// The ignore file of the directory, or `None` if it has none.
+    pub fn load(directory: &Path) -> io::Result<Option<IgnoreFile>> {
+        match fs::read_to_string(directory.join(IGNORE_FILE_NAME)) {
+            Ok(source) => Ok(Some(IgnoreFile::parse(&source))),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Parses a pattern per line. Empty lines and lines starting with `#` are skipped, `\#` and `\!` start patterns
+    /// with those characters.
+    pub fn parse(source: &str) -> IgnoreFile {
+        let patterns = source.lines()
+            .filter_map(|line| {
+                // Trailing spaces are ignored unless escaped.
+                let line = match line.trim_end().ends_with('\\') {
+                    true => &line[..(line.trim_end().len() + 1).min(line.len())],
+                    false => line.trim_end(),
+                };
+                if line.is_empty() || line.starts_with('#') {
+                    return None;
+                }
+                let (negated, line) = match line.strip_prefix('!') {
+                    Some(line) => (true, line),
+                    None => (false, line),
+                };
+                let line = line.strip_prefix('\\').filter(|line| line.starts_with(['#', '!'])).unwrap_or(line);
+                let (directory_only, line) = match line.strip_suffix('/') {
+                    Some(line) => (true, line),
+                    None => (false, line),
+                };
+                let anchored = line.contains('/');
+                let line = line.strip_prefix('/').unwrap_or(line);
+                (!line.is_empty()).then(|| IgnorePattern { glob: line.chars().collect(), negated, directory_only, anchored })
+            })
+            .collect();
+        IgnoreFile { patterns }
+    }
+
+    /// Whether the path (relative to the directory of the ignore file, separated by `/`) is ignored. The last
+    /// matching pattern decides.
+    pub fn is_ignored(&self, relative_path: &str, is_directory: bool) -> bool {
+        let path: Vec<char> = relative_path.chars().collect();
+        let file_name: Vec<char> = relative_path.rsplit('/').next().unwrap_or_default().chars().collect();
+        self.patterns.iter()
+            .rev()
+            .filter(|pattern| is_directory || !pattern.directory_only)
+            .find(|pattern| match pattern.anchored {
+                true => glob_matches(&pattern.glob, &path),
+                false => glob_matches(&pattern.glob, &file_name),
+            })
+            .is_some_and(|pattern| !pattern.negated)
+    }
+}
+
+/// Matches a glob: `*` and `?` within a path segment, `**` across segments, `[abc]`, `[a-z]` and `[!a-z]` classes,
+/// and `\` escaping the next character.
+fn glob_matches(glob: &[char], name: &[char]) -> bool {
+    match glob {
+        [] => name.is_empty(),
+        ['*', '*', '/', rest @ ..] => {
+            // Zero or more whole directories.
+            glob_matches(rest, name)
+                || (0..name.len()).any(|index| name[index] == '/' && glob_matches(rest, &name[index + 1..]))
+        }
+        ['*', '*', rest @ ..] => (0..=name.len()).any(|index| glob_matches(rest, &name[index..])),
+        ['*', rest @ ..] => {
+            let segment = name.iter().position(|&char| char == '/').unwrap_or(name.len());
+            (0..=segment).any(|index| glob_matches(rest, &name[index..]))
+        }
+        ['?', rest @ ..] => name.first().is_some_and(|&char| char != '/') && glob_matches(rest, &name[1..]),
+        ['[', rest @ ..] => {
+            let Some(end) = rest.iter().skip(1).position(|&char| char == ']').map(|index| index + 1) else {
+                return name.first() == Some(&'[') && glob_matches(rest, &name[1..]);
+            };
+            let (class, rest) = (&rest[..end], &rest[end + 1..]);
+            let (negated, class) = match class {
+                ['!' | '^', class @ ..] => (true, class),
+                class => (false, class),
+            };
+            let Some(&char) = name.first().filter(|&&char| char != '/') else {
+                return false;
+            };
+            let (mut in_class, mut index) = (false, 0);
+            while index < class.len() {
+                match class.get(index + 1..index + 3) {
+                    Some(['-', high]) => {
+                        in_class |= (class[index]..=*high).contains(&char);
+                        index += 3;
+                    }
+                    _ => {
+                        in_class |= class[index] == char;
+                        index += 1;
+                    }
+                }
+            }
+            in_class != negated && glob_matches(rest, &name[1..])
+        }
+        ['\\', literal, rest @ ..] | [literal, rest @ ..] => {
+            name.first() == Some(literal) && glob_matches(rest, &name[1..])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    #[test]
+    fn matches_paths_like_gitignore() {
+        let ignore_file = IgnoreFile::parse(&[
+            "# References",
+            "*_ref.png",
+            "!keep_ref.png",
+            "reference/",
+            "/docs/**/*.png",
+            "layer[0-9].png",
+            "\\#notes.png",
+        ].join("\n"));
+        let ignored = |path: &str| ignore_file.is_ignored(path, false);
+        assert!(ignored("height_ref.png") && ignored("art/height_ref.png"));
+        assert!(!ignored("keep_ref.png"));
+        assert!(ignore_file.is_ignored("reference", true) && !ignored("reference"));
+        assert!(ignored("docs/a/b/height.png") && ignored("docs/height.png") && !ignored("height.png"));
+        assert!(ignored("layer3.png") && !ignored("layerx.png"));
+        assert!(ignored("#notes.png"));
+        assert!(!IgnoreFile::parse("# *.png\n\n").is_ignored("height.png", false));
+    }
+
+    #[test]
+    fn loads_the_ignore_file_of_the_directory() {
+        let directory = TempDirectory::new();
+        assert!(IgnoreFile::load(directory.path()).unwrap().is_none());
+        fs::write(directory.join(IGNORE_FILE_NAME), "wip_*\n").unwrap();
+        let ignore_file = IgnoreFile::load(directory.path()).unwrap().unwrap();
+        assert!(ignore_file.is_ignored("wip_height.png", false));
+    }
+}
@@ -0,0 +1,437 @@
+//! Minimal JSON value type used for sidecar files, reports and reading JSON inputs.
+
+use std::fmt;
+use std::fmt::Write;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    /// Keys keep their insertion order.
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    pub fn object() -> JsonValue {
+        JsonValue::Object(vec![])
+    }
+
+    /// Adds a key to an object, returning it for chaining.
+    pub fn with(mut self, key: &str, value: impl Into<JsonValue>) -> JsonValue {
+        self.insert(key, value);
+        self
+    }
+
+    pub fn insert(&mut self, key: &str, value: impl Into<JsonValue>) {
+        match self {
+            JsonValue::Object(entries) => entries.push((key.into(), value.into())),
+            _ => panic!("JsonValue::insert called on a non-object value"),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            JsonValue::Object(entries) => entries.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            JsonValue::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            JsonValue::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.as_f64().filter(|number| number.fract() == 0.0 && *number >= 0.0).map(|number| number as u64)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            JsonValue::Bool(boolean) => Some(*boolean),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            JsonValue::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn parse(source: &str) -> Result<JsonValue, String> {
+        let mut parser = JsonParser {
+            bytes: source.as_bytes(),
+            position: 0,
+            depth: 0,
+        };
+        let value = parser.parse_value()?;
+        parser.skip_whitespace();
+        if parser.position != parser.bytes.len() {
+            return Err(parser.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    pub fn to_pretty_string(&self) -> String {
+        let mut string = String::new();
+        self.write(&mut string, Some(0)).unwrap();
+        string.push('\n');
+        string
+    }
+
+    fn write(&self, out: &mut String, indent: Option<usize>) -> fmt::Result {
+        let newline = |out: &mut String, level: usize| {
+            if indent.is_some() {
+                out.push('\n');
+                out.extend(std::iter::repeat_n("  ", level));
+            }
+        };
+        let level = indent.unwrap_or(0);
+        let nested = indent.map(|level| level + 1);
+        match self {
+            JsonValue::Null => out.write_str("null"),
+            JsonValue::Bool(boolean) => write!(out, "{boolean}"),
+            JsonValue::Number(number) if !number.is_finite() => out.write_str("null"),
+            JsonValue::Number(number) => write!(out, "{number}"),
+            JsonValue::String(string) => write_string(out, string),
+            JsonValue::Array(array) if array.is_empty() => out.write_str("[]"),
+            JsonValue::Array(array) => {
+                out.push('[');
+                for (index, value) in array.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    newline(out, level + 1);
+                    value.write(out, nested)?;
+                }
+                newline(out, level);
+                out.write_char(']')
+            }
+            JsonValue::Object(entries) if entries.is_empty() => out.write_str("{}"),
+            JsonValue::Object(entries) => {
+                out.push('{');
+                for (index, (key, value)) in entries.iter().enumerate() {
+                    if index > 0 {
+                        out.push(',');
+                    }
+                    newline(out, level + 1);
+                    write_string(out, key)?;
+                    out.push_str(if indent.is_some() { ": " } else { ":" });
+                    value.write(out, nested)?;
+                }
+                newline(out, level);
+                out.write_char('}')
+            }
+        }
+    }
+}
+
+/// Compact, single-line representation.
+impl fmt::Display for JsonValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut string = String::new();
+        self.write(&mut string, None)?;
+        f.write_str(&string)
+    }
+}
+
+/// Deepest nesting of objects and arrays accepted, far beyond that of any file nsdgen reads.
+const MAX_NESTING: usize = 128;
+
+struct JsonParser<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    /// Objects and arrays being parsed.
+    depth: usize,
+}
+
+impl<'a> JsonParser<'a> {
+    fn error(&self, message: &str) -> String {
+        format!("{message} at offset {}", self.position)
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.bytes.get(self.position), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            self.position += 1;
+        }
+    }
+
+    fn consume(&mut self, expected: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.bytes.get(self.position) != Some(&expected) {
+            return Err(self.error(&format!("expected `{}`", expected as char)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    fn consume_literal(&mut self, literal: &str, value: JsonValue) -> Result<JsonValue, String> {
+        if !self.bytes[self.position..].starts_with(literal.as_bytes()) {
+            return Err(self.error("invalid literal"));
+        }
+        self.position += literal.len();
+        Ok(value)
+    }
+
+    fn parse_value(&mut self) -> Result<JsonValue, String> {
+        self.skip_whitespace();
+        match self.bytes.get(self.position) {
+            Some(b'{') => self.parse_nested(Self::parse_object),
+            Some(b'[') => self.parse_nested(Self::parse_array),
+            Some(b'"') => {
+                self.position += 1;
+                Ok(JsonValue::String(self.parse_string()?))
+            }
+            Some(b't') => self.consume_literal("true", JsonValue::Bool(true)),
+            Some(b'f') => self.consume_literal("false", JsonValue::Bool(false)),
+            Some(b'n') => self.consume_literal("null", JsonValue::Null),
+            Some(_) => {
+                let start = self.position;
+                while matches!(self.bytes.get(self.position), Some(b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9')) {
+                    self.position += 1;
+                }
+                std::str::from_utf8(&self.bytes[start..self.position]).ok()
+                    .and_then(|number| number.parse::<f64>().ok())
+                    .map(JsonValue::Number)
+                    .ok_or_else(|| self.error("invalid value"))
+            }
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    /// Parses an object or array after its opening bracket, failing once they're nested deeper than `MAX_NESTING`
+    /// instead of overflowing the stack.
+    fn parse_nested(&mut self, parse: fn(&mut Self) -> Result<JsonValue, String>) -> Result<JsonValue, String> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error("too deeply nested"));
+        }
+        self.position += 1;
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_object(&mut self) -> Result<JsonValue, String> {
+        let mut entries = vec![];
+        self.skip_whitespace();
+        if self.bytes.get(self.position) == Some(&b'}') {
+            self.position += 1;
+            return Ok(JsonValue::Object(entries));
+        }
+        loop {
+            self.consume(b'"')?;
+            let key = self.parse_string()?;
+            self.consume(b':')?;
+            entries.push((key, self.parse_value()?));
+            self.skip_whitespace();
+            match self.bytes.get(self.position) {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Object(entries));
+                }
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<JsonValue, String> {
+        let mut array = vec![];
+        self.skip_whitespace();
+        if self.bytes.get(self.position) == Some(&b']') {
+            self.position += 1;
+            return Ok(JsonValue::Array(array));
+        }
+        loop {
+            array.push(self.parse_value()?);
+            self.skip_whitespace();
+            match self.bytes.get(self.position) {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(JsonValue::Array(array));
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    /// Parses the rest of a string whose opening quote was already consumed.
+    fn parse_string(&mut self) -> Result<String, String> {
+        let mut bytes = vec![];
+        loop {
+            match self.bytes.get(self.position) {
+                None => return Err(self.error("unterminated string")),
+                Some(b'"') => {
+                    self.position += 1;
+                    return String::from_utf8(bytes).map_err(|_| self.error("invalid UTF-8 in string"));
+                }
+                Some(b'\\') => {
+                    let escape = self.bytes.get(self.position + 1).copied();
+                    self.position += 2;
+                    match escape {
+                        Some(b'"') => bytes.push(b'"'),
+                        Some(b'\\') => bytes.push(b'\\'),
+                        Some(b'/') => bytes.push(b'/'),
+                        Some(b'b') => bytes.push(0x08),
+                        Some(b'f') => bytes.push(0x0C),
+                        Some(b'n') => bytes.push(b'\n'),
+                        Some(b'r') => bytes.push(b'\r'),
+                        Some(b't') => bytes.push(b'\t'),
+                        Some(b'u') => {
+                            let mut code = self.parse_hex4()?;
+                            // Surrogate pairs encode characters outside the basic multilingual plane.
+                            if (0xD800..0xDC00).contains(&code) && self.bytes[self.position..].starts_with(b"\\u") {
+                                self.position += 2;
+                                let low = self.parse_hex4()?;
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low.wrapping_sub(0xDC00) & 0x3FF);
+                            }
+                            let char = char::from_u32(code).unwrap_or(char::REPLACEMENT_CHARACTER);
+                            bytes.extend_from_slice(char.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                Some(&byte) => {
+                    bytes.push(byte);
+                    self.position += 1;
+                }
+            }
+        }
+    }
+
+    fn parse_hex4(&mut self) -> Result<u32, String> {
+        let digits = self.bytes.get(self.position..self.position + 4)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+            .ok_or_else(|| self.error("invalid unicode escape"))?;
+        self.position += 4;
+        Ok(digits)
+    }
+}
+
+fn write_string(out: &mut String, string: &str) -> fmt::Result {
+    out.push('"');
+    for char in string.chars() {
+        match char {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            char if (char as u32) < 0x20 => write!(out, "\\u{:04x}", char as u32)?,
+            char => out.push(char),
+        }
+    }
+    out.write_char('"')
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self {
+        JsonValue::Bool(value)
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self {
+        JsonValue::String(value.into())
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self {
+        JsonValue::String(value)
+    }
+}
+
+impl From<f64> for JsonValue {
+    fn from(value: f64) -> Self {
+        JsonValue::Number(value)
+    }
+}
+
+macro_rules! impl_from_integer {
+    ($($integer:ty),*) => {
+        $(impl From<$integer> for JsonValue {
+            fn from(value: $integer) -> Self {
+                JsonValue::Number(value as f64)
+            }
+        })*
+    };
+}
+
+impl_from_integer!(u8, u16, u32, u64, usize, i32, i64);
+
+impl<T: Into<JsonValue>> From<Vec<T>> for JsonValue {
+    fn from(value: Vec<T>) -> Self {
+        JsonValue::Array(value.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<JsonValue>> From<Option<T>> for JsonValue {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(JsonValue::Null, Into::into)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_both_representations() {
+        let value = JsonValue::object()
+            .with("name", "height \"map\"\n\t\\ é 🗺")
+            .with("size", 1048576u64)
+            .with("scale", -0.25)
+            .with("enabled", true)
+            .with("epsg", None::<u32>)
+            .with("tiles", vec![JsonValue::object().with("x", 0u32), JsonValue::Array(vec![]), JsonValue::object()]);
+        assert_eq!(JsonValue::parse(&value.to_string()), Ok(value.clone()));
+        assert_eq!(JsonValue::parse(&value.to_pretty_string()), Ok(value));
+    }
+
+    #[test]
+    fn parses_escapes_and_keeps_the_key_order() {
+        let value = JsonValue::parse(r#" { "b": [1, 2e3, -0.5], "a": "é🗺\/", "c": null } "#).unwrap();
+        let JsonValue::Object(entries) = &value else { panic!("not an object") };
+        assert_eq!(entries.iter().map(|(key, _)| key.as_str()).collect::<Vec<_>>(), ["b", "a", "c"]);
+        assert_eq!(value.get("a").and_then(JsonValue::as_str), Some("é🗺/"));
+        assert_eq!(value.get("b"), Some(&JsonValue::Array(vec![1.0.into(), 2000.0.into(), (-0.5).into()])));
+        assert_eq!(value.get("c"), Some(&JsonValue::Null));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let malformed = [
+            "", "{", "[1,]", "[1 2]", r#"{"a" 1}"#, r#"{"a": 1,}"#, r#"{a: 1}"#, r#""unterminated"#, r#""\x""#,
+            r#""\u12""#, "tru", "nul", "1.2.3", "-", "{} {}", "[1]]",
+        ];
+        for source in malformed {
+            assert!(JsonValue::parse(source).is_err(), "{source:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn limits_the_nesting() {
+        let nested = |depth: usize| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(JsonValue::parse(&nested(MAX_NESTING)).is_ok());
+        assert!(JsonValue::parse(&nested(MAX_NESTING + 1)).unwrap_err().contains("too deeply nested"));
+        // Unterminated and far deeper than the limit, which would overflow the stack without it.
+        assert!(JsonValue::parse(&"[{\"a\":".repeat(100_000)).is_err());
+    }
+}
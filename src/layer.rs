@@ -0,0 +1,1567 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, Cursor, Read, Seek};
+use std::path::{Path, PathBuf};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Condvar, Mutex};
+
+use image::codecs::png::PngDecoder;
+use image::{DynamicImage, GenericImageView, GrayImage, ImageBuffer, ImageDecoder, ImageError, Luma, Rgba32FImage};
+
+use crate::alpha::{premultiply, AlphaMode};
+use crate::cache::{DecodedSources, LayerCache};
+use crate::cancel::{cancelled_error, CancellationToken};
+use crate::channels::{channel_names, select_channels, ChannelMap, ChannelMapping};
+use crate::comparison::{ComparisonSheet, ResizeComparison, ResizeLoss};
+use crate::expression::Expression;
+use crate::format::{AttributeFormat, AttributeType, NSD_HEADER};
+use crate::ignore::{IgnoreFile, IGNORE_FILE_NAME};
+use crate::log::{self, Severity};
+use crate::{info, warning};
+use crate::manifest::{LayerConfig, Manifest};
+use crate::mosaic::{stitch, MosaicConfig};
+use crate::nodata::{intersect_mask, nodata_mask, with_mask};
+use crate::normalize::{normalize_percentiles, PercentileRange};
+use crate::pipeline::{apply_pipeline, feather, Operation};
+use crate::preprocess::run_preprocessor;
+use crate::progress::{ProgressCallback, ProgressEvent};
+use crate::regions::label_regions;
+use crate::resample::{resize, ResizeFilter, ValueKind};
+use crate::texels::TexelBuffer;
+use crate::tiled::{is_tiled_map, TiledConfig, TiledMap};
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct LayerDimensions {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl LayerDimensions {
+    pub fn from_power_of_two(width_power_of_two: u32, height_power_of_two: u32) -> LayerDimensions {
+        LayerDimensions {
+            width: 2u32.pow(width_power_of_two),
+            height: 2u32.pow(height_power_of_two),
+        }
+    }
+
+    pub fn get_texel_count(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+}
+
+impl Default for LayerDimensions {
+    fn default() -> Self {
+        LayerDimensions {
+            width: 1024,
+            height: 512,
+        }
+    }
+}
+
+/// Window of the source images, in source pixels, which is packed instead of the whole image.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LayerRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl std::str::FromStr for LayerRegion {
+    type Err = String;
+
+    /// Parses `x,y,width,height`.
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let values: Vec<u32> = string.split(',')
+            .map(|value| value.trim().parse::<u32>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("Invalid region {string}, expected x,y,width,height"))?;
+        let [x, y, width, height] = values[..] else {
+            return Err(format!("Invalid region {string}, expected x,y,width,height"));
+        };
+        if width == 0 || height == 0 {
+            return Err("The region must not be empty".into());
+        }
+        Ok(LayerRegion { x, y, width, height })
+    }
+}
+
+/// File format of the resized images.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ResizedImageFormat {
+    Png,
+    Tiff,
+}
+
+impl ResizedImageFormat {
+    fn image_format(&self) -> image::ImageFormat {
+        match self {
+            ResizedImageFormat::Png => image::ImageFormat::Png,
+            ResizedImageFormat::Tiff => image::ImageFormat::Tiff,
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            ResizedImageFormat::Png => "png",
+            ResizedImageFormat::Tiff => "tif",
+        }
+    }
+}
+
+/// Largest width and height of the sources, so a broken or malicious image can't exhaust the memory.
+pub const DEFAULT_MAX_SOURCE_DIMENSION: u32 = 32768;
+/// Largest allocation of the image decoders, the default of the image crate.
+pub const DEFAULT_MAX_DECODE_MEMORY: u64 = 512 * 1024 * 1024;
+
+/// Caps on decoding the sources, checked by the decoders before they allocate the images.
+#[derive(Clone, Copy, Debug)]
+pub struct DecodeLimits {
+    pub max_dimension: u32,
+    pub max_memory: u64,
+}
+
+impl Default for DecodeLimits {
+    fn default() -> DecodeLimits {
+        DecodeLimits { max_dimension: DEFAULT_MAX_SOURCE_DIMENSION, max_memory: DEFAULT_MAX_DECODE_MEMORY }
+    }
+}
+
+impl DecodeLimits {
+    fn image_limits(&self) -> image::io::Limits {
+        let mut limits = image::io::Limits::default();
+        limits.max_image_width = Some(self.max_dimension);
+        limits.max_image_height = Some(self.max_dimension);
+        limits.max_alloc = Some(self.max_memory);
+        limits
+    }
+
+    /// Decodes the image with the limits, naming the limits when it exceeds them.
+    fn decode(&self, reader: image::io::Reader<impl BufRead + Seek>) -> io::Result<DynamicImage> {
+        let mut reader = reader.with_guessed_format()?;
+        reader.limits(self.image_limits());
+        reader.decode().map_err(|error| match error {
+            ImageError::Limits(error) => self.exceeded(error),
+            error => io::Error::other(error),
+        })
+    }
+
+    /// Fails if an image of the size, with `bytes_per_pixel` bytes per pixel, exceeds the limits.
+    pub(crate) fn check(&self, width: u32, height: u32, bytes_per_pixel: u64) -> io::Result<()> {
+        if width.max(height) > self.max_dimension {
+            return Err(self.exceeded(format_args!("{width}x{height} pixels")));
+        }
+        if width as u64 * height as u64 * bytes_per_pixel > self.max_memory {
+            return Err(self.exceeded("insufficient memory"));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn exceeded(&self, error: impl std::fmt::Display) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!(
+            "The image exceeds the decode limits of {} pixels per side and {} bytes ({error}), \
+            see --max-source-dimension and --max-decode-memory",
+            self.max_dimension, self.max_memory
+        ))
+    }
+}
+
+/// Where and how the resized images are saved.
+#[derive(Clone, Default, Debug)]
+pub struct ResizedImageOptions {
+    /// Directory of the images, `_resized` in the temporary directory or next to the sources by default.
+    pub directory: Option<PathBuf>,
+    /// Format of the images, the one of the source (PNG for generated layers) by default.
+    pub format: Option<ResizedImageFormat>,
+    /// Whether existing images are replaced, otherwise they're kept.
+    pub overwrite: bool,
+}
+
+/// Settings shared by all layers of a run, which control how they're loaded.
+#[derive(Clone, Default)]
+pub struct LayerLoadOptions {
+    /// Size every layer is resized to.
+    pub dimensions: LayerDimensions,
+    /// Window of the sources to use instead of the whole images.
+    pub region: Option<LayerRegion>,
+    /// Filter for sources smaller than the target dimensions.
+    pub upscale_filter: ResizeFilter,
+    /// Filter for sources larger than the target dimensions.
+    pub downscale_filter: ResizeFilter,
+    /// Percentiles stretched to the full range of continuous layers.
+    pub normalize: Option<PercentileRange>,
+    /// Saves the resized images, into `_resized` next to the sources by default.
+    pub save_resized: Option<ResizedImageOptions>,
+    /// Directory of intermediate files like `_resized`, instead of the directory of the sources.
+    pub temp_directory: Option<PathBuf>,
+    /// Receives crops of every source and its resized image, set by `init_layers` when saving the resized images.
+    pub comparisons: Option<ComparisonSheet>,
+    /// Measures what downscaling loses from every layer, warning about the layers losing more than this fraction.
+    pub validate_resize: Option<f64>,
+    pub cache: Option<LayerCache>,
+    /// Sources decoded by earlier outputs of the run.
+    pub decoded: Option<DecodedSources>,
+    /// Masks of the target dimensions, keyed by the attribute name of their layer.
+    pub masks: BTreeMap<String, GrayImage>,
+    /// Resized images of the layers used as baselines or expression inputs, keyed by their attribute name.
+    pub references: BTreeMap<String, DynamicImage>,
+    /// Variables of expressions given on the command line.
+    pub defines: BTreeMap<String, f64>,
+    /// External command run on every image source, unless its layer has its own.
+    pub preprocess: Option<String>,
+    /// Receives a `LayerLoaded` event for every loaded layer.
+    pub progress: Option<ProgressCallback>,
+    /// Stops loading the remaining layers once cancelled.
+    pub cancellation: Option<CancellationToken>,
+    /// Fails on the first layer which can't be loaded, instead of leaving it out.
+    pub strict: bool,
+    /// Maximum number of layers being decoded or waiting to be handed to `on_layer` of `init_layers` at once,
+    /// which bounds the peak memory when `on_layer` releases the texels. Defaults to the number of workers.
+    pub resident_layers: Option<usize>,
+    /// Number of workers loading the layers in parallel, defaults to the available parallelism.
+    pub threads: Option<usize>,
+    pub decode_limits: DecodeLimits,
+}
+
+impl LayerLoadOptions {
+    /// Directory the resized images of the source are saved to.
+    fn resized_directory(&self, source: &LayerSource) -> PathBuf {
+        if let Some(directory) = self.save_resized.as_ref().and_then(|resized| resized.directory.clone()) {
+            return directory;
+        }
+        let directory = self.temp_directory.as_deref().unwrap_or(source.path.parent().unwrap());
+        directory.join("_resized")
+    }
+
+    /// Upscale and downscale filters of a layer. Categorical layers can't be interpolated, as values
+    /// in between two IDs would be unrelated IDs. Masks are averaged, then thresholded.
+    fn filters(&self, config: &LayerConfig) -> (ResizeFilter, ResizeFilter) {
+        match config.kind {
+            ValueKind::Continuous => (self.upscale_filter, self.downscale_filter),
+            ValueKind::Categorical => (ResizeFilter::Nearest, ResizeFilter::Majority),
+            ValueKind::Mask => (ResizeFilter::Nearest, ResizeFilter::Area),
+        }
+    }
+}
+
+/// A single layer found during discovery, before it's loaded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LayerSource {
+    /// File the layer is read from.
+    pub path: PathBuf,
+    /// Name of the layer unless the manifest renames it, which is also the key of its manifest entry.
+    pub stem: String,
+    /// Tile layer to rasterize, when the file is a Tiled map.
+    pub tile_layer: Option<(String, TiledConfig)>,
+    /// Whether the layer is the alpha channel of the image, split off by --split-alpha.
+    pub alpha_channel: bool,
+    /// Channels of the image making up the layer, picked by the channel map.
+    pub channels: Option<Vec<usize>>,
+    /// Grid of tiles stitched into the layer, `path` being the first one.
+    pub mosaic: Option<MosaicConfig>,
+}
+
+impl LayerSource {
+    /// An image file, named after its file stem.
+    pub fn image(path: PathBuf) -> LayerSource {
+        LayerSource {
+            stem: layer_name(&path),
+            path,
+            tile_layer: None,
+            alpha_channel: false,
+            channels: None,
+            mosaic: None,
+        }
+    }
+
+    /// Channels of an image file, named by the channel map.
+    pub fn channels(path: PathBuf, mapping: &ChannelMapping) -> LayerSource {
+        LayerSource {
+            stem: mapping.stem.clone(),
+            channels: Some(mapping.channels.clone()),
+            ..LayerSource::image(path)
+        }
+    }
+
+    /// A grid of tile images in the directory, named after the manifest entry configuring it.
+    pub fn mosaic(directory: &Path, stem: &str, mosaic: &MosaicConfig) -> LayerSource {
+        LayerSource {
+            path: directory.join(mosaic.tile_name(0, 0)),
+            stem: stem.to_string(),
+            tile_layer: None,
+            alpha_channel: false,
+            channels: None,
+            mosaic: Some(mosaic.clone()),
+        }
+    }
+
+    /// Every file the layer is read from.
+    pub fn files(&self) -> Vec<PathBuf> {
+        match (&self.mosaic, self.path.parent()) {
+            (Some(mosaic), Some(directory)) => mosaic.tile_paths(directory),
+            _ => vec![self.path.clone()],
+        }
+    }
+
+    /// Resolution of the source before resizing, read from the headers of the images without decoding them.
+    /// Mosaics are as large as all of their tiles, assuming they're all the size of the first one.
+    pub fn source_dimensions(&self) -> io::Result<LayerDimensions> {
+        let (width, height) = match (&self.tile_layer, &self.mosaic) {
+            (Some((_, config)), _) => TiledMap::load(&self.path)?.raster_dimensions(config)?,
+            (None, Some(mosaic)) => {
+                let (width, height) = image::image_dimensions(&self.path).map_err(io::Error::other)?;
+                (width * mosaic.columns, height * mosaic.rows)
+            }
+            (None, None) => image::image_dimensions(&self.path).map_err(io::Error::other)?,
+        };
+        Ok(LayerDimensions { width, height })
+    }
+
+    /// Source of the alpha channel of this image, named `<stem>_alpha`.
+    pub fn alpha(&self) -> LayerSource {
+        LayerSource {
+            stem: format!("{}_alpha", self.stem),
+            alpha_channel: true,
+            ..self.clone()
+        }
+    }
+
+    /// Whether the image has an alpha channel, read from the PNG header without decoding the image.
+    fn has_alpha(&self) -> bool {
+        fs::File::open(&self.path).ok()
+            .and_then(|file| PngDecoder::new(io::BufReader::new(file)).ok())
+            .is_some_and(|decoder| decoder.color_type().has_alpha())
+    }
+
+    /// Name of the file written by --save-resized.
+    fn resized_file_name(&self, format: Option<ResizedImageFormat>) -> PathBuf {
+        if let Some(format) = format {
+            return PathBuf::from(format!("{}.{}", self.stem, format.extension()));
+        }
+        match self.tile_layer {
+            Some(_) => PathBuf::from(format!("{}.png", self.stem)),
+            None if self.alpha_channel || self.channels.is_some() || self.mosaic.is_some() => {
+                PathBuf::from(format!("{}.png", self.stem))
+            }
+            None => PathBuf::from(self.path.file_name().unwrap()),
+        }
+    }
+}
+
+impl std::fmt::Display for LayerSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.tile_layer {
+            Some((tile_layer, _)) => write!(f, "{} (tile layer {tile_layer})", self.path.display()),
+            None if self.alpha_channel => write!(f, "{} (alpha)", self.path.display()),
+            None if self.channels.is_some() => {
+                write!(f, "{} (channels {})", self.path.display(), channel_names(self.channels.as_ref().unwrap()))
+            }
+            None if self.mosaic.is_some() => {
+                let mosaic = self.mosaic.as_ref().unwrap();
+                let directory = self.path.parent().unwrap_or(Path::new(""));
+                write!(f, "{} ({}x{} tiles)", directory.join(&mosaic.pattern).display(), mosaic.columns, mosaic.rows)
+            }
+            None => write!(f, "{}", self.path.display()),
+        }
+    }
+}
+
+pub struct Layer {
+    pub name: String,
+    /// Values of the layer, already converted to its attribute format.
+    pub texels: TexelBuffer,
+    pub format: AttributeFormat,
+    /// Where the layer was loaded from.
+    pub source: LayerSource,
+}
+
+/// Layers stored in an additional section of the file, at their own resolution.
+pub struct LayerSection {
+    pub name: String,
+    pub dimensions: LayerDimensions,
+    pub layers: Vec<Layer>,
+}
+
+/// Layers are named after the stem of their source file, unless the manifest renames them.
+pub fn layer_name(file: &Path) -> String {
+    file.file_stem().unwrap().to_string_lossy().as_ref().into()
+}
+
+/// Decodes the source (or loads it from the cache) and resizes it to the target dimensions.
+fn load_image(source: &LayerSource, config: &LayerConfig, options: &LayerLoadOptions, layer_name: &str) -> io::Result<DynamicImage> {
+    let dimensions = &options.dimensions;
+    let region = options.region;
+    let cache = options.cache.as_ref();
+
+    let (upscale_filter, downscale_filter) = options.filters(config);
+    let mut filter_key = format!(
+        "{}/{}/{}/{}", upscale_filter.name(), downscale_filter.name(), config.edge.name(), config.alpha.name()
+    );
+    if config.indexed {
+        filter_key.push_str("/indexed");
+    }
+    if let Some(classes) = &config.classes {
+        filter_key.push_str(&format!("/{classes:?}"));
+    }
+    if config.kind == ValueKind::Mask {
+        filter_key.push_str("/mask");
+    }
+    // IDs can't be stretched, and stretching values with a unit would break it.
+    let normalize = options.normalize.filter(|_| config.kind == ValueKind::Continuous && config.unit.is_empty());
+    if let Some(range) = normalize {
+        filter_key.push_str(&format!("/{}-{}", range.low, range.high));
+    }
+    if let Some(nodata) = &config.nodata {
+        filter_key.push_str(&format!("/{nodata:?}"));
+    }
+    if !config.pipeline.is_empty() {
+        filter_key.push_str(&format!("/{:?}", config.pipeline));
+    }
+    let preprocess = config.preprocess.as_ref().or(options.preprocess.as_ref()).filter(|_| source.tile_layer.is_none());
+    if let Some(command) = preprocess {
+        filter_key.push_str(&format!("/{command}"));
+    }
+    let cache_key = cache.and_then(|_| LayerCache::key(source, dimensions, region, &filter_key).ok());
+    // Comparisons and validation need the source, which isn't cached.
+    let cached_image = cache.zip(cache_key)
+        .filter(|_| options.comparisons.is_none() && options.validate_resize.is_none())
+        .and_then(|(cache, key)| cache.load(&source.stem, key));
+
+    match cached_image {
+        Some(image) => {
+            info!("Layer {layer_name} has been loaded from the cache.");
+            Ok(image)
+        }
+        None => {
+            let decode_source = || -> io::Result<DynamicImage> {
+                info!("Opening layer {layer_name} from file {source}...");
+                Ok(match &source.tile_layer {
+                    Some((tile_layer, tiled_config)) => TiledMap::load(&source.path)
+                        .and_then(|map| map.rasterize(tile_layer, tiled_config, &options.decode_limits))?,
+                    None => {
+                        let limits = &options.decode_limits;
+                        let decode = |path: &Path| -> io::Result<DynamicImage> {
+                            let processed = match preprocess {
+                                Some(command) => {
+                                    info!("Preprocessing layer {layer_name}...");
+                                    let bytes = run_preprocessor(command, path, &source.stem, options.temp_directory.as_deref()).map_err(|error| {
+                                        io::Error::new(error.kind(), format!("Could not preprocess layer {layer_name}: {error}"))
+                                    })?;
+                                    Some(bytes)
+                                }
+                                None => None,
+                            };
+                            Ok(match processed {
+                                Some(bytes) if config.indexed => DynamicImage::ImageLuma8(
+                                    read_palette_indices(Cursor::new(bytes), layer_name, limits)?
+                                ),
+                                Some(bytes) => limits.decode(image::io::Reader::new(Cursor::new(bytes)))?,
+                                None if config.indexed => DynamicImage::ImageLuma8(
+                                    read_palette_indices(File::open(path)?, layer_name, limits)?
+                                ),
+                                None => limits.decode(image::io::Reader::open(path)?)?,
+                            })
+                        };
+                        match &source.mosaic {
+                            Some(mosaic) => {
+                                let tiles = source.files().iter()
+                                    .map(|path| match path.exists() {
+                                        true => decode(path),
+                                        false => Err(io::Error::new(
+                                            io::ErrorKind::NotFound,
+                                            format!("Tile {} of layer {layer_name} is missing", path.display())
+                                        )),
+                                    })
+                                    .collect::<io::Result<_>>()?;
+                                stitch(tiles, mosaic).map_err(|error| {
+                                    io::Error::new(error.kind(), format!("Could not stitch layer {layer_name}: {error}"))
+                                })?
+                            }
+                            None => decode(&source.path)?,
+                        }
+                    }
+                })
+            };
+            // Sources shared by several outputs are decoded once, every output resizes them on its own.
+            let img = match &options.decoded {
+                Some(decoded) => {
+                    let key = format!("{source}/{}/{preprocess:?}", config.indexed);
+                    decoded.get_or_decode(key, decode_source)?
+                }
+                None => decode_source()?,
+            };
+            let img = if source.alpha_channel { alpha_channel(&img) } else { img };
+            let img = match &source.channels {
+                Some(channels) => select_channels(&img, channels),
+                None => img,
+            };
+            let img = match &config.classes {
+                Some(classes) => {
+                    let (ids, unmatched_count) = classes.classify(&img);
+                    if unmatched_count > 0 {
+                        warning!(
+                            Some(&source.path), "loading",
+                            "{unmatched_count} texels of layer {layer_name} don't match any color of the classification."
+                        );
+                    }
+                    DynamicImage::ImageLuma8(ids)
+                }
+                None => img,
+            };
+
+            let img = match region {
+                Some(region) => {
+                    if region.x >= img.width() || region.y >= img.height() {
+                        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                            "The region is outside of the {}x{} bounds of layer {layer_name}", img.width(), img.height()
+                        )));
+                    }
+                    let fits = region.x.checked_add(region.width).is_some_and(|right| right <= img.width())
+                        && region.y.checked_add(region.height).is_some_and(|bottom| bottom <= img.height());
+                    if !fits {
+                        warning!(
+                            Some(&source.path), "loading",
+                            "The region exceeds the {}x{} bounds of layer {layer_name} and will be clipped.",
+                            img.width(), img.height()
+                        );
+                    }
+                    img.crop_imm(region.x, region.y, region.width, region.height)
+                }
+                None => img,
+            };
+            let img = apply_pipeline(img, &config.pipeline);
+            let img = match config.alpha {
+                AlphaMode::Premultiply => premultiply(img),
+                _ => img,
+            };
+            let img = match normalize {
+                Some(range) => normalize_percentiles(&img, range),
+                None => img,
+            };
+
+            let upscaling = dimensions.get_texel_count() > img.width() as usize * img.height() as usize;
+            let filter = if upscaling { upscale_filter } else { downscale_filter };
+            info!("Resizing layer {layer_name} ({})...", filter.name());
+            // Sources off by a texel, like 1025x1024 exports, aren't worth a warning.
+            let (source_width, source_height) = (img.width() as u64, img.height() as u64);
+            let (width, height) = (dimensions.width as u64, dimensions.height as u64);
+            if ((source_width * height + width / 2) / width).abs_diff(source_height) > 1 {
+                warning!(
+                    Some(&source.path), "loading",
+                    "Layer {layer_name} ({source_width}x{source_height}) has a different aspect ratio than the output \
+                     ({width}x{height}) and is stretched to cover it."
+                );
+            }
+            let image = resize(&img, dimensions.width, dimensions.height, filter, config.edge);
+            let image = match config.kind {
+                ValueKind::Mask => Operation::Threshold { value: 0.5 }.apply(image),
+                _ => image,
+            };
+            // Texels without data stay without data, the mask is resized without blending.
+            let image = match &config.nodata {
+                Some(nodata) => {
+                    let mask = DynamicImage::ImageLuma8(nodata_mask(&img, nodata));
+                    let mask = resize(&mask, dimensions.width, dimensions.height, ResizeFilter::Nearest, config.edge);
+                    with_mask(image, &mask.into_luma8())
+                }
+                None => image,
+            };
+            if let Some(comparisons) = &options.comparisons {
+                comparisons.add(ResizeComparison::new(&source.stem, &img, &image));
+            }
+            if let Some(threshold) = options.validate_resize.filter(|_| !upscaling && image.dimensions() != img.dimensions()) {
+                let loss = ResizeLoss::measure(&img, &image, config.kind);
+                if loss.loss() > threshold {
+                    warning!(
+                        Some(&source.path), "loading",
+                        "Resizing loses too much of layer {layer_name} (more than {:.2}%): {loss}. Consider a larger output.",
+                        threshold * 100.0
+                    );
+                } else {
+                    info!("Resize loss of layer {layer_name}: {loss}.");
+                }
+            }
+
+            if let (Some(cache), Some(key)) = (cache, cache_key) {
+                if let Err(error) = cache.store(&source.stem, key, &image) {
+                    warning!(Some(&source.path), "cache", "Could not cache layer {layer_name}: {error}");
+                }
+            }
+            Ok(image)
+        }
+    }
+}
+
+impl Layer {
+    /// Layer of the source without any texels, standing in for it when planning a run without loading anything.
+    pub fn placeholder(source: &LayerSource, config: &LayerConfig, dimensions: &LayerDimensions) -> Layer {
+        Layer {
+            name: config.name.clone().unwrap_or_else(|| source.stem.clone()),
+            texels: TexelBuffer::empty(dimensions.width, dimensions.height, config.format),
+            format: config.format,
+            source: source.clone(),
+        }
+    }
+
+    pub fn from_source(source: &LayerSource, config: LayerConfig, options: &LayerLoadOptions) -> io::Result<Layer> {
+        let layer_name = config.name.clone().unwrap_or_else(|| source.stem.clone());
+        let image = load_image(source, &config, options, &layer_name)?;
+
+        let image = match config.baseline.as_ref().and_then(|baseline| options.references.get(baseline)) {
+            Some(baseline) => {
+                // Int attributes store the difference relative to their zero point, keeping negative differences.
+                let offset = match (config.format.attribute_type, config.format.size) {
+                    (AttributeType::Int, 1) => config.zero_point.unwrap_or(128) as f64 / u8::MAX as f64,
+                    (AttributeType::Int, _) => config.zero_point.unwrap_or(32768) as f64 / u16::MAX as f64,
+                    _ => 0.0,
+                };
+                subtract_baseline(&image, baseline, offset)
+            }
+            None => image,
+        };
+        let image = match &config.expression {
+            Some(expression) => evaluate_expression(&image, expression, options),
+            None => image,
+        };
+
+        let mask = config.mask.as_ref().and_then(|mask| options.masks.get(mask));
+        let image = match mask {
+            Some(mask) => intersect_mask(image, mask, config.nodata.is_some()),
+            None => image,
+        };
+        let image = match &config.distance_field {
+            Some(distance_field) => distance_field.apply(&image),
+            None => image,
+        };
+        let image = match config.label_regions {
+            Some(connectivity) => label_regions(&image, connectivity, &layer_name)?,
+            None => image,
+        };
+        let image = match config.feather {
+            Some(radius) => feather(image, radius, config.nodata.is_some() || mask.is_some()),
+            None => image,
+        };
+
+        if let Some(resized) = &options.save_resized {
+            let new_filepath = options.resized_directory(source).join(source.resized_file_name(resized.format));
+            let saved = match resized.format {
+                _ if !resized.overwrite && new_filepath.exists() => {
+                    info!("Keeping the existing resized image {}.", new_filepath.display());
+                    Ok(())
+                }
+                Some(format) => image.save_with_format(&new_filepath, format.image_format()),
+                None => image.save(&new_filepath),
+            };
+            if let Err(error) = saved {
+                warning!(Some(&new_filepath), "loading", "Could not save the resized image {}: {error}", new_filepath.display());
+            }
+        }
+
+        info!("Layer {layer_name} has been created.");
+
+        let nodata = config.nodata.map(|nodata| nodata.value).or(mask.map(|_| 0.0));
+        let texels = TexelBuffer::from_image(&image, &config, nodata);
+
+        Ok(Layer {
+            name: layer_name,
+            texels,
+            format: config.format,
+            source: source.clone(),
+        })
+    }
+
+    /// Encodes every texel of the layer in its attribute format, in row-major order.
+    pub fn encode_texels(&self) -> Vec<u8> {
+        self.texels.encode()
+    }
+}
+
+/// Extensions of image files which are loaded as layers.
+const LAYER_IMAGE_EXTENSIONS: [&str; 4] = ["png", "exr", "tif", "tiff"];
+/// Directory entries scanned between the progress messages of discovery.
+const DISCOVERY_PROGRESS_INTERVAL: usize = 10000;
+/// Fewest images worth checking on a thread of their own during discovery.
+const DISCOVERY_FILES_PER_THREAD: usize = 256;
+
+/// Why a file among the sources has been generated by nsdgen, if it has. Such files would otherwise be loaded as
+/// layers by the next run: visualizations and exports (`<output>.<attribute>.png` or `.exr`) written next to spatial
+/// data files, contact sheets of `--save-resized` and spatial data files given an image extension by mistake.
+fn generated_file_reason(file: &Path, data_file_stems: &HashSet<String>) -> Option<&'static str> {
+    let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+    if file_name.starts_with("_comparison_") && file_name.ends_with(".png") {
+        return Some("it's a contact sheet of --save-resized");
+    }
+    // Named after a spatial data file, with more than an extension after its name.
+    let is_derived = file_name.match_indices('.')
+        .any(|(index, _)| data_file_stems.contains(&file_name[..index]) && file_name[index + 1..].contains('.'));
+    if is_derived {
+        return Some(match file_name.ends_with(".png") {
+            true => "it's a visualization of a spatial data file",
+            false => "it's an export of a spatial data file",
+        });
+    }
+    let mut magic = [0; NSD_HEADER.len()];
+    let is_data_file = File::open(file).and_then(|mut file| file.read_exact(&mut magic)).is_ok() && magic == NSD_HEADER;
+    is_data_file.then_some("it's a spatial data file, not an image")
+}
+
+/// Why a link can't be followed: its chain of links leads back to itself, or to nothing.
+fn broken_link_reason(path: &Path) -> &'static str {
+    let mut visited = HashSet::new();
+    let mut current = path.to_path_buf();
+    while let Ok(target) = fs::read_link(&current) {
+        if !visited.insert(current.clone()) {
+            return "its links form a loop";
+        }
+        // Relative targets are relative to the directory of the link, absolute ones replace the path.
+        current = current.parent().map_or(target.clone(), |parent| parent.join(&target));
+    }
+    "it's a broken link"
+}
+
+/// Files of the directory, with progress messages for directories large enough to take a while. Symbolic links are
+/// followed unless `follow_symlinks` is off, in which case they're skipped.
+fn list_files(path: &Path, follow_symlinks: bool) -> io::Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(path)
+        .map_err(|error| io::Error::new(error.kind(), format!("Could not list the files of {}: {error}", path.display())))?;
+    let mut files = vec![];
+    let mut scanned = 0;
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        scanned += 1;
+        if scanned % DISCOVERY_PROGRESS_INTERVAL == 0 {
+            info!("Scanned {scanned} entries of {}...", path.display());
+        }
+        // Directories, like `_resized` and the caches, are never layers. The type comes with the entry on most
+        // platforms, so only links need another call.
+        let is_file = match entry.file_type() {
+            Ok(file_type) if file_type.is_symlink() && !follow_symlinks => {
+                info!("Skipping {}, it's a symbolic link (--no-follow-symlinks).", entry.path().display());
+                false
+            }
+            Ok(file_type) if file_type.is_symlink() => match fs::metadata(entry.path()) {
+                Ok(metadata) => metadata.is_file(),
+                Err(_) => {
+                    let link = entry.path();
+                    warning!(Some(&link), "discovery", "Skipping {}, {}.", link.display(), broken_link_reason(&link));
+                    false
+                }
+            },
+            Ok(file_type) => file_type.is_file(),
+            Err(_) => false,
+        };
+        if is_file {
+            files.push(entry.path());
+        }
+    }
+    if scanned >= DISCOVERY_PROGRESS_INTERVAL {
+        info!("Scanned {scanned} entries of {}, {} of them files.", path.display(), files.len());
+    }
+    Ok(files)
+}
+
+/// `generated_file_reason` of every image, checked on several threads as it reads the start of every file.
+fn generated_file_reasons(images: &[PathBuf], data_file_stems: &HashSet<String>) -> Vec<Option<&'static str>> {
+    let threads = std::thread::available_parallelism().map_or(4, |threads| threads.get());
+    let chunk_size = images.len().div_ceil(threads).max(DISCOVERY_FILES_PER_THREAD);
+    std::thread::scope(|scope| {
+        let chunks: Vec<_> = images.chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || {
+                chunk.iter().map(|image| generated_file_reason(image, data_file_stems)).collect::<Vec<_>>()
+            }))
+            .collect();
+        chunks.into_iter().flat_map(|chunk| chunk.join().unwrap()).collect()
+    })
+}
+
+/// Finds the layers in a directory: PNG, EXR and TIFF images, and tile layers of Tiled maps.
+pub fn read_layer_files(
+    path: &Path,
+    manifest: &Manifest,
+    channel_map: &ChannelMap,
+    follow_symlinks: bool
+) -> io::Result<Vec<LayerSource>> {
+    let mut files = list_files(path, follow_symlinks)?;
+    match IgnoreFile::load(path) {
+        Ok(Some(ignore_file)) => {
+            let count = files.len();
+            files.retain(|file| !ignore_file.is_ignored(&file.file_name().unwrap_or_default().to_string_lossy(), false));
+            if files.len() < count {
+                info!("Ignoring {} files matched by {IGNORE_FILE_NAME}.", count - files.len());
+            }
+        }
+        Ok(None) => {}
+        Err(error) => {
+            let ignore_path = path.join(IGNORE_FILE_NAME);
+            warning!(Some(&ignore_path), "discovery", "Could not read {}: {error}", ignore_path.display());
+        }
+    }
+    let data_file_stems: HashSet<String> = files.iter()
+        .filter(|file| file.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("nsd")))
+        .map(|file| file.file_stem().unwrap_or_default().to_string_lossy().into_owned())
+        .collect();
+
+    let mosaics: Vec<(&String, &MosaicConfig)> = manifest.layers.iter()
+        .filter_map(|(stem, config)| Some((stem, config.mosaic.as_ref()?)))
+        .collect();
+    let (images, maps): (Vec<PathBuf>, Vec<PathBuf>) = files.into_iter()
+        .filter(|file| {
+            let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+            !mosaics.iter().any(|(_, mosaic)| mosaic.matches(&file_name))
+        })
+        .filter(|file| {
+            let extension = file.extension().unwrap_or_default().to_string_lossy().to_lowercase();
+            LAYER_IMAGE_EXTENSIONS.contains(&extension.as_str()) || is_tiled_map(file)
+        })
+        .partition(|file| !is_tiled_map(file));
+    let reasons = generated_file_reasons(&images, &data_file_stems);
+
+    let mut sources = vec![];
+    for (file, reason) in images.into_iter().zip(reasons) {
+        if let Some(reason) = reason {
+            info!("Skipping {}, {reason}.", file.display());
+            continue;
+        }
+        let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+        match channel_map.mappings(&file_name) {
+            Some(mappings) => {
+                sources.extend(mappings.iter().map(|mapping| LayerSource::channels(file.clone(), mapping)));
+            }
+            None => sources.push(LayerSource::image(file)),
+        }
+    }
+    for file in maps {
+        let map = match TiledMap::load(&file) {
+            Ok(map) => map,
+            Err(error) => {
+                warning!(Some(&file), "discovery", "Could not read the Tiled map {}: {error}", file.display());
+                continue;
+            }
+        };
+        for tile_layer in map.layer_names() {
+            let selected = manifest.tiled.layers.as_ref()
+                .is_none_or(|layers| layers.iter().any(|layer| layer == tile_layer));
+            if selected {
+                sources.push(LayerSource {
+                    path: file.clone(),
+                    stem: tile_layer.to_string(),
+                    tile_layer: Some((tile_layer.to_string(), manifest.tiled.clone())),
+                    alpha_channel: false,
+                    channels: None,
+                    mosaic: None,
+                });
+            }
+        }
+    }
+    for (stem, mosaic) in mosaics {
+        sources.push(LayerSource::mosaic(path, stem, mosaic));
+    }
+    for file_name in channel_map.files.keys() {
+        if !path.join(file_name).is_file() {
+            warning!(None, "discovery", "{file_name} is in the channel map, but it was not found.");
+        }
+    }
+    Ok(sources)
+}
+
+/// Adds a `<stem>_alpha` source for every image source which has an alpha channel.
+pub fn split_alpha_sources(sources: &mut Vec<LayerSource>) {
+    let alpha_sources: Vec<LayerSource> = sources.iter()
+        .filter(|source| source.tile_layer.is_none() && !source.alpha_channel && source.channels.is_none())
+        .filter(|source| source.has_alpha())
+        .map(LayerSource::alpha)
+        .collect();
+    sources.extend(alpha_sources);
+}
+
+/// Grayscale image of the alpha channel, keeping 16-bit precision for sources with more than 8 bits.
+fn alpha_channel(image: &DynamicImage) -> DynamicImage {
+    match image.color().bytes_per_pixel() / image.color().channel_count() {
+        1 => DynamicImage::ImageLuma8(GrayImage::from_fn(image.width(), image.height(), |x, y| {
+            Luma([image.get_pixel(x, y).0[3]])
+        })),
+        _ => {
+            let rgba = image.to_rgba16();
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(image.width(), image.height(), |x, y| {
+                Luma([rgba.get_pixel(x, y).0[3]])
+            }))
+        }
+    }
+}
+
+/// Reads the palette index of every pixel of an indexed PNG, which the image crate would expand into colors.
+fn read_palette_indices(input: impl Read, layer_name: &str, limits: &DecodeLimits) -> io::Result<GrayImage> {
+    let memory = usize::try_from(limits.max_memory).unwrap_or(usize::MAX);
+    let decoder = png::Decoder::new_with_limits(input, png::Limits { bytes: memory });
+    let mut reader = decoder.read_info().map_err(|error| match error {
+        png::DecodingError::LimitsExceeded => limits.exceeded("insufficient memory"),
+        error => io::Error::other(error),
+    })?;
+    let info = reader.info();
+    if info.color_type != png::ColorType::Indexed {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("The source of layer {layer_name} is not an indexed PNG")
+        ));
+    }
+    let (width, height) = (info.width, info.height);
+    if width.max(height) > limits.max_dimension {
+        return Err(limits.exceeded(format_args!("{width}x{height} pixels")));
+    }
+    if reader.output_buffer_size() as u64 > limits.max_memory {
+        return Err(limits.exceeded("insufficient memory"));
+    }
+    let bit_depth = info.bit_depth as u8;
+
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut buffer).map_err(io::Error::other)?;
+    let indices_per_byte = 8 / bit_depth as u32;
+    let mask = ((1u16 << bit_depth) - 1) as u8;
+    Ok(GrayImage::from_fn(width, height, |x, y| {
+        // Rows of indices narrower than a byte are packed from the most significant bits.
+        let byte = buffer[y as usize * frame.line_size + (x / indices_per_byte) as usize];
+        let shift = 8 - bit_depth as u32 * (x % indices_per_byte + 1);
+        Luma([(byte >> shift) & mask])
+    }))
+}
+
+/// Loads the mask layers used by the manifest at the target dimensions of the options.
+pub fn load_masks(
+    layer_files: &[LayerSource],
+    manifest: &Manifest,
+    options: &LayerLoadOptions
+) -> io::Result<BTreeMap<String, GrayImage>> {
+    let mut masks = BTreeMap::new();
+    for name in manifest.mask_names() {
+        let source = layer_files.iter()
+            .find(|source| manifest.attribute_name(source) == name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Mask layer {name} not found")))?;
+        let mask = Layer::from_source(source, manifest.layer(&source.stem), options)?;
+        masks.insert(name.to_string(), mask.texels.to_mask());
+    }
+    Ok(masks)
+}
+
+/// Loads the resized images of the layers used as baselines or expression inputs by the manifest,
+/// at the target dimensions of the options.
+pub fn load_references(
+    layer_files: &[LayerSource],
+    manifest: &Manifest,
+    options: &LayerLoadOptions
+) -> io::Result<BTreeMap<String, DynamicImage>> {
+    let mut references = BTreeMap::new();
+    for name in manifest.reference_names(&options.defines) {
+        let source = layer_files.iter()
+            .find(|source| manifest.attribute_name(source) == name)
+            .ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{name} is used by the manifest, but it's neither a layer nor a variable")
+            ))?;
+        references.insert(name.to_string(), load_image(source, &manifest.layer(&source.stem), options, name)?);
+    }
+    Ok(references)
+}
+
+/// Input of an expression variable.
+enum ExpressionInput {
+    Value,
+    X,
+    Y,
+    U,
+    V,
+    Constant(f64),
+    Layer(Rgba32FImage),
+}
+
+/// Computes the color channels of every texel with the expression, as a float image which is clamped to the range
+/// of the attribute when converted to texels. Alpha of the image is kept.
+fn evaluate_expression(image: &DynamicImage, expression: &Expression, options: &LayerLoadOptions) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    let inputs: Vec<ExpressionInput> = expression.variables().iter()
+        .map(|name| match name.as_str() {
+            "value" => ExpressionInput::Value,
+            "x" => ExpressionInput::X,
+            "y" => ExpressionInput::Y,
+            "u" => ExpressionInput::U,
+            "v" => ExpressionInput::V,
+            "width" => ExpressionInput::Constant(width as f64),
+            "height" => ExpressionInput::Constant(height as f64),
+            name => match options.defines.get(name) {
+                Some(value) => ExpressionInput::Constant(*value),
+                None => ExpressionInput::Layer(options.references[name]
+                    .resize_exact(width, height, image::imageops::FilterType::Nearest)
+                    .into_rgba32f()),
+            },
+        })
+        .collect();
+
+    // Gray images are evaluated once per texel, their color channels are equal.
+    let color_channels = if image.color().channel_count() <= 2 { 1 } else { 3 };
+    let mut result = image.to_rgba32f();
+    let mut values = vec![0.0; inputs.len()];
+    for (x, y, pixel) in result.enumerate_pixels_mut() {
+        for channel in 0..color_channels {
+            for (value, input) in values.iter_mut().zip(&inputs) {
+                *value = match input {
+                    ExpressionInput::Value => pixel.0[channel] as f64,
+                    ExpressionInput::X => x as f64,
+                    ExpressionInput::Y => y as f64,
+                    ExpressionInput::U => (x as f64 + 0.5) / width as f64,
+                    ExpressionInput::V => (y as f64 + 0.5) / height as f64,
+                    ExpressionInput::Constant(constant) => *constant,
+                    ExpressionInput::Layer(layer) => layer.get_pixel(x, y).0[channel] as f64,
+                };
+            }
+            pixel.0[channel] = expression.evaluate(&values) as f32;
+        }
+        if color_channels == 1 {
+            pixel.0[1] = pixel.0[0];
+            pixel.0[2] = pixel.0[0];
+        }
+    }
+    DynamicImage::ImageRgba32F(result)
+}
+
+/// Difference of the first channels of the image and the baseline plus the offset, as a float image
+/// which is clamped to the range of the attribute when converted to texels. Alpha of the image is kept.
+fn subtract_baseline(image: &DynamicImage, baseline: &DynamicImage, offset: f64) -> DynamicImage {
+    let baseline = baseline.resize_exact(image.width(), image.height(), image::imageops::FilterType::Nearest).to_rgba32f();
+    let mut difference = image.to_rgba32f();
+    for (pixel, baseline_pixel) in difference.pixels_mut().zip(baseline.pixels()) {
+        let value = pixel.0[0] - baseline_pixel.0[0] + offset as f32;
+        pixel.0[..3].fill(value);
+    }
+    DynamicImage::ImageRgba32F(difference)
+}
+
+/// Sorts the sources by their `order` in the manifest, then by the names of the attributes they produce, which is the
+/// order of attributes in the file.
+pub fn sort_layer_sources(layer_files: &mut [LayerSource], manifest: &Manifest) {
+    layer_files.sort_by_cached_key(|source| {
+        let order = manifest.layers.get(&source.stem).map_or(0, |config| config.order);
+        (order, manifest.attribute_name(source), source.path.clone())
+    });
+}
+
+/// Counting semaphore limiting the number of resident layers.
+struct Permits {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Permits {
+    fn new(count: usize) -> Permits {
+        Permits { available: Mutex::new(count), released: Condvar::new() }
+    }
+
+    fn acquire(&self) {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+    }
+
+    fn release(&self) {
+        *self.available.lock().unwrap() += 1;
+        self.released.notify_one();
+    }
+}
+
+/// Message of a panic caught while loading a layer.
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().map_or("unknown error".into(), |message| message.to_string()),
+    }
+}
+
+/// A layer which could not be loaded and has been left out.
+#[derive(Debug)]
+pub struct LayerFailure {
+    /// Index of the layer in the sources passed to `init_layers`.
+    pub index: usize,
+    pub source: LayerSource,
+    pub error: io::Error,
+}
+
+/// Layers loaded by `init_layers`, in the order of their sources, and the ones which failed to load.
+pub struct LoadedLayers {
+    pub layers: Vec<Layer>,
+    pub failures: Vec<LayerFailure>,
+}
+
+/// Collects the results of every layer, failing on the first failure in strict mode.
+fn collect_layers(
+    layer_files: Vec<LayerSource>,
+    results: Vec<Option<io::Result<Layer>>>,
+    options: &LayerLoadOptions
+) -> io::Result<LoadedLayers> {
+    let mut loaded = LoadedLayers { layers: vec![], failures: vec![] };
+    for (index, (source, result)) in layer_files.into_iter().zip(results).enumerate() {
+        match result {
+            Some(Ok(layer)) => loaded.layers.push(layer),
+            Some(Err(error)) if options.strict => {
+                log::record(Severity::Error, Some(&source.path), "loading", error.to_string());
+                return Err(io::Error::new(error.kind(), format!("Could not load layer {}: {error}", source.path.display())));
+            }
+            Some(Err(error)) => loaded.failures.push(LayerFailure { index, source, error }),
+            // Skipped after a cancellation.
+            None => return Err(cancelled_error()),
+        }
+    }
+    Ok(loaded)
+}
+
+fn init_layers_parallel(
+    layer_files: Vec<LayerSource>,
+    manifest: &Manifest,
+    options: &LayerLoadOptions,
+    on_layer: &mut dyn FnMut(usize, &mut Layer)
+) -> io::Result<LoadedLayers> {
+    let jobs = layer_files.len();
+    let available_workers = options.threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(4usize, |threads| threads.get()))
+        .max(1);
+    let resident_layers = options.resident_layers.unwrap_or(available_workers).max(1);
+    let workers = jobs.min(available_workers).min(resident_layers);
+    let next_job = AtomicUsize::new(0);
+    // Taken before decoding a layer and given back once `on_layer` is done with it.
+    let permits = Permits::new(resident_layers);
+
+    // Bounded, so finished layers are handed to the consumer while the others are still loading.
+    let (sender, receiver) = mpsc::sync_channel(workers);
+    let mut results: Vec<Option<io::Result<Layer>>> = std::iter::repeat_with(|| None).take(jobs).collect();
+    std::thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let (layer_files, next_job, permits) = (&layer_files, &next_job, &permits);
+            scope.spawn(move || loop {
+                permits.acquire();
+                let index = next_job.fetch_add(1, Ordering::Relaxed);
+                // Queued layers are skipped after a cancellation, so the workers finish right after their current layer.
+                if index >= jobs || options.cancellation.as_ref().is_some_and(CancellationToken::is_cancelled) {
+                    permits.release();
+                    break;
+                }
+                let source = &layer_files[index];
+                let layer = panic::catch_unwind(AssertUnwindSafe(|| {
+                    Layer::from_source(source, manifest.layer(&source.stem), options)
+                }));
+                let layer = layer.unwrap_or_else(|payload| Err(io::Error::other(panic_message(payload))));
+                if sender.send((index, layer)).is_err() {
+                    break;
+                }
+            });
+        }
+        // Ends once every worker has finished and dropped its sender.
+        drop(sender);
+        let consumed = panic::catch_unwind(AssertUnwindSafe(|| {
+            for (index, mut layer) in receiver {
+                match &mut layer {
+                    Ok(layer) => on_layer(index, layer),
+                    // The other workers stop after their current layer.
+                    Err(_) if options.strict => next_job.store(jobs, Ordering::Relaxed),
+                    Err(_) => {}
+                }
+                results[index] = Some(layer);
+                permits.release();
+            }
+        }));
+        if let Err(payload) = consumed {
+            // Stop the workers, the ones waiting for a permit pass it on to each other as they finish.
+            next_job.store(jobs, Ordering::Relaxed);
+            permits.release();
+            panic::resume_unwind(payload);
+        }
+    });
+    collect_layers(layer_files, results, options)
+}
+
+/// Loads the layers, keeping the order of `layer_files` (see `sort_layer_sources`).
+/// `on_layer` is called with the index of every layer as soon as it's loaded, before its progress event, and may
+/// release its texels once they've been used (see `LayerLoadOptions::resident_layers`).
+/// Layers which fail to load are left out and returned as failures, unless `LayerLoadOptions::strict` is set.
+/// Fails if the loading is cancelled (see `LayerLoadOptions::cancellation`).
+pub fn init_layers(
+    layer_files: Vec<LayerSource>,
+    manifest: &Manifest,
+    options: &LayerLoadOptions,
+    run_sequential: bool,
+    mut on_layer: impl FnMut(usize, &mut Layer)
+) -> io::Result<LoadedLayers> {
+    assert!(!layer_files.is_empty());
+
+    let total = layer_files.len();
+    let mut loaded = 0;
+    let progress = options.progress.clone();
+    let mut on_layer = |index: usize, layer: &mut Layer| {
+        on_layer(index, layer);
+        loaded += 1;
+        if let Some(progress) = &progress {
+            progress(&ProgressEvent::LayerLoaded { name: layer.name.clone(), loaded, total });
+        }
+    };
+
+    let mut options = options.clone();
+    if options.save_resized.is_some() {
+        let path = options.resized_directory(&layer_files[0]);
+        if let Err(error) = fs::create_dir_all(&path) {
+            warning!(Some(&path), "loading", "Could not create directory {}: {error}", path.display());
+            options.save_resized = None;
+        } else {
+            options.comparisons = Some(ComparisonSheet::default());
+        }
+    }
+
+    let stems: Vec<String> = layer_files.iter().map(|source| source.stem.clone()).collect();
+    let directory = options.resized_directory(&layer_files[0]);
+    let loaded = match run_sequential {
+        true => init_layers_sequential(layer_files, manifest, &options, &mut on_layer),
+        false => init_layers_parallel(layer_files, manifest, &options, &mut on_layer),
+    }?;
+
+    if let Some(comparisons) = &options.comparisons {
+        let dimensions = &options.dimensions;
+        let path = directory.join(format!("_comparison_{}x{}.png", dimensions.width, dimensions.height));
+        let order: Vec<&str> = stems.iter().map(String::as_str).collect();
+        match comparisons.write(&path, &order) {
+            Ok(rows) => info!("Saved the resize comparison of {} to {}.", rows.join(", "), path.display()),
+            Err(error) => warning!(Some(&path), "loading", "Could not save the resize comparison: {error}"),
+        }
+    }
+    Ok(loaded)
+}
+
+fn init_layers_sequential(
+    layer_files: Vec<LayerSource>,
+    manifest: &Manifest,
+    options: &LayerLoadOptions,
+    on_layer: &mut dyn FnMut(usize, &mut Layer)
+) -> io::Result<LoadedLayers> {
+    let mut results = vec![];
+    for (index, source) in layer_files.iter().enumerate() {
+        if let Some(cancellation) = &options.cancellation {
+            cancellation.check()?;
+        }
+        let mut layer = Layer::from_source(source, manifest.layer(&source.stem), options);
+        match &mut layer {
+            Ok(layer) => on_layer(index, layer),
+            Err(_) if options.strict => {
+                results.push(Some(layer));
+                break;
+            }
+            Err(_) => {}
+        }
+        results.push(Some(layer));
+    }
+    // Layers after a failure in strict mode aren't loaded.
+    results.resize_with(layer_files.len(), || None);
+    collect_layers(layer_files, results, options)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    /// Layer of the given format loaded from `<name>.png`.
+    pub(crate) fn sample_layer(name: &str, image: &DynamicImage, format: AttributeFormat) -> Layer {
+        let config = LayerConfig { format, ..LayerConfig::default() };
+        Layer {
+            name: name.to_string(),
+            texels: TexelBuffer::from_image(image, &config, None),
+            format,
+            source: LayerSource::image(PathBuf::from(format!("{name}.png"))),
+        }
+    }
+
+    /// Saves the image as `<name>.png` into the directory and loads it resized to the dimensions of the options.
+    fn load_png(
+        directory: &TempDirectory,
+        name: &str,
+        image: &DynamicImage,
+        config: &LayerConfig,
+        options: &LayerLoadOptions
+    ) -> io::Result<DynamicImage> {
+        let path = directory.join(format!("{name}.png"));
+        image.save(&path).unwrap();
+        load_image(&LayerSource::image(path), config, options, name)
+    }
+
+    #[test]
+    fn reports_directories_which_can_not_be_listed() {
+        let directory = TempDirectory::new();
+        let missing = directory.join("missing");
+        let error = read_layer_files(&missing, &Manifest::default(), &ChannelMap::default(), true).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().contains(&missing.display().to_string()), "{error}");
+    }
+
+    #[test]
+    fn skips_the_files_generated_by_earlier_runs() {
+        let directory = TempDirectory::new();
+        for name in ["height.png", "terrain_v2.png"] {
+            GrayImage::new(1, 1).save(directory.join(name)).unwrap();
+        }
+        fs::write(directory.join("terrain.nsd"), NSD_HEADER).unwrap();
+        for name in ["terrain.height.png", "terrain.grass.exr", "_comparison_4x2.png", "spatial.png"] {
+            fs::write(directory.join(name), NSD_HEADER).unwrap();
+        }
+        fs::create_dir(directory.join("_resized")).unwrap();
+        GrayImage::new(1, 1).save(directory.join("_resized/height.png")).unwrap();
+
+        let sources = read_layer_files(directory.path(), &Manifest::default(), &ChannelMap::default(), true).unwrap();
+        let mut stems: Vec<&str> = sources.iter().map(|source| source.stem.as_str()).collect();
+        stems.sort();
+        assert_eq!(stems, ["height", "terrain_v2"]);
+    }
+
+    #[test]
+    fn fails_on_sources_exceeding_the_decode_limits() {
+        let directory = TempDirectory::new();
+        let image = DynamicImage::ImageLuma8(GrayImage::new(8, 2));
+        let load = |max_dimension, max_memory| {
+            let options = LayerLoadOptions {
+                dimensions: LayerDimensions { width: 8, height: 2 },
+                decode_limits: DecodeLimits { max_dimension, max_memory },
+                ..LayerLoadOptions::default()
+            };
+            load_png(&directory, "height", &image, &LayerConfig::default(), &options)
+        };
+        assert!(load(8, 16).is_ok());
+        let error = load(4, 16).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("decode limits of 4 pixels per side"), "{error}");
+        assert!(load(8, 15).unwrap_err().to_string().contains("--max-decode-memory"));
+        assert!(DecodeLimits::default().check(4, 4, 1 << 30).is_err());
+    }
+
+    #[test]
+    fn checks_the_discovered_images_in_parallel_keeping_their_order() {
+        let directory = TempDirectory::new();
+        let images: Vec<PathBuf> = (0..DISCOVERY_FILES_PER_THREAD * 3).map(|index| {
+            let path = directory.join(format!("layer{index}.png"));
+            fs::write(&path, if index % 7 == 0 { NSD_HEADER.as_slice() } else { b"not a header" }).unwrap();
+            path
+        }).collect();
+        let reasons = generated_file_reasons(&images, &HashSet::new());
+        assert_eq!(reasons.len(), images.len());
+        for (index, reason) in reasons.iter().enumerate() {
+            assert_eq!(reason.is_some(), index % 7 == 0, "{}", images[index].display());
+        }
+    }
+
+    #[test]
+    fn loads_only_the_region_of_the_sources() {
+        assert_eq!("2, 1,4,2".parse(), Ok(LayerRegion { x: 2, y: 1, width: 4, height: 2 }));
+        assert!("2,1,4".parse::<LayerRegion>().is_err());
+        assert!("2,1,0,2".parse::<LayerRegion>().is_err());
+
+        let directory = TempDirectory::new();
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(8, 4, |x, y| Luma([(x + 10 * y) as u8])));
+        let options = |region| LayerLoadOptions {
+            dimensions: LayerDimensions { width: 4, height: 2 },
+            region: Some(region),
+            ..LayerLoadOptions::default()
+        };
+        let region = LayerRegion { x: 2, y: 1, width: 4, height: 2 };
+        let loaded = load_png(&directory, "height", &image, &LayerConfig::default(), &options(region)).unwrap();
+        assert_eq!(loaded.to_luma8().into_raw(), [12, 13, 14, 15, 22, 23, 24, 25]);
+
+        let outside = LayerRegion { x: 8, ..region };
+        let error = load_png(&directory, "height", &image, &LayerConfig::default(), &options(outside)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn picks_the_filter_by_the_direction_of_the_resize() {
+        let options = |width| LayerLoadOptions {
+            dimensions: LayerDimensions { width, height: 1 },
+            upscale_filter: ResizeFilter::Nearest,
+            downscale_filter: ResizeFilter::Area,
+            ..LayerLoadOptions::default()
+        };
+        let directory = TempDirectory::new();
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(4, 1, vec![0, 100, 200, 100]).unwrap());
+        let load = |width| load_png(&directory, "height", &image, &LayerConfig::default(), &options(width)).unwrap().to_luma8().into_raw();
+        assert_eq!(load(8), [0, 0, 100, 100, 200, 200, 100, 100]);
+        assert_eq!(load(2), [50, 150]);
+
+        // Categorical layers are never interpolated, whatever the filters given.
+        let categorical = LayerConfig { kind: ValueKind::Categorical, ..LayerConfig::default() };
+        assert_eq!(options(2).filters(&categorical), (ResizeFilter::Nearest, ResizeFilter::Majority));
+    }
+
+    #[test]
+    fn thresholds_masks_and_keeps_the_values_with_a_unit() {
+        let directory = TempDirectory::new();
+        let options = LayerLoadOptions {
+            dimensions: LayerDimensions { width: 2, height: 1 },
+            normalize: Some(PercentileRange { low: 0.0, high: 100.0 }),
+            ..LayerLoadOptions::default()
+        };
+        let mask = LayerConfig { kind: ValueKind::Mask, ..LayerConfig::default() };
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(8, 1, vec![0, 255, 255, 255, 0, 0, 255, 0]).unwrap());
+        assert_eq!(load_png(&directory, "playable", &image, &mask, &options).unwrap().to_luma8().into_raw(), [255, 0]);
+
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(2, 1, vec![50, 150]).unwrap());
+        let meters = LayerConfig { unit: "meters".into(), ..LayerConfig::default() };
+        assert_eq!(load_png(&directory, "height", &image, &meters, &options).unwrap().to_luma8().into_raw(), [50, 150]);
+        assert_eq!(load_png(&directory, "grass", &image, &LayerConfig::default(), &options).unwrap().to_luma8().into_raw(), [0, 255]);
+    }
+
+    #[test]
+    fn splits_the_alpha_channels_into_layers() {
+        let directory = TempDirectory::new();
+        let image = image::RgbaImage::from_fn(2, 1, |x, _| image::Rgba([10, 20, 30, [255, 64][x as usize]]));
+        image.save(directory.join("grass.png")).unwrap();
+        GrayImage::new(2, 1).save(directory.join("height.png")).unwrap();
+
+        let mut sources = vec![
+            LayerSource::image(directory.join("grass.png")),
+            LayerSource::image(directory.join("height.png")),
+        ];
+        split_alpha_sources(&mut sources);
+        let stems: Vec<&str> = sources.iter().map(|source| source.stem.as_str()).collect();
+        assert_eq!(stems, ["grass", "height", "grass_alpha"]);
+
+        let options = LayerLoadOptions { dimensions: LayerDimensions { width: 2, height: 1 }, ..LayerLoadOptions::default() };
+        let alpha = load_image(&sources[2], &LayerConfig::default(), &options, "grass_alpha").unwrap();
+        assert_eq!(alpha.to_luma8().into_raw(), [255, 64]);
+    }
+
+    #[test]
+    fn reads_the_palette_indices_of_indexed_pngs() {
+        let mut bytes = vec![];
+        let mut encoder = png::Encoder::new(&mut bytes, 5, 1);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Two);
+        encoder.set_palette(vec![0, 0, 0, 255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        // Indices 3, 0, 1, 2 and 1, packed from the most significant bits.
+        encoder.write_header().unwrap().write_image_data(&[0b11_00_01_10, 0b01_00_00_00]).unwrap();
+
+        let limits = DecodeLimits::default();
+        let indices = read_palette_indices(Cursor::new(&bytes), "biomes", &limits).unwrap();
+        assert_eq!(indices.into_raw(), [3, 0, 1, 2, 1]);
+
+        let mut gray = vec![];
+        DynamicImage::ImageLuma8(GrayImage::new(1, 1)).write_to(&mut Cursor::new(&mut gray), image::ImageOutputFormat::Png).unwrap();
+        let error = read_palette_indices(Cursor::new(&gray), "biomes", &limits).unwrap_err();
+        assert_eq!(error.to_string(), "The source of layer biomes is not an indexed PNG");
+    }
+
+    #[test]
+    fn subtracts_the_baseline_around_the_zero_point() {
+        let directory = TempDirectory::new();
+        GrayImage::from_raw(2, 1, vec![100, 50]).unwrap().save(directory.join("height.png")).unwrap();
+        let baseline = DynamicImage::ImageLuma8(GrayImage::from_raw(2, 1, vec![60, 80]).unwrap());
+        let options = LayerLoadOptions {
+            dimensions: LayerDimensions { width: 2, height: 1 },
+            references: BTreeMap::from([("ground".to_string(), baseline)]),
+            ..LayerLoadOptions::default()
+        };
+        let config = LayerConfig {
+            format: AttributeFormat::new(AttributeType::Int, 1).unwrap(),
+            baseline: Some("ground".into()),
+            ..LayerConfig::default()
+        };
+        let layer = Layer::from_source(&LayerSource::image(directory.join("height.png")), config, &options).unwrap();
+        assert_eq!(layer.texels.encode(), [40i8, -30].map(|value| value as u8));
+    }
+
+    /// Sources of 2x1 layers named after the stems, in the directory.
+    fn png_sources(directory: &TempDirectory, stems: &[&str]) -> Vec<LayerSource> {
+        stems.iter().enumerate().map(|(index, stem)| {
+            let path = directory.join(format!("{stem}.png"));
+            GrayImage::from_pixel(2, 1, Luma([index as u8])).save(&path).unwrap();
+            LayerSource::image(path)
+        }).collect()
+    }
+
+    #[test]
+    fn saves_the_resized_images_where_and_how_requested() {
+        let directory = TempDirectory::new();
+        let resized = directory.join("resized");
+        let mut options = LayerLoadOptions {
+            dimensions: LayerDimensions { width: 4, height: 2 },
+            save_resized: Some(ResizedImageOptions {
+                directory: Some(resized.clone()),
+                format: Some(ResizedImageFormat::Tiff),
+                overwrite: true,
+            }),
+            ..LayerLoadOptions::default()
+        };
+        let sources = png_sources(&directory, &["grass", "height"]);
+        init_layers(sources.clone(), &Manifest::default(), &options, false, |_, _| {}).unwrap();
+        let image = image::open(resized.join("height.tif")).unwrap();
+        assert_eq!(image.to_luma8().into_raw(), [1; 8]);
+        assert!(resized.join("grass.tif").is_file());
+
+        // Existing images are kept unless they're overwritten, e.g. after editing them by hand.
+        fs::write(resized.join("height.tif"), "edited").unwrap();
+        options.save_resized.as_mut().unwrap().overwrite = false;
+        init_layers(sources, &Manifest::default(), &options, false, |_, _| {}).unwrap();
+        assert_eq!(fs::read_to_string(resized.join("height.tif")).unwrap(), "edited");
+    }
+
+    #[test]
+    fn loads_the_layers_in_parallel_keeping_their_order() {
+        let directory = TempDirectory::new();
+        let stems = ["a", "b", "c", "d", "e"];
+        let options = LayerLoadOptions {
+            dimensions: LayerDimensions { width: 2, height: 1 },
+            threads: Some(3),
+            ..LayerLoadOptions::default()
+        };
+        let mut handed_over = vec![];
+        let loaded = init_layers(png_sources(&directory, &stems), &Manifest::default(), &options, false, |index, _| {
+            handed_over.push(index);
+        }).unwrap();
+        let names: Vec<&str> = loaded.layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, stems);
+        assert_eq!(loaded.layers[3].texels.encode(), [3, 3]);
+        handed_over.sort();
+        assert_eq!(handed_over, [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn holds_only_the_resident_layers_at_once() {
+        let directory = TempDirectory::new();
+        let options = LayerLoadOptions {
+            dimensions: LayerDimensions { width: 2, height: 1 },
+            threads: Some(4),
+            resident_layers: Some(1),
+            ..LayerLoadOptions::default()
+        };
+        // With a single resident layer, the next one is only decoded once the previous one has been handed over.
+        let mut handed_over = vec![];
+        let sources = png_sources(&directory, &["a", "b", "c", "d", "e", "f"]);
+        init_layers(sources, &Manifest::default(), &options, false, |index, layer| {
+            handed_over.push(index);
+            layer.texels.release();
+        }).unwrap();
+        assert_eq!(handed_over, [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn leaves_out_the_layers_which_fail_to_load_unless_strict() {
+        let directory = TempDirectory::new();
+        let sources = png_sources(&directory, &["a", "b", "c"]);
+        fs::write(&sources[1].path, b"not a png").unwrap();
+        let options = LayerLoadOptions {
+            dimensions: LayerDimensions { width: 2, height: 1 },
+            threads: Some(2),
+            ..LayerLoadOptions::default()
+        };
+        let loaded = init_layers(sources.clone(), &Manifest::default(), &options, false, |_, _| {}).unwrap();
+        let names: Vec<&str> = loaded.layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["a", "c"]);
+        assert_eq!(loaded.failures.len(), 1);
+        assert_eq!((loaded.failures[0].index, &loaded.failures[0].source), (1, &sources[1]));
+
+        let strict = LayerLoadOptions { strict: true, ..options };
+        let error = init_layers(sources.clone(), &Manifest::default(), &strict, true, |_, _| {}).err().unwrap();
+        assert!(error.to_string().starts_with(&format!("Could not load layer {}", sources[1].path.display())), "{error}");
+    }
+}
@@ -0,0 +1,53 @@
+pub mod alpha;
+pub mod base64;
+pub mod builder;
+pub mod cache;
+pub mod cancel;
+pub mod channels;
+pub mod classify;
+pub mod comparison;
+pub mod diff;
+pub mod distance;
+pub mod dump;
+pub mod edit;
+pub mod export;
+pub mod expression;
+pub mod format;
+pub mod geotiff;
+pub mod hooks;
+pub mod http;
+pub mod ignore;
+pub mod json;
+pub mod layer;
+pub mod lock;
+pub mod log;
+pub mod manifest;
+pub mod mmap;
+pub mod model;
+pub mod mosaic;
+pub mod naming;
+pub mod nodata;
+pub mod normalize;
+pub mod pipeline;
+pub mod preprocess;
+pub mod preset;
+pub mod progress;
+pub mod reader;
+pub mod regions;
+pub mod reload;
+pub mod resample;
+pub mod sidecar;
+pub mod split;
+pub mod summary;
+pub mod system;
+#[cfg(test)]
+mod testing;
+pub mod texels;
+pub mod tiled;
+pub mod timestamp;
+pub mod toml;
+pub mod tui;
+pub mod verify;
+pub mod visualize;
+pub mod writer;
+pub mod xml;
@@ -0,0 +1,186 @@
+//! Lock files keeping concurrent runs from writing the same output at the same time.
+
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::info;
+use crate::system::host_name;
+use crate::timestamp::current_unix_time;
+
+/// Time between the attempts to take a lock held by another run.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Run holding a lock, as recorded in the lock file.
+#[derive(Clone, PartialEq, Debug)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub host: String,
+    /// Unix time the lock was taken at.
+    pub started: u64,
+}
+
+impl LockHolder {
+    fn current() -> LockHolder {
+        LockHolder { pid: std::process::id(), host: host_name().unwrap_or_default(), started: current_unix_time() }
+    }
+
+    fn parse(text: &str) -> Option<LockHolder> {
+        let field = |key: &str| text.lines().find_map(|line| line.strip_prefix(key)?.strip_prefix('='));
+        Some(LockHolder {
+            pid: field("pid")?.parse().ok()?,
+            host: field("host")?.to_string(),
+            started: field("started")?.parse().ok()?,
+        })
+    }
+
+    fn to_text(&self) -> String {
+        format!("pid={}\nhost={}\nstarted={}\n", self.pid, self.host, self.started)
+    }
+}
+
+impl std::fmt::Display for LockHolder {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let age = current_unix_time().saturating_sub(self.started);
+        write!(formatter, "process {} on {} (since {age} seconds ago)", self.pid, self.host)
+    }
+}
+
+/// Lock file of an output, `<output>.lock`, locked by the operating system while it's held. The system releases the
+/// lock when the process exits, even when it crashes, so a lock file left behind doesn't block later runs.
+pub struct OutputLock {
+    path: PathBuf,
+    /// Holds the lock until it's dropped.
+    _file: File,
+}
+
+impl OutputLock {
+    pub fn path_of(output: &Path) -> PathBuf {
+        let mut path = output.as_os_str().to_owned();
+        path.push(".lock");
+        PathBuf::from(path)
+    }
+
+    /// Takes the lock of the output. A lock held by another run is waited for up to `wait`, then taking it fails
+    /// naming its holder, as recorded in the lock file.
+    pub fn acquire(output: &Path, wait: Duration) -> io::Result<OutputLock> {
+        let path = OutputLock::path_of(output);
+        let deadline = Instant::now() + wait;
+        let mut waiting = false;
+        loop {
+            let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path)?;
+            match file.try_lock() {
+                Ok(()) if is_same_file(&file, &path) => {
+                    file.set_len(0)?;
+                    file.write_all(LockHolder::current().to_text().as_bytes())?;
+                    return Ok(OutputLock { path, _file: file });
+                }
+                // The previous holder removed the file after it was opened, the next attempt opens the new one.
+                Ok(()) => continue,
+                Err(TryLockError::WouldBlock) => {}
+                Err(TryLockError::Error(error)) => return Err(error),
+            }
+            drop(file);
+
+            // The holder may still be writing its details.
+            let holder = fs::read_to_string(&path).ok().and_then(|text| LockHolder::parse(&text));
+            let holder = holder.map_or("another run".to_string(), |holder| holder.to_string());
+            if Instant::now() >= deadline {
+                return Err(io::Error::new(io::ErrorKind::WouldBlock, format!(
+                    "{} is being written by {holder}, see its lock file {}", output.display(), path.display()
+                )));
+            }
+            if !waiting {
+                info!("Waiting for {holder} to finish writing {}...", output.display());
+                waiting = true;
+            }
+            thread::sleep(POLL_INTERVAL.min(deadline.saturating_duration_since(Instant::now())));
+        }
+    }
+}
+
+/// Whether the locked file is still the one at the path, which its previous holder may have removed after it was
+/// opened. Lock files are only removed on Unix, where open files can be told apart by their inode.
+#[cfg(unix)]
+fn is_same_file(file: &File, path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(locked), Ok(current)) => (locked.dev(), locked.ino()) == (current.dev(), current.ino()),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_file: &File, _path: &Path) -> bool {
+    true
+}
+
+impl Drop for OutputLock {
+    fn drop(&mut self) {
+        // Removed while it's still locked, so runs waiting for it retry with a new file. Other platforms keep the
+        // file, as runs which opened it before couldn't tell that it was removed.
+        if cfg!(unix) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    #[test]
+    fn excludes_other_runs_until_released() {
+        let directory = TempDirectory::new();
+        let output = directory.join("out.nsd");
+        let lock = OutputLock::acquire(&output, Duration::ZERO).unwrap();
+        let holder = LockHolder::parse(&fs::read_to_string(OutputLock::path_of(&output)).unwrap()).unwrap();
+        assert_eq!(holder.pid, std::process::id());
+
+        let error = OutputLock::acquire(&output, Duration::ZERO).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        assert!(error.to_string().contains(&format!("process {}", holder.pid)), "{error}");
+
+        drop(lock);
+        assert_eq!(OutputLock::path_of(&output).exists(), !cfg!(unix));
+        drop(OutputLock::acquire(&output, Duration::ZERO).unwrap());
+    }
+
+    #[test]
+    fn takes_over_lock_files_left_behind() {
+        let directory = TempDirectory::new();
+        let output = directory.join("out.nsd");
+        let holder = LockHolder { pid: u32::MAX, host: "elsewhere".into(), started: 0 };
+        fs::write(OutputLock::path_of(&output), holder.to_text()).unwrap();
+        let _lock = OutputLock::acquire(&output, Duration::ZERO).unwrap();
+        let text = fs::read_to_string(OutputLock::path_of(&output)).unwrap();
+        assert_eq!(LockHolder::parse(&text).unwrap().pid, std::process::id());
+    }
+
+    #[test]
+    fn is_held_by_one_run_at_a_time() {
+        let directory = TempDirectory::new();
+        let output = directory.join("out.nsd");
+        let holders = Arc::new(AtomicUsize::new(0));
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                let (output, holders) = (&output, holders.clone());
+                scope.spawn(move || {
+                    for _ in 0..5 {
+                        let _lock = OutputLock::acquire(output, Duration::from_secs(60)).unwrap();
+                        assert_eq!(holders.fetch_add(1, Ordering::SeqCst), 0, "Two runs held the lock");
+                        thread::yield_now();
+                        holders.fetch_sub(1, Ordering::SeqCst);
+                    }
+                });
+            }
+        });
+    }
+}
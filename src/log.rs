@@ -0,0 +1,133 @@
+//! Progress messages of the generator. They go to the standard output, unless a mode uses it for something else.
+//! Warnings go to the standard error and can also be recorded for a machine-readable report.
+
+use std::fmt;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::json::JsonValue;
+
+static TO_STDERR: AtomicBool = AtomicBool::new(false);
+
+/// Number of warnings printed by the process.
+static WARNINGS: AtomicUsize = AtomicUsize::new(0);
+
+/// Diagnostics of the current run, while they're being recorded.
+static DIAGNOSTICS: Mutex<Option<Vec<Diagnostic>>> = Mutex::new(None);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// A warning or error of a run, with the file it concerns and the phase it happened in (e.g. `discovery`,
+/// `loading`, `cache`, `writing`).
+#[derive(Clone, Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub file: Option<PathBuf>,
+    pub phase: &'static str,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::object()
+            .with("severity", self.severity.name())
+            .with("file", self.file.as_ref().map(|file| file.to_string_lossy().into_owned()))
+            .with("phase", self.phase)
+            .with("message", self.message.as_str())
+    }
+}
+
+/// Records every diagnostic from now on, until `take_diagnostics`. Runs in parallel share the record.
+pub fn record_diagnostics() {
+    *DIAGNOSTICS.lock().unwrap() = Some(vec![]);
+}
+
+/// Stops recording and returns the diagnostics recorded so far.
+pub fn take_diagnostics() -> Vec<Diagnostic> {
+    DIAGNOSTICS.lock().unwrap().take().unwrap_or_default()
+}
+
+/// Adds a diagnostic to the record, if one is being recorded, without printing it.
+pub fn record(severity: Severity, file: Option<&Path>, phase: &'static str, message: String) {
+    if let Some(diagnostics) = DIAGNOSTICS.lock().unwrap().as_mut() {
+        diagnostics.push(Diagnostic { severity, file: file.map(Path::to_path_buf), phase, message });
+    }
+}
+
+/// Prints a warning to the standard error and records it.
+pub fn warning(file: Option<&Path>, phase: &'static str, message: fmt::Arguments) {
+    let message = message.to_string();
+    eprintln!("{message}");
+    WARNINGS.fetch_add(1, Ordering::Relaxed);
+    record(Severity::Warning, file, phase, message);
+}
+
+/// Number of warnings printed so far. Runs in parallel share the count.
+pub fn warning_count() -> usize {
+    WARNINGS.load(Ordering::Relaxed)
+}
+
+/// Prints the progress messages to the standard error from now on.
+pub fn redirect_to_stderr() {
+    TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+pub fn print(message: fmt::Arguments) {
+    // Like `println!`, but a closed output isn't worth stopping the generation for.
+    let _ = match TO_STDERR.load(Ordering::Relaxed) {
+        true => writeln!(io::stderr().lock(), "{message}"),
+        false => writeln!(io::stdout().lock(), "{message}"),
+    };
+}
+
+/// `println!` for progress messages, see `log::print`.
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::print(format_args!($($arg)*))
+    };
+}
+
+/// `eprintln!` for warnings about a file (an `Option<&Path>`) in a phase of the run, see `log::warning`.
+#[macro_export]
+macro_rules! warning {
+    ($file:expr, $phase:expr, $($arg:tt)*) => {
+        $crate::log::warning($file, $phase, format_args!($($arg)*))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_diagnostics_until_taken() {
+        record_diagnostics();
+        record(Severity::Error, Some(Path::new("height.png")), "loading", "Could not load layer height".into());
+        // Other tests may warn at the same time, so only this diagnostic is looked for.
+        let diagnostics = take_diagnostics();
+        let diagnostic = diagnostics.iter().find(|diagnostic| diagnostic.message == "Could not load layer height").unwrap();
+        assert_eq!(
+            diagnostic.to_json().to_string(),
+            r#"{"severity":"error","file":"height.png","phase":"loading","message":"Could not load layer height"}"#
+        );
+
+        record(Severity::Warning, None, "writing", "Not recorded".into());
+        assert!(take_diagnostics().is_empty());
+    }
+}
@@ -1,299 +1,2877 @@
-use std::{fs, io};
-use std::io::Write;
-use std::os::windows::fs::MetadataExt;
-use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsString;
+use std::fs;
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Write};
+use std::net::{IpAddr, TcpListener, TcpStream};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::process::exit;
-use std::sync::mpsc;
-use std::time::Instant;
-
-use clap::{Parser, ArgAction};
-use flate2::Compression;
-use flate2::write::ZlibEncoder;
-use image::{DynamicImage, GenericImageView};
-use image::imageops::FilterType;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::{ArgMatches, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ArgAction, ValueEnum};
+use clap::parser::ValueSource;
+use clap::builder::BoolishValueParser;
 use thousands::Separable;
-use threadpool::ThreadPool;
 
-const NSD_HEADER: [u8; 16] = [
-    0x4E, 0x53, 0x47, 0xFF, 0x53, 0x70, 0x61, 0x74, 0x69, 0x61, 0x6C, 0x00, 0x00, 0x00, 0x00, 0x00
-];
-const NSD_DIM_HEADER: [u8; 4] = [
-    0x44, 0x49, 0x4D, 0xFA
-];
-const NSD_ATTR_HEADER: [u8; 4] = [
-    0x41, 0x54, 0x52, 0xFA
-];
-const NSD_DATA_HEADER: [u8; 4] = [
-    0x44, 0x41, 0x54, 0xFA
-];
+use nsdgen::cache::{DecodedSources, LayerCache, CHECKPOINT_DIRECTORY_NAME, DEFAULT_CACHE_DIRECTORY_NAME};
+use nsdgen::cancel::{cancel_on_interrupt, CancellableWriter, CancellationToken};
+use nsdgen::channels::ChannelMap;
+use nsdgen::classify::ColorClassification;
+use nsdgen::diff::diff_structure;
+use nsdgen::dump::dump;
+use nsdgen::edit::{edit_file, EditOperation};
+use nsdgen::export::export_houdini;
+use nsdgen::format::{AttributeFormat, AttributeType, NsdExtensions, NsdWorldScale, TexelOrder};
+use nsdgen::geotiff::common_georeference;
+use nsdgen::hooks::run_hook;
+use nsdgen::http::{post, read_request, write_response, Request};
+use nsdgen::lock::OutputLock;
+use nsdgen::log::{self, Diagnostic, Severity};
+use nsdgen::{info, warning};
+use nsdgen::json::JsonValue;
+use nsdgen::layer::{
+    init_layers, load_masks, load_references, read_layer_files, sort_layer_sources, split_alpha_sources, DecodeLimits, Layer, LayerDimensions,
+    LayerFailure, LayerLoadOptions, LayerRegion, LayerSection, LayerSource, LoadedLayers, ResizedImageFormat, ResizedImageOptions,
+    DEFAULT_MAX_DECODE_MEMORY, DEFAULT_MAX_SOURCE_DIMENSION
+};
+use nsdgen::manifest::{Manifest, OutputConfig};
+use nsdgen::tui::{TuiCommand, TuiSession, TUI_HELP};
+use nsdgen::model::{data_files, file_from_json, file_to_json, DataEncoding};
+use nsdgen::normalize::PercentileRange;
+use nsdgen::preset::InputPreset;
+use nsdgen::progress::{ProgressCallback, ProgressEvent, ProgressWriter};
+use nsdgen::mmap::MappedFile;
+use nsdgen::naming::{directory_name, OutputNameFields, OutputTemplate};
+use nsdgen::reader::{NsdFileView, NsdReader};
+use nsdgen::resample::{ResizeFilter, ValueKind};
+use nsdgen::sidecar::{write_unreal_descriptor, DescriptorOptions};
+use nsdgen::split::{plan_split, write_split};
+use nsdgen::summary::{format_table, read_sizes, write_summary, OutputSummary};
+use nsdgen::timestamp::{current_unix_time, format_date};
+use nsdgen::verify::{compare_files, verify_file};
+use nsdgen::visualize::{self, Colormap, ValueRange, VisualizeOptions};
+use nsdgen::system::{available_disk_space, available_memory};
+use nsdgen::writer::{aligned_row_stride, estimate_file_size, texel_stride, write_binary, write_parsed_file, DataPacker};
+
+#[derive(Parser)]
+#[clap(disable_help_flag = true, args_conflicts_with_subcommands = true)]
+struct CliArgs {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Read newline-delimited JSON-RPC requests from the standard input and answer on the standard output,
+    /// for driving nsdgen from an editor
+    #[arg(long, exclusive = true, default_value_t = false)]
+    ipc: bool,
+
+    // Running without a command is the same as running `generate`
+    #[command(flatten)]
+    generate: Option<GenerateArgs>,
+}
+
+impl CliArgs {
+    fn try_parse_command_line<I, T>(command_line: I) -> Result<CliArgs, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<OsString> + Clone,
+    {
+        CliArgs::from_matches(&CliArgs::command().try_get_matches_from(command_line)?)
+    }
+
+    /// Options set in the environment make the generate arguments of the top level look given, which would fail
+    /// on their missing directory, so they're only read without a command.
+    fn from_matches(matches: &ArgMatches) -> Result<CliArgs, clap::Error> {
+        match matches.subcommand() {
+            Some(_) => Ok(CliArgs { command: Some(Command::from_arg_matches(matches)?), ipc: false, generate: None }),
+            None => CliArgs::from_arg_matches(matches),
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Generate a spatial data file from the layers in a directory
+    Generate(GenerateArgs),
+    /// Print the structure and metadata of a spatial data file
+    Inspect(InspectArgs),
+    /// Convert a spatial data file into a format used by other tools
+    Export(ExportArgs),
+    /// Run a local HTTP API which generates spatial data files on request
+    Serve(ServeArgs),
+    /// Generate in memory and compare the result against a golden spatial data file
+    Check(CheckArgs),
+    /// Describe a spatial data file as JSON, with its texel data in raw files next to it
+    ToJson(ToJsonArgs),
+    /// Build a spatial data file from its JSON description
+    FromJson(FromJsonArgs),
+    /// Print every chunk of a spatial data file with its offset, size, decoded fields and first bytes
+    Dump(DumpArgs),
+    /// Compare two spatial data files chunk by chunk
+    Diff(DiffArgs),
+    /// Fix the header, dimensions or attributes of a spatial data file without repacking its texel data
+    Edit(EditArgs),
+    /// Render an attribute of a spatial data file as a false-color PNG
+    Visualize(VisualizeArgs),
+    /// Choose, reorder and configure the layers of a directory interactively, then generate
+    Tui(TuiArgs),
+}
+
+#[derive(Args, Clone)]
+struct GenerateArgs {
+    #[arg(long, action = ArgAction::Help, help = "Show help")]
+    help: Option<bool>,
+
+    /// Input directory which contains the layer files.
+    #[arg()]
+    directory: PathBuf,
+
+    /// Output file name (placed inside the specified input directory, defaults to the directory name with .nsd)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Directory the output is placed in instead of the input directory, created if it doesn't exist
+    #[arg(long, env = "NSDGEN_OUTPUT_DIR", value_name = "DIRECTORY")]
+    output_dir: Option<PathBuf>,
+
+    /// Directory of intermediate files (resized images, checkpoints, preprocessed images and the output while it's
+    /// written) instead of the input and output directories, e.g. when the sources are on a read-only share
+    #[arg(long, env = "NSDGEN_TEMP_DIR", value_name = "DIRECTORY")]
+    temp_dir: Option<PathBuf>,
+
+    /// Directory of the layer cache (defaults to .nsdcache inside the input directory)
+    #[arg(long, env = "NSDGEN_CACHE_DIR", value_name = "DIRECTORY")]
+    cache_dir: Option<PathBuf>,
+
+    /// Output file name with the placeholders {dir}, {width}, {height}, {layers} and {date} (YYYY-MM-DD),
+    /// e.g. "{dir}_{width}x{height}.nsd"
+    #[arg(long, value_name = "TEMPLATE", conflicts_with = "output")]
+    output_template: Option<OutputTemplate>,
+
+    /// Manifest file with additional settings (defaults to nsdgen.toml inside the input directory, if present)
+    #[arg(short, long, env = "NSDGEN_MANIFEST")]
+    manifest: Option<PathBuf>,
+
+    /// Channel map naming the layers packed into the channels of source files
+    /// (defaults to channels.toml inside the input directory, if present)
+    #[arg(long, value_name = "FILE")]
+    channels: Option<PathBuf>,
+
+    /// Skip symbolic links among the sources instead of loading the files they point to
+    #[arg(long, default_value_t = false)]
+    no_follow_symlinks: bool,
+
+    /// Recognize the default output names of a terrain generator and map them to attributes
+    #[arg(long, value_enum)]
+    input_preset: Option<InputPresetArg>,
+
+    /// Defaults of a workflow for the filters, size, attribute types, normalization and output name, used for the
+    /// options which aren't given
+    #[arg(long, value_enum)]
+    preset: Option<PresetArg>,
+
+    /// Only store these layers (attribute names, comma-separated)
+    #[arg(long, value_delimiter = ',', value_name = "NAMES")]
+    layers: Vec<String>,
+
+    /// Leave these layers (attribute names, comma-separated) out
+    #[arg(long, value_delimiter = ',', value_name = "NAMES")]
+    skip_layers: Vec<String>,
+
+    /// TOML file mapping the colors of color-coded layers to the IDs stored for them
+    #[arg(long)]
+    classify: Option<PathBuf>,
+
+    /// Texture width will be set to 2^wpower (min=0, max=12) [default: 10]
+    #[arg(short, long, value_parser = clap::value_parser!(u8).range(0..=12), value_name = "WIDTH_POWER")]
+    wpower: Option<u8>,
+
+    /// Texture height will be set to 2^hpower (min=0, max=12) [default: 9]
+    #[arg(short, long, value_parser = clap::value_parser!(u8).range(0..=12), value_name = "HEIGHT_POWER")]
+    hpower: Option<u8>,
+
+    /// Derive the texture height from the width by an aspect ratio WIDTH:HEIGHT (e.g. 2:1) instead of --hpower
+    #[arg(long, value_parser = parse_aspect, value_name = "RATIO", conflicts_with_all = ["hpower", "auto_size"])]
+    aspect: Option<(u32, u32)>,
+
+    /// Size the texture like the largest source of the main section (or the region) instead of using
+    /// --wpower/--hpower, optionally snapped to a power of two
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "exact", conflicts_with_all = ["wpower", "hpower"])]
+    auto_size: Option<AutoSizeArg>,
+
+    /// Filter used for layers smaller than the target size (defaults to nearest, or the filter of the --preset)
+    #[arg(long, value_enum, env = "NSDGEN_FILTER")]
+    upscale_filter: Option<ResizeFilterArg>,
+
+    /// Filter used for layers larger than the target size (defaults to nearest, or the filter of the --preset)
+    #[arg(long, value_enum, env = "NSDGEN_FILTER")]
+    downscale_filter: Option<ResizeFilterArg>,
+
+    /// Also store the alpha channel of every image with one as a separate <name>_alpha layer
+    #[arg(long, default_value_t = false)]
+    split_alpha: bool,
+
+    /// Only pack this window of the source layers, given in source pixels as x,y,width,height
+    #[arg(long, value_name = "X,Y,WIDTH,HEIGHT")]
+    region: Option<LayerRegion>,
+
+    /// Stretch the values between these percentiles of every continuous layer to the full range, clipping the rest
+    #[arg(long, value_name = "LOW,HIGH")]
+    normalize_percentile: Option<PercentileRange>,
+
+    /// Shell command producing each layer's image from its source, run before decoding. The source path is in the
+    /// NSDGEN_INPUT environment variable; the image is read from the file NSDGEN_OUTPUT if the command writes it,
+    /// or from stdout
+    #[arg(long, env = "NSDGEN_PREPROCESS", value_name = "COMMAND")]
+    preprocess: Option<String>,
+
+    /// Variable available to the expressions and include conditions of the manifest (repeatable)
+    #[arg(long = "define", value_name = "NAME=VALUE", value_parser = parse_define)]
+    defines: Vec<(String, f64)>,
+
+    /// Order of the texels in the data chunk
+    #[arg(long, value_enum, default_value_t = TexelOrderArg::RowMajor)]
+    order: TexelOrderArg,
+
+    /// Pad every row of the texel data to a multiple of this many bytes, e.g. for uploading it as a GPU buffer
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), value_name = "BYTES")]
+    row_alignment: Option<u32>,
+
+    /// Fail unless the sources of every section have the same resolution, instead of resampling them all
+    #[arg(long, default_value_t = false)]
+    require_uniform_source: bool,
+
+    /// Maximum number of layers of every section, which is the number of attributes consumers of the file support
+    #[arg(long, env = "NSDGEN_MAX_LAYERS", default_value_t = 32, value_parser = clap::value_parser!(u32).range(1..), value_name = "COUNT")]
+    max_layers: u32,
+
+    /// Split the output into a grid of files with an index when its texel data exceeds this many bytes.
+    /// Larger files than the format's limit of 4 GiB are always split
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..), value_name = "BYTES")]
+    max_data_size: Option<u64>,
+
+    /// Save every resized layer as an image, with a contact sheet comparing them to their sources
+    #[arg(long, default_value_t = false)]
+    save_resized: bool,
+
+    /// Directory of the resized images (defaults to _resized next to the sources or in --temp-dir)
+    #[arg(long, requires = "save_resized", value_name = "DIRECTORY")]
+    resized_dir: Option<PathBuf>,
+
+    /// File format of the resized images (defaults to the format of the source)
+    #[arg(long, requires = "save_resized", value_enum)]
+    resized_format: Option<ResizedFormatArg>,
+
+    /// What happens to resized images which already exist
+    #[arg(long, requires = "save_resized", value_enum, default_value_t = ExistingResizedArg::Overwrite)]
+    existing_resized: ExistingResizedArg,
+
+    /// Measure what downscaling loses from every layer by upscaling it back, warning about the layers losing more
+    /// than this fraction (of the range on average, or of the texels changed for categorical and mask layers)
+    #[arg(long, num_args = 0..=1, default_missing_value = "0.05", value_name = "THRESHOLD")]
+    validate_resize: Option<f64>,
+
+    #[arg(long, default_value_t = false)]
+    run_sequential: bool,
+
+    /// Number of threads loading the layers (defaults to the number of CPU threads)
+    #[arg(long, env = "NSDGEN_THREADS", value_parser = clap::value_parser!(u32).range(1..), value_name = "COUNT")]
+    threads: Option<u32>,
+
+    /// Write every warning and error of the run with its file and phase to this JSON file
+    #[arg(long, env = "NSDGEN_ERROR_REPORT", value_name = "FILE")]
+    error_report: Option<PathBuf>,
+
+    /// Fail as soon as a layer can't be loaded, instead of leaving it out and listing it at the end of the run
+    #[arg(long, env = "NSDGEN_STRICT", default_value_t = false, value_parser = BoolishValueParser::new())]
+    strict: bool,
+
+    /// Maximum number of layers decoded at once and waiting to be packed, which bounds the peak memory
+    /// (defaults to the number of threads)
+    #[arg(long, env = "NSDGEN_RESIDENT_LAYERS", value_parser = clap::value_parser!(u32).range(1..), value_name = "COUNT")]
+    resident_layers: Option<u32>,
+
+    /// Largest width and height of a source image, larger ones fail to load before they're decoded
+    #[arg(long, default_value_t = DEFAULT_MAX_SOURCE_DIMENSION, value_parser = clap::value_parser!(u32).range(1..), value_name = "PIXELS")]
+    max_source_dimension: u32,
+
+    /// Largest amount of memory the decoder of a source image may allocate
+    #[arg(long, default_value_t = DEFAULT_MAX_DECODE_MEMORY, value_parser = clap::value_parser!(u64).range(1..), value_name = "BYTES")]
+    max_decode_memory: u64,
+
+    /// Cache resized layers in .nsdcache inside the input directory and reuse them when their sources didn't change
+    #[arg(long, env = "NSDGEN_CACHE", default_value_t = false, value_parser = BoolishValueParser::new())]
+    cache: bool,
+
+    /// Re-open the generated file and compare it against the in-memory data
+    #[arg(long, default_value_t = false)]
+    verify: bool,
+
+    /// Keep the loaded layers and the written tiles of split outputs until the run succeeds, so running it again
+    /// after an interruption continues where it stopped
+    #[arg(long, default_value_t = false)]
+    resume: bool,
+
+    /// Write the output even if the file system of the output doesn't have room for the largest size it could have
+    #[arg(long, default_value_t = false)]
+    skip_space_check: bool,
+
+    /// Seconds to wait for another run writing the same output to finish, instead of failing right away
+    #[arg(long, default_value_t = 0, value_name = "SECONDS")]
+    wait_for_lock: u64,
+
+    /// Command run after each output is generated, e.g. to copy it into the content directory of the engine, with
+    /// the path of the output in the NSDGEN_OUTPUT environment variable and the input directory in NSDGEN_DIR
+    #[arg(long, env = "NSDGEN_ON_SUCCESS", value_name = "COMMAND")]
+    on_success: Option<String>,
+
+    /// Command run after a generation fails, with the input directory in the NSDGEN_DIR environment variable and the
+    /// error in NSDGEN_ERROR
+    #[arg(long, env = "NSDGEN_ON_FAILURE", value_name = "COMMAND")]
+    on_failure: Option<String>,
+
+    /// http:// URL a JSON summary of the run (outputs, sizes, durations and failures) is POSTed to when it completes.
+    /// https:// isn't supported, services which require it need a local relay
+    #[arg(long, env = "NSDGEN_NOTIFY_URL", value_parser = parse_notify_url, value_name = "URL")]
+    notify_url: Option<String>,
+
+    /// Write the size, layer count, duration and warnings of every output to this CSV (.csv) or JSON file. Sizes are
+    /// compared against the summary of the previous run, if the file exists
+    #[arg(long, value_name = "FILE")]
+    summary: Option<PathBuf>,
+
+    /// Plan the output without loading the layers or writing anything, reporting its size and the memory needed
+    #[arg(long, default_value_t = false)]
+    dry_run: bool,
+
+    /// Write a JSON import descriptor for the Unreal Engine importer next to the output file
+    #[arg(long, default_value_t = false)]
+    ue_descriptor: bool,
+
+    /// Leave machine- and time-dependent values out of generated files, so identical inputs produce identical bytes
+    #[arg(long, default_value_t = false)]
+    reproducible: bool,
+
+    /// Unix time recorded as the generation time (defaults to SOURCE_DATE_EPOCH or the current time)
+    #[arg(long, value_name = "UNIX_SECONDS")]
+    timestamp: Option<u64>,
+
+    /// Output of the manifest generated by this run, when the manifest has several.
+    #[arg(skip)]
+    output_config: Option<OutputConfig>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum InputPresetArg {
+    /// Gaea build outputs (height, flow, wear, deposits, ...)
+    Gaea,
+    /// World Machine output devices (heightfield, flow, wear, deposition, ...)
+    WorldMachine,
+}
+
+impl From<InputPresetArg> for InputPreset {
+    fn from(preset: InputPresetArg) -> Self {
+        match preset {
+            InputPresetArg::Gaea => InputPreset::Gaea,
+            InputPresetArg::WorldMachine => InputPreset::WorldMachine,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum PresetArg {
+    /// Landscape heightmaps: uint 2, stretched to the full range, cubic upscaling, sides rounded up to a power of two
+    UnrealLandscape,
+    /// Splatmap weights: byte 1, linear upscaling, the size of the sources
+    Splatmap,
+    /// GIS rasters: float 4 keeping the source values, linear upscaling, the size of the sources, dated names
+    Gis,
+}
+
+/// Settings of a preset, used for the options which aren't given explicitly.
+struct PresetDefaults {
+    upscale_filter: ResizeFilter,
+    downscale_filter: ResizeFilter,
+    /// Used unless a size is given.
+    auto_size: AutoSizeArg,
+    /// Format of the layers which the manifest doesn't configure.
+    format: AttributeFormat,
+    normalize: Option<PercentileRange>,
+    /// Used unless an output name is given.
+    output_template: &'static str,
+}
+
+impl GenerateArgs {
+    fn cache_directory(&self) -> PathBuf {
+        self.cache_dir.clone().unwrap_or_else(|| self.directory.join(DEFAULT_CACHE_DIRECTORY_NAME))
+    }
+
+    fn checkpoint_directory(&self) -> PathBuf {
+        self.temp_dir.as_ref().unwrap_or(&self.directory).join(CHECKPOINT_DIRECTORY_NAME)
+    }
+}
+
+impl PresetArg {
+    fn defaults(&self) -> PresetDefaults {
+        match self {
+            PresetArg::UnrealLandscape => PresetDefaults {
+                upscale_filter: ResizeFilter::CatmullRom,
+                downscale_filter: ResizeFilter::Area,
+                auto_size: AutoSizeArg::Up,
+                format: AttributeFormat { attribute_type: AttributeType::UInt, size: 2 },
+                normalize: Some(PercentileRange { low: 0.0, high: 100.0 }),
+                output_template: "{dir}_{width}x{height}.nsd",
+            },
+            PresetArg::Splatmap => PresetDefaults {
+                upscale_filter: ResizeFilter::Triangle,
+                downscale_filter: ResizeFilter::Area,
+                auto_size: AutoSizeArg::Exact,
+                format: AttributeFormat { attribute_type: AttributeType::Byte, size: 1 },
+                normalize: None,
+                output_template: "{dir}_splat_{width}x{height}.nsd",
+            },
+            PresetArg::Gis => PresetDefaults {
+                upscale_filter: ResizeFilter::Triangle,
+                downscale_filter: ResizeFilter::Area,
+                auto_size: AutoSizeArg::Exact,
+                format: AttributeFormat { attribute_type: AttributeType::Float, size: 4 },
+                normalize: None,
+                output_template: "{dir}_{date}.nsd",
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AutoSizeArg {
+    /// The size of the largest source
+    Exact,
+    /// Every side rounded up to the next power of two
+    Up,
+    /// Every side rounded down to the previous power of two
+    Down,
+}
+
+impl AutoSizeArg {
+    fn snap(&self, side: u32) -> u32 {
+        match self {
+            AutoSizeArg::Exact => side,
+            AutoSizeArg::Up => side.next_power_of_two(),
+            AutoSizeArg::Down => 1 << side.ilog2(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ResizeFilterArg {
+    Nearest,
+    /// Linear
+    Triangle,
+    /// Cubic
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+    /// Average of all covered source texels, keeps thin features when downscaling
+    Area,
+}
+
+impl From<ResizeFilterArg> for ResizeFilter {
+    fn from(filter: ResizeFilterArg) -> Self {
+        match filter {
+            ResizeFilterArg::Nearest => ResizeFilter::Nearest,
+            ResizeFilterArg::Triangle => ResizeFilter::Triangle,
+            ResizeFilterArg::CatmullRom => ResizeFilter::CatmullRom,
+            ResizeFilterArg::Gaussian => ResizeFilter::Gaussian,
+            ResizeFilterArg::Lanczos3 => ResizeFilter::Lanczos3,
+            ResizeFilterArg::Area => ResizeFilter::Area,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ResizedFormatArg {
+    Png,
+    Tiff,
+}
+
+impl From<ResizedFormatArg> for ResizedImageFormat {
+    fn from(format: ResizedFormatArg) -> Self {
+        match format {
+            ResizedFormatArg::Png => ResizedImageFormat::Png,
+            ResizedFormatArg::Tiff => ResizedImageFormat::Tiff,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ExistingResizedArg {
+    /// Replace them with the images of this run
+    Overwrite,
+    /// Keep them, e.g. when they have been edited
+    Skip,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum TexelOrderArg {
+    /// Rows follow each other (the default, expected by all readers)
+    RowMajor,
+    /// Columns follow each other, recorded in the DIM chunk
+    ColumnMajor,
+}
+
+impl From<TexelOrderArg> for TexelOrder {
+    fn from(order: TexelOrderArg) -> Self {
+        match order {
+            TexelOrderArg::RowMajor => TexelOrder::RowMajor,
+            TexelOrderArg::ColumnMajor => TexelOrder::ColumnMajor,
+        }
+    }
+}
+
+#[derive(Args)]
+struct InspectArgs {
+    #[arg(long, action = ArgAction::Help, help = "Show help")]
+    help: Option<bool>,
+
+    /// Spatial data file to inspect
+    #[arg()]
+    file: PathBuf,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    /// One EXR per attribute, named <stem>.<attribute>.exr, for Houdini heightfield layers
+    Houdini,
+}
+
+#[derive(Args)]
+struct ExportArgs {
+    #[arg(long, action = ArgAction::Help, help = "Show help")]
+    help: Option<bool>,
+
+    /// Spatial data file to export
+    #[arg()]
+    file: PathBuf,
+
+    #[arg(short, long, value_enum)]
+    format: ExportFormat,
+
+    /// Directory for the exported files (defaults to the directory of the input file)
+    #[arg(short, long)]
+    output_dir: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct TuiArgs {
+    #[arg(long, action = ArgAction::Help, help = "Show help")]
+    help: Option<bool>,
+
+    /// Input directory which contains the layer files
+    #[arg()]
+    directory: PathBuf,
+
+    /// Manifest file the changes start from (defaults to nsdgen.toml inside the input directory, if present)
+    #[arg(short, long)]
+    manifest: Option<PathBuf>,
+
+    /// Channel map naming the layers packed into the channels of source files
+    /// (defaults to channels.toml inside the input directory, if present)
+    #[arg(long, value_name = "FILE")]
+    channels: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct VisualizeArgs {
+    #[arg(long, action = ArgAction::Help, help = "Show help")]
+    help: Option<bool>,
+
+    /// Spatial data file to render
+    #[arg()]
+    file: PathBuf,
+
+    /// Attribute to render, of the main section or any other section
+    #[arg(long, value_name = "NAME")]
+    attr: String,
+
+    /// Colors of the values (defaults to viridis, categorical for categorical attributes and grayscale for masks)
+    #[arg(long, value_enum)]
+    colormap: Option<ColormapArg>,
+
+    /// Component of vector attributes to render
+    #[arg(long, default_value_t = 0)]
+    component: usize,
+
+    /// Values mapped to the ends of the colormap (defaults to the smallest and largest value with data)
+    #[arg(long, value_name = "LOW,HIGH")]
+    range: Option<ValueRange>,
+
+    /// Add the range, or the IDs of categorical attributes with their colors, below the image
+    #[arg(long)]
+    legend: bool,
+
+    /// PNG to write (defaults to <stem>.<attribute>.png next to the input file)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ColormapArg {
+    Viridis,
+    Magma,
+    Grayscale,
+    /// A distinct color per ID
+    Categorical,
+}
+
+impl From<ColormapArg> for Colormap {
+    fn from(colormap: ColormapArg) -> Self {
+        match colormap {
+            ColormapArg::Viridis => Colormap::Viridis,
+            ColormapArg::Magma => Colormap::Magma,
+            ColormapArg::Grayscale => Colormap::Grayscale,
+            ColormapArg::Categorical => Colormap::Categorical,
+        }
+    }
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    #[command(flatten)]
+    generate: GenerateArgs,
+
+    /// Golden spatial data file the generated data is compared against
+    #[arg(long, value_name = "GOLDEN_FILE")]
+    against: PathBuf,
+
+    /// Largest difference allowed between the values of a texel in continuous layers (categorical layers must match)
+    #[arg(long, default_value_t = 0.0)]
+    tolerance: f64,
+}
+
+#[derive(Args)]
+struct ToJsonArgs {
+    #[arg(long, action = ArgAction::Help, help = "Show help")]
+    help: Option<bool>,
+
+    /// Spatial data file to convert
+    #[arg()]
+    file: PathBuf,
+
+    /// JSON file to write (defaults to <stem>.json next to the input file). The texel data is written next to it
+    /// as <stem>.data and <stem>.<section>.data
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Embed the texel data as base64 instead of writing raw files
+    #[arg(long, default_value_t = false)]
+    embed: bool,
+}
+
+#[derive(Args)]
+struct FromJsonArgs {
+    #[arg(long, action = ArgAction::Help, help = "Show help")]
+    help: Option<bool>,
+
+    /// JSON description to convert, as written by `to-json`
+    #[arg()]
+    file: PathBuf,
+
+    /// Spatial data file to write (defaults to <stem>.nsd next to the input file)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Args)]
+struct DumpArgs {
+    #[arg(long, action = ArgAction::Help, help = "Show help")]
+    help: Option<bool>,
+
+    /// Spatial data file to dump
+    #[arg()]
+    file: PathBuf,
+
+    /// Number of bytes shown of the texel data and of unknown chunks
+    #[arg(short, long, default_value_t = 32)]
+    bytes: usize,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    #[arg(long, action = ArgAction::Help, help = "Show help")]
+    help: Option<bool>,
+
+    /// Spatial data file before the change
+    #[arg()]
+    old: PathBuf,
+
+    /// Spatial data file after the change
+    #[arg()]
+    new: PathBuf,
+
+    /// Also compare the values of every attribute, like `check`
+    #[arg(long, default_value_t = false)]
+    texels: bool,
+
+    /// Largest difference allowed between the values of a texel with --texels
+    #[arg(long, default_value_t = 0.0, requires = "texels")]
+    tolerance: f64,
+}
+
+#[derive(Args)]
+struct EditArgs {
+    #[arg(long, action = ArgAction::Help, help = "Show help")]
+    help: Option<bool>,
+
+    /// Spatial data file to edit
+    #[arg()]
+    file: PathBuf,
+
+    /// File to write the edited file to (defaults to editing the file in place)
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// New vendor of the header (exactly 3 characters)
+    #[arg(long)]
+    vendor: Option<String>,
+
+    /// New sub-identifier of the header (up to 12 characters)
+    #[arg(long)]
+    identifier: Option<String>,
+
+    /// New dimensions as WIDTHxHEIGHT, with the same number of texels
+    #[arg(long, value_parser = parse_dimensions)]
+    dimensions: Option<LayerDimensions>,
+
+    /// Change the type of an attribute as NAME=TYPE (byte, uint, int or float), reinterpreting its bytes
+    #[arg(long = "set-type", value_name = "NAME=TYPE", value_parser = parse_attribute_type)]
+    set_types: Vec<(String, AttributeType)>,
+
+    /// Change the size of an attribute as NAME=BYTES. The combined size of all attributes must stay the same
+    #[arg(long = "set-size", value_name = "NAME=BYTES", value_parser = parse_attribute_size)]
+    set_sizes: Vec<(String, u8)>,
+
+    /// Rename an attribute as OLD=NEW, after changing types and sizes
+    #[arg(long = "rename", value_name = "OLD=NEW", value_parser = parse_rename)]
+    renames: Vec<(String, String)>,
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    #[arg(long, action = ArgAction::Help, help = "Show help")]
+    help: Option<bool>,
+
+    /// Address and port the API listens on
+    #[arg(long, default_value = "127.0.0.1:7878")]
+    address: String,
+}
+
+fn parse_dimensions(dimensions: &str) -> Result<LayerDimensions, String> {
+    let invalid = || format!("Invalid dimensions {dimensions}, expected WIDTHxHEIGHT");
+    let (width, height) = dimensions.split_once('x').ok_or_else(invalid)?;
+    Ok(LayerDimensions {
+        width: width.parse().map_err(|_| invalid())?,
+        height: height.parse().map_err(|_| invalid())?,
+    })
+}
+
+fn parse_aspect(ratio: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("Invalid aspect ratio {ratio}, expected WIDTH:HEIGHT");
+    let (width, height) = ratio.split_once(':').ok_or_else(invalid)?;
+    let width: u32 = width.parse().map_err(|_| invalid())?;
+    let height: u32 = height.parse().map_err(|_| invalid())?;
+    if width == 0 || height == 0 {
+        return Err(invalid());
+    }
+    Ok((width, height))
+}
+
+fn parse_notify_url(url: &str) -> Result<String, String> {
+    if url.starts_with("https://") {
+        return Err("https:// URLs are not supported, POST to a local relay which forwards to them instead".into());
+    }
+    match url.strip_prefix("http://") {
+        Some(rest) if !rest.is_empty() && !rest.starts_with('/') => Ok(url.to_string()),
+        _ => Err(format!("Invalid URL {url}, expected http://host[:port]/path")),
+    }
+}
+
+fn parse_attribute_type(assignment: &str) -> Result<(String, AttributeType), String> {
+    let (name, type_name) = assignment.split_once('=').ok_or("Expected NAME=TYPE")?;
+    let attribute_type = AttributeType::from_name(type_name).ok_or_else(|| format!("Unknown attribute type {type_name}"))?;
+    Ok((name.to_string(), attribute_type))
+}
+
+fn parse_attribute_size(assignment: &str) -> Result<(String, u8), String> {
+    let (name, size) = assignment.split_once('=').ok_or("Expected NAME=BYTES")?;
+    Ok((name.to_string(), size.parse().map_err(|_| format!("Invalid size {size}"))?))
+}
+
+fn parse_rename(assignment: &str) -> Result<(String, String), String> {
+    let (from, to) = assignment.split_once('=').ok_or("Expected OLD=NEW")?;
+    Ok((from.to_string(), to.to_string()))
+}
+
+/// Parses a `--define` as `NAME=VALUE`.
+fn parse_define(define: &str) -> Result<(String, f64), String> {
+    let (name, value) = define.split_once('=')
+        .ok_or_else(|| format!("Invalid definition {define}, expected NAME=VALUE"))?;
+    let value = value.trim().parse::<f64>()
+        .map_err(|_| format!("Invalid value of {name}, expected a number"))?;
+    Ok((name.trim().to_string(), value))
+}
+
+fn main() {
+    let args = CliArgs::try_parse_command_line(std::env::args_os()).unwrap_or_else(|error| error.exit());
+
+    if args.ipc {
+        run_ipc();
+        return;
+    }
+
+    let result = match args.command {
+        Some(Command::Generate(generate_args)) => generate(generate_args, GenerateSession::interruptible()).map(|_| ()),
+        Some(Command::Inspect(inspect_args)) => {
+            inspect(inspect_args);
+            Ok(())
+        }
+        Some(Command::Export(export_args)) => {
+            export(export_args);
+            Ok(())
+        }
+        Some(Command::Serve(serve_args)) => serve(serve_args),
+        Some(Command::Check(check_args)) => check(check_args),
+        Some(Command::ToJson(to_json_args)) => to_json(to_json_args),
+        Some(Command::FromJson(from_json_args)) => from_json(from_json_args),
+        Some(Command::Dump(dump_args)) => dump_file(dump_args),
+        Some(Command::Diff(diff_args)) => diff(diff_args),
+        Some(Command::Edit(edit_args)) => edit(edit_args),
+        Some(Command::Visualize(visualize_args)) => visualize(visualize_args),
+        Some(Command::Tui(tui_args)) => tui(tui_args),
+        None => generate(args.generate.expect("Either a command or the generate arguments are required"), GenerateSession::interruptible())
+            .map(|_| ()),
+    };
+    if let Err(error) = result {
+        eprintln!("{error}");
+        exit(1);
+    }
+}
+
+const DEFAULT_WPOWER: u8 = 10;
+const DEFAULT_HPOWER: u8 = 9;
+
+const CANCELLED_MESSAGE: &str = "Generation has been cancelled.";
+
+/// State provided by long-running modes to the generation runs they start.
+#[derive(Default)]
+struct GenerateSession {
+    /// Manifest used instead of looking one up in the input directory.
+    manifest: Option<Manifest>,
+    /// Cache used instead of the one enabled by --cache, kept warm between runs.
+    cache: Option<LayerCache>,
+    /// Receives the progress events. Layers of the main attributes and of the sections are counted together.
+    progress: Option<ProgressCallback>,
+    /// Stops the run. The layers still queued aren't loaded, and a cancelled run never leaves an output behind.
+    cancelled: Option<CancellationToken>,
+    /// Sources decoded by the earlier outputs of a manifest with several outputs.
+    decoded: Option<DecodedSources>,
+}
+
+impl GenerateSession {
+    /// Session of a single run from the command line, cancelled by Ctrl+C.
+    fn interruptible() -> GenerateSession {
+        let token = CancellationToken::new();
+        cancel_on_interrupt(&token);
+        GenerateSession { cancelled: Some(token), ..GenerateSession::default() }
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.as_ref().is_some_and(CancellationToken::is_cancelled)
+    }
+
+    fn check_cancelled(&self) -> Result<(), String> {
+        match self.is_cancelled() {
+            true => Err(CANCELLED_MESSAGE.to_string()),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Layers and chunks of a spatial data file generated in memory.
+struct GeneratedFile {
+    manifest: Manifest,
+    layers: Vec<Layer>,
+    dimensions: LayerDimensions,
+    extensions: NsdExtensions,
+    raw_data: Vec<u8>,
+    sections: Vec<LayerSection>,
+    /// Layers of all sections which could not be loaded and have been left out.
+    failures: Vec<LayerFailure>,
+}
+
+/// The manifest given on the command line or found in the input directory.
+fn load_manifest(args: &GenerateArgs) -> Result<Manifest, String> {
+    match Manifest::find(args.manifest.as_deref(), &args.directory) {
+        Some(path) => {
+            info!("Using manifest {}", path.display());
+            Manifest::load(&path).map_err(|error| format!("Could not load the manifest: {error}"))
+        }
+        None => Ok(Manifest::default()),
+    }
+}
+
+/// The channel map given on the command line or found in the input directory.
+fn load_channel_map(explicit_path: Option<&Path>, directory: &Path) -> Result<ChannelMap, String> {
+    match ChannelMap::find(explicit_path, directory) {
+        Some(path) => {
+            info!("Using channel map {}", path.display());
+            ChannelMap::load(&path).map_err(|error| format!("Could not load the channel map: {error}"))
+        }
+        None => Ok(ChannelMap::default()),
+    }
+}
+
+/// Loads and packs the layers without writing anything.
+fn generate_in_memory(args: &GenerateArgs, session: &mut GenerateSession) -> Result<GeneratedFile, String> {
+    info!("Trying to generate spatial data file using layers from directory {}...",
+             args.directory.display());
+
+    let mut manifest = match session.manifest.take() {
+        Some(manifest) => manifest,
+        None => load_manifest(args)?,
+    };
+
+    // Resized images saved among the sources would be discovered as layers by the next run.
+    if let Some(directory) = args.resized_dir.as_ref().filter(|_| args.save_resized) {
+        if fs::canonicalize(directory).ok() == fs::canonicalize(&args.directory).ok() {
+            return Err("The resized images can't be saved into the input directory (see --resized-dir).".into());
+        }
+    }
+
+    let channel_map = load_channel_map(args.channels.as_deref(), &args.directory)?;
+    let mut layers = read_layer_files(&args.directory, &manifest, &channel_map, !args.no_follow_symlinks)
+        .map_err(|error| error.to_string())?;
+    if layers.is_empty() {
+        return Err("Layers not found.".into());
+    }
+    // Checked before the layers are filtered or split into sections, which leave out configured layers on purpose.
+    for name in manifest.layers.keys() {
+        if !layers.iter().any(|source| source.stem == *name) {
+            warning!(None, "discovery", "Layer {name} is configured in the manifest, but its file was not found.");
+        }
+    }
+
+    if args.split_alpha {
+        split_alpha_sources(&mut layers);
+    }
+
+    if let Some(preset) = args.input_preset {
+        manifest.apply_input_preset(preset.into(), &layers);
+    }
+    let preset = args.preset.map(|preset| preset.defaults());
+    if let Some(preset) = &preset {
+        manifest.apply_default_format(preset.format, &layers);
+    }
+
+    if let Some(path) = &args.classify {
+        let classification = ColorClassification::load(path)
+            .map_err(|error| format!("Could not load the color classification: {error}"))?;
+        manifest.apply_classification(classification);
+    }
+
+    // Excluded and skipped layers can still be used as masks or references by other layers.
+    let output_config = args.output_config.clone().unwrap_or_default();
+    // The preset sizes the output unless a size is given.
+    let sized = args.wpower.is_some() || args.hpower.is_some() || args.aspect.is_some();
+    let auto_size = args.auto_size.or(preset.as_ref().filter(|_| !sized).map(|preset| preset.auto_size));
+    let dimensions = match (output_config.dimensions, auto_size, args.aspect) {
+        (Some(dimensions), _, _) => dimensions,
+        (None, Some(auto_size), _) => auto_dimensions(&layers, &manifest, args.region, auto_size)?,
+        (None, None, Some(aspect)) => aspect_dimensions(args.wpower.unwrap_or(DEFAULT_WPOWER), aspect)?,
+        (None, None, None) => LayerDimensions::from_power_of_two(
+            args.wpower.unwrap_or(DEFAULT_WPOWER) as u32, args.hpower.unwrap_or(DEFAULT_HPOWER) as u32
+        ),
+    };
+    let defines: BTreeMap<String, f64> = args.defines.iter().cloned().collect();
+    let all_layers = layers.clone();
+    let included: Vec<bool> = layers.iter()
+        .map(|source| manifest.is_included(source, &dimensions, &defines))
+        .collect::<Result<_, _>>()
+        .map_err(|error| error.to_string())?;
+    let mut included = included.into_iter();
+    layers.retain(|source| {
+        let included = included.next().expect("Every layer has been checked.");
+        if !included {
+            info!("Layer {} is excluded by its include condition.", manifest.attribute_name(source));
+        }
+        included
+    });
+
+    for name in args.layers.iter().chain(&args.skip_layers) {
+        if !all_layers.iter().any(|source| manifest.attribute_name(source) == *name) {
+            return Err(format!("Layer {name} given to --layers or --skip-layers was not found."));
+        }
+    }
+    layers.retain(|source| {
+        let name = manifest.attribute_name(source);
+        (args.layers.is_empty() || args.layers.contains(&name)) && !args.skip_layers.contains(&name)
+    });
+    if layers.is_empty() {
+        return Err("No layers are left to store.".into());
+    }
+
+    let mut attribute_names: Vec<(String, String)> = layers.iter()
+        .map(|source| (manifest.attribute_name(source), source.to_string()))
+        .collect();
+    attribute_names.sort();
+    if let Some(pair) = attribute_names.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        return Err(format!("{} and {} both produce layer {}.", pair[0].1, pair[1].1, pair[0].0));
+    }
+    sort_layer_sources(&mut layers, &manifest);
+
+    let sources = manifest.split_sections(layers).map_err(|error| error.to_string())?;
+    // Checked before loading anything, a file with too many attributes would only fail in the consumer.
+    let section_layers = sources.sections.iter().map(|(name, _, sources)| (format!("Section {name}"), sources));
+    for (section, sources) in std::iter::once(("The main section".to_string(), &sources.main)).chain(section_layers) {
+        if sources.len() > args.max_layers as usize {
+            return Err(format!(
+                "{section} has {} layers, but consumers load at most {} (see --max-layers).", sources.len(), args.max_layers
+            ));
+        }
+        if args.require_uniform_source {
+            check_uniform_sources(&section, sources)?;
+        }
+    }
+    let section_sources = sources.sections.iter().flat_map(|(_, _, sources)| sources);
+    let decode_limits = DecodeLimits { max_dimension: args.max_source_dimension, max_memory: args.max_decode_memory };
+    let georeference = common_georeference(sources.main.iter().chain(section_sources), &decode_limits)
+        .map_err(|error| format!("The georeferenced layers don't line up: {error}"))?;
+    // The world placement only depends on the dimensions, the layers only add their names and formats.
+    let world = manifest.world.map(|world| world.scale(&dimensions))
+        .or(georeference.map(|georeference| georeference.world_scale(args.region, &dimensions)));
+
+    if args.dry_run {
+        let placeholders = |sources: &[LayerSource], dimensions: &LayerDimensions| -> Vec<Layer> {
+            sources.iter().map(|source| Layer::placeholder(source, &manifest.layer(&source.stem), dimensions)).collect()
+        };
+        let layers = placeholders(&sources.main, &dimensions);
+        let sections = sources.sections.iter()
+            .map(|(name, dimensions, sources)| LayerSection {
+                name: name.clone(),
+                dimensions: dimensions.clone(),
+                layers: placeholders(sources, dimensions),
+            })
+            .collect();
+        let extensions = make_file_extensions(args, &manifest, &layers, &dimensions, world)?;
+        return Ok(GeneratedFile { manifest, layers, dimensions, extensions, raw_data: vec![], sections, failures: vec![] });
+    }
+
+    let cache = if session.cache.is_some() {
+        session.cache.take()
+    } else if args.cache || args.resume {
+        // Resumed runs read the layers loaded so far from the cache, or from their own checkpoint without it.
+        let directory = match args.cache {
+            true => args.cache_directory(),
+            false => args.checkpoint_directory(),
+        };
+        LayerCache::new(directory)
+            .map_err(|error| warning!(None, "cache", "Could not create the layer cache, continuing without it: {error}"))
+            .ok()
+    } else {
+        None
+    };
+    let mut load_options = LayerLoadOptions {
+        dimensions: dimensions.clone(),
+        region: args.region,
+        upscale_filter: output_config.upscale_filter
+            .or(args.upscale_filter.map(ResizeFilter::from))
+            .or(preset.as_ref().map(|preset| preset.upscale_filter))
+            .unwrap_or(ResizeFilter::Nearest),
+        downscale_filter: output_config.downscale_filter
+            .or(args.downscale_filter.map(ResizeFilter::from))
+            .or(preset.as_ref().map(|preset| preset.downscale_filter))
+            .unwrap_or(ResizeFilter::Nearest),
+        normalize: args.normalize_percentile.or(preset.as_ref().and_then(|preset| preset.normalize)),
+        save_resized: args.save_resized.then(|| ResizedImageOptions {
+            directory: args.resized_dir.clone(),
+            format: args.resized_format.map(ResizedImageFormat::from),
+            overwrite: args.existing_resized == ExistingResizedArg::Overwrite,
+        }),
+        temp_directory: args.temp_dir.clone(),
+        comparisons: None,
+        validate_resize: args.validate_resize,
+        cache,
+        decoded: session.decoded.clone(),
+        masks: BTreeMap::new(),
+        references: BTreeMap::new(),
+        defines,
+        preprocess: args.preprocess.clone(),
+        progress: None,
+        cancellation: session.cancelled.clone(),
+        strict: args.strict,
+        resident_layers: args.resident_layers.map(|count| count as usize),
+        threads: args.threads.map(|count| count as usize),
+        decode_limits,
+    };
+    // Masks and referenced layers are loaded at the dimensions of every section they might be used in.
+    let with_references = |options: LayerLoadOptions| -> Result<LayerLoadOptions, String> {
+        let masks = load_masks(&all_layers, &manifest, &options).map_err(|error| error.to_string())?;
+        let references = load_references(&all_layers, &manifest, &options).map_err(|error| error.to_string())?;
+        Ok(LayerLoadOptions { masks, references, ..options })
+    };
+    load_options = with_references(load_options)?;
+    let mut section_options: Vec<LayerLoadOptions> = manifest.sections.values()
+        .map(|section| with_references(LayerLoadOptions {
+            dimensions: section.dimensions.clone(),
+            ..load_options.clone()
+        }))
+        .collect::<Result<_, _>>()?;
+
+    session.check_cancelled()?;
+    let total_layers = sources.main.len() + sources.sections.iter().map(|(_, _, sources)| sources.len()).sum::<usize>();
+    if let Some(progress) = session.progress.clone() {
+        let loaded_layers = Arc::new(AtomicUsize::new(0));
+        let progress: ProgressCallback = Arc::new(move |event| match event {
+            ProgressEvent::LayerLoaded { name, .. } => progress(&ProgressEvent::LayerLoaded {
+                name: name.clone(),
+                loaded: loaded_layers.fetch_add(1, Ordering::Relaxed) + 1,
+                total: total_layers,
+            }),
+            event => progress(event),
+        });
+        for options in std::iter::once(&mut load_options).chain(&mut section_options) {
+            options.progress = Some(progress.clone());
+        }
+    }
+
+    // Layers are interleaved into the texel data as they finish loading.
+    let formats = sources.main.iter().map(|source| manifest.layer(&source.stem).format).collect();
+    let mut packer = DataPacker::new(formats, &dimensions, args.order.into());
+    if let Some(progress) = &session.progress {
+        packer = packer.with_progress(progress.clone());
+    }
+    let load_error = |error: io::Error| match session.is_cancelled() {
+        true => CANCELLED_MESSAGE.to_string(),
+        false => error.to_string(),
+    };
+    let layers = init_layers(sources.main, &manifest, &load_options, args.run_sequential, |index, layer| {
+        packer.pack(index, layer);
+        // Verification compares the file against the texels of the layers, otherwise they aren't needed anymore.
+        if !args.verify {
+            layer.texels.release();
+        }
+    });
+    let LoadedLayers { layers, mut failures } = layers.map_err(load_error)?;
+    if layers.is_empty() {
+        let reasons: Vec<String> = failures.iter()
+            .map(|failure| format!("\n- {}: {}", failure.source.path.display(), failure.error))
+            .collect();
+        return Err(format!("None of the layers could be loaded:{}", reasons.concat()));
+    }
+    let skipped: Vec<usize> = failures.iter().map(|failure| failure.index).collect();
+    let raw_data = packer.finish_without(&skipped);
+
+    let sections: Vec<LayerSection> = sources.sections.into_iter().zip(&section_options)
+        .map(|((name, dimensions, sources), section_options)| {
+            let loaded = init_layers(sources, &manifest, section_options, args.run_sequential, |_, _| {})
+                .map_err(load_error)?;
+            failures.extend(loaded.failures);
+            Ok(LayerSection { name, dimensions, layers: loaded.layers })
+        })
+        .collect::<Result<_, String>>()?;
+
+    // Groups keep the layers which have been loaded.
+    for failure in &failures {
+        let name = manifest.attribute_name(&failure.source);
+        for members in manifest.groups.values_mut() {
+            members.retain(|member| *member != name);
+        }
+    }
+    manifest.groups.retain(|_, members| !members.is_empty());
+
+    info!("Sorted layers:");
+    for layer in &layers {
+        info!("- {}", layer.name);
+    }
+    for section in &sections {
+        info!("Section {} ({}x{}):", section.name, section.dimensions.width, section.dimensions.height);
+        for layer in &section.layers {
+            info!("- {}", layer.name);
+        }
+    }
+
+    let extensions = make_file_extensions(args, &manifest, &layers, &dimensions, world)?;
+    Ok(GeneratedFile { manifest, layers, dimensions, extensions, raw_data, sections, failures })
+}
+
+/// Optional chunks of the file of the layers, with the manifest overriding the placement of georeferenced sources.
+fn make_file_extensions(
+    args: &GenerateArgs,
+    manifest: &Manifest,
+    layers: &[Layer],
+    dimensions: &LayerDimensions,
+    world: Option<NsdWorldScale>
+) -> Result<NsdExtensions, String> {
+    let mut extensions = manifest.make_extensions(layers).map_err(|error| error.to_string())?;
+    extensions.texel_order = args.order.into();
+    extensions.row_stride = args.row_alignment
+        .map(|alignment| aligned_row_stride(layers, dimensions, extensions.texel_order, alignment));
+    extensions.world = world;
+    Ok(extensions)
+}
+
+/// Output written by a run.
+struct GeneratedOutput {
+    path: PathBuf,
+    /// Total size of the written files, `None` for dry runs.
+    size: Option<u64>,
+    layers: usize,
+    failed_layers: usize,
+    warnings: usize,
+    duration: Duration,
+}
+
+impl GeneratedOutput {
+    fn summary(&self) -> OutputSummary {
+        OutputSummary {
+            output: self.path.display().to_string(),
+            size: self.size,
+            layers: self.layers,
+            failed_layers: self.failed_layers,
+            warnings: self.warnings,
+            duration_seconds: self.duration.as_secs_f64(),
+        }
+    }
+}
+
+/// Prints a table of the outputs when there are several, and writes it to the --summary file.
+fn summarize_outputs(outputs: &[GeneratedOutput], summary_path: Option<&Path>) {
+    if outputs.len() < 2 && summary_path.is_none() {
+        return;
+    }
+    let summaries: Vec<OutputSummary> = outputs.iter().map(GeneratedOutput::summary).collect();
+    let previous_sizes = summary_path.filter(|path| path.exists())
+        .map(|path| read_sizes(path).unwrap_or_else(|error| {
+            warning!(Some(path), "writing", "Could not read the previous summary: {error}");
+            HashMap::new()
+        }))
+        .unwrap_or_default();
+    info!("Summary:");
+    for line in format_table(&summaries, &previous_sizes) {
+        info!("    {line}");
+    }
+    if let Some(path) = summary_path {
+        if let Err(error) = write_summary(path, &summaries) {
+            warning!(Some(path), "writing", "Could not write the summary: {error}");
+        }
+    }
+}
+
+/// Generates the spatial data file and returns its path.
+fn generate(args: GenerateArgs, session: GenerateSession) -> Result<Vec<PathBuf>, String> {
+    let output_paths = |outputs: Vec<GeneratedOutput>| outputs.into_iter().map(|output| output.path).collect();
+    let notify_url = args.notify_url.clone().filter(|_| !args.dry_run);
+    let summary_path = args.summary.clone();
+    if args.error_report.is_none() && notify_url.is_none() {
+        let result = generate_with_hooks(args, session);
+        if let Ok(outputs) = &result {
+            summarize_outputs(outputs, summary_path.as_deref());
+        }
+        return result.map(output_paths);
+    }
+    let (report_path, directory) = (args.error_report.clone(), args.directory.clone());
+    let start = Instant::now();
+    log::record_diagnostics();
+    let result = generate_with_hooks(args, session);
+    if let Ok(outputs) = &result {
+        summarize_outputs(outputs, summary_path.as_deref());
+    }
+    let mut diagnostics = log::take_diagnostics();
+    // Errors recorded with their file already explain the failure.
+    if let Err(error) = &result {
+        if !diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error) {
+            diagnostics.push(Diagnostic { severity: Severity::Error, file: None, phase: "generation", message: error.clone() });
+        }
+    }
+    let count = |severity: Severity| diagnostics.iter().filter(|diagnostic| diagnostic.severity == severity).count();
+    let report = JsonValue::object()
+        .with("succeeded", result.is_ok())
+        .with("warnings", count(Severity::Warning))
+        .with("errors", count(Severity::Error))
+        .with("diagnostics", diagnostics.iter().map(Diagnostic::to_json).collect::<Vec<_>>());
+    if let Some(report_path) = report_path {
+        if let Err(error) = fs::write(&report_path, report.to_pretty_string()) {
+            eprintln!("Could not write the error report {}: {error}", report_path.display());
+        }
+    }
+    if let Some(url) = notify_url {
+        notify_completion(&url, &directory, &result, &diagnostics, start.elapsed());
+    }
+    result.map(output_paths)
+}
+
+/// POSTs the summary of a completed run: its outputs with their sizes and durations, and the errors, including the
+/// layers which have been left out.
+fn notify_completion(
+    url: &str,
+    directory: &Path,
+    result: &Result<Vec<GeneratedOutput>, String>,
+    diagnostics: &[Diagnostic],
+    duration: Duration,
+) {
+    let absolute = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()).to_string_lossy().into_owned();
+    let outputs: Vec<JsonValue> = result.iter().flatten()
+        .map(|output| JsonValue::object()
+            .with("path", absolute(&output.path))
+            .with("size", output.size)
+            .with("layers", output.layers)
+            .with("warnings", output.warnings)
+            .with("duration_seconds", output.duration.as_secs_f64()))
+        .collect();
+    let failures: Vec<JsonValue> = diagnostics.iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .map(Diagnostic::to_json)
+        .collect();
+    let summary = JsonValue::object()
+        .with("event", "generated")
+        .with("directory", absolute(directory))
+        .with("succeeded", result.is_ok())
+        .with("duration_seconds", duration.as_secs_f64())
+        .with("outputs", outputs)
+        .with("warnings", diagnostics.iter().filter(|diagnostic| diagnostic.severity == Severity::Warning).count())
+        .with("failures", failures)
+        .with("error", result.as_ref().err().cloned());
+    let sent = post(url, "application/json", summary.to_string().as_bytes()).and_then(|status| match status {
+        200..=299 => Ok(()),
+        _ => Err(io::Error::other(format!("the server answered with status {status}"))),
+    });
+    if let Err(error) = sent {
+        eprintln!("Could not send the summary of the run to {url}: {error}");
+    }
+}
+
+/// Generates the outputs and runs the --on-success command for each of them, or the --on-failure command. A failing
+/// --on-success command fails the run, a failing --on-failure command is only a warning.
+fn generate_with_hooks(args: GenerateArgs, session: GenerateSession) -> Result<Vec<GeneratedOutput>, String> {
+    let (on_success, on_failure) = (args.on_success.clone(), args.on_failure.clone());
+    let directory = args.directory.display().to_string();
+    let run_success_hook = on_success.is_some() && !args.dry_run;
+    let result = generate_outputs(args, session);
+    match (&result, on_success, on_failure) {
+        (Ok(paths), Some(command), _) if run_success_hook => {
+            for GeneratedOutput { path, .. } in paths {
+                info!("Running the --on-success command for {}...", path.display());
+                run_hook(&command, &[("output", &path.display().to_string()), ("dir", &directory)])
+                    .map_err(|error| format!("The --on-success command failed: {error}"))?;
+            }
+        }
+        (Err(error), _, Some(command)) => {
+            info!("Running the --on-failure command...");
+            if let Err(hook_error) = run_hook(&command, &[("dir", &directory), ("error", error)]) {
+                warning!(None, "generation", "The --on-failure command failed: {hook_error}");
+            }
+        }
+        _ => {}
+    }
+    result
+}
+
+/// Generates every output of the manifest, sharing the decoded sources, or the single output of the command line.
+fn generate_outputs(args: GenerateArgs, mut session: GenerateSession) -> Result<Vec<GeneratedOutput>, String> {
+    let manifest = match session.manifest.take() {
+        Some(manifest) => manifest,
+        None => load_manifest(&args)?,
+    };
+    // The checkpoint is kept until every output has been generated.
+    let checkpoint = (args.resume && !args.cache).then(|| args.checkpoint_directory());
+    let remove_checkpoint = || {
+        if let Some(directory) = checkpoint.as_ref().filter(|directory| directory.exists()) {
+            if let Err(error) = fs::remove_dir_all(directory) {
+                warning!(Some(directory), "writing", "Could not remove the checkpoint: {error}");
+            }
+        }
+    };
+    // An output named on the command line replaces the outputs of the manifest.
+    if manifest.outputs.is_empty() || args.output.is_some() || args.output_template.is_some() {
+        session.manifest = Some(manifest);
+        let output = generate_file(args, session)?;
+        remove_checkpoint();
+        return Ok(vec![output]);
+    }
+
+    let decoded = DecodedSources::default();
+    let mut outputs = vec![];
+    for (name, output) in &manifest.outputs {
+        info!("Generating output {name}...");
+        let output_args = GenerateArgs {
+            output: Some(output.file.clone().unwrap_or_else(|| PathBuf::from(format!("{name}.nsd")))),
+            layers: match output.layers.is_empty() {
+                true => args.layers.clone(),
+                false => output.layers.clone(),
+            },
+            output_config: Some(output.clone()),
+            ..args.clone()
+        };
+        let output_session = GenerateSession {
+            manifest: Some(manifest.clone()),
+            cache: session.cache.clone(),
+            progress: session.progress.clone(),
+            cancelled: session.cancelled.clone(),
+            decoded: Some(decoded.clone()),
+        };
+        let output = generate_file(output_args, output_session)
+            .map_err(|error| format!("Could not generate output {name}: {error}"))?;
+        outputs.push(output);
+    }
+    remove_checkpoint();
+    Ok(outputs)
+}
+
+fn generate_file(args: GenerateArgs, mut session: GenerateSession) -> Result<GeneratedOutput, String> {
+    let start = Instant::now();
+    let warnings = log::warning_count();
+    let GeneratedFile { manifest, layers, dimensions, extensions, raw_data, sections, failures } =
+        generate_in_memory(&args, &mut session)?;
+
+    session.check_cancelled()?;
+    if !args.dry_run {
+        info!("Generating the spatial data file...");
+    }
+
+    // The name of the preset is used unless a name is given.
+    let preset_template = args.preset.filter(|_| args.output.is_none())
+        .map(|preset| preset.defaults().output_template.parse::<OutputTemplate>().expect("Preset templates are valid."));
+    let output = match args.output_template.as_ref().or(preset_template.as_ref()) {
+        Some(template) => PathBuf::from(template.expand(&OutputNameFields {
+            dir: directory_name(&args.directory).unwrap_or_default(),
+            width: dimensions.width,
+            height: dimensions.height,
+            layers: layers.len(),
+            date: format_date(args.timestamp.unwrap_or_else(current_unix_time)),
+        })),
+        // Named after the directory, so the outputs of several directories don't share a name.
+        None => args.output.clone().unwrap_or_else(|| match directory_name(&args.directory) {
+            Some(name) => PathBuf::from(format!("{name}.nsd")),
+            None => PathBuf::from("OutputFile.nsd"),
+        }),
+    };
+    let mut spatial_data_path = args.output_dir.clone().unwrap_or(args.directory.clone());
+    if let Some(directory) = &args.output_dir {
+        fs::create_dir_all(directory)
+            .map_err(|error| format!("Could not create the output directory {}: {error}", directory.display()))?;
+    }
+    spatial_data_path.push(output);
+
+    let size_limit = args.max_data_size.unwrap_or(u64::MAX).min(u32::MAX as u64);
+    let split = plan_split(&layers, &dimensions, &extensions, args.row_alignment, size_limit)
+        .map_err(|error| format!("Could not split the spatial data file: {error}"))?;
+    if args.dry_run {
+        report_dry_run(&args, &spatial_data_path, &manifest, &layers, &dimensions, &extensions, &sections);
+        if let Some(grid) = split {
+            info!(
+                "The texel data exceeds {size_limit} bytes and would be split into {}x{} files of {}x{} texels.",
+                grid.columns, grid.rows, grid.tile.width, grid.tile.height
+            );
+        }
+        return Ok(GeneratedOutput {
+            path: spatial_data_path,
+            size: None,
+            layers: layers.len(),
+            failed_layers: failures.len(),
+            warnings: log::warning_count() - warnings,
+            duration: start.elapsed(),
+        });
+    }
+    // Held until the output is complete, so concurrent runs targeting the same output don't interleave their writes.
+    let _lock = OutputLock::acquire(&spatial_data_path, Duration::from_secs(args.wait_for_lock))
+        .map_err(|error| format!("Could not lock the output: {error}"))?;
+    // Checked before writing, so a full disk doesn't fail halfway through a long write.
+    if !args.skip_space_check {
+        let estimate = estimate_file_size(&manifest.header, &layers, &dimensions, &extensions, &sections);
+        check_disk_space(&spatial_data_path, estimate.max_file_size())?;
+    }
+    if let Some(grid) = split {
+        info!(
+            "The texel data exceeds {size_limit} bytes and will be split into {}x{} files of {}x{} texels.",
+            grid.columns, grid.rows, grid.tile.width, grid.tile.height
+        );
+        let paths = write_split(&spatial_data_path, &manifest.header, &layers, &extensions, &raw_data, &sections, &grid, args.resume)
+            .map_err(|error| format!("Could not save the split spatial data files: {error}"))?;
+        let index_path = &paths[0];
+        info!("Files indexed by {} have been generated successfully!", index_path.display());
+        if args.verify {
+            warning!(Some(index_path), "verification", "Split files can't be verified.");
+        }
+        if args.ue_descriptor {
+            warning!(Some(index_path), "writing", "Import descriptors aren't written for split files.");
+        }
+        for error in manifest.reload.send(index_path, &args.directory) {
+            warning!(Some(index_path), "reload", "{error}");
+        }
+        let total_size: u64 = paths.iter().filter_map(|path| fs::metadata(path).ok()).map(|metadata| metadata.len()).sum();
+        info!("Stats:");
+        info!("    Total size: {} bytes in {} files", total_size.separate_with_commas(), paths.len());
+        info!("    Time took: {:.5} seconds", (Instant::now() - start).as_secs_f64());
+        report_failures(&failures);
+        return Ok(GeneratedOutput {
+            path: index_path.clone(),
+            size: Some(total_size),
+            layers: layers.len(),
+            failed_layers: failures.len(),
+            warnings: log::warning_count() - warnings,
+            duration: start.elapsed(),
+        });
+    }
+
+    // Written next to the output first, so a failed or cancelled run doesn't leave a truncated file behind.
+    let mut partial_path = match &args.temp_dir {
+        Some(directory) => directory.join(spatial_data_path.file_name().unwrap_or_default()).into_os_string(),
+        None => spatial_data_path.clone().into_os_string(),
+    };
+    partial_path.push(".partial");
+    let partial_path = PathBuf::from(partial_path);
+    if let Some(directory) = &args.temp_dir {
+        fs::create_dir_all(directory)
+            .map_err(|error| format!("Could not create the temporary directory {}: {error}", directory.display()))?;
+    }
+    let written = fs::File::create(&partial_path).and_then(|file| {
+        let progress = session.progress.clone().unwrap_or_else(|| Arc::new(|_| {}));
+        let file = CancellableWriter::new(file, session.cancelled.clone().unwrap_or_default());
+        let mut output = BufWriter::new(ProgressWriter::new(file, progress));
+        write_binary(&mut output, &manifest.header, layers.as_slice(), &dimensions, &extensions, &raw_data, &sections)?;
+        output.flush()?;
+        drop(output);
+        move_file(&partial_path, &spatial_data_path)
+    });
+    if let Err(error) = written {
+        let _ = fs::remove_file(&partial_path);
+        return Err(match session.is_cancelled() {
+            true => CANCELLED_MESSAGE.to_string(),
+            false => format!("Could not save the spatial data file: {error}"),
+        });
+    }
+
+    info!("File {} has been generated successfully!", spatial_data_path.display());
+
+    if args.verify {
+        info!("Verifying the spatial data file...");
+        verify_file(&spatial_data_path, &manifest.header, layers.as_slice(), &dimensions, &extensions, &sections)
+            .map_err(|error| format!("Verification of {} failed: {error}", spatial_data_path.display()))?;
+        info!("Verification passed.");
+    }
+
+    if args.ue_descriptor {
+        let descriptor_options = DescriptorOptions {
+            generated_at: args.timestamp.or((!args.reproducible).then(current_unix_time)),
+            include_source_directory: !args.reproducible,
+        };
+        let written = write_unreal_descriptor(
+            &spatial_data_path, &manifest.header, &layers, &dimensions, &extensions, &sections, &descriptor_options
+        );
+        let path = written.map_err(|error| format!("Could not write the import descriptor: {error}"))?;
+        info!("Import descriptor {} has been written.", path.display());
+    }
+
+    for error in manifest.reload.send(&spatial_data_path, &args.directory) {
+        warning!(Some(&spatial_data_path), "reload", "{error}");
+    }
+
+    let file_size = fs::metadata(&spatial_data_path)
+        .map_or(0, |metadata| metadata.len());
+    let duration = Instant::now() - start;
+
+    info!("Stats:");
+    info!("    File size: {} bytes", file_size.separate_with_commas());
+    info!("    Time took: {:.5} seconds", duration.as_secs_f64());
+    report_failures(&failures);
+    Ok(GeneratedOutput {
+        path: spatial_data_path,
+        size: Some(file_size),
+        layers: layers.len(),
+        failed_layers: failures.len(),
+        warnings: log::warning_count() - warnings,
+        duration,
+    })
+}
 
-#[derive(Clone)]
-struct LayerDimensions {
-    width: u32,
-    height: u32,
+/// Dimensions with a width of 2^wpower and the height derived from the aspect ratio.
+fn aspect_dimensions(wpower: u8, (ratio_width, ratio_height): (u32, u32)) -> Result<LayerDimensions, String> {
+    let width = 1u64 << wpower;
+    let height = width * ratio_height as u64;
+    if !height.is_multiple_of(ratio_width as u64) || height < ratio_width as u64 {
+        return Err(format!(
+            "A width of {width} can't be divided by the aspect ratio {ratio_width}:{ratio_height} into whole texels"
+        ));
+    }
+    let height = height / ratio_width as u64;
+    if height > 4096 {
+        return Err(format!("The aspect ratio {ratio_width}:{ratio_height} makes the texture {height} texels high (max 4096)"));
+    }
+    Ok(LayerDimensions { width: width as u32, height: height as u32 })
 }
 
-impl LayerDimensions {
-    pub fn from_power_of_two(width_power_of_two: u32, height_power_of_two: u32) -> LayerDimensions {
-        LayerDimensions {
-            width: 2u32.pow(width_power_of_two),
-            height: 2u32.pow(height_power_of_two),
+/// Size of the largest source of the main section (the one with the most texels), or of the region if one is given.
+fn auto_dimensions(
+    sources: &[LayerSource],
+    manifest: &Manifest,
+    region: Option<LayerRegion>,
+    auto_size: AutoSizeArg
+) -> Result<LayerDimensions, String> {
+    let largest = match region {
+        Some(region) => LayerDimensions { width: region.width, height: region.height },
+        None => {
+            let mut largest = LayerDimensions { width: 0, height: 0 };
+            let in_section = |source: &LayerSource| {
+                let name = manifest.attribute_name(source);
+                manifest.sections.values().any(|section| section.layers.contains(&name))
+            };
+            for source in sources.iter().filter(|source| !in_section(source)) {
+                let dimensions = source.source_dimensions()
+                    .map_err(|error| format!("Could not read the resolution of {source}: {error}"))?;
+                if dimensions.get_texel_count() > largest.get_texel_count() {
+                    largest = dimensions;
+                }
+            }
+            largest
         }
+    };
+    if largest.get_texel_count() == 0 {
+        return Err("The output size can't be derived from empty sources.".into());
+    }
+    let dimensions = LayerDimensions { width: auto_size.snap(largest.width), height: auto_size.snap(largest.height) };
+    info!("Output size: {}x{} (largest source {}x{})", dimensions.width, dimensions.height, largest.width, largest.height);
+    Ok(dimensions)
+}
+
+/// Fails unless all sources have the same resolution, listing the sources of every resolution otherwise.
+fn check_uniform_sources(section: &str, sources: &[LayerSource]) -> Result<(), String> {
+    let mut resolutions: BTreeMap<(u32, u32), Vec<String>> = BTreeMap::new();
+    for source in sources {
+        let dimensions = source.source_dimensions()
+            .map_err(|error| format!("Could not read the resolution of {source}: {error}"))?;
+        resolutions.entry((dimensions.width, dimensions.height)).or_default().push(source.to_string());
+    }
+    if resolutions.len() <= 1 {
+        return Ok(());
+    }
+    let listed: Vec<String> = resolutions.iter()
+        .map(|((width, height), sources)| format!("{width}x{height}: {}", sources.join(", ")))
+        .collect();
+    Err(format!("{section} has sources of different resolutions ({}).", listed.join("; ")))
+}
+
+/// Renames the file, or copies it when it's on another file system, like the temporary directory can be.
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+/// Logs the planned size of the output and the memory needed to generate it, warning when either is too large. Like
+/// the other progress messages, the report goes to stderr when stdout carries the protocol of the editor integration.
+fn report_dry_run(
+    args: &GenerateArgs,
+    path: &Path,
+    manifest: &Manifest,
+    layers: &[Layer],
+    dimensions: &LayerDimensions,
+    extensions: &NsdExtensions,
+    sections: &[LayerSection]
+) {
+    let estimate = estimate_file_size(&manifest.header, layers, dimensions, extensions, sections);
+    info!("Output: {}", path.display());
+    info!("Dimensions: {}x{}", dimensions.width, dimensions.height);
+    info!("Layers: {} ({} bytes per texel)", layers.len(), texel_stride(layers));
+    for layer in layers {
+        info!("- {} ({} {})", layer.name, layer.format.attribute_type.name(), layer.format.size);
+    }
+    for section in sections {
+        info!("Section {}: {}x{}, {} layers", section.name, section.dimensions.width, section.dimensions.height, section.layers.len());
     }
+    info!("Uncompressed texel data: {} bytes", estimate.raw_data.separate_with_commas());
+    info!(
+        "File size: {} bytes of chunks plus the compressed texel data, at most {} bytes in total",
+        estimate.fixed.separate_with_commas(), estimate.max_file_size().separate_with_commas()
+    );
 
-    pub fn get_texel_count(&self) -> usize {
-        self.width as usize * self.height as usize
+    if let Err(error) = check_disk_space(path, estimate.max_file_size()) {
+        warning!(Some(path), "planning", "{error}");
+    }
+
+    let peak_memory = estimate_peak_memory(args, layers, dimensions, sections, estimate.raw_data);
+    info!("Estimated peak memory: {} bytes", peak_memory.separate_with_commas());
+    if let Some(available) = available_memory() {
+        info!("Available memory: {} bytes", available.separate_with_commas());
+        if peak_memory > available {
+            warning!(Some(path), "planning", "Generating needs about {} bytes of memory, but only {} bytes are available \
+                (see --resident-layers).", peak_memory.separate_with_commas(), available.separate_with_commas());
+        }
+    }
+    // Each data chunk records its sizes in 32 bits, the main section can still be split into several files.
+    for section in sections {
+        let size = texel_stride(&section.layers) as u64 * section.dimensions.width as u64 * section.dimensions.height as u64;
+        if size > u32::MAX as u64 {
+            warning!(Some(path), "planning", "The texel data of section {} is {} bytes, more than a data chunk can hold.",
+                section.name, size.separate_with_commas());
+        }
+    }
+    if estimate.max_file_size() > u32::MAX as u64 {
+        warning!(Some(path), "planning", "The file may exceed 4 GiB, which some consumers can't read.");
     }
 }
 
-impl Default for LayerDimensions {
-    fn default() -> Self {
-        LayerDimensions {
-            width: 1024,
-            height: 512,
+/// Fails if the file system of the output has less space available than the size of the output.
+fn check_disk_space(path: &Path, required: u64) -> Result<(), String> {
+    let directory = path.parent().filter(|directory| !directory.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    match available_disk_space(directory) {
+        Ok(Some(available)) if available < required => Err(format!(
+            "{} has {} bytes of free space, but the output may need up to {} bytes (see --skip-space-check).",
+            directory.display(), available.separate_with_commas(), required.separate_with_commas()
+        )),
+        Ok(_) => Ok(()),
+        Err(error) => {
+            warning!(Some(path), "writing", "Could not check the free space of {}: {error}", directory.display());
+            Ok(())
         }
     }
 }
 
-struct Layer {
-    name: String,
-    image: DynamicImage,
+/// Rough peak memory of generating the layers: the interleaved texel data, the layers loaded at once, each with its
+/// decoded source, its resized image and its texels, and the texels kept until the end.
+fn estimate_peak_memory(
+    args: &GenerateArgs,
+    layers: &[Layer],
+    dimensions: &LayerDimensions,
+    sections: &[LayerSection],
+    raw_data: u64
+) -> u64 {
+    // Sources are decoded to at most four 16-bit channels.
+    const DECODED_TEXEL_SIZE: u64 = 8;
+    let texel_count = |dimensions: &LayerDimensions| dimensions.width as u64 * dimensions.height as u64;
+    let texel_bytes = |layer: &Layer, dimensions: &LayerDimensions| texel_count(dimensions) * layer.format.size as u64;
+    let mut working_sets: Vec<u64> = layers.iter()
+        .map(|layer| {
+            let source = layer.source.source_dimensions().unwrap_or(dimensions.clone());
+            (texel_count(&source) + texel_count(dimensions)) * DECODED_TEXEL_SIZE + texel_bytes(layer, dimensions)
+        })
+        .collect();
+    working_sets.sort_unstable_by(|a, b| b.cmp(a));
+    let threads = args.threads.map(|count| count as usize)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(4, |threads| threads.get()));
+    let resident_layers = args.resident_layers.map_or(threads, |count| count as usize).max(1);
+    let loading: u64 = working_sets.iter().take(resident_layers).sum();
+
+    // Sections keep their texels until they're written, verification keeps those of the main section too.
+    let kept_sections: u64 = sections.iter()
+        .flat_map(|section| section.layers.iter().map(|layer| texel_bytes(layer, &section.dimensions)))
+        .sum();
+    let kept_main: u64 = match args.verify {
+        true => layers.iter().map(|layer| texel_bytes(layer, dimensions)).sum(),
+        false => 0,
+    };
+    raw_data + loading + kept_sections + kept_main
+}
+
+/// Lists the layers which have been left out, at the end of a run.
+fn report_failures(failures: &[LayerFailure]) {
+    if failures.is_empty() {
+        return;
+    }
+    match failures.len() {
+        1 => eprintln!("1 layer could not be loaded and has been left out:"),
+        count => eprintln!("{count} layers could not be loaded and have been left out:"),
+    }
+    for failure in failures {
+        eprintln!("- {}: {}", failure.source.path.display(), failure.error);
+        log::record(Severity::Error, Some(&failure.source.path), "loading", failure.error.to_string());
+    }
+}
+
+fn check(args: CheckArgs) -> Result<(), String> {
+    let golden = NsdReader::open(&args.against)
+        .map_err(|error| format!("Could not read {}: {error}", args.against.display()))?;
+    let GeneratedFile { manifest, layers, dimensions, extensions, raw_data, sections, failures } =
+        generate_in_memory(&args.generate, &mut GenerateSession::default())?;
+    report_failures(&failures);
+
+    let mut bytes = Cursor::new(vec![]);
+    write_binary(&mut bytes, &manifest.header, &layers, &dimensions, &extensions, &raw_data, &sections)
+        .map_err(|error| format!("Could not build the spatial data file: {error}"))?;
+    let regenerated = NsdReader::from_bytes(bytes.get_ref())
+        .map_err(|error| format!("Could not read back the generated data: {error}"))?;
+
+    let exact_layers: Vec<String> = layers.iter()
+        .chain(sections.iter().flat_map(|section| &section.layers))
+        .filter(|layer| manifest.layer(&layer.source.stem).kind != ValueKind::Continuous)
+        .map(|layer| layer.name.clone())
+        .collect();
+    let differences = compare_files(&golden, &regenerated, args.tolerance, &exact_layers);
+    if differences.is_empty() {
+        println!("The generated data matches {}.", args.against.display());
+        return Ok(());
+    }
+    println!("The generated data differs from {}:", args.against.display());
+    for difference in &differences {
+        println!("- {difference}");
+    }
+    match differences.len() {
+        1 => Err("1 difference found.".into()),
+        count => Err(format!("{count} differences found.")),
+    }
 }
 
-impl Layer {
-    pub fn from_file(file: &PathBuf, dimensions: &LayerDimensions, save_resized: bool) -> Layer {
-        let layer_name: String = file.file_stem().unwrap().to_string_lossy().as_ref().into();
+fn inspect(args: InspectArgs) {
+    let fail = |error: io::Error| -> ! {
+        eprintln!("Could not read {}: {error}", args.file.display());
+        exit(1);
+    };
+    // Parsed in place, so the texel data is never decompressed.
+    let file = MappedFile::open(&args.file).unwrap_or_else(|error| fail(error));
+    let reader = NsdFileView::parse(&file).unwrap_or_else(|error| fail(error));
+
+    let dimensions = reader.dimensions();
+    println!("File: {}", args.file.display());
+    println!("Header: {} / {}", reader.header().vendor(), reader.header().identifier());
+    println!("Dimensions: {}x{}", dimensions.width, dimensions.height);
+    println!("Texel stride: {} bytes", reader.texel_stride());
+    println!("Texel order: {}", reader.texel_order().name());
+    if let Some(row_stride) = reader.extensions().row_stride {
+        println!("Row stride: {row_stride} bytes");
+    }
+    if let Some(world) = reader.world() {
+        println!(
+            "World: origin ({}, {}), {} x {} units per texel{}",
+            world.origin[0], world.origin[1], world.units_per_texel[0], world.units_per_texel[1],
+            world.epsg.map_or(String::new(), |epsg| format!(" in EPSG:{epsg}"))
+        );
+    }
+    println!("Data size: {} bytes", reader.data_size().separate_with_commas());
+
+    println!("Attributes:");
+    for (index, attribute) in reader.attributes().iter().enumerate() {
+        let group = reader.groups().iter()
+            .find(|group| group.attributes.contains(&(index as u32)))
+            .map_or(String::new(), |group| format!(" [{}]", group.name));
         println!(
-            "Opening layer {layer_name} from file {}...",
-            file.to_str().unwrap()
+            "- {} ({}, {} bytes){group}",
+            attribute.name, attribute.format.attribute_type.name(), attribute.format.size
         );
+        if let Some(metadata) = reader.attribute_metadata(index) {
+            if !metadata.description.is_empty() {
+                println!("      {}", metadata.description);
+            }
+            for (key, value) in &metadata.tags {
+                println!("      {key} = {value}");
+            }
+            if let Some(nodata) = metadata.nodata {
+                println!("      nodata: {nodata}");
+            }
+            if !metadata.unit.is_empty() {
+                println!("      unit: {}", metadata.unit);
+            }
+            if metadata.kind != ValueKind::Continuous {
+                println!("      kind: {}", metadata.kind.name());
+            }
+            if let Some(priority) = metadata.priority {
+                println!("      priority: {priority}");
+            }
+            if let Some(weight) = metadata.weight {
+                println!("      weight: {weight}");
+            }
+        }
+    }
+
+    for section in reader.sections() {
+        println!("Section {} ({}x{}):", section.name, section.dimensions.width, section.dimensions.height);
+        for attribute in &section.attributes {
+            println!(
+                "- {} ({}, {} bytes)",
+                attribute.name, attribute.format.attribute_type.name(), attribute.format.size
+            );
+        }
+    }
+}
+
+fn export(args: ExportArgs) {
+    let reader = NsdReader::open(&args.file).unwrap_or_else(|error| {
+        eprintln!("Could not read {}: {error}", args.file.display());
+        exit(1);
+    });
 
-        let reader = image::io::Reader::open(&file).unwrap();
-        let img = reader.with_guessed_format().unwrap().decode().unwrap();
+    let directory = args.output_dir
+        .unwrap_or_else(|| args.file.parent().map_or(PathBuf::from("."), PathBuf::from));
+    if let Err(error) = fs::create_dir_all(&directory) {
+        eprintln!("Could not create directory {}: {error}", directory.display());
+        exit(1);
+    }
+    let stem = args.file.file_stem().unwrap_or_default().to_string_lossy();
+
+    let result = match args.format {
+        ExportFormat::Houdini => export_houdini(&reader, &stem, &directory),
+    };
+    match result {
+        Ok(paths) => {
+            for path in paths {
+                println!("Exported {}", path.display());
+            }
+        }
+        Err(error) => {
+            eprintln!("Export failed: {error}");
+            exit(1);
+        }
+    }
+}
 
-        println!("Resizing layer {layer_name}...");
-        let image = img.resize(dimensions.width, dimensions.height, FilterType::Nearest);
+fn visualize(args: VisualizeArgs) -> Result<(), String> {
+    let reader = NsdReader::open(&args.file).map_err(|error| format!("Could not read {}: {error}", args.file.display()))?;
+    let options = VisualizeOptions {
+        colormap: args.colormap.map(Colormap::from),
+        component: args.component,
+        range: args.range,
+        legend: args.legend,
+    };
+    let image = visualize::visualize(&reader, &args.attr, &options).map_err(|error| error.to_string())?;
+    let output = args.output.unwrap_or_else(|| {
+        let stem = args.file.file_stem().unwrap_or_default().to_string_lossy();
+        args.file.with_file_name(format!("{stem}.{}.png", args.attr))
+    });
+    image.save(&output).map_err(|error| format!("Could not write {}: {error}", output.display()))?;
+    println!("Written {}", output.display());
+    Ok(())
+}
 
-        if save_resized {
-            let mut new_filepath = file.parent().unwrap().to_path_buf();
-            new_filepath.push("_resized");
-            new_filepath.push(file.file_name().unwrap());
+fn tui(args: TuiArgs) -> Result<(), String> {
+    let manifest = match Manifest::find(args.manifest.as_deref(), &args.directory) {
+        Some(path) => Manifest::load(&path).map_err(|error| format!("Could not load the manifest: {error}"))?,
+        None => Manifest::default(),
+    };
+    let channel_map = load_channel_map(args.channels.as_deref(), &args.directory)?;
+    let sources = read_layer_files(&args.directory, &manifest, &channel_map, true).map_err(|error| error.to_string())?;
+    if sources.is_empty() {
+        return Err("Layers not found.".into());
+    }
+    let mut session = TuiSession::new(manifest, sources);
+    println!("{}\n\n{TUI_HELP}", session.list());
 
-            if let Err(_) = image.save(&new_filepath) {
-                eprintln!("Could not save the resized image {}", new_filepath.display());
+    let mut lines = io::stdin().lines();
+    loop {
+        print!("> ");
+        io::stdout().flush().map_err(|error| error.to_string())?;
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line.map_err(|error| error.to_string())?;
+        let command = match TuiCommand::parse(&line) {
+            Ok(command) => command,
+            Err(error) => {
+                eprintln!("{error}");
+                continue;
+            }
+        };
+        match &command {
+            TuiCommand::List => println!("{}", session.list()),
+            TuiCommand::Help => println!("{TUI_HELP}"),
+            TuiCommand::Quit => return Ok(()),
+            TuiCommand::Histogram(index) => match session.histogram(*index) {
+                Ok(histogram) => println!("{histogram}"),
+                Err(error) => eprintln!("{error}"),
+            },
+            TuiCommand::Generate(generate_args) => {
+                let excluded = session.excluded_names();
+                let directory = args.directory.to_string_lossy();
+                let mut command_line = vec!["nsdgen", "generate", directory.as_ref()];
+                command_line.extend(generate_args.iter().map(String::as_str));
+                let skip_layers = excluded.join(",");
+                if !excluded.is_empty() {
+                    command_line.extend(["--skip-layers", skip_layers.as_str()]);
+                }
+                let generate_args = match CliArgs::try_parse_command_line(command_line).map(|args| args.command) {
+                    Ok(Some(Command::Generate(generate_args))) => generate_args,
+                    Ok(_) => unreachable!("The command line starts with generate."),
+                    Err(error) => {
+                        eprintln!("{error}");
+                        continue;
+                    }
+                };
+                let run = GenerateSession { manifest: Some(session.manifest), ..GenerateSession::interruptible() };
+                return generate(generate_args, run).map(|_| ());
             }
+            _ => match session.apply(&command) {
+                Ok(()) => println!("{}", session.list()),
+                Err(error) => eprintln!("{error}"),
+            },
+        }
+    }
+}
+
+fn to_json(args: ToJsonArgs) -> Result<(), String> {
+    let reader = NsdReader::open(&args.file)
+        .map_err(|error| format!("Could not read {}: {error}", args.file.display()))?;
+    let output = args.output.unwrap_or_else(|| args.file.with_extension("json"));
+    let directory = output.parent().unwrap_or(Path::new(""));
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+
+    let encoding = match args.embed {
+        true => DataEncoding::Base64,
+        false => DataEncoding::External(stem.clone()),
+    };
+    if !args.embed {
+        for (file, data) in data_files(&reader, &stem) {
+            let path = directory.join(file);
+            fs::write(&path, data).map_err(|error| format!("Could not write {}: {error}", path.display()))?;
+            println!("Written {}", path.display());
         }
+    }
+    fs::write(&output, file_to_json(&reader, &encoding).to_pretty_string())
+        .map_err(|error| format!("Could not write {}: {error}", output.display()))?;
+    println!("Written {}", output.display());
+    Ok(())
+}
+
+fn from_json(args: FromJsonArgs) -> Result<(), String> {
+    let source = fs::read_to_string(&args.file)
+        .map_err(|error| format!("Could not read {}: {error}", args.file.display()))?;
+    let model = JsonValue::parse(&source).map_err(|error| format!("Invalid JSON in {}: {error}", args.file.display()))?;
+    let directory = args.file.parent().unwrap_or(Path::new(""));
+    let file = file_from_json(&model, directory).map_err(|error| format!("Invalid description in {}: {error}", args.file.display()))?;
+
+    let output = args.output.unwrap_or_else(|| args.file.with_extension("nsd"));
+    let written = fs::File::create(&output).and_then(|created| {
+        let mut writer = BufWriter::new(created);
+        write_parsed_file(&mut writer, &file)?;
+        writer.flush()
+    });
+    written.map_err(|error| format!("Could not write {}: {error}", output.display()))?;
+    println!("Written {}", output.display());
+    Ok(())
+}
+
+fn dump_file(args: DumpArgs) -> Result<(), String> {
+    let bytes = fs::read(&args.file).map_err(|error| format!("Could not read {}: {error}", args.file.display()))?;
+    let mut output = String::new();
+    let result = dump(&bytes, args.bytes, &mut output);
+    print!("{output}");
+    result.map_err(|error| format!("Malformed file: {error}"))
+}
+
+fn diff(args: DiffArgs) -> Result<(), String> {
+    let read = |path: &Path| fs::read(path).map_err(|error| format!("Could not read {}: {error}", path.display()));
+    let (old, new) = (read(&args.old)?, read(&args.new)?);
+    let mut differences = diff_structure(&old, &new).map_err(|error| format!("Could not compare the files: {error}"))?;
+    if args.texels {
+        let parse = |bytes: &[u8]| NsdReader::from_bytes(bytes).map_err(|error| error.to_string());
+        differences.extend(compare_files(&parse(&old)?, &parse(&new)?, args.tolerance, &[]));
+    }
+    if differences.is_empty() {
+        println!("The files are identical.");
+        return Ok(());
+    }
+    for difference in &differences {
+        println!("- {difference}");
+    }
+    match differences.len() {
+        1 => Err("1 difference found.".into()),
+        count => Err(format!("{count} differences found.")),
+    }
+}
+
+fn edit(args: EditArgs) -> Result<(), String> {
+    let mut operations = vec![];
+    operations.extend(args.vendor.map(EditOperation::Vendor));
+    operations.extend(args.identifier.map(EditOperation::Identifier));
+    operations.extend(args.dimensions.map(EditOperation::Dimensions));
+    for (name, attribute_type) in args.set_types {
+        operations.push(EditOperation::AttributeType { name, attribute_type });
+    }
+    for (name, size) in args.set_sizes {
+        operations.push(EditOperation::AttributeSize { name, size });
+    }
+    for (from, to) in args.renames {
+        operations.push(EditOperation::RenameAttribute { from, to });
+    }
+    if operations.is_empty() {
+        return Err("Nothing to edit, see `nsdgen edit --help`.".into());
+    }
+
+    let output = args.output.unwrap_or_else(|| args.file.clone());
+    edit_file(&args.file, &output, &operations).map_err(|error| format!("Could not edit {}: {error}", args.file.display()))?;
+    println!("Written {}", output.display());
+    Ok(())
+}
 
-        println!("Layer {layer_name} has been created.");
+#[derive(Clone, Copy, PartialEq)]
+enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+}
 
-        Layer {
-            name: layer_name,
-            image,
+impl JobState {
+    fn name(self) -> &'static str {
+        match self {
+            JobState::Queued => "queued",
+            JobState::Running => "running",
+            JobState::Done => "done",
+            JobState::Failed => "failed",
         }
     }
 }
 
-fn read_layer_files(path: &PathBuf) -> Vec<PathBuf> {
-    std::fs::read_dir(path)
-        .expect("Invalid path")
-        .map(|res| res.map(|dir| dir.path()))
-        .filter_map(|path| path.ok())
-        .filter(|path| path.extension().unwrap_or("".as_ref()).eq("png"))
-        .collect()
+/// Generation job submitted to the API. Its ID is the index in the job list.
+struct Job {
+    state: JobState,
+    loaded_layers: usize,
+    total_layers: usize,
+    /// Fraction of the texel data packed.
+    packed: f64,
+    bytes_written: u64,
+    /// Generated files, several for manifests with several outputs.
+    outputs: Vec<PathBuf>,
+    error: Option<String>,
+}
+
+impl Job {
+    fn to_json(&self, id: usize) -> JsonValue {
+        JsonValue::object()
+            .with("id", id)
+            .with("state", self.state.name())
+            .with("loaded_layers", self.loaded_layers)
+            .with("total_layers", self.total_layers)
+            .with("packed", self.packed)
+            .with("bytes_written", self.bytes_written)
+            .with("output", self.outputs.first().map(|path| path.to_string_lossy().into_owned()))
+            .with("outputs", self.outputs.iter().map(|path| path.to_string_lossy().into_owned()).collect::<Vec<_>>())
+            .with("error", self.error.clone())
+    }
+}
+
+type Jobs = Arc<Mutex<Vec<Job>>>;
+
+struct QueuedJob {
+    id: usize,
+    args: GenerateArgs,
+    manifest: Option<Manifest>,
 }
 
-fn init_layers_parallel(layer_files: Vec<PathBuf>, dimensions: &LayerDimensions, save_resized: bool) -> Vec<Layer> {
-    let jobs = layer_files.len();
-    let available_workers = std::thread::available_parallelism().map_or(4usize, |threads| threads.get());
-    let workers = std::cmp::min(jobs, available_workers);
-    let pool = ThreadPool::new(workers);
+/// How long a client of the HTTP API may take to send its request.
+const CONNECTION_TIMEOUT: Duration = Duration::from_secs(30);
+
+fn serve(args: ServeArgs) -> Result<(), String> {
+    let listener = TcpListener::bind(&args.address)
+        .map_err(|error| format!("Could not listen on {}: {error}", args.address))?;
+    println!("Listening on http://{}", args.address);
 
+    let jobs: Jobs = Arc::default();
     let (sender, receiver) = mpsc::channel();
-    for file in layer_files {
-        let s = sender.clone();
-        let dimensions_cloned = dimensions.clone();
-        pool.execute(move|| {
-            s.send(Layer::from_file(&file, &dimensions_cloned, save_resized))
-                .expect("The layer will never be sent.");
+    let worker_jobs = jobs.clone();
+    thread::spawn(move || run_jobs(receiver, worker_jobs));
+
+    // Requests are answered right away on threads of their own, so a slow client doesn't hold up the others. The
+    // jobs run one after another on the worker thread.
+    for stream in listener.incoming().filter_map(|stream| stream.ok()) {
+        let (jobs, sender) = (jobs.clone(), sender.clone());
+        thread::spawn(move || {
+            let handled = stream.set_read_timeout(Some(CONNECTION_TIMEOUT))
+                .and_then(|_| handle_connection(stream, &jobs, &sender));
+            if let Err(error) = handled {
+                eprintln!("Could not handle a request: {error}");
+            }
         });
     }
+    Ok(())
+}
 
-    receiver.iter().take(jobs).collect()
+/// Cache of the input directory (or of `--cache-dir`), created on first use. The caches keep their layers in memory
+/// between the jobs.
+fn directory_cache(caches: &mut HashMap<PathBuf, LayerCache>, args: &GenerateArgs) -> Option<LayerCache> {
+    let directory = args.cache_directory();
+    if let Some(cache) = caches.get(&directory) {
+        return Some(cache.clone());
+    }
+    match LayerCache::new(directory.clone()) {
+        Ok(cache) => Some(caches.entry(directory).or_insert(cache.keep_in_memory()).clone()),
+        Err(error) => {
+            warning!(None, "cache", "Could not create the layer cache, continuing without it: {error}");
+            None
+        }
+    }
 }
 
-fn init_layers(
-    layer_files: Vec<PathBuf>,
-    dimensions: &LayerDimensions,
-    mut save_resized: bool,
-    run_sequential: bool
-) -> Vec<Layer> {
-    assert!(!layer_files.is_empty());
+fn run_jobs(receiver: mpsc::Receiver<QueuedJob>, jobs: Jobs) {
+    let mut caches: HashMap<PathBuf, LayerCache> = HashMap::new();
+    for job in receiver {
+        let cache = directory_cache(&mut caches, &job.args);
+
+        jobs.lock().unwrap()[job.id].state = JobState::Running;
+        let progress_jobs = jobs.clone();
+        let session = GenerateSession {
+            manifest: job.manifest,
+            cache,
+            progress: Some(Arc::new(move |event| {
+                let entry = &mut progress_jobs.lock().unwrap()[job.id];
+                match *event {
+                    ProgressEvent::LayerLoaded { loaded, total, .. } => {
+                        entry.loaded_layers = loaded;
+                        entry.total_layers = total;
+                    }
+                    ProgressEvent::Packed { fraction } => entry.packed = fraction,
+                    ProgressEvent::BytesWritten { bytes } => entry.bytes_written = bytes,
+                }
+            })),
+            cancelled: None,
+            decoded: None,
+        };
+        // An unexpected panic fails the job instead of stopping the server.
+        let result = panic::catch_unwind(AssertUnwindSafe(|| generate(job.args, session)))
+            .unwrap_or_else(|_| Err("Generation failed unexpectedly, see the server output".into()));
 
-    if save_resized {
-        let mut path = layer_files[0].parent().unwrap().to_path_buf();
-        path.push("_resized");
-        if let Err(_) = fs::create_dir(&path) {
-            eprintln!("Could not create directory {}", path.display());
-            save_resized = false;
+        let entry = &mut jobs.lock().unwrap()[job.id];
+        match result {
+            Ok(paths) => {
+                entry.state = JobState::Done;
+                entry.outputs = paths;
+            }
+            Err(error) => {
+                eprintln!("{error}");
+                entry.state = JobState::Failed;
+                entry.error = Some(error);
+            }
         }
     }
+}
 
-    let mut layers: Vec<Layer> = if !run_sequential {
-        init_layers_parallel(layer_files, &dimensions, save_resized)
-    }
-    else {
-        layer_files
-            .iter()
-            .map(|file| Layer::from_file(&file, &dimensions, save_resized))
-            .collect()
+fn handle_connection(stream: TcpStream, jobs: &Jobs, sender: &mpsc::Sender<QueuedJob>) -> io::Result<()> {
+    let (status, content_type, body) = match read_request(&mut BufReader::new(&stream)) {
+        Ok(request) if !is_loopback_host(&request) => {
+            error_response(403, "Requests must be sent to localhost or a loopback address")
+        }
+        Ok(request) if is_cross_origin(&request) => error_response(403, "Requests from web pages are not allowed"),
+        Ok(request) => route(&request, jobs, sender),
+        Err(error) => error_response(400, &error.to_string()),
     };
-    layers.sort_by(|lhs, rhs| lhs.name.cmp(&rhs.name));
-    layers
+    write_response(&mut &stream, status, content_type, &body)
 }
 
-fn make_attribute_bytes(layers: &[Layer]) -> Box<[u8]> {
-    let mut attribute_bytes: Vec<u8> = vec![];
-    for layer in layers {
-        attribute_bytes.extend_from_slice(NSD_ATTR_HEADER.as_slice());
-        attribute_bytes.extend_from_slice(layer.name.as_ref());
-        // string termination
-        attribute_bytes.push(0);
-        // attribute size
-        attribute_bytes.push(1);
-        // attribute type (ESpatialDataTexelAttributeType::Byte)
-        attribute_bytes.push(3);
+/// Whether the request was sent to a loopback name or address. Web pages can point their own domain at 127.0.0.1
+/// (DNS rebinding), making their requests look same-origin, but the browser still sends that domain as the Host.
+fn is_loopback_host(request: &Request) -> bool {
+    let Some(host) = request.header("host") else {
+        return false;
+    };
+    // The port follows the closing bracket of IPv6 addresses, e.g. `[::1]:7878`.
+    let name = match host.strip_prefix('[') {
+        Some(address) => address.split(']').next().unwrap_or_default(),
+        None => host.rsplit_once(':').map_or(host, |(name, _)| name),
+    };
+    name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok_and(|address| address.is_loopback())
+}
+
+/// Whether a browser sent the request on behalf of a page of another origin, which could otherwise queue jobs on the
+/// local server.
+fn is_cross_origin(request: &Request) -> bool {
+    match (request.header("origin"), request.header("host")) {
+        (None, _) => false,
+        (Some(origin), Some(host)) => origin != format!("http://{host}"),
+        (Some(_), None) => true,
     }
-    attribute_bytes.into_boxed_slice()
 }
 
-fn make_dimensions_bytes(dimensions: &LayerDimensions) -> Box<[u8]> {
-    let mut bytes: Vec<u8> = vec![];
-    bytes.extend_from_slice(NSD_DIM_HEADER.as_slice());
-    bytes.extend_from_slice(dimensions.width.to_le_bytes().as_slice());
-    bytes.extend_from_slice(dimensions.height.to_le_bytes().as_slice());
-    bytes.extend_from_slice(1u32.to_le_bytes().as_slice());
-    bytes.extend_from_slice(1u32.to_le_bytes().as_slice());
-    bytes.into_boxed_slice()
+type Response = (u16, &'static str, Vec<u8>);
+
+fn json_response(status: u16, value: &JsonValue) -> Response {
+    (status, "application/json", value.to_pretty_string().into_bytes())
 }
 
-fn make_data_bytes(layers: &[Layer], dimensions: &LayerDimensions) -> io::Result<Box<[u8]>> {
-    let mut bytes: Vec<u8> = vec![];
+fn error_response(status: u16, message: &str) -> Response {
+    json_response(status, &JsonValue::object().with("error", message))
+}
 
-    bytes.extend_from_slice(NSD_DATA_HEADER.as_slice());
-    let combined_size = layers.len() * dimensions.width as usize * dimensions.height as usize;
-    if combined_size > u32::MAX as usize {
-        panic!("For now, data chunks larger than u32::MAX are unsupported");
+/// Endpoints:
+/// - `POST /jobs` queues a job, see `parse_job`.
+/// - `GET /jobs` and `GET /jobs/<id>` return the state and progress of the jobs.
+/// - `GET /jobs/<id>/result` returns the generated file of a finished job.
+fn route(request: &Request, jobs: &Jobs, sender: &mpsc::Sender<QueuedJob>) -> Response {
+    let segments = request.segments();
+    match (request.method.as_str(), segments.as_slice()) {
+        ("POST", ["jobs"]) => {
+            // Browsers can't send JSON to other origins without asking first, unlike forms and plain text.
+            let content_type = request.header("content-type").and_then(|value| value.split(';').next()).unwrap_or_default();
+            if !content_type.trim().eq_ignore_ascii_case("application/json") {
+                return error_response(415, "Jobs must be sent as application/json");
+            }
+            let job = std::str::from_utf8(&request.body)
+                .map_err(|_| "The request body must be UTF-8".to_string())
+                .and_then(JsonValue::parse)
+                .and_then(|job| parse_job(&job, false));
+            let (args, manifest) = match job {
+                Ok(job) => job,
+                Err(error) => return error_response(400, &error),
+            };
+            let mut jobs = jobs.lock().unwrap();
+            let id = jobs.len();
+            jobs.push(Job {
+                state: JobState::Queued,
+                loaded_layers: 0,
+                total_layers: 0,
+                packed: 0.0,
+                bytes_written: 0,
+                outputs: vec![],
+                error: None,
+            });
+            sender.send(QueuedJob { id, args, manifest }).expect("The worker thread runs as long as the server.");
+            json_response(202, &jobs[id].to_json(id))
+        }
+        ("GET", ["jobs"]) => {
+            let jobs = jobs.lock().unwrap();
+            let list: Vec<JsonValue> = jobs.iter().enumerate().map(|(id, job)| job.to_json(id)).collect();
+            json_response(200, &JsonValue::Array(list))
+        }
+        ("GET", ["jobs", id, rest @ ..]) => {
+            let jobs = jobs.lock().unwrap();
+            let Some((id, job)) = id.parse::<usize>().ok().and_then(|id| Some((id, jobs.get(id)?))) else {
+                return error_response(404, "Unknown job");
+            };
+            match rest {
+                [] => json_response(200, &job.to_json(id)),
+                ["result"] => match (job.outputs.first(), job.state) {
+                    (Some(path), JobState::Done) => match fs::read(path) {
+                        Ok(bytes) => (200, "application/octet-stream", bytes),
+                        Err(error) => error_response(500, &format!("Could not read {}: {error}", path.display())),
+                    },
+                    _ => error_response(409, &format!("The job is {}", job.state.name())),
+                },
+                _ => error_response(404, "Unknown endpoint"),
+            }
+        }
+        (_, ["jobs", ..]) => error_response(405, "Method not allowed"),
+        _ => error_response(404, "Unknown endpoint"),
     }
-    bytes.extend_from_slice((combined_size as u32).to_le_bytes().as_slice());
+}
 
-    let texel_count = dimensions.get_texel_count();
-    let mut raw_data: Vec<u8> = vec![];
-    raw_data.reserve(texel_count * layers.len());
-    for i in 0..texel_count {
-        for layer in layers {
-            let rgba = layer.image.get_pixel(i as u32 % dimensions.width, i as u32 / dimensions.width);
-            raw_data.push(rgba.0[0]);
-        }
+/// Options of `generate` which run commands or contact other machines.
+const COMMAND_OPTIONS: [&str; 4] = ["on_success", "on_failure", "preprocess", "notify_url"];
+
+/// Manifest key of the first setting which runs commands or contacts other machines, which jobs of the HTTP API
+/// can't set.
+fn command_key(manifest: &Manifest) -> Option<String> {
+    if let Some((name, _)) = manifest.layers.iter().find(|(_, config)| config.preprocess.is_some()) {
+        return Some(format!("the preprocess command of layer {name}"));
     }
+    match (&manifest.reload.udp, &manifest.reload.webhook) {
+        (Some(_), _) => Some("reload.udp".into()),
+        (_, Some(_)) => Some("reload.webhook".into()),
+        _ => None,
+    }
+}
 
-    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
-    encoder.write_all(raw_data.as_slice())?;
-    let compressed_bytes = encoder.finish()?;
+/// Reads a job from a JSON object with the input `directory`, optional `args` given to `generate` and an optional
+/// `manifest` (TOML source) used instead of the manifest file. Without `allow_commands`, jobs can't set the options
+/// and manifest keys running commands, as for the HTTP API, which any local process can call. This covers the
+/// manifest of the input directory or of `--manifest` too, which is then loaded right away, so the job runs the
+/// manifest which was checked.
+fn parse_job(job: &JsonValue, allow_commands: bool) -> Result<(GenerateArgs, Option<Manifest>), String> {
+    let directory = job.get("directory").and_then(JsonValue::as_str)
+        .ok_or_else(|| "Jobs require the input `directory`".to_string())?;
 
-    bytes.extend_from_slice((compressed_bytes.len() as u32).to_le_bytes().as_slice());
-    bytes.extend(compressed_bytes);
+    let mut command_line = vec!["nsdgen", "generate", directory];
+    if let Some(args) = job.get("args") {
+        let args: Option<Vec<&str>> = args.as_array().and_then(|args| args.iter().map(JsonValue::as_str).collect());
+        command_line.extend(args.ok_or_else(|| "`args` must be an array of strings".to_string())?);
+    }
+    let matches = CliArgs::command().try_get_matches_from(command_line).map_err(|error| error.to_string())?;
+    let generate_matches = matches.subcommand_matches("generate").expect("The command line starts with generate.");
+    let command_option = COMMAND_OPTIONS.iter()
+        .find(|id| generate_matches.value_source(id) == Some(ValueSource::CommandLine));
+    if let Some(id) = command_option.filter(|_| !allow_commands) {
+        return Err(format!("Jobs can't set --{}", id.replace('_', "-")));
+    }
+    let args = match CliArgs::from_matches(&matches).map_err(|error| error.to_string())?.command {
+        Some(Command::Generate(args)) => args,
+        _ => unreachable!("The command line starts with generate."),
+    };
 
-    Ok(bytes.into_boxed_slice())
+    let manifest = match job.get("manifest") {
+        Some(manifest) => {
+            let source = manifest.as_str().ok_or_else(|| "`manifest` must be a string".to_string())?;
+            Some(Manifest::parse(source).map_err(|error| format!("Invalid manifest: {error}"))?)
+        }
+        None if !allow_commands => Some(load_manifest(&args)?),
+        None => None,
+    };
+    if let Some(key) = manifest.as_ref().and_then(command_key).filter(|_| !allow_commands) {
+        return Err(format!("Jobs can't set {key}"));
+    }
+    Ok((args, manifest))
 }
 
-fn make_binary(layers: &[Layer], dimensions: &LayerDimensions) -> io::Result<Vec<u8>> {
-    let mut bytes: Vec<u8> = vec![];
-    bytes.extend_from_slice(NSD_HEADER.as_slice());
+/// JSON-RPC error codes.
+const PARSE_ERROR: i32 = -32700;
+const INVALID_REQUEST: i32 = -32600;
+const METHOD_NOT_FOUND: i32 = -32601;
+const INVALID_PARAMS: i32 = -32602;
+const GENERATION_FAILED: i32 = -32000;
 
-    let dimensions_bytes = make_dimensions_bytes(dimensions);
-    bytes.extend_from_slice(&*dimensions_bytes);
+/// Writes a message as a single line of the standard output.
+fn send_message(message: JsonValue) {
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{message}").and_then(|_| stdout.flush());
+}
 
-    let attribute_bytes = make_attribute_bytes(layers);
-    bytes.extend_from_slice(&*attribute_bytes);
+fn send_result(id: JsonValue, result: JsonValue) {
+    send_message(JsonValue::object().with("jsonrpc", "2.0").with("id", id).with("result", result));
+}
 
-    let data_bytes = make_data_bytes(layers, dimensions)?;
-    bytes.extend_from_slice(&*data_bytes);
+fn send_error(id: JsonValue, code: i32, message: &str) {
+    let error = JsonValue::object().with("code", code).with("message", message);
+    send_message(JsonValue::object().with("jsonrpc", "2.0").with("id", id).with("error", error));
+}
 
-    Ok(bytes)
+struct IpcJob {
+    id: JsonValue,
+    args: GenerateArgs,
+    manifest: Option<Manifest>,
+    cancelled: CancellationToken,
 }
 
-#[derive(Parser)]
-#[clap(disable_help_flag = true)]
-struct CliArgs {
-    #[arg(long, action = ArgAction::Help, help = "Show help")]
-    help: Option<bool>,
+/// Methods:
+/// - `generate` queues a job with the same parameters as `POST /jobs` of `serve` and answers with the `output` path
+///   once it's done. `progress` notifications with the request `id` and the fields of a `ProgressEvent` are sent
+///   while it runs.
+/// - `inspect` answers with the structure and metadata of the spatial data `file`.
+/// - `cancel` stops the `generate` request with the given `id`.
+///
+/// Progress messages of the generator go to the standard error, so the standard output only carries JSON-RPC.
+fn run_ipc() {
+    nsdgen::log::redirect_to_stderr();
 
-    /// Input directory which contains the layer files.
-    #[arg()]
-    directory: PathBuf,
+    let (sender, receiver) = mpsc::channel::<IpcJob>();
+    let worker = thread::spawn(move || run_ipc_jobs(receiver));
+    let mut cancellations: Vec<(JsonValue, CancellationToken)> = vec![];
 
-    /// Output file name (placed inside the specified input directory)
-    #[arg(short, long)]
-    output: Option<PathBuf>,
+    for line in io::stdin().lock().lines() {
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let request = match JsonValue::parse(&line) {
+            Ok(request) => request,
+            Err(error) => {
+                send_error(JsonValue::Null, PARSE_ERROR, &error);
+                continue;
+            }
+        };
+        // Requests without an ID are notifications, which don't get answers.
+        let id = request.get("id").cloned();
+        let reply_error = |code, message: &str| {
+            if let Some(id) = id.clone() {
+                send_error(id, code, message);
+            }
+        };
+        let Some(method) = request.get("method").and_then(JsonValue::as_str) else {
+            reply_error(INVALID_REQUEST, "Requests require a `method`");
+            continue;
+        };
+        let params = request.get("params").cloned().unwrap_or(JsonValue::object());
 
-    /// Texture width will be set to 2^wpower (min=0, max=12)
-    #[arg(short, long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=12), value_name = "WIDTH_POWER")]
-    wpower: u8,
+        match method {
+            "generate" => match parse_job(&params, true) {
+                Ok((args, manifest)) => {
+                    let cancelled = CancellationToken::new();
+                    let id = id.unwrap_or(JsonValue::Null);
+                    cancellations.push((id.clone(), cancelled.clone()));
+                    sender.send(IpcJob { id, args, manifest, cancelled }).expect("The worker thread runs as long as the loop.");
+                }
+                Err(error) => reply_error(INVALID_PARAMS, &error),
+            },
+            "inspect" => {
+                let Some(file) = params.get("file").and_then(JsonValue::as_str) else {
+                    reply_error(INVALID_PARAMS, "inspect requires the `file` to inspect");
+                    continue;
+                };
+                match NsdReader::open(file.as_ref()) {
+                    Ok(reader) => {
+                        if let Some(id) = id {
+                            send_result(id, describe_file(&reader));
+                        }
+                    }
+                    Err(error) => reply_error(GENERATION_FAILED, &format!("Could not read {file}: {error}")),
+                }
+            }
+            "cancel" => {
+                let target = params.get("id").cloned().unwrap_or(JsonValue::Null);
+                let found = cancellations.iter().find(|(id, _)| *id == target);
+                if let Some((_, cancelled)) = found {
+                    cancelled.cancel();
+                }
+                if let Some(id) = id {
+                    send_result(id, JsonValue::object().with("cancelled", found.is_some()));
+                }
+            }
+            _ => reply_error(METHOD_NOT_FOUND, &format!("Unknown method {method}")),
+        }
+    }
 
-    /// Texture height will be set to 2^hpower (min=0, max=12)
-    #[arg(short, long, default_value_t = 9, value_parser = clap::value_parser!(u8).range(0..=12), value_name = "HEIGHT_POWER")]
-    hpower: u8,
+    // The queued jobs still run after the input is closed.
+    drop(sender);
+    let _ = worker.join();
+}
 
-    #[arg(long, default_value_t = false)]
-    save_resized: bool,
+fn run_ipc_jobs(receiver: mpsc::Receiver<IpcJob>) {
+    let mut caches: HashMap<PathBuf, LayerCache> = HashMap::new();
+    for job in receiver {
+        let cache = directory_cache(&mut caches, &job.args);
+        let progress_id = job.id.clone();
+        let session = GenerateSession {
+            manifest: job.manifest,
+            cache,
+            progress: Some(Arc::new(move |event| {
+                let mut params = event.to_json();
+                params.insert("id", progress_id.clone());
+                send_message(JsonValue::object().with("jsonrpc", "2.0").with("method", "progress").with("params", params));
+            })),
+            cancelled: Some(job.cancelled),
+            decoded: None,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| generate(job.args, session)))
+            .unwrap_or_else(|_| Err("Generation failed unexpectedly, see the standard error".into()));
+        match result {
+            Ok(paths) => {
+                let paths: Vec<String> = paths.iter().map(|path| path.to_string_lossy().into_owned()).collect();
+                send_result(job.id, JsonValue::object().with("output", paths[0].clone()).with("outputs", paths));
+            }
+            Err(error) => send_error(job.id, GENERATION_FAILED, &error),
+        }
+    }
+}
 
-    #[arg(long, default_value_t = false)]
-    run_sequential: bool
+/// Structure and metadata of the file, like `inspect` prints them.
+fn describe_file(reader: &NsdReader) -> JsonValue {
+    file_to_json(reader, &DataEncoding::Omitted).with("texel_stride", reader.texel_stride())
 }
 
-fn main() {
-    let args = CliArgs::parse();
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
 
-    println!("Trying to generate spatial data file using layers from directory {}...",
-             args.directory.display());
+    use nsdgen::builder::NsdBuilder;
+    use nsdgen::manifest::DEFAULT_MANIFEST_NAME;
 
-    let start = Instant::now();
+    use super::*;
 
-    let layers = read_layer_files(&args.directory);
-    if layers.is_empty() {
-        eprintln!("Layers not found.");
-        exit(1);
+    fn request_to(host: Option<&str>) -> Request {
+        let headers = host.map(|host| ("Host".to_string(), host.to_string())).into_iter().collect();
+        Request { method: "POST".into(), path: "/jobs".into(), headers, body: vec![] }
     }
 
-    let dimensions = LayerDimensions::from_power_of_two(args.wpower as u32, args.hpower as u32);
-    let layers = init_layers(layers, &dimensions, args.save_resized, args.run_sequential);
+    #[test]
+    fn accepts_only_loopback_hosts() {
+        for host in ["localhost", "LOCALHOST:7878", "127.0.0.1:7878", "127.1.2.3", "[::1]:7878", "[::1]"] {
+            assert!(is_loopback_host(&request_to(Some(host))), "{host}");
+        }
+        for host in ["evil.example:7878", "localhost.evil.example", "192.168.1.5:7878", "[::2]:7878", "0.0.0.0", ""] {
+            assert!(!is_loopback_host(&request_to(Some(host))), "{host}");
+        }
+        assert!(!is_loopback_host(&request_to(None)));
+    }
 
-    println!("Sorted layers:");
-    for layer in &layers {
-        println!("- {}", layer.name);
+    #[test]
+    fn rejects_command_options_and_manifest_keys_in_jobs() {
+        let directory = env::temp_dir().join(format!("nsdgen-test-jobs-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let job = |args: &[&str], manifest: Option<&str>| {
+            let args: Vec<JsonValue> = args.iter().map(|&arg| arg.into()).collect();
+            let mut job = JsonValue::object().with("directory", directory.display().to_string()).with("args", args);
+            if let Some(manifest) = manifest {
+                job.insert("manifest", manifest);
+            }
+            job
+        };
+        let error = |job: &JsonValue| parse_job(job, false).err().unwrap_or_default();
+
+        assert!(error(&job(&["--on-success", "true"], None)).contains("--on-success"));
+        assert!(error(&job(&[], Some("[layers.a]\npreprocess = \"true\""))).contains("layer a"));
+        assert!(error(&job(&[], Some("[reload]\nwebhook = \"http://example.com\""))).contains("reload.webhook"));
+        assert!(parse_job(&job(&[], Some("[layers.a]\npreprocess = \"true\"")), true).is_ok());
+
+        // The manifests of the directory and of --manifest are checked as well.
+        let other = directory.join("other.toml");
+        fs::write(&other, "[reload]\nudp = \"example.com:9\"").unwrap();
+        assert!(error(&job(&["--manifest", &other.display().to_string()], None)).contains("reload.udp"));
+        assert!(parse_job(&job(&[], None), false).is_ok());
+        fs::write(directory.join(DEFAULT_MANIFEST_NAME), "[layers.a]\npreprocess = \"true\"").unwrap();
+        assert!(error(&job(&[], None)).contains("layer a"));
+        let (_, manifest) = parse_job(&job(&[], None), true).unwrap();
+        assert!(manifest.is_none());
+
+        fs::remove_dir_all(&directory).unwrap();
     }
 
-    println!("Generating the spatial data file...");
+    #[test]
+    fn describes_files_for_inspect_requests() {
+        let path = env::temp_dir().join(format!("nsdgen-test-inspect-{}.nsd", process::id()));
+        let builder = NsdBuilder::new(LayerDimensions { width: 2, height: 1 })
+            .add_layer("grass", AttributeFormat::default(), &[1, 2]).unwrap()
+            .add_layer("height", AttributeFormat::new(AttributeType::UInt, 2).unwrap(), &[0, 1, 2, 3]).unwrap();
+        builder.write(&mut fs::File::create(&path).unwrap()).unwrap();
 
-    let spatial_data_bytes = make_binary(layers.as_slice(), &dimensions)
-        .expect("Could not create the spatial data file.");
+        let description = describe_file(&NsdReader::open(&path).unwrap());
+        assert_eq!(description.get("texel_stride").and_then(JsonValue::as_u64), Some(3));
+        assert_eq!(description.get("width").and_then(JsonValue::as_u64), Some(2));
+        let names: Vec<&str> = description.get("attributes").and_then(JsonValue::as_array).unwrap().iter()
+            .map(|attribute| attribute.get("name").and_then(JsonValue::as_str).unwrap())
+            .collect();
+        assert_eq!(names, ["grass", "height"]);
+        // Files are described without their data.
+        assert!(description.get("data").is_none());
 
-    let mut spatial_data_path = args.directory.clone();
-    spatial_data_path.push(args.output.unwrap_or(PathBuf::from("OutputFile.nsd")));
-    if let Err(_) = fs::write(&spatial_data_path, spatial_data_bytes) {
-        eprintln!("Could not save the spatial data file.");
-        exit(1);
+        fs::remove_file(&path).unwrap();
     }
 
-    println!("File {} has been generated successfully!", spatial_data_path.display());
+    /// Arguments of `nsdgen generate` for the directory.
+    fn generate_args(directory: &Path, args: &[&str]) -> GenerateArgs {
+        let directory = directory.display().to_string();
+        let command_line = ["nsdgen", "generate", directory.as_str()].into_iter().chain(args.iter().copied());
+        match CliArgs::try_parse_command_line(command_line).unwrap().command {
+            Some(Command::Generate(args)) => args,
+            _ => unreachable!("The command line starts with generate."),
+        }
+    }
 
-    let file_size = fs::metadata(&spatial_data_path)
-        .map_or(0, |metadata| metadata.file_size())
-        .separate_with_commas();
-    let duration = (Instant::now() - start)
-        .as_secs_f64();
-
-    println!("Stats:");
-    println!("    File size: {file_size} bytes");
-    println!("    Time took: {duration:.5} seconds");
+    #[test]
+    fn packs_only_the_selected_layers() {
+        let directory = env::temp_dir().join(format!("nsdgen-test-subset-{}", process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        for name in ["grass", "height", "rivers"] {
+            image::GrayImage::new(2, 1).save(directory.join(format!("{name}.png"))).unwrap();
+        }
+        let generate = |args: &[&str]| generate_in_memory(&generate_args(&directory, args), &mut GenerateSession::default());
+
+        let generated = generate(&["-w", "2", "-h", "1", "--layers", "grass,height", "--skip-layers", "height"]).unwrap();
+        let names: Vec<&str> = generated.layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["grass"]);
+        let generated = generate(&["-w", "2", "-h", "1", "--skip-layers", "rivers"]).unwrap();
+        let names: Vec<&str> = generated.layers.iter().map(|layer| layer.name.as_str()).collect();
+        assert_eq!(names, ["grass", "height"]);
+
+        let error = generate(&["-w", "2", "-h", "1", "--layers", "lakes"]).err().unwrap();
+        assert_eq!(error, "Layer lakes given to --layers or --skip-layers was not found.");
+        let error = generate(&["-w", "2", "-h", "1", "--skip-layers", "grass,height,rivers"]).err().unwrap();
+        assert_eq!(error, "No layers are left to store.");
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    /// Directory of the test with gray PNG sources of the given names and sizes.
+    fn source_directory(test: &str, sources: &[(&str, u32, u32)]) -> PathBuf {
+        let directory = env::temp_dir().join(format!("nsdgen-test-{test}-{}", process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        for (name, width, height) in sources {
+            image::GrayImage::new(*width, *height).save(directory.join(format!("{name}.png"))).unwrap();
+        }
+        directory
+    }
+
+    #[test]
+    fn fails_before_loading_sections_with_too_many_layers() {
+        let directory = source_directory("max-layers", &[("grass", 2, 1), ("height", 2, 1), ("rivers", 2, 1)]);
+        let generate = |args: &[&str]| generate_in_memory(&generate_args(&directory, args), &mut GenerateSession::default());
+        let error = generate(&["-w", "1", "-h", "0", "--max-layers", "2"]).err().unwrap();
+        assert_eq!(error, "The main section has 3 layers, but consumers load at most 2 (see --max-layers).");
+        assert!(generate(&["-w", "1", "-h", "0", "--max-layers", "3"]).is_ok());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn fails_on_sources_of_different_resolutions_when_required() {
+        let directory = source_directory("uniform", &[("grass", 2, 1), ("height", 4, 2), ("rivers", 2, 1)]);
+        let generate = |args: &[&str]| generate_in_memory(&generate_args(&directory, args), &mut GenerateSession::default());
+        let error = generate(&["-w", "1", "-h", "0", "--require-uniform-source"]).err().unwrap();
+        let (grass, height, rivers) = (directory.join("grass.png"), directory.join("height.png"), directory.join("rivers.png"));
+        assert_eq!(error, format!(
+            "The main section has sources of different resolutions (2x1: {}, {}; 4x2: {}).",
+            grass.display(), rivers.display(), height.display()
+        ));
+        assert!(generate(&["-w", "1", "-h", "0"]).is_ok());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn sizes_the_output_like_the_largest_source() {
+        // 3x2 has the most texels, even though 5x1 is wider.
+        let directory = source_directory("auto-size", &[("grass", 5, 1), ("height", 3, 2)]);
+        let dimensions = |auto_size: &str| {
+            let args = generate_args(&directory, &["--auto-size", auto_size]);
+            generate_in_memory(&args, &mut GenerateSession::default()).map(|generated| generated.dimensions)
+        };
+        assert_eq!(dimensions("exact"), Ok(LayerDimensions { width: 3, height: 2 }));
+        assert_eq!(dimensions("up"), Ok(LayerDimensions { width: 4, height: 2 }));
+        assert_eq!(dimensions("down"), Ok(LayerDimensions { width: 2, height: 2 }));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn reads_the_options_from_the_environment() {
+        // Only variables which don't change the results of the other tests, which parse arguments concurrently.
+        env::set_var("NSDGEN_THREADS", "3");
+        env::set_var("NSDGEN_RESIDENT_LAYERS", "2");
+        let directory = env::temp_dir();
+        let args = generate_args(&directory, &[]);
+        assert_eq!((args.threads, args.resident_layers), (Some(3), Some(2)));
+        // The command line takes precedence.
+        let args = generate_args(&directory, &["--threads", "5"]);
+        assert_eq!((args.threads, args.resident_layers), (Some(5), Some(2)));
+        env::remove_var("NSDGEN_THREADS");
+        env::remove_var("NSDGEN_RESIDENT_LAYERS");
+    }
+
+    #[test]
+    fn generates_every_output_of_the_manifest() {
+        let directory = source_directory("outputs", &[("grass", 2, 2), ("height", 2, 2)]);
+        fs::write(directory.join(DEFAULT_MANIFEST_NAME), r#"
+            [outputs.full]
+            width = 4
+            height = 4
+            [outputs.terrain]
+            file = "terrain_height.nsd"
+            layers = ["height"]
+        "#).unwrap();
+        let outputs = |args: &[&str]| {
+            let args = generate_args(&directory, &[&["--dry-run"], args].concat());
+            let outputs = generate_outputs(args, GenerateSession::default()).unwrap();
+            outputs.into_iter()
+                .map(|output| (output.path.file_name().unwrap().to_string_lossy().into_owned(), output.layers))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(outputs(&[]), [("full.nsd".to_string(), 2), ("terrain_height.nsd".to_string(), 1)]);
+        // An output named on the command line replaces the outputs of the manifest.
+        assert_eq!(outputs(&["-o", "single.nsd"]), [("single.nsd".to_string(), 2)]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn uses_the_defaults_of_the_preset_unless_given() {
+        let directory = source_directory("preset", &[("height", 3, 2)]);
+        let generate = |args: &[&str]| {
+            let args = generate_args(&directory, &[&["--preset", "unreal-landscape"], args].concat());
+            let generated = generate_in_memory(&args, &mut GenerateSession::default()).unwrap();
+            (generated.dimensions, generated.layers[0].format)
+        };
+        let uint = AttributeFormat::new(AttributeType::UInt, 2).unwrap();
+        assert_eq!(generate(&[]), (LayerDimensions { width: 4, height: 2 }, uint));
+        assert_eq!(generate(&["--auto-size", "exact"]), (LayerDimensions { width: 3, height: 2 }, uint));
+
+        let args = generate_args(&directory, &["--preset", "unreal-landscape", "--dry-run"]);
+        let output = generate_file(args, GenerateSession::default()).unwrap();
+        let name = format!("nsdgen-test-preset-{}_4x2.nsd", process::id());
+        assert_eq!(output.path.file_name().unwrap().to_string_lossy(), name);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    #[cfg(any(all(target_os = "linux", target_pointer_width = "64"), windows))]
+    fn fails_when_the_output_does_not_fit_on_the_disk() {
+        let path = env::temp_dir().join("terrain.nsd");
+        assert_eq!(check_disk_space(&path, 0), Ok(()));
+        let error = check_disk_space(&path, u64::MAX).unwrap_err();
+        assert!(error.contains("(see --skip-space-check)"), "{error}");
+    }
+
+    #[test]
+    fn keeps_the_intermediate_files_out_of_the_input_directory() {
+        let directory = source_directory("temp-dir", &[("height", 2, 2)]);
+        let temp_directory = directory.with_extension("temp");
+        let cache_directory = directory.with_extension("cache");
+        let (temp, cache) = (temp_directory.display().to_string(), cache_directory.display().to_string());
+        let args = generate_args(&directory, &["-w", "1", "-h", "1", "-o", "terrain.nsd", "--cache", "--temp-dir", &temp, "--cache-dir", &cache]);
+        let output = generate_file(args, GenerateSession::default()).unwrap();
+
+        assert_eq!(output.path, directory.join("terrain.nsd"));
+        assert_eq!(NsdReader::open(&output.path).unwrap().data(), [0; 4]);
+        assert!(fs::read_dir(&cache_directory).unwrap().next().is_some());
+        assert!(fs::read_dir(&temp_directory).unwrap().next().is_none());
+        let mut names: Vec<_> = fs::read_dir(&directory).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        names.sort();
+        assert_eq!(names, ["height.png", "terrain.nsd"]);
+
+        for directory in [directory, temp_directory, cache_directory] {
+            fs::remove_dir_all(directory).unwrap();
+        }
+    }
+
+    #[test]
+    fn posts_the_summary_of_completed_runs() {
+        assert!(parse_notify_url("https://example.com/hook").unwrap_err().contains("local relay"));
+        assert!(parse_notify_url("http:///hook").is_err());
+        assert!(parse_notify_url("ftp://example.com").is_err());
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = parse_notify_url(&format!("http://{}/runs", listener.local_addr().unwrap())).unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let request = read_request(&mut BufReader::new(&stream)).unwrap();
+            write_response(&mut &stream, 204, "text/plain", b"").unwrap();
+            request
+        });
+        let output = GeneratedOutput {
+            path: PathBuf::from("terrain.nsd"),
+            size: Some(1234),
+            layers: 2,
+            failed_layers: 1,
+            warnings: 0,
+            duration: Duration::from_secs(2),
+        };
+        let diagnostics = [Diagnostic {
+            severity: Severity::Error,
+            file: None,
+            phase: "loading",
+            message: "Could not load layer rivers".into(),
+        }];
+        notify_completion(&url, Path::new("terrain"), &Ok(vec![output]), &diagnostics, Duration::from_secs(3));
+
+        let request = server.join().unwrap();
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/runs"));
+        let summary = JsonValue::parse(std::str::from_utf8(&request.body).unwrap()).unwrap();
+        assert_eq!(summary.get("succeeded"), Some(&JsonValue::from(true)));
+        let outputs = summary.get("outputs").and_then(JsonValue::as_array).unwrap();
+        assert_eq!(outputs[0].get("size").and_then(JsonValue::as_u64), Some(1234));
+        assert_eq!(summary.get("failures").and_then(JsonValue::as_array).map(<[JsonValue]>::len), Some(1));
+    }
+
+    #[test]
+    fn names_the_output_after_the_input_directory() {
+        let directory = source_directory("default-name", &[("height", 2, 2)]);
+        let output = |args: &[&str]| {
+            let args = generate_args(&directory.join("."), &[&["--dry-run", "-w", "1", "-h", "1"], args].concat());
+            generate_file(args, GenerateSession::default()).map(|output| output.path.file_name().unwrap().to_owned())
+        };
+        let name = format!("nsdgen-test-default-name-{}", process::id());
+        assert_eq!(output(&[]), Ok(format!("{name}.nsd").into()));
+        assert_eq!(output(&["-o", "terrain.nsd"]), Ok("terrain.nsd".into()));
+        assert_eq!(output(&["--output-template", "{dir}_{width}x{height}.nsd"]), Ok(format!("{name}_2x2.nsd").into()));
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn derives_the_height_from_the_aspect_ratio() {
+        assert_eq!(parse_aspect("2:1"), Ok((2, 1)));
+        for invalid in ["2", "2:0", "a:1", "2:1:1"] {
+            assert!(parse_aspect(invalid).is_err(), "{invalid} was accepted");
+        }
+        assert_eq!(aspect_dimensions(8, (2, 1)), Ok(LayerDimensions { width: 256, height: 128 }));
+        assert_eq!(aspect_dimensions(8, (16, 9)), Ok(LayerDimensions { width: 256, height: 144 }));
+        assert!(aspect_dimensions(8, (3, 1)).unwrap_err().contains("can't be divided"));
+        assert!(aspect_dimensions(12, (1, 2)).unwrap_err().contains("8192 texels high"));
+    }
 }
@@ -0,0 +1,891 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::alpha::AlphaMode;
+use crate::classify::{parse_color, ColorClasses};
+use crate::distance::DistanceField;
+use crate::expression::Expression;
+use crate::format::{AttributeFormat, AttributeType, NsdAttributeMetadata, NsdExtensions, NsdGroup, NsdHeader, NsdWorldScale};
+use crate::layer::{Layer, LayerDimensions, LayerSource};
+use crate::mosaic::MosaicConfig;
+use crate::nodata::{NoData, NoDataSource};
+use crate::pipeline::Operation;
+use crate::regions::Connectivity;
+use crate::reload::ReloadNotifications;
+use crate::resample::{EdgeMode, ResizeFilter, ValueKind};
+use crate::tiled::TiledConfig;
+use crate::toml::{self, Table, Value};
+
+/// Variables available to the expressions of layers besides the `--define`d ones and other layers:
+/// the texel's value (from 0 to 1 for integer sources), its coordinates, the coordinates of its center
+/// normalized to 0-1, and the dimensions of the layer.
+pub const EXPRESSION_VARIABLES: [&str; 7] = ["value", "x", "y", "u", "v", "width", "height"];
+
+/// Manifest picked up from the input directory when none is given explicitly.
+pub const DEFAULT_MANIFEST_NAME: &str = "nsdgen.toml";
+
+/// Distance in texels mapped to the end of the attribute range by distance fields without `max_distance`.
+const DEFAULT_MAX_DISTANCE: f64 = 16.0;
+
+/// Generation settings which don't fit on the command line.
+#[derive(Clone, Default)]
+pub struct Manifest {
+    pub header: NsdHeader,
+    /// Per-layer settings, keyed by the layer file name without extension.
+    pub layers: BTreeMap<String, LayerConfig>,
+    /// Attribute categories, mapping group names to the names of their layers.
+    pub groups: BTreeMap<String, Vec<String>>,
+    pub tiled: TiledConfig,
+    /// Additional sections at their own resolution, keyed by section name.
+    pub sections: BTreeMap<String, SectionConfig>,
+    /// Attribute name of the layer masking every other layer, unless they have their own mask.
+    pub mask: Option<String>,
+    /// Notifications sent after the file is written, so running games or editors reload it.
+    pub reload: ReloadNotifications,
+    /// Placement of the main section in world space, stored in the file for consumers.
+    pub world: Option<WorldConfig>,
+    /// Files generated in one run instead of a single output, keyed by output name.
+    pub outputs: BTreeMap<String, OutputConfig>,
+}
+
+/// World placement from the `[world]` table, either explicit or spanning the given bounds.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum WorldConfig {
+    Scale(NsdWorldScale),
+    /// `[min_x, min_y, max_x, max_y]` covered by the whole main section, whatever its resolution.
+    Bounds([f64; 4]),
+}
+
+impl WorldConfig {
+    pub fn scale(&self, dimensions: &LayerDimensions) -> NsdWorldScale {
+        match *self {
+            WorldConfig::Scale(scale) => scale,
+            WorldConfig::Bounds([min_x, min_y, max_x, max_y]) => NsdWorldScale {
+                origin: [min_x, min_y],
+                units_per_texel: [(max_x - min_x) / dimensions.width as f64, (max_y - min_y) / dimensions.height as f64],
+                epsg: None,
+            },
+        }
+    }
+}
+
+/// One of several files generated from the same sources, with its own layers and settings.
+#[derive(Clone, Default, Debug)]
+pub struct OutputConfig {
+    /// File name inside the input directory, `<output name>.nsd` by default.
+    pub file: Option<PathBuf>,
+    /// Attribute names of the stored layers, all of them if empty.
+    pub layers: Vec<String>,
+    /// Size of the main section, instead of the one given on the command line.
+    pub dimensions: Option<LayerDimensions>,
+    pub upscale_filter: Option<ResizeFilter>,
+    pub downscale_filter: Option<ResizeFilter>,
+}
+
+/// Layers stored in an additional section of the file instead of the main one.
+#[derive(Clone)]
+pub struct SectionConfig {
+    pub dimensions: LayerDimensions,
+    /// Names of the attributes in the section.
+    pub layers: Vec<String>,
+}
+
+/// Layer sources split into the main section and the additional ones.
+pub struct SectionedSources {
+    pub main: Vec<LayerSource>,
+    /// Section name, dimensions and sources of every additional section.
+    pub sections: Vec<(String, LayerDimensions, Vec<LayerSource>)>,
+}
+
+#[derive(Clone, Default)]
+pub struct LayerConfig {
+    /// Attribute name, if it should differ from the file name.
+    pub name: Option<String>,
+    pub format: AttributeFormat,
+    /// What the attribute means, for people reading the file later.
+    pub description: String,
+    /// Unit of the values, e.g. meters or probability. Layers with a unit aren't normalized.
+    pub unit: String,
+    pub tags: BTreeMap<String, String>,
+    /// How resampling treats the borders of the layer.
+    pub edge: EdgeMode,
+    /// What transparent texels of the source mean.
+    pub alpha: AlphaMode,
+    /// Texels without valid data and the value stored for them.
+    pub nodata: Option<NoData>,
+    /// Attribute name of the layer whose texels in the lower half of its range are cleared in this layer,
+    /// either set to the nodata value or zero.
+    pub mask: Option<String>,
+    /// Attribute name of the layer subtracted from this one, e.g. a reference terrain for height deltas.
+    pub baseline: Option<String>,
+    /// Processing steps applied to the source image, in order.
+    pub pipeline: Vec<Operation>,
+    /// External command producing the image to load in place of the source, overriding `--preprocess`.
+    pub preprocess: Option<String>,
+    /// Per-texel math computing the stored values, see `EXPRESSION_VARIABLES` for its inputs.
+    pub expression: Option<Expression>,
+    /// Condition of storing the layer at all, with the target `width` and `height` and the `--define`d variables.
+    pub include: Option<Expression>,
+    /// Source value stored as zero by int attributes, the middle of the source range by default.
+    pub zero_point: Option<i64>,
+    /// Whether the values can be interpolated when resizing.
+    pub kind: ValueKind,
+    /// Whether the palette indices of an indexed PNG are stored instead of its colors.
+    pub indexed: bool,
+    /// Colors of the source mapped to the stored IDs, set by `--classify`.
+    pub classes: Option<ColorClasses>,
+    /// Grid of tile images stitched into the source of the layer, which has no file of its own.
+    pub mosaic: Option<MosaicConfig>,
+    /// Position among the attributes, lower first. Attributes of the same order are sorted by name.
+    pub order: i64,
+    /// Rank of the attribute when overlapping systems conflict at runtime, stored in the metadata.
+    pub priority: Option<i32>,
+    /// Weight of the attribute when the runtime blends overlapping systems, stored in the metadata.
+    pub weight: Option<f64>,
+    /// Radius in target texels of the falloff the edges are softened into after resizing.
+    pub feather: Option<f32>,
+    /// Distances to the resized mask stored instead of the mask.
+    pub distance_field: Option<DistanceField>,
+    /// IDs of the connected regions of the resized mask stored instead of the mask.
+    pub label_regions: Option<Connectivity>,
+}
+
+fn invalid_manifest(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn check_keys(table: &Table, context: &str, known_keys: &[&str]) -> io::Result<()> {
+    match table.keys().find(|key| !known_keys.contains(&key.as_str())) {
+        Some(key) => Err(invalid_manifest(format!("Unknown key `{key}` in {context}"))),
+        None => Ok(()),
+    }
+}
+
+fn get_table<'t>(table: &'t Table, key: &str, context: &str) -> io::Result<Option<&'t Table>> {
+    table.get(key)
+        .map(|value| value.as_table()
+            .ok_or_else(|| invalid_manifest(format!("`{key}` in {context} must be a table, got {}", value.type_name()))))
+        .transpose()
+}
+
+fn get_integer(table: &Table, key: &str, context: &str) -> io::Result<Option<i64>> {
+    table.get(key)
+        .map(|value| value.as_integer()
+            .ok_or_else(|| invalid_manifest(format!("`{key}` in {context} must be an integer, got {}", value.type_name()))))
+        .transpose()
+}
+
+fn get_bool(table: &Table, key: &str, context: &str) -> io::Result<Option<bool>> {
+    table.get(key)
+        .map(|value| value.as_bool()
+            .ok_or_else(|| invalid_manifest(format!("`{key}` in {context} must be a boolean, got {}", value.type_name()))))
+        .transpose()
+}
+
+fn get_str<'t>(table: &'t Table, key: &str, context: &str) -> io::Result<Option<&'t str>> {
+    table.get(key)
+        .map(|value| value.as_str()
+            .ok_or_else(|| invalid_manifest(format!("`{key}` in {context} must be a string, got {}", value.type_name()))))
+        .transpose()
+}
+
+/// Float, or integer converted to a float.
+fn get_number(table: &Table, key: &str, context: &str) -> io::Result<Option<f64>> {
+    table.get(key)
+        .map(|value| value.as_float().or(value.as_integer().map(|integer| integer as f64))
+            .ok_or_else(|| invalid_manifest(format!("`{key}` in {context} must be a number, got {}", value.type_name()))))
+        .transpose()
+}
+
+fn get_floats<const N: usize>(table: &Table, key: &str, context: &str) -> io::Result<Option<[f64; N]>> {
+    table.get(key)
+        .map(|value| value.as_array()
+            .and_then(|values| values.iter().map(|value| value.as_float()).collect::<Option<Vec<f64>>>())
+            .and_then(|values| values.try_into().ok())
+            .ok_or_else(|| invalid_manifest(format!("`{key}` in {context} must be an array of {N} numbers"))))
+        .transpose()
+}
+
+fn parse_world(table: &Table) -> io::Result<WorldConfig> {
+    check_keys(table, "[world]", &["origin", "units_per_texel", "bounds"])?;
+    let bounds = get_floats::<4>(table, "bounds", "[world]")?;
+    let origin = get_floats::<2>(table, "origin", "[world]")?;
+    // A single number applies to both axes.
+    let units_per_texel = match table.get("units_per_texel") {
+        Some(value) if value.as_float().is_some() => value.as_float().map(|units| [units, units]),
+        _ => get_floats::<2>(table, "units_per_texel", "[world]")?,
+    };
+
+    let world = match (bounds, origin, units_per_texel) {
+        (Some(bounds), None, None) => WorldConfig::Bounds(bounds),
+        (None, origin, Some(units_per_texel)) => WorldConfig::Scale(NsdWorldScale {
+            origin: origin.unwrap_or_default(),
+            units_per_texel,
+            epsg: None,
+        }),
+        (Some(_), _, _) => return Err(invalid_manifest(
+            "`bounds` in [world] can't be combined with `origin` or `units_per_texel`".into()
+        )),
+        (None, _, None) => return Err(invalid_manifest("[world] requires either `bounds` or `units_per_texel`".into())),
+    };
+
+    let values = match &world {
+        WorldConfig::Scale(scale) => [scale.origin, scale.units_per_texel].concat(),
+        WorldConfig::Bounds(bounds) => bounds.to_vec(),
+    };
+    if values.iter().any(|value| !value.is_finite()) {
+        return Err(invalid_manifest("The values in [world] must be finite".into()));
+    }
+    match world {
+        WorldConfig::Scale(scale) if scale.units_per_texel.contains(&0.0) => {
+            Err(invalid_manifest("`units_per_texel` in [world] can't be zero".into()))
+        }
+        WorldConfig::Bounds([min_x, min_y, max_x, max_y]) if min_x == max_x || min_y == max_y => {
+            Err(invalid_manifest("`bounds` in [world] must span an area".into()))
+        }
+        _ => Ok(world),
+    }
+}
+
+impl Manifest {
+    /// Returns the manifest path to use: the explicit one, or the default one if it exists in the input directory.
+    pub fn find(explicit_path: Option<&Path>, directory: &Path) -> Option<PathBuf> {
+        match explicit_path {
+            Some(path) => Some(path.to_path_buf()),
+            None => Some(directory.join(DEFAULT_MANIFEST_NAME)).filter(|path| path.is_file()),
+        }
+    }
+
+    pub fn load(path: &Path) -> io::Result<Manifest> {
+        let source = fs::read_to_string(path)?;
+        Manifest::parse(&source)
+            .map_err(|error| invalid_manifest(format!("{}: {error}", path.display())))
+    }
+
+    pub fn parse(source: &str) -> io::Result<Manifest> {
+        let root = toml::parse(source).map_err(invalid_manifest)?;
+        check_keys(&root, "manifest", &["header", "layers", "groups", "tiled", "sections", "mask", "reload", "world", "outputs"])?;
+
+        let mut manifest = Manifest {
+            mask: get_str(&root, "mask", "manifest")?.map(String::from),
+            ..Manifest::default()
+        };
+
+        if let Some(header) = get_table(&root, "header", "manifest")? {
+            check_keys(header, "[header]", &["vendor", "identifier"])?;
+            let default_header = NsdHeader::default();
+            let vendor = get_str(header, "vendor", "[header]")?.map_or(default_header.vendor(), String::from);
+            let identifier = get_str(header, "identifier", "[header]")?.map_or(default_header.identifier(), String::from);
+            manifest.header = NsdHeader::new(&vendor, &identifier)
+                .map_err(|error| invalid_manifest(error.to_string()))?;
+        }
+
+        if let Some(layers) = get_table(&root, "layers", "manifest")? {
+            for (name, layer) in layers {
+                let context = format!("[layers.{name}]");
+                let layer = layer.as_table()
+                    .ok_or_else(|| invalid_manifest(format!("{context} must be a table")))?;
+                manifest.layers.insert(name.clone(), LayerConfig::parse(layer, &context)?);
+            }
+        }
+
+        if let Some(groups) = get_table(&root, "groups", "manifest")? {
+            for (name, members) in groups {
+                let members = members.as_array()
+                    .and_then(|members| members.iter().map(|member| member.as_str().map(String::from)).collect())
+                    .ok_or_else(|| invalid_manifest(format!("Group `{name}` must be an array of layer names")))?;
+                manifest.groups.insert(name.clone(), members);
+            }
+        }
+
+        if let Some(tiled) = get_table(&root, "tiled", "manifest")? {
+            check_keys(tiled, "[tiled]", &["texels_per_tile", "layers", "property"])?;
+            if let Some(texels_per_tile) = get_integer(tiled, "texels_per_tile", "[tiled]")? {
+                manifest.tiled.texels_per_tile = u32::try_from(texels_per_tile).ok().filter(|&texels| texels > 0)
+                    .ok_or_else(|| invalid_manifest(format!("Invalid texels_per_tile {texels_per_tile} in [tiled]")))?;
+            }
+            if let Some(layers) = tiled.get("layers") {
+                let layers = layers.as_array()
+                    .and_then(|layers| layers.iter().map(|layer| layer.as_str().map(String::from)).collect())
+                    .ok_or_else(|| invalid_manifest("`layers` in [tiled] must be an array of tile layer names".into()))?;
+                manifest.tiled.layers = Some(layers);
+            }
+            manifest.tiled.property = get_str(tiled, "property", "[tiled]")?.map(String::from);
+        }
+
+        if let Some(sections) = get_table(&root, "sections", "manifest")? {
+            for (name, section) in sections {
+                let context = format!("[sections.{name}]");
+                let section = section.as_table()
+                    .ok_or_else(|| invalid_manifest(format!("{context} must be a table")))?;
+                let section = SectionConfig::parse(section, &context)?;
+                for layer in &section.layers {
+                    if let Some((other, _)) = manifest.sections.iter().find(|(_, other)| other.layers.contains(layer)) {
+                        return Err(invalid_manifest(format!("Layer {layer} belongs to both {other} and {name} sections")));
+                    }
+                }
+                manifest.sections.insert(name.clone(), section);
+            }
+        }
+
+        if let Some(reload) = get_table(&root, "reload", "manifest")? {
+            check_keys(reload, "[reload]", &["touch", "udp", "webhook"])?;
+            manifest.reload = ReloadNotifications {
+                touch: get_str(reload, "touch", "[reload]")?.map(PathBuf::from),
+                udp: get_str(reload, "udp", "[reload]")?.map(String::from),
+                webhook: get_str(reload, "webhook", "[reload]")?.map(String::from),
+            };
+            if manifest.reload.webhook.as_ref().is_some_and(|url| !url.starts_with("http://")) {
+                return Err(invalid_manifest("`webhook` in [reload] must be an http:// URL".into()));
+            }
+        }
+
+        if let Some(world) = get_table(&root, "world", "manifest")? {
+            manifest.world = Some(parse_world(world)?);
+        }
+
+        if let Some(outputs) = get_table(&root, "outputs", "manifest")? {
+            for (name, output) in outputs {
+                let context = format!("[outputs.{name}]");
+                let output = output.as_table()
+                    .ok_or_else(|| invalid_manifest(format!("{context} must be a table")))?;
+                manifest.outputs.insert(name.clone(), OutputConfig::parse(output, &context)?);
+            }
+        }
+
+        Ok(manifest)
+    }
+
+    /// Builds the optional chunks for the given (sorted) layers.
+    pub fn make_extensions(&self, layers: &[Layer]) -> io::Result<NsdExtensions> {
+        let mut extensions = NsdExtensions::default();
+
+        for (name, members) in &self.groups {
+            let mut attributes = vec![];
+            for member in members {
+                let index = layers.iter().position(|layer| layer.name == *member)
+                    .ok_or_else(|| invalid_manifest(format!("Group {name} references layer {member}, which was not found")))?;
+                if let Some(other) = extensions.groups.iter().find(|group| group.attributes.contains(&(index as u32))) {
+                    return Err(invalid_manifest(format!("Layer {member} belongs to both {} and {name} groups", other.name)));
+                }
+                attributes.push(index as u32);
+            }
+            extensions.groups.push(NsdGroup {
+                name: name.clone(),
+                attributes,
+            });
+        }
+
+        for (index, layer) in layers.iter().enumerate() {
+            let config = self.layer(&layer.source.stem);
+            let described = !config.description.is_empty() || !config.tags.is_empty() || !config.unit.is_empty();
+            let ranked = config.priority.is_some() || config.weight.is_some();
+            let kind = config.stored_kind();
+            if described || ranked || config.nodata.is_some() || kind != ValueKind::Continuous {
+                extensions.attribute_metadata.push(NsdAttributeMetadata {
+                    attribute: index as u32,
+                    description: config.description,
+                    tags: config.tags,
+                    nodata: config.nodata.map(|nodata| nodata.value),
+                    unit: config.unit,
+                    kind,
+                    priority: config.priority,
+                    weight: config.weight,
+                });
+            }
+        }
+
+        Ok(extensions)
+    }
+
+    /// Splits the (sorted) sources by the section their attributes belong to, keeping their order.
+    pub fn split_sections(&self, sources: Vec<LayerSource>) -> io::Result<SectionedSources> {
+        let mut sections: Vec<(String, LayerDimensions, Vec<LayerSource>)> = self.sections.iter()
+            .map(|(name, section)| (name.clone(), section.dimensions.clone(), vec![]))
+            .collect();
+        let mut main = vec![];
+        for source in sources {
+            let attribute_name = self.attribute_name(&source);
+            match self.sections.values().position(|section| section.layers.contains(&attribute_name)) {
+                Some(index) => sections[index].2.push(source),
+                None => main.push(source),
+            }
+        }
+
+        for ((name, _, sources), section) in sections.iter().zip(self.sections.values()) {
+            for layer in &section.layers {
+                if !sources.iter().any(|source| self.attribute_name(source) == *layer) {
+                    return Err(invalid_manifest(format!("Section {name} references layer {layer}, which was not found")));
+                }
+            }
+        }
+        if main.is_empty() {
+            return Err(invalid_manifest("At least one layer must remain in the main section".into()));
+        }
+
+        Ok(SectionedSources { main, sections })
+    }
+
+    /// Name of the attribute produced by the given layer.
+    pub fn attribute_name(&self, source: &LayerSource) -> String {
+        self.layers.get(&source.stem).and_then(|config| config.name.clone()).unwrap_or(source.stem.clone())
+    }
+
+    /// Settings of the layer loaded from a file with the given stem, or the defaults if the manifest doesn't mention it.
+    pub fn layer(&self, stem: &str) -> LayerConfig {
+        let mut config = self.layers.get(stem).cloned().unwrap_or_default();
+        config.mask = config.mask.or(self.mask.clone())
+            // A mask layer doesn't mask itself.
+            .filter(|mask| *mask != config.name.as_deref().unwrap_or(stem));
+        config
+    }
+
+    /// Attribute names of the layers used as masks.
+    pub fn mask_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.mask.iter().chain(self.layers.values().filter_map(|config| config.mask.as_ref()))
+            .map(String::as_str)
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Attribute names of the layers used as baselines or as inputs of expressions.
+    /// Variables of expressions are inputs unless they're built in or defined.
+    pub fn reference_names(&self, defines: &BTreeMap<String, f64>) -> Vec<&str> {
+        let baselines = self.layers.values().filter_map(|config| config.baseline.as_deref());
+        let inputs = self.layers.values()
+            .filter_map(|config| config.expression.as_ref())
+            .flat_map(|expression| expression.variables())
+            .map(String::as_str)
+            .filter(|name| !EXPRESSION_VARIABLES.contains(name) && !defines.contains_key(*name));
+        let mut names: Vec<&str> = baselines.chain(inputs).collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    /// Whether the `include` condition of the layer holds.
+    pub fn is_included(&self, source: &LayerSource, dimensions: &LayerDimensions, defines: &BTreeMap<String, f64>) -> io::Result<bool> {
+        let Some(condition) = self.layer(&source.stem).include else {
+            return Ok(true);
+        };
+        condition
+            .evaluate_with(|name| match name {
+                "width" => Some(dimensions.width as f64),
+                "height" => Some(dimensions.height as f64),
+                _ => defines.get(name).copied(),
+            })
+            .map(|value| value != 0.0)
+            .map_err(|error| invalid_manifest(format!("{error} in `include` of layer {}", source.stem)))
+    }
+}
+
+impl LayerConfig {
+    /// Kind of the values stored in the file, which differs from the kind of the source for distance fields
+    /// and region labels.
+    pub fn stored_kind(&self) -> ValueKind {
+        match (self.distance_field, self.label_regions) {
+            (Some(_), _) => ValueKind::Continuous,
+            (None, Some(_)) => ValueKind::Categorical,
+            (None, None) => self.kind,
+        }
+    }
+
+    fn parse(table: &Table, context: &str) -> io::Result<LayerConfig> {
+        check_keys(table, context, &["name", "type", "size", "description", "unit", "tags", "edge", "alpha", "nodata", "nodata_color", "zero_point", "indexed", "kind", "mask", "baseline", "pipeline", "preprocess", "expression", "include", "mosaic", "order", "priority", "weight", "feather", "distance_field", "max_distance", "label_regions"])?;
+
+        let mut config = LayerConfig {
+            name: get_str(table, "name", context)?.map(String::from),
+            mask: get_str(table, "mask", context)?.map(String::from),
+            baseline: get_str(table, "baseline", context)?.map(String::from),
+            preprocess: get_str(table, "preprocess", context)?.map(String::from),
+            ..LayerConfig::default()
+        };
+
+        let attribute_type = match get_str(table, "type", context)? {
+            Some(name) => AttributeType::from_name(name)
+                .ok_or_else(|| invalid_manifest(format!("Unknown attribute type `{name}` in {context}")))?,
+            None => config.format.attribute_type,
+        };
+        let size = match get_integer(table, "size", context)? {
+            Some(size) => u8::try_from(size)
+                .map_err(|_| invalid_manifest(format!("Invalid size {size} in {context}")))?,
+            None => attribute_type.default_size(),
+        };
+        config.format = AttributeFormat::new(attribute_type, size)
+            .map_err(|error| invalid_manifest(format!("{error} in {context}")))?;
+
+        config.zero_point = get_integer(table, "zero_point", context)?;
+        if config.zero_point.is_some() && attribute_type != AttributeType::Int {
+            return Err(invalid_manifest(format!("`zero_point` in {context} requires type = \"int\"")));
+        }
+
+        if let Some(pipeline) = table.get("pipeline") {
+            let steps = pipeline.as_array()
+                .ok_or_else(|| invalid_manifest(format!("`pipeline` in {context} must be an array, got {}", pipeline.type_name())))?;
+            config.pipeline = steps.iter()
+                .map(|step| Operation::parse(step).map_err(|error| invalid_manifest(format!("{error} in {context}"))))
+                .collect::<io::Result<_>>()?;
+        }
+
+        for (key, expression) in [("expression", &mut config.expression), ("include", &mut config.include)] {
+            if let Some(source) = get_str(table, key, context)? {
+                *expression = Some(Expression::parse(source)
+                    .map_err(|error| invalid_manifest(format!("{error} in `{key}` of {context}")))?);
+            }
+        }
+
+        if let Some(mosaic) = get_table(table, "mosaic", context)? {
+            config.mosaic = Some(MosaicConfig::parse(mosaic).map_err(|error| invalid_manifest(format!("{error} in {context}")))?);
+        }
+
+        config.indexed = get_bool(table, "indexed", context)?.unwrap_or_default();
+        config.order = get_integer(table, "order", context)?.unwrap_or_default();
+        config.priority = get_integer(table, "priority", context)?
+            .map(|priority| i32::try_from(priority)
+                .map_err(|_| invalid_manifest(format!("`priority` {priority} in {context} is out of range"))))
+            .transpose()?;
+        config.weight = get_number(table, "weight", context)?;
+        config.feather = get_number(table, "feather", context)?
+            .map(|radius| match radius > 0.0 {
+                true => Ok(radius as f32),
+                false => Err(invalid_manifest(format!("`feather` in {context} must be positive"))),
+            })
+            .transpose()?;
+        let max_distance = get_number(table, "max_distance", context)?;
+        config.distance_field = match (get_str(table, "distance_field", context)?, max_distance) {
+            (_, Some(max_distance)) if max_distance <= 0.0 => {
+                return Err(invalid_manifest(format!("`max_distance` in {context} must be positive")));
+            }
+            (Some(mode), max_distance) => Some(
+                DistanceField::from_name(mode, max_distance.unwrap_or(DEFAULT_MAX_DISTANCE) as f32)
+                    .ok_or_else(|| invalid_manifest(format!(
+                        "Unknown distance field `{mode}` in {context}, expected unsigned or signed"
+                    )))?
+            ),
+            (None, Some(_)) => return Err(invalid_manifest(format!("`max_distance` in {context} requires `distance_field`"))),
+            (None, None) => None,
+        };
+        config.label_regions = get_integer(table, "label_regions", context)?
+            .map(|connectivity| Connectivity::from_number(connectivity).ok_or_else(|| invalid_manifest(format!(
+                "`label_regions` in {context} must be 4 or 8"
+            ))))
+            .transpose()?;
+        if config.label_regions.is_some() && (config.distance_field.is_some() || config.feather.is_some()) {
+            return Err(invalid_manifest(format!(
+                "`label_regions` in {context} can't be combined with `distance_field` or `feather`"
+            )));
+        }
+        config.unit = get_str(table, "unit", context)?.unwrap_or_default().into();
+        // Palette indices and values in the ID unit are IDs unless stated otherwise, e.g. for a palette of gradient steps.
+        config.kind = match get_str(table, "kind", context)? {
+            Some(kind) => ValueKind::from_name(kind).ok_or_else(|| invalid_manifest(format!(
+                "Unknown kind `{kind}` in {context}, expected continuous, categorical or mask"
+            )))?,
+            None if config.indexed || config.unit.eq_ignore_ascii_case("id") => ValueKind::Categorical,
+            None => ValueKind::Continuous,
+        };
+
+        if let Some(edge) = get_str(table, "edge", context)? {
+            config.edge = EdgeMode::from_name(edge)
+                .ok_or_else(|| invalid_manifest(format!("Unknown edge mode `{edge}` in {context}, expected clamp or wrap")))?;
+        }
+
+        let nodata = get_number(table, "nodata", context)?;
+        let nodata_color = match get_str(table, "nodata_color", context)? {
+            Some(color) => Some(parse_color(color)
+                .ok_or_else(|| invalid_manifest(format!("Invalid nodata color `{color}` in {context}, expected #rrggbb")))?),
+            None => None,
+        };
+        let alpha = get_str(table, "alpha", context)?;
+        let nodata_source = match (alpha, nodata_color) {
+            (Some("mask"), Some(_)) => return Err(invalid_manifest(format!(
+                "`nodata_color` in {context} can't be combined with alpha = \"mask\""
+            ))),
+            (Some("mask"), None) => Some(NoDataSource::Transparency),
+            (_, Some(color)) => Some(NoDataSource::Color(color)),
+            (_, None) => nodata.map(|_| NoDataSource::Value),
+        };
+        config.nodata = nodata_source.map(|source| NoData { value: nodata.unwrap_or(0.0), source });
+        config.alpha = match alpha {
+            None | Some("ignore" | "mask") => AlphaMode::Ignore,
+            Some("premultiply") => AlphaMode::Premultiply,
+            Some(alpha) => return Err(invalid_manifest(format!(
+                "Unknown alpha mode `{alpha}` in {context}, expected ignore, premultiply or mask"
+            ))),
+        };
+
+        config.description = get_str(table, "description", context)?.unwrap_or_default().into();
+        if let Some(tags) = get_table(table, "tags", context)? {
+            for (key, value) in tags {
+                let value = match value {
+                    Value::String(string) => string.clone(),
+                    Value::Integer(integer) => integer.to_string(),
+                    Value::Float(float) => float.to_string(),
+                    Value::Boolean(boolean) => boolean.to_string(),
+                    _ => return Err(invalid_manifest(format!("Tag `{key}` in {context} must be a scalar value"))),
+                };
+                config.tags.insert(key.clone(), value);
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl OutputConfig {
+    fn parse(table: &Table, context: &str) -> io::Result<OutputConfig> {
+        check_keys(table, context, &["file", "layers", "width", "height", "upscale_filter", "downscale_filter"])?;
+
+        let mut output = OutputConfig {
+            file: get_str(table, "file", context)?.map(PathBuf::from),
+            ..OutputConfig::default()
+        };
+        if let Some(layers) = table.get("layers") {
+            output.layers = layers.as_array()
+                .and_then(|layers| layers.iter().map(|layer| layer.as_str().map(String::from)).collect())
+                .ok_or_else(|| invalid_manifest(format!("`layers` in {context} must be an array of layer names")))?;
+        }
+
+        let width = get_integer(table, "width", context)?;
+        let height = get_integer(table, "height", context)?;
+        output.dimensions = match (width, height) {
+            (Some(width), Some(height)) => {
+                let [width, height] = [(width, "width"), (height, "height")].map(|(size, key)| {
+                    u32::try_from(size).ok().filter(|size| (1..=4096).contains(size))
+                        .ok_or_else(|| invalid_manifest(format!("Invalid {key} {size} in {context}, expected 1-4096")))
+                });
+                Some(LayerDimensions { width: width?, height: height? })
+            }
+            (None, None) => None,
+            _ => return Err(invalid_manifest(format!("`width` and `height` in {context} must be given together"))),
+        };
+
+        for (key, filter) in [("upscale_filter", &mut output.upscale_filter), ("downscale_filter", &mut output.downscale_filter)] {
+            if let Some(name) = get_str(table, key, context)? {
+                *filter = Some(ResizeFilter::from_name(name)
+                    .ok_or_else(|| invalid_manifest(format!("Unknown filter `{name}` in `{key}` of {context}")))?);
+            }
+        }
+
+        Ok(output)
+    }
+}
+
+impl SectionConfig {
+    fn parse(table: &Table, context: &str) -> io::Result<SectionConfig> {
+        check_keys(table, context, &["width", "height", "layers"])?;
+
+        let mut dimensions = [0u32; 2];
+        for (value, key) in dimensions.iter_mut().zip(["width", "height"]) {
+            let size = get_integer(table, key, context)?
+                .ok_or_else(|| invalid_manifest(format!("`{key}` is required in {context}")))?;
+            *value = u32::try_from(size).ok().filter(|size| (1..=4096).contains(size))
+                .ok_or_else(|| invalid_manifest(format!("Invalid {key} {size} in {context}, expected 1-4096")))?;
+        }
+
+        let layers = table.get("layers")
+            .and_then(|layers| layers.as_array())
+            .and_then(|layers| layers.iter().map(|layer| layer.as_str().map(String::from)).collect())
+            .ok_or_else(|| invalid_manifest(format!("`layers` in {context} must be an array of layer names")))?;
+
+        Ok(SectionConfig {
+            dimensions: LayerDimensions { width: dimensions[0], height: dimensions[1] },
+            layers,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::texels::TexelBuffer;
+
+    #[test]
+    fn parses_a_manifest() {
+        let manifest = Manifest::parse(r#"
+            mask = "playable"
+            [header]
+            vendor = "ABC"
+            identifier = "Terrain"
+            [layers.height]
+            name = "elevation"
+            type = "uint"
+            size = 2
+            unit = "meters"
+            edge = "wrap"
+            tags = { owner = "environment" }
+            [groups]
+            terrain = ["elevation", "grass"]
+            [tiled]
+            texels_per_tile = 4
+            [sections.coarse]
+            width = 64
+            height = 32
+            layers = ["biomes"]
+            [world]
+            units_per_texel = 2.5
+        "#).unwrap();
+
+        assert_eq!((manifest.header.vendor(), manifest.header.identifier()), ("ABC".into(), "Terrain".into()));
+        assert_eq!(manifest.mask.as_deref(), Some("playable"));
+        let height = manifest.layer("height");
+        assert_eq!(height.name.as_deref(), Some("elevation"));
+        assert_eq!(height.format, AttributeFormat::new(AttributeType::UInt, 2).unwrap());
+        assert_eq!(height.unit, "meters");
+        assert_eq!(height.edge, EdgeMode::Wrap);
+        assert_eq!(height.tags, BTreeMap::from([("owner".to_string(), "environment".to_string())]));
+        assert_eq!(manifest.layer("grass").format, AttributeFormat::default());
+        assert_eq!(manifest.groups["terrain"], ["elevation", "grass"]);
+        assert_eq!(manifest.tiled.texels_per_tile, 4);
+        assert_eq!(manifest.sections["coarse"].dimensions, LayerDimensions { width: 64, height: 32 });
+        assert_eq!(manifest.sections["coarse"].layers, ["biomes"]);
+        assert_eq!(manifest.world, Some(WorldConfig::Scale(NsdWorldScale { origin: [0.0; 2], units_per_texel: [2.5; 2], epsg: None })));
+    }
+
+    #[test]
+    fn rejects_invalid_manifests() {
+        let invalid = [
+            "unknown = 1",
+            "[layers.height]\nunknown = 1",
+            "[layers.height]\ntype = \"uint\"\nsize = 3",
+            "[layers.height]\ntype = \"double\"",
+            "[layers.height]\nsize = \"2\"",
+            "[header]\nvendor = \"TOOLONG\"",
+            "[groups]\nterrain = \"height\"",
+            "[tiled]\ntexels_per_tile = 0",
+            "[sections.coarse]\nwidth = 5000\nheight = 1\nlayers = []",
+            "[sections.a]\nwidth = 1\nheight = 1\nlayers = [\"x\"]\n[sections.b]\nwidth = 1\nheight = 1\nlayers = [\"x\"]",
+            "[reload]\nwebhook = \"https://example.com\"",
+            "[world]\nunits_per_texel = 0",
+            "[world]\nbounds = [0, 0, 1, 1]\norigin = [0, 0]",
+            "[layers.height",
+        ];
+        for source in invalid {
+            let error = Manifest::parse(source).err().unwrap_or_else(|| panic!("{source:?} was accepted"));
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData, "{source:?}: {error}");
+        }
+    }
+
+    fn layers(names: &[&str]) -> Vec<Layer> {
+        names.iter().map(|name| Layer {
+            name: name.to_string(),
+            texels: TexelBuffer::empty(1, 1, AttributeFormat::default()),
+            format: AttributeFormat::default(),
+            source: LayerSource::image(PathBuf::from(format!("{name}.png"))),
+        }).collect()
+    }
+
+    #[test]
+    fn groups_attributes_by_their_index() {
+        let manifest = Manifest::parse("[groups]\nterrain = [\"height\", \"grass\"]\nwater = [\"rivers\"]").unwrap();
+        let extensions = manifest.make_extensions(&layers(&["grass", "height", "rivers"])).unwrap();
+        assert_eq!(extensions.groups, [
+            NsdGroup { name: "terrain".into(), attributes: vec![1, 0] },
+            NsdGroup { name: "water".into(), attributes: vec![2] },
+        ]);
+
+        let error = |source: &str| Manifest::parse(source).unwrap().make_extensions(&layers(&["height"])).err().unwrap().to_string();
+        assert!(error("[groups]\na = [\"height\"]\nb = [\"height\"]").contains("belongs to both a and b groups"));
+        assert!(error("[groups]\na = [\"grass\"]").contains("references layer grass, which was not found"));
+    }
+
+    #[test]
+    fn describes_only_the_attributes_with_metadata() {
+        let manifest = Manifest::parse(r#"
+            [layers.height]
+            description = "Height above sea level"
+            tags = { owner = "environment" }
+        "#).unwrap();
+        let extensions = manifest.make_extensions(&layers(&["grass", "height"])).unwrap();
+        assert_eq!(extensions.attribute_metadata, [NsdAttributeMetadata {
+            attribute: 1,
+            description: "Height above sea level".into(),
+            tags: BTreeMap::from([("owner".into(), "environment".into())]),
+            ..NsdAttributeMetadata::default()
+        }]);
+    }
+
+    #[test]
+    fn stores_the_unit_and_the_kind_of_the_values() {
+        let manifest = Manifest::parse(r#"
+            [layers.biomes]
+            unit = "ID"
+            [layers.height]
+            unit = "meters"
+            [layers.playable]
+            kind = "mask"
+        "#).unwrap();
+        assert_eq!(manifest.layer("biomes").kind, ValueKind::Categorical);
+        assert_eq!(manifest.layer("height").kind, ValueKind::Continuous);
+        let extensions = manifest.make_extensions(&layers(&["biomes", "grass", "height", "playable"])).unwrap();
+        let described: Vec<_> = extensions.attribute_metadata.iter()
+            .map(|metadata| (metadata.attribute, metadata.unit.as_str(), metadata.kind))
+            .collect();
+        assert_eq!(described, [
+            (0, "ID", ValueKind::Categorical),
+            (2, "meters", ValueKind::Continuous),
+            (3, "", ValueKind::Mask),
+        ]);
+        assert!(Manifest::parse("[layers.height]\nkind = \"binary\"").is_err());
+    }
+
+    #[test]
+    fn stores_the_priority_and_the_weight_of_the_attributes() {
+        let manifest = Manifest::parse(r#"
+            [layers.height]
+            priority = -2
+            [layers.rivers]
+            weight = 1
+        "#).unwrap();
+        let extensions = manifest.make_extensions(&layers(&["grass", "height", "rivers"])).unwrap();
+        let ranked: Vec<_> = extensions.attribute_metadata.iter()
+            .map(|metadata| (metadata.attribute, metadata.priority, metadata.weight))
+            .collect();
+        assert_eq!(ranked, [(1, Some(-2), None), (2, None, Some(1.0))]);
+
+        let error = Manifest::parse("[layers.height]\npriority = 3000000000").err().unwrap();
+        assert!(error.to_string().contains("out of range"), "{error}");
+        assert!(Manifest::parse("[layers.height]\nweight = \"high\"").is_err());
+    }
+
+    #[test]
+    fn splits_the_sources_by_their_section() {
+        let manifest = Manifest::parse(r#"
+            [layers.biomes]
+            name = "biome"
+            [sections.coarse]
+            width = 2
+            height = 2
+            layers = ["biome", "rivers"]
+        "#).unwrap();
+        let sources = |names: &[&str]| layers(names).into_iter().map(|layer| layer.source).collect::<Vec<_>>();
+        let split = manifest.split_sections(sources(&["biomes", "grass", "height", "rivers"])).unwrap();
+        let stems = |sources: &[LayerSource]| sources.iter().map(|source| source.stem.clone()).collect::<Vec<_>>();
+        assert_eq!(stems(&split.main), ["grass", "height"]);
+        assert_eq!(split.sections.len(), 1);
+        let (name, dimensions, section) = &split.sections[0];
+        assert_eq!((name.as_str(), dimensions), ("coarse", &LayerDimensions { width: 2, height: 2 }));
+        assert_eq!(stems(section), ["biomes", "rivers"]);
+
+        let error = |names: &[&str]| manifest.split_sections(sources(names)).err().unwrap().to_string();
+        assert!(error(&["biomes", "grass"]).contains("references layer rivers, which was not found"));
+        assert!(error(&["biomes", "rivers"]).contains("At least one layer must remain in the main section"));
+    }
+
+    #[test]
+    fn places_the_texels_within_the_world_bounds() {
+        let manifest = Manifest::parse("[world]\nbounds = [100, 50, 164, 82]").unwrap();
+        let scale = manifest.world.unwrap().scale(&LayerDimensions { width: 32, height: 8 });
+        assert_eq!(scale, NsdWorldScale { origin: [100.0, 50.0], units_per_texel: [2.0, 4.0], epsg: None });
+        assert_eq!(scale.texel_to_world(32.0, 8.0), [164.0, 82.0]);
+        assert_eq!(scale.world_to_texel(101.0, 52.0), [0.5, 0.5]);
+
+        let manifest = Manifest::parse("[world]\norigin = [1, 2]\nunits_per_texel = [0.5, -0.5]").unwrap();
+        let scale = manifest.world.unwrap().scale(&LayerDimensions { width: 32, height: 8 });
+        assert_eq!(scale.texel_to_world(4.0, 4.0), [3.0, 0.0]);
+    }
+}
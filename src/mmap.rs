@@ -0,0 +1,133 @@
+//! Read-only memory maps of files, so large spatial data files can be parsed in place instead of being read into
+//! memory first. Platforms without `mmap` read the file instead.
+
+use std::fs::File;
+use std::io;
+use std::ops::Deref;
+use std::path::Path;
+
+/// Contents of a file, mapped into memory where supported. The file must not be modified while it's mapped.
+pub struct MappedFile {
+    map: map::Map,
+}
+
+impl MappedFile {
+    pub fn open(path: &Path) -> io::Result<MappedFile> {
+        let file = File::open(path)?;
+        let length = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "The file is too large to be mapped"))?;
+        Ok(MappedFile { map: map::Map::new(&file, length)? })
+    }
+}
+
+impl Deref for MappedFile {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.map.bytes()
+    }
+}
+
+#[cfg(all(unix, target_pointer_width = "64"))]
+mod map {
+    use std::ffi::c_void;
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::AsRawFd;
+
+    const PROT_READ: i32 = 1;
+    const MAP_PRIVATE: i32 = 2;
+
+    extern "C" {
+        fn mmap(address: *mut c_void, length: usize, protection: i32, flags: i32, fd: i32, offset: i64) -> *mut c_void;
+        fn munmap(address: *mut c_void, length: usize) -> i32;
+    }
+
+    pub struct Map {
+        address: *mut c_void,
+        length: usize,
+    }
+
+    // The mapping is read-only and owned by the map.
+    unsafe impl Send for Map {}
+    unsafe impl Sync for Map {}
+
+    impl Map {
+        pub fn new(file: &File, length: usize) -> io::Result<Map> {
+            // Empty mappings are invalid.
+            if length == 0 {
+                return Ok(Map { address: std::ptr::null_mut(), length });
+            }
+            let address = unsafe { mmap(std::ptr::null_mut(), length, PROT_READ, MAP_PRIVATE, file.as_raw_fd(), 0) };
+            if address as isize == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(Map { address, length })
+        }
+
+        pub fn bytes(&self) -> &[u8] {
+            match self.length {
+                0 => &[],
+                _ => unsafe { std::slice::from_raw_parts(self.address as *const u8, self.length) },
+            }
+        }
+    }
+
+    impl Drop for Map {
+        fn drop(&mut self) {
+            if self.length != 0 {
+                unsafe { munmap(self.address, self.length) };
+            }
+        }
+    }
+}
+
+#[cfg(not(all(unix, target_pointer_width = "64")))]
+mod map {
+    use std::fs::File;
+    use std::io::{self, Read};
+
+    pub struct Map {
+        bytes: Vec<u8>,
+    }
+
+    impl Map {
+        pub fn new(mut file: &File, length: usize) -> io::Result<Map> {
+            let mut bytes = Vec::with_capacity(length);
+            file.read_to_end(&mut bytes)?;
+            Ok(Map { bytes })
+        }
+
+        pub fn bytes(&self) -> &[u8] {
+            &self.bytes
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::reader::tests::{all_extensions, sample_file};
+    use crate::reader::NsdFileView;
+    use crate::testing::TempDirectory;
+    use crate::writer::write_parsed_file;
+
+    #[test]
+    fn parses_mapped_files_in_place() {
+        let directory = TempDirectory::new();
+        let path = directory.join("terrain.nsd");
+        let file = sample_file(all_extensions());
+        write_parsed_file(&mut File::create(&path).unwrap(), &file).unwrap();
+
+        let mapped = MappedFile::open(&path).unwrap();
+        assert_eq!(&mapped[..], fs::read(&path).unwrap());
+        let view = NsdFileView::parse(&mapped).unwrap();
+        assert_eq!(view.main_section().decompress().unwrap(), file.data());
+
+        fs::write(directory.join("empty.nsd"), []).unwrap();
+        assert!(MappedFile::open(&directory.join("empty.nsd")).unwrap().is_empty());
+        assert!(MappedFile::open(&directory.join("missing.nsd")).is_err());
+    }
+}
@@ -0,0 +1,354 @@
+//! JSON model of the structure of spatial data files, for debugging and exchanging files with other tools.
+//!
+//! A file is an object with the `vendor`, `identifier`, `width`, `height`, `texel_order`, `row_stride`, `world`
+//! (`origin`, `units_per_texel` and `epsg`, or null), `attributes` (`name`, `type`, `size` and their metadata),
+//! `groups`, `data` and `sections` (`name`, `width`, `height`, `attributes` and `data`). Attributes without metadata
+//! have no `description`, `tags`, `nodata`, `unit` and `kind`.
+//! Groups reference attributes by name. The texel data is uncompressed, without row padding and in the texel
+//! order of the file, either as a base64 string or as `{"file": "<path>"}` relative to the model.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::base64;
+use crate::format::{AttributeFormat, AttributeType, NsdAttributeMetadata, NsdExtensions, NsdGroup, NsdHeader, NsdWorldScale, TexelOrder};
+use crate::json::JsonValue;
+use crate::layer::LayerDimensions;
+use crate::reader::{NsdAttribute, NsdReader, NsdSection};
+use crate::resample::ValueKind;
+
+/// How the texel data is stored in the model.
+#[derive(Clone, Debug, PartialEq)]
+pub enum DataEncoding {
+    /// Left out, for describing the structure only. Such models can't be converted back.
+    Omitted,
+    /// Embedded as a base64 string.
+    Base64,
+    /// Referenced raw files, named after this prefix (see `data_files`), which the caller writes.
+    External(String),
+}
+
+/// Raw texel data of the main section and of every other section, named `<prefix>.data` and
+/// `<prefix>.<section>.data`.
+pub fn data_files<'a>(reader: &'a NsdReader, prefix: &str) -> Vec<(String, &'a [u8])> {
+    let sanitize = |name: &str| name.replace(|char: char| !(char.is_ascii_alphanumeric() || char == '_' || char == '-'), "_");
+    std::iter::once((format!("{prefix}.data"), reader.data()))
+        .chain(reader.sections().iter().map(|section| (format!("{prefix}.{}.data", sanitize(&section.name)), section.data.as_slice())))
+        .collect()
+}
+
+fn attribute_to_json(attribute: &NsdAttribute, metadata: Option<&NsdAttributeMetadata>) -> JsonValue {
+    let mut value = JsonValue::object()
+        .with("name", attribute.name.as_str())
+        .with("type", attribute.format.attribute_type.name())
+        .with("size", attribute.format.size);
+    if let Some(metadata) = metadata {
+        let tags = metadata.tags.iter().fold(JsonValue::object(), |tags, (key, value)| tags.with(key, value.as_str()));
+        value.insert("description", metadata.description.as_str());
+        value.insert("tags", tags);
+        value.insert("nodata", metadata.nodata);
+        value.insert("unit", metadata.unit.as_str());
+        value.insert("kind", metadata.kind.name());
+        value.insert("priority", metadata.priority.map(|priority| priority as f64));
+        value.insert("weight", metadata.weight);
+    }
+    value
+}
+
+fn insert_data(value: &mut JsonValue, data: &[u8], encoding: &DataEncoding, file: String) {
+    match encoding {
+        DataEncoding::Omitted => {}
+        DataEncoding::Base64 => value.insert("data", base64::encode(data)),
+        DataEncoding::External(_) => value.insert("data", JsonValue::object().with("file", file)),
+    }
+}
+
+/// The structure of the file, and its texel data unless it's omitted.
+pub fn file_to_json(reader: &NsdReader, encoding: &DataEncoding) -> JsonValue {
+    let mut files = match encoding {
+        DataEncoding::External(prefix) => data_files(reader, prefix).into_iter().map(|(file, _)| file).collect(),
+        _ => vec![String::new(); reader.sections().len() + 1],
+    }.into_iter();
+    let attributes: Vec<JsonValue> = reader.attributes().iter().enumerate()
+        .map(|(index, attribute)| attribute_to_json(attribute, reader.attribute_metadata(index)))
+        .collect();
+    let groups: Vec<JsonValue> = reader.groups().iter()
+        .map(|group| {
+            let attributes: Vec<JsonValue> = group.attributes.iter()
+                .map(|&index| reader.attributes()[index as usize].name.as_str().into())
+                .collect();
+            JsonValue::object().with("name", group.name.as_str()).with("attributes", attributes)
+        })
+        .collect();
+    let main_file = files.next().unwrap_or_default();
+    let sections: Vec<JsonValue> = reader.sections().iter().zip(files)
+        .map(|(section, file)| {
+            let mut value = JsonValue::object()
+                .with("name", section.name.as_str())
+                .with("width", section.dimensions.width)
+                .with("height", section.dimensions.height)
+                .with("attributes", section.attributes.iter().map(|attribute| attribute_to_json(attribute, None)).collect::<Vec<_>>());
+            insert_data(&mut value, &section.data, encoding, file);
+            value
+        })
+        .collect();
+
+    let mut value = JsonValue::object()
+        .with("vendor", reader.header().vendor())
+        .with("identifier", reader.header().identifier())
+        .with("width", reader.dimensions().width)
+        .with("height", reader.dimensions().height)
+        .with("texel_order", reader.texel_order().name())
+        .with("row_stride", reader.extensions().row_stride)
+        .with("world", reader.world().map(|world| JsonValue::object()
+            .with("origin", world.origin.to_vec())
+            .with("units_per_texel", world.units_per_texel.to_vec())
+            .with("epsg", world.epsg)))
+        .with("attributes", attributes)
+        .with("groups", groups);
+    insert_data(&mut value, reader.data(), encoding, main_file);
+    value.insert("sections", sections);
+    value
+}
+
+fn get<'a>(value: &'a JsonValue, key: &str, context: &str) -> Result<&'a JsonValue, String> {
+    value.get(key).ok_or_else(|| format!("{context} requires `{key}`"))
+}
+
+fn get_str<'a>(value: &'a JsonValue, key: &str, context: &str) -> Result<&'a str, String> {
+    get(value, key, context)?.as_str().ok_or_else(|| format!("`{key}` of {context} must be a string"))
+}
+
+fn get_u32(value: &JsonValue, key: &str, context: &str) -> Result<u32, String> {
+    get(value, key, context)?.as_u64().and_then(|number| u32::try_from(number).ok())
+        .ok_or_else(|| format!("`{key}` of {context} must be a non-negative integer"))
+}
+
+fn get_array<'a>(value: &'a JsonValue, key: &str, context: &str) -> Result<&'a [JsonValue], String> {
+    match value.get(key) {
+        Some(array) => array.as_array().ok_or_else(|| format!("`{key}` of {context} must be an array")),
+        None => Ok(&[]),
+    }
+}
+
+fn dimensions_from_json(value: &JsonValue, context: &str) -> Result<LayerDimensions, String> {
+    Ok(LayerDimensions { width: get_u32(value, "width", context)?, height: get_u32(value, "height", context)? })
+}
+
+fn data_from_json(value: &JsonValue, context: &str, directory: &Path) -> Result<Vec<u8>, String> {
+    let data = value.get("data").ok_or_else(|| format!("{context} has no texel `data`"))?;
+    if let Some(file) = data.get("file").and_then(JsonValue::as_str) {
+        let path = directory.join(file);
+        return fs::read(&path).map_err(|error| format!("Could not read the data of {context} from {}: {error}", path.display()));
+    }
+    let text = data.as_str().ok_or_else(|| format!("`data` of {context} must be a base64 string or {{\"file\": path}}"))?;
+    base64::decode(text).map_err(|error| format!("`data` of {context}: {error}"))
+}
+
+/// Attribute and its metadata, if it has any.
+fn attribute_from_json(value: &JsonValue, index: u32) -> Result<(NsdAttribute, Option<NsdAttributeMetadata>), String> {
+    let name = get_str(value, "name", "an attribute")?.to_string();
+    let context = format!("attribute {name}");
+    let type_name = get_str(value, "type", &context)?;
+    let attribute_type = AttributeType::from_name(type_name)
+        .ok_or_else(|| format!("Unknown type {type_name} of {context}"))?;
+    let size = get_u32(value, "size", &context)?;
+    let format = u8::try_from(size).ok()
+        .and_then(|size| AttributeFormat::new(attribute_type, size).ok())
+        .ok_or_else(|| format!("Unsupported size {size} of {context}"))?;
+
+    let has_metadata = ["description", "tags", "nodata", "unit", "kind"].iter().any(|key| value.get(key).is_some());
+    let metadata = match has_metadata {
+        true => {
+            let description = match value.get("description") {
+                Some(_) => get_str(value, "description", &context)?.to_string(),
+                None => String::new(),
+            };
+            let tags = match value.get("tags") {
+                Some(JsonValue::Object(entries)) => entries.iter()
+                    .map(|(key, tag)| match tag.as_str() {
+                        Some(tag) => Ok((key.clone(), tag.to_string())),
+                        None => Err(format!("Tag {key} of {context} must be a string")),
+                    })
+                    .collect::<Result<BTreeMap<_, _>, _>>()?,
+                Some(_) => return Err(format!("`tags` of {context} must be an object")),
+                None => BTreeMap::new(),
+            };
+            let nodata = match value.get("nodata") {
+                Some(JsonValue::Null) | None => None,
+                Some(nodata) => Some(nodata.as_f64().ok_or_else(|| format!("`nodata` of {context} must be a number"))?),
+            };
+            let unit = match value.get("unit") {
+                Some(_) => get_str(value, "unit", &context)?.to_string(),
+                None => String::new(),
+            };
+            let kind = match value.get("kind") {
+                Some(_) => {
+                    let name = get_str(value, "kind", &context)?;
+                    ValueKind::from_name(name).ok_or_else(|| format!("Unknown kind {name} of {context}"))?
+                }
+                None => ValueKind::Continuous,
+            };
+            let priority = match value.get("priority") {
+                Some(JsonValue::Null) | None => None,
+                Some(priority) => Some(priority.as_f64()
+                    .filter(|priority| priority.fract() == 0.0 && (i32::MIN as f64..=i32::MAX as f64).contains(priority))
+                    .ok_or_else(|| format!("`priority` of {context} must be a 32-bit integer"))? as i32),
+            };
+            let weight = match value.get("weight") {
+                Some(JsonValue::Null) | None => None,
+                Some(weight) => Some(weight.as_f64().ok_or_else(|| format!("`weight` of {context} must be a number"))?),
+            };
+            Some(NsdAttributeMetadata { attribute: index, description, tags, nodata, unit, kind, priority, weight })
+        }
+        false => None,
+    };
+    Ok((NsdAttribute { name, format }, metadata))
+}
+
+/// Builds the file described by a model with its texel data, the inverse of `file_to_json`.
+/// Referenced data files are read relative to the directory.
+pub fn file_from_json(value: &JsonValue, directory: &Path) -> Result<NsdReader, String> {
+    let header = NsdHeader::new(get_str(value, "vendor", "The file")?, get_str(value, "identifier", "The file")?)
+        .map_err(|error| error.to_string())?;
+    let dimensions = dimensions_from_json(value, "The file")?;
+
+    let mut attributes = vec![];
+    let mut attribute_metadata = vec![];
+    for (index, attribute) in get_array(value, "attributes", "The file")?.iter().enumerate() {
+        let (attribute, metadata) = attribute_from_json(attribute, index as u32)?;
+        attributes.push(attribute);
+        attribute_metadata.extend(metadata);
+    }
+
+    let groups = get_array(value, "groups", "The file")?.iter()
+        .map(|group| {
+            let name = get_str(group, "name", "a group")?.to_string();
+            let members = get_array(group, "attributes", &format!("group {name}"))?.iter()
+                .map(|member| {
+                    let member = member.as_str().ok_or_else(|| format!("Members of group {name} must be attribute names"))?;
+                    attributes.iter().position(|attribute| attribute.name == member)
+                        .map(|index| index as u32)
+                        .ok_or_else(|| format!("Group {name} references a nonexistent attribute {member}"))
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(NsdGroup { name, attributes: members })
+        })
+        .collect::<Result<_, String>>()?;
+
+    let texel_order = match value.get("texel_order") {
+        Some(_) => {
+            let name = get_str(value, "texel_order", "The file")?;
+            TexelOrder::from_name(name).ok_or_else(|| format!("Unknown texel order {name}"))?
+        }
+        None => TexelOrder::RowMajor,
+    };
+    let row_stride = match value.get("row_stride") {
+        Some(JsonValue::Null) | None => None,
+        Some(_) => Some(get_u32(value, "row_stride", "The file")?),
+    };
+    let world = match value.get("world") {
+        Some(JsonValue::Null) | None => None,
+        Some(world) => {
+            let pair = |key: &str| -> Result<[f64; 2], String> {
+                get_array(world, key, "The world")?.iter().map(|value| value.as_f64()).collect::<Option<Vec<f64>>>()
+                    .and_then(|values| values.try_into().ok())
+                    .ok_or_else(|| format!("`{key}` of the world must be an array of 2 numbers"))
+            };
+            let epsg = match world.get("epsg") {
+                Some(JsonValue::Null) | None => None,
+                Some(_) => Some(get_u32(world, "epsg", "The world")?),
+            };
+            Some(NsdWorldScale { origin: pair("origin")?, units_per_texel: pair("units_per_texel")?, epsg })
+        }
+    };
+    let extensions = NsdExtensions { groups, attribute_metadata, texel_order, row_stride, world };
+
+    let sections = get_array(value, "sections", "The file")?.iter()
+        .map(|section| {
+            let name = get_str(section, "name", "a section")?.to_string();
+            let context = format!("section {name}");
+            let attributes = get_array(section, "attributes", &context)?.iter()
+                .map(|attribute| Ok(attribute_from_json(attribute, 0)?.0))
+                .collect::<Result<_, String>>()?;
+            Ok(NsdSection {
+                dimensions: dimensions_from_json(section, &context)?,
+                data: data_from_json(section, &context, directory)?,
+                name,
+                attributes,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+
+    let data = data_from_json(value, "The file", directory)?;
+    NsdReader::from_parts(header, dimensions, attributes, data, extensions, sections).map_err(|error| error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::reader::tests::{all_extensions, assert_same_file, sample_file};
+    use crate::testing::TempDirectory;
+    use crate::writer::write_parsed_file;
+
+    fn model(reader: &NsdReader) -> String {
+        file_to_json(reader, &DataEncoding::Base64).to_string()
+    }
+
+    fn from_model(source: &str) -> Result<NsdReader, String> {
+        file_from_json(&JsonValue::parse(source)?, Path::new("."))
+    }
+
+    #[test]
+    fn round_trips_files() {
+        for extensions in [NsdExtensions::default(), all_extensions()] {
+            let file = sample_file(extensions);
+            assert_same_file(&from_model(&model(&file)).unwrap(), &file);
+        }
+    }
+
+    #[test]
+    fn rejects_omitted_data() {
+        let json = file_to_json(&sample_file(all_extensions()), &DataEncoding::Omitted);
+        assert!(file_from_json(&json, Path::new(".")).err().unwrap().contains("no texel `data`"));
+    }
+
+    #[test]
+    fn rejects_invalid_models() {
+        let source = model(&sample_file(all_extensions()));
+        let invalid = |from: &str, to: &str| {
+            assert!(source.contains(from), "{from} is not in the model");
+            from_model(&source.replacen(from, to, 1)).err().unwrap()
+        };
+        assert!(invalid("\"width\":3", "\"width\":4").contains("18 bytes"));
+        assert!(invalid("\"width\":3", "\"width\":-3").contains("width"));
+        assert!(invalid("\"vendor\":\"ABC\"", "\"vendor\":\"ABCDE\"").contains("ABCDE"));
+        assert!(invalid("\"type\":\"uint\"", "\"type\":\"complex\"").contains("complex"));
+        assert!(invalid("\"size\":2", "\"size\":3").contains("height"));
+        assert!(invalid("\"texel_order\":\"column-major\"", "\"texel_order\":\"diagonal\"").contains("diagonal"));
+        assert!(invalid("\"data\":\"", "\"data\":\"!").contains("invalid base64 character `!`"));
+        assert!(invalid("\"grass\"]", "\"rock\"]").contains("rock"));
+        assert!(from_model("[]").is_err());
+    }
+
+    #[test]
+    fn round_trips_files_with_external_data() {
+        let directory = TempDirectory::new();
+        let file = sample_file(all_extensions());
+        let json = file_to_json(&file, &DataEncoding::External("terrain".into()));
+        let files = data_files(&file, "terrain");
+        assert_eq!(files.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>(), ["terrain.data", "terrain.coarse.data"]);
+        for (name, data) in files {
+            fs::write(directory.join(name), data).unwrap();
+        }
+        let converted = file_from_json(&json, directory.path()).unwrap();
+        assert_same_file(&converted, &file);
+
+        let mut bytes = Cursor::new(vec![]);
+        write_parsed_file(&mut bytes, &converted).unwrap();
+        assert_same_file(&NsdReader::from_bytes(bytes.get_ref()).unwrap(), &file);
+    }
+}
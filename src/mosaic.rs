@@ -0,0 +1,174 @@
+//! Layers whose source is split into a grid of tile images, stitched together before they're processed.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use image::{imageops, DynamicImage, ImageBuffer, Pixel};
+
+use crate::toml::{Table, Value};
+
+/// Grid of tile images making up the source of a layer.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MosaicConfig {
+    /// File name of the tiles, where `{X}` and `{Y}` stand for the column and row numbers.
+    pub pattern: String,
+    pub columns: u32,
+    pub rows: u32,
+    /// Number of the first column and row in the file names.
+    pub first: u32,
+    /// Whether row `first` is the bottom row of the grid instead of the top one.
+    pub y_up: bool,
+}
+
+fn get_count(table: &Table, key: &str) -> Result<u32, String> {
+    let count = table.get(key).and_then(Value::as_integer)
+        .ok_or_else(|| format!("`mosaic` requires the number of `{key}`"))?;
+    u32::try_from(count).ok().filter(|&count| count > 0)
+        .ok_or_else(|| format!("Invalid number of {key} {count} in `mosaic`"))
+}
+
+impl MosaicConfig {
+    /// Parses the `mosaic` table of a layer.
+    pub fn parse(table: &Table) -> Result<MosaicConfig, String> {
+        if let Some(key) = table.keys().find(|key| !["pattern", "columns", "rows", "first", "y_up"].contains(&key.as_str())) {
+            return Err(format!("Unknown key `{key}` in `mosaic`"));
+        }
+        let pattern = table.get("pattern").and_then(Value::as_str)
+            .ok_or_else(|| "`mosaic` requires the `pattern` of the tile file names".to_string())?;
+        if !pattern.contains("{X}") || !pattern.contains("{Y}") {
+            return Err(format!("The mosaic pattern {pattern} must contain {{X}} and {{Y}}"));
+        }
+        let first = match table.get("first") {
+            Some(first) => first.as_integer().and_then(|first| u32::try_from(first).ok())
+                .ok_or_else(|| "`first` in `mosaic` must be a non-negative integer".to_string())?,
+            None => 0,
+        };
+        let y_up = match table.get("y_up") {
+            Some(y_up) => y_up.as_bool().ok_or_else(|| "`y_up` in `mosaic` must be a boolean".to_string())?,
+            None => false,
+        };
+        Ok(MosaicConfig {
+            pattern: pattern.to_string(),
+            columns: get_count(table, "columns")?,
+            rows: get_count(table, "rows")?,
+            first,
+            y_up,
+        })
+    }
+
+    /// File name of the tile in the given column and row of the grid, counted from the top left.
+    pub fn tile_name(&self, column: u32, row: u32) -> String {
+        let y = if self.y_up { self.rows - 1 - row } else { row };
+        self.pattern
+            .replace("{X}", &(self.first + column).to_string())
+            .replace("{Y}", &(self.first + y).to_string())
+    }
+
+    /// Paths of all tiles in the directory, row by row from the top left.
+    pub fn tile_paths(&self, directory: &Path) -> Vec<PathBuf> {
+        (0..self.rows)
+            .flat_map(|row| (0..self.columns).map(move |column| (column, row)))
+            .map(|(column, row)| directory.join(self.tile_name(column, row)))
+            .collect()
+    }
+
+    /// Whether the file name matches the pattern, with `{X}` and `{Y}` standing for any number.
+    /// Such files are tiles, even outside of the grid, and never become layers of their own.
+    pub fn matches(&self, file_name: &str) -> bool {
+        let pattern = self.pattern.replace("{Y}", "{X}");
+        matches_parts(&pattern.split("{X}").collect::<Vec<_>>(), file_name)
+    }
+}
+
+/// Matches the literal parts of a pattern, separated by numbers.
+fn matches_parts(parts: &[&str], name: &str) -> bool {
+    let Some(rest) = name.strip_prefix(parts[0]) else {
+        return false;
+    };
+    if parts.len() == 1 {
+        return rest.is_empty();
+    }
+    let digits = rest.len() - rest.trim_start_matches(|char: char| char.is_ascii_digit()).len();
+    // The number can be followed by more digits of the literal part.
+    (1..=digits).any(|length| matches_parts(&parts[1..], &rest[length..]))
+}
+
+fn stitch_buffers<P: Pixel>(
+    tiles: Vec<ImageBuffer<P, Vec<P::Subpixel>>>,
+    columns: u32,
+    rows: u32
+) -> ImageBuffer<P, Vec<P::Subpixel>> {
+    let (tile_width, tile_height) = tiles[0].dimensions();
+    let mut image = ImageBuffer::new(tile_width * columns, tile_height * rows);
+    for (index, tile) in tiles.iter().enumerate() {
+        let (column, row) = (index as u32 % columns, index as u32 / columns);
+        imageops::replace(&mut image, tile, (column * tile_width) as i64, (row * tile_height) as i64);
+    }
+    image
+}
+
+/// Stitches the tiles (row by row from the top left) into one image of the color type of the first tile.
+/// All tiles need to have the same dimensions.
+pub fn stitch(tiles: Vec<DynamicImage>, config: &MosaicConfig) -> io::Result<DynamicImage> {
+    let (width, height) = (tiles[0].width(), tiles[0].height());
+    if let Some(index) = tiles.iter().position(|tile| (tile.width(), tile.height()) != (width, height)) {
+        let (column, row) = (index as u32 % config.columns, index as u32 / config.columns);
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+            "Tile {} is {}x{}, but the other tiles are {width}x{height}",
+            config.tile_name(column, row), tiles[index].width(), tiles[index].height()
+        )));
+    }
+
+    let (columns, rows) = (config.columns, config.rows);
+    macro_rules! stitch_as {
+        ($variant:ident, $convert:ident) => {
+            DynamicImage::$variant(stitch_buffers(tiles.iter().map(DynamicImage::$convert).collect(), columns, rows))
+        };
+    }
+    Ok(match &tiles[0] {
+        DynamicImage::ImageLuma8(_) => stitch_as!(ImageLuma8, to_luma8),
+        DynamicImage::ImageLumaA8(_) => stitch_as!(ImageLumaA8, to_luma_alpha8),
+        DynamicImage::ImageRgb8(_) => stitch_as!(ImageRgb8, to_rgb8),
+        DynamicImage::ImageLuma16(_) => stitch_as!(ImageLuma16, to_luma16),
+        DynamicImage::ImageLumaA16(_) => stitch_as!(ImageLumaA16, to_luma_alpha16),
+        DynamicImage::ImageRgb16(_) => stitch_as!(ImageRgb16, to_rgb16),
+        DynamicImage::ImageRgba16(_) => stitch_as!(ImageRgba16, to_rgba16),
+        DynamicImage::ImageRgb32F(_) => stitch_as!(ImageRgb32F, to_rgb32f),
+        DynamicImage::ImageRgba32F(_) => stitch_as!(ImageRgba32F, to_rgba32f),
+        _ => stitch_as!(ImageRgba8, to_rgba8),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GrayImage;
+
+    use super::*;
+
+    fn config(y_up: bool) -> MosaicConfig {
+        MosaicConfig { pattern: "height_{X}_{Y}.png".into(), columns: 2, rows: 2, first: 1, y_up }
+    }
+
+    #[test]
+    fn names_the_tiles_of_the_grid() {
+        assert_eq!(config(false).tile_name(1, 0), "height_2_1.png");
+        assert_eq!(config(true).tile_name(1, 0), "height_2_2.png");
+        assert!(config(false).matches("height_12_3.png"));
+        assert!(!config(false).matches("height_1.png"));
+        assert!(!config(false).matches("height_a_1.png"));
+    }
+
+    #[test]
+    fn stitches_the_tiles_row_by_row() {
+        let tiles: Vec<DynamicImage> = (0..4)
+            .map(|index| DynamicImage::ImageLuma8(GrayImage::from_pixel(2, 1, image::Luma([index * 10]))))
+            .collect();
+        let stitched = stitch(tiles.clone(), &config(false)).unwrap();
+        assert_eq!(stitched.to_luma8().into_raw(), [0, 0, 10, 10, 20, 20, 30, 30]);
+
+        let mut uneven = tiles;
+        uneven[3] = DynamicImage::ImageLuma8(GrayImage::new(1, 1));
+        let error = stitch(uneven, &config(false)).unwrap_err();
+        assert_eq!(error.to_string(), "Tile height_2_2.png is 1x1, but the other tiles are 2x1");
+    }
+}
@@ -0,0 +1,110 @@
+//! Names of output files expanded from templates like `{dir}_{width}x{height}.nsd`, so the outputs of different
+//! inputs or settings don't overwrite each other.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Names of the placeholders of output templates.
+pub const OUTPUT_TEMPLATE_PLACEHOLDERS: [&str; 5] = ["dir", "width", "height", "layers", "date"];
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// Values of the placeholders of a generated file.
+pub struct OutputNameFields {
+    /// Name of the input directory.
+    pub dir: String,
+    pub width: u32,
+    pub height: u32,
+    /// Number of attributes of the main section.
+    pub layers: usize,
+    /// Generation date as `YYYY-MM-DD`.
+    pub date: String,
+}
+
+impl OutputNameFields {
+    fn get(&self, placeholder: &str) -> Option<String> {
+        Some(match placeholder {
+            "dir" => self.dir.clone(),
+            "width" => self.width.to_string(),
+            "height" => self.height.to_string(),
+            "layers" => self.layers.to_string(),
+            "date" => self.date.clone(),
+            _ => return None,
+        })
+    }
+}
+
+/// A file name with `{placeholder}`s, checked when it's parsed.
+#[derive(Clone, Debug)]
+pub struct OutputTemplate {
+    template: String,
+}
+
+impl OutputTemplate {
+    /// Splits the template into literal text and placeholder names, failing on unknown or unclosed placeholders.
+    fn parts(template: &str) -> io::Result<Vec<(bool, &str)>> {
+        let mut parts = vec![];
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            parts.push((false, &rest[..start]));
+            let end = rest[start..].find('}')
+                .ok_or_else(|| invalid_input(format!("Unclosed placeholder in output template `{template}`")))?;
+            let placeholder = &rest[start + 1..start + end];
+            if !OUTPUT_TEMPLATE_PLACEHOLDERS.contains(&placeholder) {
+                return Err(invalid_input(format!(
+                    "Unknown placeholder `{{{placeholder}}}` in output template `{template}`, expected one of {}",
+                    OUTPUT_TEMPLATE_PLACEHOLDERS.map(|name| format!("{{{name}}}")).join(", ")
+                )));
+            }
+            parts.push((true, placeholder));
+            rest = &rest[start + end + 1..];
+        }
+        parts.push((false, rest));
+        Ok(parts)
+    }
+
+    pub fn expand(&self, fields: &OutputNameFields) -> String {
+        Self::parts(&self.template).expect("The template has been checked when parsed.").into_iter()
+            .map(|(placeholder, part)| match placeholder {
+                true => fields.get(part).expect("Placeholders have been checked when parsed."),
+                false => part.to_string(),
+            })
+            .collect()
+    }
+}
+
+impl FromStr for OutputTemplate {
+    type Err = io::Error;
+
+    fn from_str(template: &str) -> io::Result<OutputTemplate> {
+        Self::parts(template)?;
+        Ok(OutputTemplate { template: template.to_string() })
+    }
+}
+
+/// Name of the directory, resolving `.` and other relative paths.
+pub fn directory_name(directory: &Path) -> Option<String> {
+    let directory = fs::canonicalize(directory).unwrap_or_else(|_| directory.to_path_buf());
+    directory.file_name().map(|name| name.to_string_lossy().into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_the_placeholders_of_templates() {
+        let fields = OutputNameFields { dir: "terrain".into(), width: 512, height: 256, layers: 3, date: "2024-05-01".into() };
+        let template: OutputTemplate = "{dir}_{width}x{height}_{layers}_{date}.nsd".parse().unwrap();
+        assert_eq!(template.expand(&fields), "terrain_512x256_3_2024-05-01.nsd");
+        assert_eq!("out.nsd".parse::<OutputTemplate>().unwrap().expand(&fields), "out.nsd");
+
+        let error = |template: &str| template.parse::<OutputTemplate>().unwrap_err().to_string();
+        assert!(error("{dir}_{size}.nsd").contains("Unknown placeholder `{size}`"));
+        assert!(error("{dir.nsd").contains("Unclosed placeholder"));
+    }
+}
@@ -0,0 +1,143 @@
+//! Texels without valid data, which store a dedicated nodata value so consumers can tell them apart from zero.
+
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, GrayImage, Luma};
+
+/// How texels without valid data are recognized in the source.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum NoDataSource {
+    /// Texels which are less than half opaque.
+    Transparency,
+    /// Texels of this sentinel color.
+    Color([u8; 3]),
+    /// Texels whose first channel already has the nodata value.
+    Value,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct NoData {
+    /// Value stored for the texels without data.
+    pub value: f64,
+    pub source: NoDataSource,
+}
+
+/// Mask of the texels with valid data (255) and without (0).
+pub fn nodata_mask(image: &DynamicImage, nodata: &NoData) -> GrayImage {
+    let (width, height) = (image.width(), image.height());
+    let bytes_per_channel = image.color().bytes_per_pixel() / image.color().channel_count();
+    let is_valid: Box<dyn Fn(u32, u32) -> bool> = match nodata.source {
+        NoDataSource::Transparency if !image.color().has_alpha() => Box::new(|_, _| true),
+        NoDataSource::Transparency => {
+            let rgba = image.to_rgba16();
+            Box::new(move |x, y| rgba.get_pixel(x, y).0[3] >= 32768)
+        }
+        NoDataSource::Color(color) => {
+            let rgb = image.to_rgb8();
+            Box::new(move |x, y| rgb.get_pixel(x, y).0 != color)
+        }
+        NoDataSource::Value if bytes_per_channel == 1 => {
+            let rgba = image.to_rgba8();
+            Box::new(move |x, y| rgba.get_pixel(x, y).0[0] as f64 != nodata.value)
+        }
+        NoDataSource::Value if bytes_per_channel == 2 => {
+            let rgba = image.to_rgba16();
+            Box::new(move |x, y| rgba.get_pixel(x, y).0[0] as f64 != nodata.value)
+        }
+        NoDataSource::Value => {
+            let rgba = image.to_rgba32f();
+            Box::new(move |x, y| rgba.get_pixel(x, y).0[0] != nodata.value as f32)
+        }
+    };
+    GrayImage::from_fn(width, height, |x, y| Luma([if is_valid(x, y) { u8::MAX } else { 0 }]))
+}
+
+/// Replaces the alpha channel of the image (adding one if needed) with the mask,
+/// which marks the texels without data for `TexelBuffer::from_image`.
+pub fn with_mask(image: DynamicImage, mask: &GrayImage) -> DynamicImage {
+    let bytes_per_channel = image.color().bytes_per_pixel() / image.color().channel_count();
+    let channels = image.color().channel_count();
+    let is_valid = |x, y| mask.get_pixel(x, y).0[0] >= 128;
+    match (bytes_per_channel, channels) {
+        (1, 1 | 2) => {
+            let mut buffer = image.to_luma_alpha8();
+            buffer.enumerate_pixels_mut().for_each(|(x, y, pixel)| pixel.0[1] = if is_valid(x, y) { u8::MAX } else { 0 });
+            DynamicImage::ImageLumaA8(buffer)
+        }
+        (1, _) => {
+            let mut buffer = image.to_rgba8();
+            buffer.enumerate_pixels_mut().for_each(|(x, y, pixel)| pixel.0[3] = if is_valid(x, y) { u8::MAX } else { 0 });
+            DynamicImage::ImageRgba8(buffer)
+        }
+        (2, 1 | 2) => {
+            let mut buffer = image.to_luma_alpha16();
+            buffer.enumerate_pixels_mut().for_each(|(x, y, pixel)| pixel.0[1] = if is_valid(x, y) { u16::MAX } else { 0 });
+            DynamicImage::ImageLumaA16(buffer)
+        }
+        (2, _) => {
+            let mut buffer = image.to_rgba16();
+            buffer.enumerate_pixels_mut().for_each(|(x, y, pixel)| pixel.0[3] = if is_valid(x, y) { u16::MAX } else { 0 });
+            DynamicImage::ImageRgba16(buffer)
+        }
+        _ => {
+            let mut buffer = image.to_rgba32f();
+            buffer.enumerate_pixels_mut().for_each(|(x, y, pixel)| pixel.0[3] = if is_valid(x, y) { 1.0 } else { 0.0 });
+            DynamicImage::ImageRgba32F(buffer)
+        }
+    }
+}
+
+/// Removes the texels outside of the mask (a mask layer of the manifest) from the data of the image,
+/// keeping the texels without data it already has.
+pub fn intersect_mask(image: DynamicImage, mask: &GrayImage, has_nodata: bool) -> DynamicImage {
+    // Masks made for other dimensions are resized to the image.
+    let mask = match mask.dimensions() == (image.width(), image.height()) {
+        true => mask.clone(),
+        false => imageops::resize(mask, image.width(), image.height(), FilterType::Nearest),
+    };
+    if !has_nodata {
+        return with_mask(image, &mask);
+    }
+    let valid = nodata_mask(&image, &NoData { value: 0.0, source: NoDataSource::Transparency });
+    let combined = GrayImage::from_fn(mask.width(), mask.height(), |x, y| {
+        Luma([mask.get_pixel(x, y).0[0].min(valid.get_pixel(x, y).0[0])])
+    });
+    with_mask(image, &combined)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, LumaA, Rgb, RgbImage};
+
+    use super::*;
+    use crate::manifest::LayerConfig;
+    use crate::texels::TexelBuffer;
+
+    #[test]
+    fn stores_the_nodata_value_for_texels_without_data() {
+        let colors = [[10, 20, 30], [255, 0, 255], [40, 50, 60]];
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(3, 1, |x, _| Rgb(colors[x as usize])));
+        let by_color = NoData { value: 0.0, source: NoDataSource::Color([255, 0, 255]) };
+        let mask = nodata_mask(&image, &by_color);
+        assert_eq!(mask.as_raw(), &[255, 0, 255]);
+        let by_value = NoData { value: 40.0, source: NoDataSource::Value };
+        assert_eq!(nodata_mask(&image, &by_value).into_raw(), [255, 255, 0]);
+        let by_transparency = NoData { value: 0.0, source: NoDataSource::Transparency };
+        assert_eq!(nodata_mask(&image, &by_transparency).into_raw(), [255; 3]);
+
+        let texels = TexelBuffer::from_image(&with_mask(image, &mask), &LayerConfig::default(), Some(99.0));
+        assert_eq!(texels.encode(), [10, 99, 40]);
+    }
+
+    #[test]
+    fn clears_the_texels_outside_of_the_mask() {
+        let image = DynamicImage::ImageLumaA8(ImageBuffer::from_fn(4, 1, |x, _| LumaA([x as u8 + 1, [255, 0, 255, 0][x as usize]])));
+        // A half-size mask is resized to the layer.
+        let mask = GrayImage::from_raw(2, 1, vec![255, 0]).unwrap();
+        let masked = intersect_mask(image.clone(), &mask, true);
+        assert_eq!(masked.to_luma_alpha8().into_raw(), [1, 255, 2, 0, 3, 0, 4, 0]);
+
+        // Without nodata, the alpha of the source means nothing and only the mask counts.
+        let masked = intersect_mask(image, &mask, false);
+        assert_eq!(masked.to_luma_alpha8().into_raw(), [1, 255, 2, 255, 3, 0, 4, 0]);
+    }
+}
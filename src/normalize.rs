@@ -0,0 +1,98 @@
+//! Stretching of layer values to the full range of their samples.
+
+use image::{DynamicImage, ImageBuffer, Pixel};
+
+use crate::resample::{map_buffer, Sample};
+
+/// Percentiles of the source values which are stretched to the bounds of the sample range.
+/// Values outside of them are clipped, so a few outliers don't compress the range of the whole layer.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct PercentileRange {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl std::str::FromStr for PercentileRange {
+    type Err = String;
+
+    /// Parses `low,high`.
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let values: Vec<f64> = string.split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("Invalid percentiles {string}, expected low,high"))?;
+        let [low, high] = values[..] else {
+            return Err(format!("Invalid percentiles {string}, expected low,high"));
+        };
+        if !(0.0..high).contains(&low) || high > 100.0 {
+            return Err("The percentiles must satisfy 0 <= low < high <= 100".into());
+        }
+        Ok(PercentileRange { low, high })
+    }
+}
+
+/// Stretches every color channel of the image so its percentiles span the sample range. Alpha is kept as is.
+pub fn normalize_percentiles(image: &DynamicImage, range: PercentileRange) -> DynamicImage {
+    let has_alpha = image.color().has_alpha();
+    map_buffer!(image, buffer => stretch(buffer, range, has_alpha))
+}
+
+/// Value at the given percentile of the sorted values, using the nearest rank.
+fn percentile(sorted: &[f64], percentile: f64) -> f64 {
+    let rank = (percentile / 100.0 * (sorted.len() - 1) as f64).round() as usize;
+    sorted[rank]
+}
+
+fn stretch<P>(image: &ImageBuffer<P, Vec<P::Subpixel>>, range: PercentileRange, has_alpha: bool) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel,
+    P::Subpixel: Sample,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let color_channels = if has_alpha { channels - 1 } else { channels };
+    let mut samples = image.as_raw().clone();
+    if samples.is_empty() {
+        return image.clone();
+    }
+
+    for channel in 0..color_channels {
+        let mut values: Vec<f64> = samples.iter().skip(channel).step_by(channels).map(|sample| sample.to_f64()).collect();
+        values.sort_by(f64::total_cmp);
+        let (low, high) = (percentile(&values, range.low), percentile(&values, range.high));
+        // A flat channel has nothing to stretch.
+        if high <= low {
+            continue;
+        }
+        let scale = P::Subpixel::MAX / (high - low);
+        for sample in samples.iter_mut().skip(channel).step_by(channels) {
+            let value = (sample.to_f64().clamp(low, high) - low) * scale;
+            *sample = P::Subpixel::from_f64(value);
+        }
+    }
+    ImageBuffer::from_raw(image.width(), image.height(), samples).expect("The buffer has the size of the image.")
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, LumaA};
+
+    use super::*;
+
+    #[test]
+    fn stretches_the_percentiles_to_the_full_range() {
+        assert_eq!("10, 90".parse(), Ok(PercentileRange { low: 10.0, high: 90.0 }));
+        for invalid in ["10", "90,10", "0,101", "a,b"] {
+            assert!(invalid.parse::<PercentileRange>().is_err(), "{invalid:?} was accepted");
+        }
+
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(101, 1, |x, _| image::Luma([x as u8])));
+        let stretched = normalize_percentiles(&image, "10,90".parse().unwrap()).to_luma8();
+        let values: Vec<u8> = [0, 10, 50, 90, 100].iter().map(|&x| stretched.get_pixel(x, 0)[0]).collect();
+        assert_eq!(values, [0, 0, 128, 255, 255]);
+
+        // Alpha is left alone.
+        let image = DynamicImage::ImageLumaA8(ImageBuffer::from_fn(2, 1, |x, _| LumaA([x as u8 * 10, 77])));
+        let stretched = normalize_percentiles(&image, "0,100".parse().unwrap()).to_luma_alpha8();
+        assert_eq!(stretched.into_raw(), [0, 77, 255, 77]);
+    }
+}
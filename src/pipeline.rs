@@ -0,0 +1,316 @@
+//! Per-layer processing steps applied to the source image before it's resized.
+
+use image::{DynamicImage, ImageBuffer, Luma, Pixel, Rgb, Rgb32FImage};
+
+use crate::nodata::with_mask;
+use crate::resample::{map_buffer, Sample};
+use crate::toml::{Table, Value};
+
+/// Step of a layer's processing pipeline. Values are fractions of the sample range, from 0 to 1.
+#[derive(Clone, PartialEq, Debug)]
+pub enum Operation {
+    /// Keeps a single channel (0 to 3 for r, g, b and a) as a grayscale image.
+    Channel(usize),
+    /// Maps values linearly from one range to another, clamping the result.
+    Remap { from: (f64, f64), to: (f64, f64) },
+    /// Gaussian blur with the given standard deviation in source texels.
+    Blur { sigma: f32 },
+    /// Values at or above the threshold become 1, the other ones 0.
+    Threshold { value: f64 },
+    Invert,
+    /// Maximum over a disc of the given radius in source texels, growing bright areas such as obstacle masks.
+    Dilate { radius: f32 },
+    /// Minimum over a disc of the given radius in source texels, shrinking bright areas.
+    Erode { radius: f32 },
+    /// Erosion followed by dilation, removing bright areas smaller than the disc.
+    Open { radius: f32 },
+    /// Dilation followed by erosion, filling dark gaps smaller than the disc.
+    Close { radius: f32 },
+}
+
+fn as_number(value: &Value) -> Option<f64> {
+    value.as_float().or(value.as_integer().map(|integer| integer as f64))
+}
+
+fn get_number(table: &Table, key: &str, op: &str) -> Result<f64, String> {
+    let value = table.get(key).ok_or_else(|| format!("Operation {op} requires `{key}`"))?;
+    as_number(value).ok_or_else(|| format!("`{key}` of operation {op} must be a number, got {}", value.type_name()))
+}
+
+fn get_range(table: &Table, key: &str, op: &str) -> Result<(f64, f64), String> {
+    let range: Option<Vec<f64>> = table.get(key)
+        .and_then(Value::as_array)
+        .and_then(|range| range.iter().map(as_number).collect());
+    match range.as_deref() {
+        Some(&[low, high]) => Ok((low, high)),
+        _ => Err(format!("`{key}` of operation {op} must be an array of two numbers")),
+    }
+}
+
+impl Operation {
+    /// Parses a step, either the name of an operation without parameters or a table with its name in `op`.
+    pub fn parse(value: &Value) -> Result<Operation, String> {
+        let (op, table) = match value {
+            Value::String(op) => (op.as_str(), None),
+            Value::Table(table) => {
+                let op = table.get("op").and_then(Value::as_str)
+                    .ok_or_else(|| "Pipeline steps must have an `op` name".to_string())?;
+                (op, Some(table))
+            }
+            _ => return Err(format!("Pipeline steps must be strings or tables, got {}", value.type_name())),
+        };
+        let known_keys: &[&str] = match op {
+            "channel" => &["op", "channel"],
+            "remap" => &["op", "from", "to"],
+            "blur" => &["op", "sigma"],
+            "threshold" => &["op", "value"],
+            "invert" => &["op"],
+            "dilate" | "erode" | "open" | "close" => &["op", "radius"],
+            _ => return Err(format!(
+                "Unknown operation `{op}`, expected channel, remap, blur, threshold, invert, dilate, erode, open or close"
+            )),
+        };
+        let empty = Table::new();
+        let table = table.unwrap_or(&empty);
+        if let Some(key) = table.keys().find(|key| !known_keys.contains(&key.as_str())) {
+            return Err(format!("Unknown key `{key}` in operation {op}"));
+        }
+
+        match op {
+            "channel" => {
+                let channel = match table.get("channel").and_then(Value::as_str) {
+                    Some("r") => 0,
+                    Some("g") => 1,
+                    Some("b") => 2,
+                    Some("a") => 3,
+                    _ => return Err("Operation channel requires `channel`, one of r, g, b or a".into()),
+                };
+                Ok(Operation::Channel(channel))
+            }
+            "remap" => {
+                let from = get_range(table, "from", op)?;
+                if from.0 == from.1 {
+                    return Err("`from` of operation remap must be a non-empty range".into());
+                }
+                Ok(Operation::Remap { from, to: get_range(table, "to", op)? })
+            }
+            "blur" => {
+                let sigma = get_number(table, "sigma", op)?;
+                if sigma <= 0.0 {
+                    return Err("`sigma` of operation blur must be positive".into());
+                }
+                Ok(Operation::Blur { sigma: sigma as f32 })
+            }
+            "threshold" => Ok(Operation::Threshold { value: get_number(table, "value", op)? }),
+            "invert" => Ok(Operation::Invert),
+            _ => {
+                let radius = get_number(table, "radius", op)?;
+                if radius <= 0.0 {
+                    return Err(format!("`radius` of operation {op} must be positive"));
+                }
+                let radius = radius as f32;
+                Ok(match op {
+                    "dilate" => Operation::Dilate { radius },
+                    "erode" => Operation::Erode { radius },
+                    "open" => Operation::Open { radius },
+                    _ => Operation::Close { radius },
+                })
+            }
+        }
+    }
+
+    pub fn apply(&self, image: DynamicImage) -> DynamicImage {
+        match self {
+            Operation::Channel(channel) => select_channel(&image, *channel),
+            Operation::Remap { from, to } => {
+                let scale = (to.1 - to.0) / (from.1 - from.0);
+                map_color_samples(&image, |value| to.0 + (value - from.0) * scale)
+            }
+            Operation::Blur { sigma } => image.blur(*sigma),
+            Operation::Threshold { value: threshold } => {
+                map_color_samples(&image, |value| if value >= *threshold { 1.0 } else { 0.0 })
+            }
+            Operation::Invert => map_color_samples(&image, |value| 1.0 - value),
+            Operation::Dilate { radius } => morph(&image, *radius, true),
+            Operation::Erode { radius } => morph(&image, *radius, false),
+            Operation::Open { radius } => morph(&morph(&image, *radius, false), *radius, true),
+            Operation::Close { radius } => morph(&morph(&image, *radius, true), *radius, false),
+        }
+    }
+}
+
+/// Softens the edges of the resized layer into falloffs of about `radius` texels on either side, with a Gaussian
+/// blur. Texels without data (marked by alpha) stay without data.
+pub fn feather(image: DynamicImage, radius: f32, has_nodata: bool) -> DynamicImage {
+    let blurred = image.blur(radius / 2.0);
+    if !has_nodata || !image.color().has_alpha() {
+        return blurred;
+    }
+    let alpha = select_channel(&image, 3).into_luma8();
+    with_mask(blurred, &alpha)
+}
+
+/// Runs the steps in order.
+pub fn apply_pipeline(image: DynamicImage, pipeline: &[Operation]) -> DynamicImage {
+    pipeline.iter().fold(image, |image, operation| operation.apply(image))
+}
+
+/// Channel of the image as a grayscale image. Gray images have the same value in r, g and b, and opaque images
+/// the maximum alpha.
+fn select_channel(image: &DynamicImage, channel: usize) -> DynamicImage {
+    let (width, height) = (image.width(), image.height());
+    match image.color().bytes_per_pixel() / image.color().channel_count() {
+        1 => {
+            let rgba = image.to_rgba8();
+            DynamicImage::ImageLuma8(ImageBuffer::from_fn(width, height, |x, y| Luma([rgba.get_pixel(x, y).0[channel]])))
+        }
+        2 => {
+            let rgba = image.to_rgba16();
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(width, height, |x, y| Luma([rgba.get_pixel(x, y).0[channel]])))
+        }
+        _ => {
+            // There's no grayscale float image, float layers read the first channel.
+            let rgba = image.to_rgba32f();
+            DynamicImage::ImageRgb32F(Rgb32FImage::from_fn(width, height, |x, y| Rgb([rgba.get_pixel(x, y).0[channel]; 3])))
+        }
+    }
+}
+
+/// Applies the function to the normalized color samples, keeping alpha. Integer samples are clamped.
+fn map_color_samples(image: &DynamicImage, function: impl Fn(f64) -> f64) -> DynamicImage {
+    let has_alpha = image.color().has_alpha();
+    map_buffer!(image, buffer => map_samples(buffer, has_alpha, &function))
+}
+
+/// Dilates (maximum) or erodes (minimum) the color samples over a disc of the radius, keeping alpha. Texels past
+/// the edges of the image don't take part.
+fn morph(image: &DynamicImage, radius: f32, dilate: bool) -> DynamicImage {
+    let reach = radius.floor() as i64;
+    let disc: Vec<(i64, i64)> = (-reach..=reach)
+        .flat_map(|dy| (-reach..=reach).map(move |dx| (dx, dy)))
+        .filter(|(dx, dy)| ((dx * dx + dy * dy) as f32) <= radius * radius)
+        .collect();
+    let has_alpha = image.color().has_alpha();
+    map_buffer!(image, buffer => morph_samples(buffer, has_alpha, &disc, dilate))
+}
+
+fn morph_samples<P>(
+    image: &ImageBuffer<P, Vec<P::Subpixel>>,
+    has_alpha: bool,
+    disc: &[(i64, i64)],
+    dilate: bool
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel,
+    P::Subpixel: Sample,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let color_channels = if has_alpha { channels - 1 } else { channels };
+    let (width, height) = (image.width() as i64, image.height() as i64);
+    let source = image.as_raw();
+    let mut samples = source.clone();
+    for y in 0..height {
+        for x in 0..width {
+            let index = (y * width + x) as usize * channels;
+            for channel in 0..color_channels {
+                let neighbors = disc.iter()
+                    .map(|(dx, dy)| (x + dx, y + dy))
+                    .filter(|&(nx, ny)| nx >= 0 && ny >= 0 && nx < width && ny < height)
+                    .map(|(nx, ny)| source[(ny * width + nx) as usize * channels + channel].to_f64());
+                let value = match dilate {
+                    true => neighbors.fold(f64::NEG_INFINITY, f64::max),
+                    false => neighbors.fold(f64::INFINITY, f64::min),
+                };
+                samples[index + channel] = P::Subpixel::from_f64(value);
+            }
+        }
+    }
+    ImageBuffer::from_raw(image.width(), image.height(), samples).expect("The buffer has the size of the image.")
+}
+
+fn map_samples<P>(
+    image: &ImageBuffer<P, Vec<P::Subpixel>>,
+    has_alpha: bool,
+    function: &impl Fn(f64) -> f64
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel,
+    P::Subpixel: Sample,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let mut samples = image.as_raw().clone();
+    for (index, sample) in samples.iter_mut().enumerate() {
+        if has_alpha && index % channels == channels - 1 {
+            continue;
+        }
+        *sample = P::Subpixel::from_f64(function(sample.to_f64() / P::Subpixel::MAX) * P::Subpixel::MAX);
+    }
+    ImageBuffer::from_raw(image.width(), image.height(), samples).expect("The buffer has the size of the image.")
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, Luma, Rgb, RgbImage, Rgba, RgbaImage};
+
+    use super::*;
+    use crate::toml;
+
+    fn parse_pipeline(source: &str) -> Result<Vec<Operation>, String> {
+        let root = toml::parse(&format!("pipeline = {source}"))?;
+        root["pipeline"].as_array().unwrap().iter().map(Operation::parse).collect()
+    }
+
+    #[test]
+    fn runs_the_steps_in_order() {
+        let pipeline = parse_pipeline(r#"[{ op = "channel", channel = "g" }, { op = "remap", from = [0, 0.5], to = [0, 1] }, "invert"]"#).unwrap();
+        assert_eq!(pipeline, [
+            Operation::Channel(1),
+            Operation::Remap { from: (0.0, 0.5), to: (0.0, 1.0) },
+            Operation::Invert,
+        ]);
+        let image = DynamicImage::ImageRgb8(RgbImage::from_fn(3, 1, |x, _| Rgb([255, [0, 51, 200][x as usize], 0])));
+        assert_eq!(apply_pipeline(image.clone(), &pipeline).to_luma8().into_raw(), [255, 153, 0]);
+
+        let threshold = parse_pipeline(r#"[{ op = "channel", channel = "g" }, { op = "threshold", value = 0.2 }]"#).unwrap();
+        assert_eq!(apply_pipeline(image, &threshold).to_luma8().into_raw(), [0, 255, 255]);
+
+        for invalid in [r#"["sharpen"]"#, r#"[{ op = "blur" }]"#, r#"[{ op = "blur", sigma = 0 }]"#, r#"[{ op = "invert", value = 1 }]"#, "[1]"] {
+            assert!(parse_pipeline(invalid).is_err(), "{invalid} was accepted");
+        }
+    }
+
+    #[test]
+    fn feathers_the_edges_keeping_the_texels_without_data() {
+        let edge = DynamicImage::ImageLuma8(GrayImage::from_raw(8, 1, vec![0, 0, 0, 0, 255, 255, 255, 255]).unwrap());
+        let feathered = feather(edge, 2.0, false).to_luma8().into_raw();
+        assert!(feathered.windows(2).all(|pair| pair[0] <= pair[1]), "{feathered:?}");
+        assert!(feathered[2] > 0 && feathered[5] < 255, "{feathered:?}");
+        assert!((feathered[3] as i32 + feathered[4] as i32 - 255).abs() <= 1, "{feathered:?}");
+
+        let alpha = [255, 255, 0, 255];
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 1, |x, _| Rgba([(x * 80) as u8, 0, 0, alpha[x as usize]])));
+        let feathered = feather(image, 2.0, true).to_rgba8();
+        let feathered_alpha: Vec<u8> = feathered.pixels().map(|pixel| pixel[3]).collect();
+        assert_eq!(feathered_alpha, alpha);
+    }
+
+    #[test]
+    fn grows_and_shrinks_the_bright_areas() {
+        let pipeline = parse_pipeline(r#"[{ op = "dilate", radius = 1 }]"#).unwrap();
+        assert_eq!(pipeline, [Operation::Dilate { radius: 1.0 }]);
+        let mut dot = GrayImage::new(3, 3);
+        dot.put_pixel(1, 1, Luma([255]));
+        let dot = DynamicImage::ImageLuma8(dot);
+        let dilated = apply_pipeline(dot.clone(), &pipeline);
+        assert_eq!(dilated.to_luma8().into_raw(), [0, 255, 0, 255, 255, 255, 0, 255, 0]);
+        assert_eq!(Operation::Erode { radius: 1.0 }.apply(dilated.clone()), dot);
+        assert_eq!(Operation::Open { radius: 1.0 }.apply(dot).to_luma8().into_raw(), [0; 9]);
+
+        let gap = DynamicImage::ImageLuma8(GrayImage::from_raw(5, 1, vec![255, 255, 0, 255, 255]).unwrap());
+        assert_eq!(Operation::Close { radius: 1.0 }.apply(gap).to_luma8().into_raw(), [255; 5]);
+
+        for invalid in [r#"[{ op = "erode" }]"#, r#"[{ op = "open", radius = 0 }]"#, r#"[{ op = "close", radius = 1, size = 2 }]"#] {
+            assert!(parse_pipeline(invalid).is_err(), "{invalid} was accepted");
+        }
+    }
+}
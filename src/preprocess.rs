@@ -0,0 +1,91 @@
+//! External commands which process the source images of layers before they're decoded.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{self, Command, Stdio};
+
+/// Runs the command through the shell and returns the image it produced.
+///
+/// The path of the source image is passed in the `NSDGEN_INPUT` environment variable, and the path of a temporary
+/// file in `temp_directory` (the system's by default) in `NSDGEN_OUTPUT`. The paths are never pasted into the
+/// command, so they can't be interpreted by the shell. If the command writes the image to `NSDGEN_OUTPUT`, it's read
+/// from there, otherwise from the standard output of the command.
+pub fn run_preprocessor(command: &str, input: &Path, stem: &str, temp_directory: Option<&Path>) -> io::Result<Vec<u8>> {
+    let output_path = temp_directory.map_or_else(env::temp_dir, Path::to_path_buf)
+        .join(format!("nsdgen-{}-{stem}.out", process::id()));
+    let _ = fs::remove_file(&output_path);
+
+    let output = shell(command)
+        .env("NSDGEN_INPUT", input)
+        .env("NSDGEN_OUTPUT", &output_path)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()?;
+    if !output.status.success() {
+        let _ = fs::remove_file(&output_path);
+        return Err(io::Error::other(format!("`{command}` failed with {}", output.status)));
+    }
+
+    match fs::read(&output_path) {
+        Ok(image) => {
+            let _ = fs::remove_file(&output_path);
+            Ok(image)
+        }
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(output.stdout),
+        Err(error) => Err(io::Error::new(error.kind(), format!("Could not read {}: {error}", output_path.display()))),
+    }
+}
+
+/// The command run through the shell of the platform.
+pub(crate) fn shell(command: &str) -> Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    #[test]
+    fn passes_paths_without_the_shell_interpreting_them() {
+        let directory = TempDirectory::new();
+        let input = directory.join("it's a \"source\"; $(touch injected).png");
+        fs::write(&input, b"image").unwrap();
+
+        let image = run_preprocessor("cat \"$NSDGEN_INPUT\"; printf ' %s' \"$NSDGEN_OUTPUT\"", &input, "a", None).unwrap();
+        let expected_output = env::temp_dir().join(format!("nsdgen-{}-a.out", process::id()));
+        assert_eq!(String::from_utf8(image).unwrap(), format!("image {}", expected_output.display()));
+        assert!(!directory.join("injected").exists() && !Path::new("injected").exists());
+    }
+
+    #[test]
+    fn reads_the_output_file_from_the_temp_directory() {
+        let (sources, temp) = (TempDirectory::new(), TempDirectory::new());
+        let input = sources.join("source.png");
+        fs::write(&input, b"image").unwrap();
+
+        let command = "case \"$NSDGEN_OUTPUT\" in \"$NSDGEN_TEMP\"/*) tr a-z A-Z < \"$NSDGEN_INPUT\" > \"$NSDGEN_OUTPUT\";; esac";
+        let command = command.replace("$NSDGEN_TEMP", &temp.path().display().to_string());
+        assert_eq!(run_preprocessor(&command, &input, "b", Some(temp.path())).unwrap(), b"IMAGE");
+        assert_eq!(fs::read_dir(temp.path()).unwrap().count(), 0, "The output file was left behind");
+    }
+
+    #[test]
+    fn fails_with_the_command() {
+        let directory = TempDirectory::new();
+        let error = run_preprocessor("exit 3", &directory.join("source.png"), "c", Some(directory.path())).unwrap_err();
+        assert!(error.to_string().starts_with("`exit 3` failed"), "{error}");
+    }
+}
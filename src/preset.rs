@@ -0,0 +1,112 @@
+use crate::format::{AttributeFormat, AttributeType};
+use crate::info;
+use crate::layer::LayerSource;
+use crate::manifest::{LayerConfig, Manifest};
+
+/// Recognizes the default output naming of terrain generators.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InputPreset {
+    /// Gaea build outputs, e.g. `Height.png`, `Erosion_Flow.png`, `Erosion_Deposits.png`.
+    Gaea,
+    /// World Machine output devices, e.g. `Heightfield Output.png`, `Flow Map.png`, `Deposition Map.png`.
+    WorldMachine,
+}
+
+const BYTE: AttributeFormat = AttributeFormat { attribute_type: AttributeType::Byte, size: 1 };
+const BYTE_VECTOR3: AttributeFormat = AttributeFormat { attribute_type: AttributeType::Byte, size: 3 };
+const UINT16: AttributeFormat = AttributeFormat { attribute_type: AttributeType::UInt, size: 2 };
+
+/// Keywords found in the output file names, checked in order, with the attribute they map to.
+const GAEA_OUTPUTS: &[(&[&str], &str, AttributeFormat)] = &[
+    (&["deposits", "deposit", "sediment"], "deposition", BYTE),
+    (&["flow"], "flow", BYTE),
+    (&["wear"], "wear", BYTE),
+    (&["talus"], "talus", BYTE),
+    (&["normals", "normal"], "normal", BYTE_VECTOR3),
+    (&["heightmap", "height", "elevation"], "height", UINT16),
+];
+
+const WORLD_MACHINE_OUTPUTS: &[(&[&str], &str, AttributeFormat)] = &[
+    (&["deposition", "deposit"], "deposition", BYTE),
+    (&["flow"], "flow", BYTE),
+    (&["wear"], "wear", BYTE),
+    (&["talus"], "talus", BYTE),
+    (&["normals", "normal"], "normal", BYTE_VECTOR3),
+    (&["heightfield", "heightmap", "height", "terrain"], "height", UINT16),
+];
+
+impl InputPreset {
+    fn outputs(&self) -> &'static [(&'static [&'static str], &'static str, AttributeFormat)] {
+        match self {
+            InputPreset::Gaea => GAEA_OUTPUTS,
+            InputPreset::WorldMachine => WORLD_MACHINE_OUTPUTS,
+        }
+    }
+
+    /// Attribute name and format for a file, if its name is recognized by the preset.
+    pub fn layer_config(&self, stem: &str) -> Option<LayerConfig> {
+        let words: Vec<String> = stem
+            .split(|char: char| !char.is_ascii_alphanumeric())
+            .map(|word| word.to_ascii_lowercase())
+            .collect();
+
+        self.outputs().iter()
+            .find(|(keywords, _, _)| keywords.iter().any(|keyword| words.iter().any(|word| word == keyword)))
+            .map(|(_, name, format)| LayerConfig {
+                name: Some(name.to_string()),
+                format: *format,
+                ..LayerConfig::default()
+            })
+    }
+}
+
+impl Manifest {
+    /// Configures the recognized layer files which the manifest doesn't configure explicitly.
+    pub fn apply_input_preset(&mut self, preset: InputPreset, layer_files: &[LayerSource]) {
+        for source in layer_files {
+            if self.layers.contains_key(&source.stem) {
+                continue;
+            }
+            if let Some(config) = preset.layer_config(&source.stem) {
+                info!("Preset maps {} to attribute {}.", source.stem, config.name.as_deref().unwrap_or(&source.stem));
+                self.layers.insert(source.stem.clone(), config);
+            }
+        }
+    }
+
+    /// Sets the format of the layer files which the manifest doesn't configure explicitly.
+    pub fn apply_default_format(&mut self, format: AttributeFormat, layer_files: &[LayerSource]) {
+        for source in layer_files {
+            if !self.layers.contains_key(&source.stem) {
+                self.layers.insert(source.stem.clone(), LayerConfig { format, ..LayerConfig::default() });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn recognizes_the_output_names_of_the_generators() {
+        let name = |preset: InputPreset, stem| preset.layer_config(stem).map(|config| (config.name.unwrap(), config.format));
+        assert_eq!(name(InputPreset::Gaea, "Erosion_Deposits"), Some(("deposition".into(), BYTE)));
+        assert_eq!(name(InputPreset::Gaea, "Height"), Some(("height".into(), UINT16)));
+        assert_eq!(name(InputPreset::WorldMachine, "Heightfield Output"), Some(("height".into(), UINT16)));
+        assert_eq!(name(InputPreset::WorldMachine, "Normal Map"), Some(("normal".into(), BYTE_VECTOR3)));
+        // Keywords match whole words only.
+        assert_eq!(name(InputPreset::Gaea, "Overflowing"), None);
+    }
+
+    #[test]
+    fn keeps_the_layers_configured_by_the_manifest() {
+        let mut manifest = Manifest::parse("[layers.Height]\nname = \"elevation\"").unwrap();
+        let sources = ["Height", "Flow"].map(|stem| LayerSource::image(PathBuf::from(format!("{stem}.png"))));
+        manifest.apply_input_preset(InputPreset::Gaea, &sources);
+        assert_eq!(manifest.layer("Height").name.as_deref(), Some("elevation"));
+        assert_eq!(manifest.layer("Flow").name.as_deref(), Some("flow"));
+    }
+}
@@ -0,0 +1,124 @@
+//! Progress events of a generation, for host applications rendering their own progress.
+
+use std::io::{self, Seek, SeekFrom, Write};
+use std::sync::Arc;
+
+use crate::json::JsonValue;
+
+/// Output growth between two `BytesWritten` events.
+const BYTES_WRITTEN_INTERVAL: u64 = 1024 * 1024;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProgressEvent {
+    /// A layer has been loaded and resized, `loaded` out of the `total` layers of the run.
+    LayerLoaded { name: String, loaded: usize, total: usize },
+    /// Fraction of the texel data interleaved so far, from 0 to 1.
+    Packed { fraction: f64 },
+    /// Size of the output written so far.
+    BytesWritten { bytes: u64 },
+}
+
+/// Receives the progress events. Layers are loaded on worker threads, so it may be called from any thread.
+pub type ProgressCallback = Arc<dyn Fn(&ProgressEvent) + Send + Sync>;
+
+impl ProgressEvent {
+    /// The event as `{"event": "<layer_loaded|packed|bytes_written>", ...fields}`.
+    pub fn to_json(&self) -> JsonValue {
+        match self {
+            ProgressEvent::LayerLoaded { name, loaded, total } => JsonValue::object()
+                .with("event", "layer_loaded")
+                .with("layer", name.as_str())
+                .with("loaded_layers", *loaded)
+                .with("total_layers", *total),
+            ProgressEvent::Packed { fraction } => JsonValue::object()
+                .with("event", "packed")
+                .with("fraction", *fraction),
+            ProgressEvent::BytesWritten { bytes } => JsonValue::object()
+                .with("event", "bytes_written")
+                .with("bytes", *bytes),
+        }
+    }
+}
+
+/// Writer reporting how much of the output has been written, every megabyte and when it's flushed.
+/// Seeking back to patch earlier bytes doesn't count as progress.
+pub struct ProgressWriter<W> {
+    inner: W,
+    callback: ProgressCallback,
+    position: u64,
+    end: u64,
+    reported: u64,
+}
+
+impl<W> ProgressWriter<W> {
+    pub fn new(inner: W, callback: ProgressCallback) -> ProgressWriter<W> {
+        ProgressWriter { inner, callback, position: 0, end: 0, reported: 0 }
+    }
+
+    fn report(&mut self) {
+        if self.end != self.reported {
+            self.reported = self.end;
+            (self.callback)(&ProgressEvent::BytesWritten { bytes: self.end });
+        }
+    }
+}
+
+impl<W: Write> Write for ProgressWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.position += written as u64;
+        self.end = self.end.max(self.position);
+        if self.end - self.reported >= BYTES_WRITTEN_INTERVAL {
+            self.report();
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()?;
+        self.report();
+        Ok(())
+    }
+}
+
+impl<W: Seek> Seek for ProgressWriter<W> {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        self.position = self.inner.seek(position)?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[test]
+    fn reports_the_bytes_written_without_patches() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let received = events.clone();
+        let mut writer = ProgressWriter::new(Cursor::new(vec![]), Arc::new(move |event: &ProgressEvent| {
+            received.lock().unwrap().push(event.clone());
+        }));
+        writer.write_all(&vec![0; BYTES_WRITTEN_INTERVAL as usize + 10]).unwrap();
+        // Patching earlier bytes, like the size of a chunk, doesn't grow the output.
+        writer.seek(SeekFrom::Start(4)).unwrap();
+        writer.write_all(&[1; 4]).unwrap();
+        writer.seek(SeekFrom::End(0)).unwrap();
+        writer.write_all(&[2; 6]).unwrap();
+        writer.flush().unwrap();
+
+        let bytes: Vec<u64> = events.lock().unwrap().iter()
+            .map(|event| match event {
+                ProgressEvent::BytesWritten { bytes } => *bytes,
+                event => panic!("Unexpected event {event:?}"),
+            })
+            .collect();
+        assert_eq!(bytes, [BYTES_WRITTEN_INTERVAL + 10, BYTES_WRITTEN_INTERVAL + 16]);
+
+        let json = ProgressEvent::LayerLoaded { name: "height".into(), loaded: 1, total: 3 }.to_json();
+        assert_eq!(json.to_string(), r#"{"event":"layer_loaded","layer":"height","loaded_layers":1,"total_layers":3}"#);
+    }
+}
@@ -0,0 +1,818 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use flate2::read::ZlibDecoder;
+
+use crate::format::*;
+use crate::layer::{LayerDimensions, LayerRegion};
+use crate::mmap::MappedFile;
+use crate::resample::ValueKind;
+
+pub struct NsdAttribute {
+    pub name: String,
+    pub format: AttributeFormat,
+}
+
+/// Additional section of a file, stored at its own resolution in a SEC chunk.
+pub struct NsdSection {
+    pub name: String,
+    pub dimensions: LayerDimensions,
+    pub attributes: Vec<NsdAttribute>,
+    /// Uncompressed, interleaved texel data in row-major order.
+    pub data: Vec<u8>,
+}
+
+/// Parsed contents of a spatial data file, with the DATA chunk already decompressed.
+pub struct NsdReader {
+    header: NsdHeader,
+    dimensions: LayerDimensions,
+    attributes: Vec<NsdAttribute>,
+    data: Vec<u8>,
+    extensions: NsdExtensions,
+    sections: Vec<NsdSection>,
+}
+
+/// DIM, ATR and DATA chunks of a section parsed in place, with the texel data still compressed.
+pub struct NsdSectionView<'a> {
+    /// Empty for the main section.
+    pub name: String,
+    pub dimensions: LayerDimensions,
+    pub attributes: Vec<NsdAttribute>,
+    pub texel_order: TexelOrder,
+    pub row_stride: Option<u32>,
+    /// Uncompressed size of the texel data declared by the DATA chunk, including any row padding.
+    pub combined_size: usize,
+    pub compressed: &'a [u8],
+}
+
+/// Structure of a spatial data file parsed in place, e.g. from a `MappedFile`, without decompressing any texel
+/// data. Attributes are decompressed one at a time on request, so inspecting huge files takes little memory.
+pub struct NsdFileView<'a> {
+    header: NsdHeader,
+    main: NsdSectionView<'a>,
+    extensions: NsdExtensions,
+    sections: Vec<NsdSectionView<'a>>,
+}
+
+/// Interleaved values of a single attribute, borrowed from the texel data, in the texel order of the file.
+#[derive(Clone, Copy)]
+pub struct AttributeView<'a> {
+    data: &'a [u8],
+    stride: usize,
+    offset: usize,
+    size: usize,
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+struct ByteCursor<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteCursor<'a> {
+    fn take(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.bytes.len() - self.position < count {
+            return Err(invalid_data(format!("Unexpected end of file at offset {}", self.position)));
+        }
+        let slice = &self.bytes[self.position..self.position + count];
+        self.position += count;
+        Ok(slice)
+    }
+
+    fn peek(&self, count: usize) -> Option<&'a [u8]> {
+        self.bytes.get(self.position..self.position + count)
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn read_i32(&mut self) -> io::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn read_f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn read_string(&mut self) -> io::Result<String> {
+        let remaining = &self.bytes[self.position..];
+        let length = remaining.iter().position(|&byte| byte == 0)
+            .ok_or_else(|| invalid_data("Unterminated string"))?;
+        let string = String::from_utf8_lossy(&remaining[..length]).into_owned();
+        self.position += length + 1;
+        Ok(string)
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    fn expect(&mut self, magic: &[u8], chunk: &str) -> io::Result<()> {
+        let offset = self.position;
+        if self.take(magic.len())? != magic {
+            return Err(invalid_data(format!("Expected {chunk} chunk at offset {offset}")));
+        }
+        Ok(())
+    }
+}
+
+impl NsdReader {
+    /// Parses the file from a memory map, so only the decompressed texel data is held in memory.
+    pub fn open(path: &Path) -> io::Result<NsdReader> {
+        NsdReader::from_bytes(&MappedFile::open(path)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<NsdReader> {
+        let NsdFileView { header, main, extensions, sections } = NsdFileView::parse(bytes)?;
+        let mut data = main.decompress()?;
+        if let Some(row_stride) = extensions.row_stride {
+            data = unpad_rows(&data, &main.dimensions, &main.attributes, extensions.texel_order, row_stride as usize)?;
+        }
+        let sections = sections.into_iter()
+            .map(|section| Ok(NsdSection {
+                data: section.decompress()?,
+                name: section.name,
+                dimensions: section.dimensions,
+                attributes: section.attributes,
+            }))
+            .collect::<io::Result<_>>()?;
+
+        Ok(NsdReader {
+            header,
+            dimensions: main.dimensions,
+            attributes: main.attributes,
+            data,
+            extensions,
+            sections,
+        })
+    }
+
+    /// Assembles a file from its parts, checking that they're consistent with each other. The texel data is
+    /// uncompressed and without row padding, in the texel order of the extensions.
+    pub fn from_parts(
+        header: NsdHeader,
+        dimensions: LayerDimensions,
+        attributes: Vec<NsdAttribute>,
+        data: Vec<u8>,
+        extensions: NsdExtensions,
+        sections: Vec<NsdSection>
+    ) -> io::Result<NsdReader> {
+        let check_data = |name: &str, dimensions: &LayerDimensions, attributes: &[NsdAttribute], data: &[u8]| {
+            let stride: usize = attributes.iter().map(|attribute| attribute.format.size as usize).sum();
+            let expected = stride * dimensions.get_texel_count();
+            match data.len() == expected {
+                true => Ok(()),
+                false => Err(invalid_data(format!("{name} has {} bytes of texel data, expected {expected}", data.len()))),
+            }
+        };
+        check_data("The main section", &dimensions, &attributes, &data)?;
+        for section in &sections {
+            check_data(&format!("Section {}", section.name), &section.dimensions, &section.attributes, &section.data)?;
+        }
+        let attribute_count = attributes.len() as u32;
+        if let Some(group) = extensions.groups.iter().find(|group| group.attributes.iter().any(|&index| index >= attribute_count)) {
+            return Err(invalid_data(format!("Group {} references a nonexistent attribute", group.name)));
+        }
+        if extensions.attribute_metadata.iter().any(|metadata| metadata.attribute >= attribute_count) {
+            return Err(invalid_data("Metadata references a nonexistent attribute"));
+        }
+        Ok(NsdReader { header, dimensions, attributes, data, extensions, sections })
+    }
+
+    pub fn header(&self) -> &NsdHeader {
+        &self.header
+    }
+
+    pub fn dimensions(&self) -> &LayerDimensions {
+        &self.dimensions
+    }
+
+    pub fn attributes(&self) -> &[NsdAttribute] {
+        &self.attributes
+    }
+
+    /// Combined size of all attributes of a single texel.
+    pub fn texel_stride(&self) -> usize {
+        self.attributes.iter().map(|attribute| attribute.format.size as usize).sum()
+    }
+
+    /// Offset of the attribute's bytes within a texel.
+    pub fn attribute_offset(&self, attribute: usize) -> usize {
+        self.attributes[..attribute].iter().map(|attribute| attribute.format.size as usize).sum()
+    }
+
+    pub fn texel_order(&self) -> TexelOrder {
+        self.extensions.texel_order
+    }
+
+    /// Placement of the texels in world space, if the file stores it.
+    pub fn world(&self) -> Option<&NsdWorldScale> {
+        self.extensions.world.as_ref()
+    }
+
+    /// Bytes of a single attribute of the texel at the given row-major index, regardless of the texel order.
+    pub fn attribute_bytes(&self, attribute: usize, texel: usize) -> &[u8] {
+        let LayerDimensions { width, height } = self.dimensions;
+        let x = (texel % width as usize) as u32;
+        let y = (texel / width as usize) as u32;
+        let index = self.texel_order().storage_index(x, y, width, height);
+        let start = index * self.texel_stride() + self.attribute_offset(attribute);
+        &self.data[start..start + self.attributes[attribute].format.size as usize]
+    }
+
+    /// Decoded components of an attribute at the texel (x, y), one per channel of byte vectors, otherwise a single
+    /// value. `None` outside of the texels.
+    pub fn sample(&self, attribute: usize, x: u32, y: u32) -> Option<Vec<f64>> {
+        let LayerDimensions { width, height } = self.dimensions;
+        if x >= width || y >= height {
+            return None;
+        }
+        let format = &self.attributes[attribute].format;
+        let bytes = self.attribute_view(attribute).get(self.texel_order().storage_index(x, y, width, height));
+        Some((0..format.component_count()).map(|component| format.decode_component(bytes, component)).collect())
+    }
+
+    /// Bilinear interpolation of the components of an attribute at normalized coordinates, with (0, 0) the top
+    /// left corner and (1, 1) the bottom right corner of the texels. Texel centers are at `(x + 0.5) / width`, and
+    /// coordinates outside of the texels are clamped to the edge, like a texture sampler does. Categorical
+    /// attributes, whose values can't be blended, should use `sample` instead. `None` if there are no texels.
+    pub fn sample_bilinear(&self, attribute: usize, u: f64, v: f64) -> Option<Vec<f64>> {
+        let LayerDimensions { width, height } = self.dimensions;
+        if width == 0 || height == 0 {
+            return None;
+        }
+        let axis = |coordinate: f64, size: u32| -> (u32, u32, f64) {
+            let position = (coordinate * size as f64 - 0.5).clamp(0.0, (size - 1) as f64);
+            let first = position.floor() as u32;
+            (first, (first + 1).min(size - 1), position - first as f64)
+        };
+        let (x0, x1, tx) = axis(u, width);
+        let (y0, y1, ty) = axis(v, height);
+        let texel = |x: u32, y: u32| self.sample(attribute, x, y);
+        let (top_left, top_right, bottom_left, bottom_right) = (texel(x0, y0)?, texel(x1, y0)?, texel(x0, y1)?, texel(x1, y1)?);
+        Some((0..top_left.len())
+            .map(|component| {
+                let top = top_left[component] + (top_right[component] - top_left[component]) * tx;
+                let bottom = bottom_left[component] + (bottom_right[component] - bottom_left[component]) * tx;
+                top + (bottom - top) * ty
+            })
+            .collect())
+    }
+
+    /// Values of an attribute within the region, row by row from its top left corner regardless of the texel
+    /// order, `format.size` bytes each. Fails if the region reaches outside of the texels.
+    pub fn read_region(&self, attribute: usize, region: &LayerRegion) -> io::Result<Vec<u8>> {
+        let LayerDimensions { width, height } = self.dimensions;
+        let fits = region.x.checked_add(region.width).is_some_and(|right| right <= width)
+            && region.y.checked_add(region.height).is_some_and(|bottom| bottom <= height);
+        if !fits {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                "Region {},{} {}x{} is outside of the {width}x{height} texels", region.x, region.y, region.width, region.height
+            )));
+        }
+        let view = self.attribute_view(attribute);
+        let mut values = Vec::with_capacity(region.width as usize * region.height as usize * view.size);
+        for y in region.y..region.y + region.height {
+            for x in region.x..region.x + region.width {
+                values.extend_from_slice(view.get(self.texel_order().storage_index(x, y, width, height)));
+            }
+        }
+        Ok(values)
+    }
+
+    /// Every texel as `(x, y, bytes)`, with the bytes of all attributes in the order of `attributes`. The texels
+    /// are visited in the order they're stored, so column-major files are iterated column by column.
+    pub fn texels(&self) -> impl Iterator<Item = (u32, u32, &[u8])> + '_ {
+        let LayerDimensions { width, height } = self.dimensions;
+        let order = self.texel_order();
+        // Files without attributes have no texel data to iterate.
+        self.data.chunks_exact(self.texel_stride().max(1)).enumerate().map(move |(index, texel)| {
+            let (x, y) = order.coordinates(index, width, height);
+            (x, y, texel)
+        })
+    }
+
+    /// Uncompressed, interleaved texel data, with any row padding removed.
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Values of a single attribute, borrowed from the interleaved texel data.
+    pub fn attribute_view(&self, attribute: usize) -> AttributeView<'_> {
+        AttributeView {
+            data: &self.data,
+            stride: self.texel_stride(),
+            offset: self.attribute_offset(attribute),
+            size: self.attributes[attribute].format.size as usize,
+        }
+    }
+
+    pub fn extensions(&self) -> &NsdExtensions {
+        &self.extensions
+    }
+
+    /// Additional sections stored at their own resolution.
+    pub fn sections(&self) -> &[NsdSection] {
+        &self.sections
+    }
+
+    pub fn groups(&self) -> &[NsdGroup] {
+        &self.extensions.groups
+    }
+
+    /// Description and tags of the attribute at the given index, if it has any.
+    pub fn attribute_metadata(&self, attribute: usize) -> Option<&NsdAttributeMetadata> {
+        self.extensions.attribute_metadata.iter().find(|metadata| metadata.attribute as usize == attribute)
+    }
+}
+
+impl<'a> AttributeView<'a> {
+    /// Number of texels.
+    pub fn len(&self) -> usize {
+        self.data.len().checked_div(self.stride).unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes of the value at the given storage index.
+    pub fn get(&self, index: usize) -> &'a [u8] {
+        let start = index * self.stride + self.offset;
+        &self.data[start..start + self.size]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'a [u8]> + 'a {
+        let view = *self;
+        (0..view.len()).map(move |index| view.get(index))
+    }
+}
+
+impl<'a> NsdSectionView<'a> {
+    /// Combined size of all attributes of a single texel.
+    pub fn texel_stride(&self) -> usize {
+        self.attributes.iter().map(|attribute| attribute.format.size as usize).sum()
+    }
+
+    /// Checks that the DATA chunk declares the size of the texels of the dimensions and attributes, including the
+    /// padding of rows of `row_stride` bytes.
+    fn check_combined_size(&self, order: TexelOrder, row_stride: Option<u32>) -> io::Result<()> {
+        let name = match self.name.is_empty() {
+            true => "The main section".to_string(),
+            false => format!("Section {}", self.name),
+        };
+        let (row_length, row_count) = order.rows(self.dimensions.width, self.dimensions.height);
+        let row_size = row_length.checked_mul(self.texel_stride())
+            .ok_or_else(|| invalid_data(format!("{name} is too large")))?;
+        let row_stride = row_stride.map_or(row_size, |row_stride| row_stride as usize);
+        if row_stride < row_size {
+            return Err(invalid_data(format!("Row stride {row_stride} of {name} is shorter than its rows")));
+        }
+        match row_stride.checked_mul(row_count) {
+            Some(expected) if expected == self.combined_size => Ok(()),
+            _ => Err(invalid_data(format!(
+                "{name} declares {} bytes of texel data, but its dimensions and attributes need {}",
+                self.combined_size, row_stride as u128 * row_count as u128
+            ))),
+        }
+    }
+
+    /// All texel data, as stored in the DATA chunk.
+    pub fn decompress(&self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::with_capacity(self.combined_size);
+        ZlibDecoder::new(self.compressed).read_to_end(&mut data)?;
+        if data.len() != self.combined_size {
+            return Err(invalid_data(format!(
+                "DATA chunk declares {} bytes, but {} were decompressed", self.combined_size, data.len()
+            )));
+        }
+        Ok(data)
+    }
+}
+
+impl<'a> NsdFileView<'a> {
+    pub fn parse(bytes: &'a [u8]) -> io::Result<NsdFileView<'a>> {
+        let mut cursor = ByteCursor { bytes, position: 0 };
+
+        let header = NsdHeader::from_bytes(cursor.take(NSD_HEADER.len())?.try_into().unwrap())?;
+        let main = read_section(&mut cursor, String::new())?;
+
+        let mut extensions = NsdExtensions::default();
+        let mut sections = vec![];
+        while !cursor.is_at_end() {
+            let magic = cursor.take(4)?;
+            let chunk_size = cursor.read_u32()? as usize;
+            let mut chunk = ByteCursor { bytes: cursor.take(chunk_size)?, position: 0 };
+            if magic == NSD_GROUP_HEADER.as_slice() {
+                extensions.groups = read_groups(&mut chunk, main.attributes.len())?;
+            } else if magic == NSD_METADATA_HEADER.as_slice() {
+                extensions.attribute_metadata = read_attribute_metadata(&mut chunk, main.attributes.len())?;
+            } else if magic == NSD_WORLD_HEADER.as_slice() {
+                let world = NsdWorldScale {
+                    origin: [chunk.read_f64()?, chunk.read_f64()?],
+                    units_per_texel: [chunk.read_f64()?, chunk.read_f64()?],
+                    epsg: match chunk.is_at_end() {
+                        true => None,
+                        false => Some(chunk.read_u32()?),
+                    },
+                };
+                if world.units_per_texel.iter().any(|units| *units == 0.0 || !units.is_finite()) {
+                    return Err(invalid_data(format!("Invalid world units per texel {:?}", world.units_per_texel)));
+                }
+                extensions.world = Some(world);
+            } else if magic == NSD_SECTION_HEADER.as_slice() {
+                let name = chunk.read_string()?;
+                sections.push(read_section(&mut chunk, name)?);
+            }
+            // Unknown optional chunks are skipped.
+        }
+
+        extensions.texel_order = main.texel_order;
+        extensions.row_stride = main.row_stride;
+        main.check_combined_size(extensions.texel_order, extensions.row_stride)?;
+        for section in &sections {
+            if section.texel_order != TexelOrder::RowMajor || section.row_stride.is_some() {
+                return Err(invalid_data(format!("Section {} isn't row-major with tightly packed rows", section.name)));
+            }
+            section.check_combined_size(TexelOrder::RowMajor, None)?;
+        }
+        Ok(NsdFileView { header, main, extensions, sections })
+    }
+
+    pub fn header(&self) -> &NsdHeader {
+        &self.header
+    }
+
+    pub fn dimensions(&self) -> &LayerDimensions {
+        &self.main.dimensions
+    }
+
+    pub fn attributes(&self) -> &[NsdAttribute] {
+        &self.main.attributes
+    }
+
+    /// Combined size of all attributes of a single texel.
+    pub fn texel_stride(&self) -> usize {
+        self.main.texel_stride()
+    }
+
+    pub fn texel_order(&self) -> TexelOrder {
+        self.extensions.texel_order
+    }
+
+    /// Placement of the texels in world space, if the file stores it.
+    pub fn world(&self) -> Option<&NsdWorldScale> {
+        self.extensions.world.as_ref()
+    }
+
+    /// Size of the texel data without row padding, as returned by `NsdReader::data`.
+    pub fn data_size(&self) -> usize {
+        self.texel_stride() * self.main.dimensions.get_texel_count()
+    }
+
+    /// The main section, with its compressed texel data.
+    pub fn main_section(&self) -> &NsdSectionView<'a> {
+        &self.main
+    }
+
+    pub fn extensions(&self) -> &NsdExtensions {
+        &self.extensions
+    }
+
+    /// Additional sections stored at their own resolution.
+    pub fn sections(&self) -> &[NsdSectionView<'a>] {
+        &self.sections
+    }
+
+    pub fn groups(&self) -> &[NsdGroup] {
+        &self.extensions.groups
+    }
+
+    /// Description and tags of the attribute at the given index, if it has any.
+    pub fn attribute_metadata(&self, attribute: usize) -> Option<&NsdAttributeMetadata> {
+        self.extensions.attribute_metadata.iter().find(|metadata| metadata.attribute as usize == attribute)
+    }
+
+    /// Values of a single attribute, in the texel order of the file. The texel data is decompressed one row at a
+    /// time, so only the values of the attribute are held in memory.
+    pub fn attribute_data(&self, attribute: usize) -> io::Result<Vec<u8>> {
+        let LayerDimensions { width, height } = self.main.dimensions;
+        let stride = self.texel_stride();
+        let offset = self.main.attributes[..attribute].iter().map(|attribute| attribute.format.size as usize).sum::<usize>();
+        let size = self.main.attributes[attribute].format.size as usize;
+        let (row_length, row_count) = self.texel_order().rows(width, height);
+        let row_stride = self.extensions.row_stride.map_or(row_length * stride, |row_stride| row_stride as usize);
+        if row_stride < row_length * stride || row_stride * row_count != self.main.combined_size {
+            return Err(invalid_data(format!("Row stride {row_stride} doesn't match the DATA chunk")));
+        }
+
+        let mut decoder = ZlibDecoder::new(self.main.compressed);
+        let mut row = vec![0; row_stride];
+        let mut values = Vec::with_capacity(row_length * row_count * size);
+        for _ in 0..row_count {
+            decoder.read_exact(&mut row)?;
+            for texel in row[..row_length * stride].chunks_exact(stride) {
+                values.extend_from_slice(&texel[offset..offset + size]);
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// Reads the DIM, ATR and DATA chunks, leaving the texel data compressed.
+fn read_section<'a>(cursor: &mut ByteCursor<'a>, name: String) -> io::Result<NsdSectionView<'a>> {
+    cursor.expect(NSD_DIM_HEADER.as_slice(), "DIM")?;
+    let dimensions = LayerDimensions {
+        width: cursor.read_u32()?,
+        height: cursor.read_u32()?,
+    };
+    let code = cursor.read_u32()?;
+    let texel_order = TexelOrder::from_code(code)
+        .ok_or_else(|| invalid_data(format!("Unknown texel order {code}")))?;
+    let row_stride = match cursor.read_u32()? {
+        1 => None,
+        row_stride => Some(row_stride),
+    };
+
+    let mut attributes = vec![];
+    while cursor.peek(NSD_ATTR_HEADER.len()) == Some(NSD_ATTR_HEADER.as_slice()) {
+        cursor.take(NSD_ATTR_HEADER.len())?;
+        let name = cursor.read_string()?;
+        let size = cursor.read_u8()?;
+        let type_code = cursor.read_u8()?;
+        let attribute_type = AttributeType::from_code(type_code)
+            .ok_or_else(|| invalid_data(format!("Attribute {name} has an unknown type {type_code}")))?;
+        let format = AttributeFormat::new(attribute_type, size)
+            .map_err(|error| invalid_data(format!("Attribute {name}: {error}")))?;
+        attributes.push(NsdAttribute { name, format });
+    }
+
+    cursor.expect(NSD_DATA_HEADER.as_slice(), "DATA")?;
+    let combined_size = cursor.read_u32()? as usize;
+    let compressed_size = cursor.read_u32()? as usize;
+    let compressed = cursor.take(compressed_size)?;
+
+    Ok(NsdSectionView { name, dimensions, attributes, texel_order, row_stride, combined_size, compressed })
+}
+
+fn unpad_rows(
+    data: &[u8],
+    dimensions: &LayerDimensions,
+    attributes: &[NsdAttribute],
+    order: TexelOrder,
+    row_stride: usize
+) -> io::Result<Vec<u8>> {
+    let (row_length, row_count) = order.rows(dimensions.width, dimensions.height);
+    let row_size = row_length * attributes.iter().map(|attribute| attribute.format.size as usize).sum::<usize>();
+    if row_stride < row_size || data.len() != row_stride * row_count {
+        return Err(invalid_data(format!("Row stride {row_stride} doesn't match the DATA chunk")));
+    }
+    Ok(data.chunks_exact(row_stride).flat_map(|row| &row[..row_size]).copied().collect())
+}
+
+fn read_groups(chunk: &mut ByteCursor, attribute_count: usize) -> io::Result<Vec<NsdGroup>> {
+    let group_count = chunk.read_u32()?;
+    let mut groups = vec![];
+    for _ in 0..group_count {
+        let name = chunk.read_string()?;
+        let member_count = chunk.read_u32()?;
+        let mut attributes = vec![];
+        for _ in 0..member_count {
+            let attribute = chunk.read_u32()?;
+            if attribute as usize >= attribute_count {
+                return Err(invalid_data(format!("Group {name} references a nonexistent attribute {attribute}")));
+            }
+            attributes.push(attribute);
+        }
+        groups.push(NsdGroup { name, attributes });
+    }
+    Ok(groups)
+}
+
+fn read_attribute_metadata(chunk: &mut ByteCursor, attribute_count: usize) -> io::Result<Vec<NsdAttributeMetadata>> {
+    let entry_count = chunk.read_u32()?;
+    let mut entries = vec![];
+    for _ in 0..entry_count {
+        let attribute = chunk.read_u32()?;
+        if attribute as usize >= attribute_count {
+            return Err(invalid_data(format!("Metadata references a nonexistent attribute {attribute}")));
+        }
+        let description = chunk.read_string()?;
+        let tag_count = chunk.read_u32()?;
+        let mut tags = BTreeMap::new();
+        for _ in 0..tag_count {
+            let key = chunk.read_string()?;
+            tags.insert(key, chunk.read_string()?);
+        }
+        entries.push(NsdAttributeMetadata { attribute, description, tags, ..NsdAttributeMetadata::default() });
+    }
+    // Files written before nodata support end here.
+    if !chunk.is_at_end() {
+        for entry in &mut entries {
+            if chunk.read_u8()? != 0 {
+                entry.nodata = Some(chunk.read_f64()?);
+            }
+        }
+    }
+    // And before unit support here.
+    if !chunk.is_at_end() {
+        for entry in &mut entries {
+            entry.unit = chunk.read_string()?;
+            let code = chunk.read_u8()?;
+            entry.kind = ValueKind::from_code(code).ok_or_else(|| invalid_data(format!("Unknown value kind {code}")))?;
+        }
+    }
+    // And before priorities and blend weights here.
+    if !chunk.is_at_end() {
+        for entry in &mut entries {
+            if chunk.read_u8()? != 0 {
+                entry.priority = Some(chunk.read_i32()?);
+            }
+            if chunk.read_u8()? != 0 {
+                entry.weight = Some(chunk.read_f64()?);
+            }
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::collections::BTreeMap;
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::writer::write_parsed_file;
+
+    /// 3x2 texels of a 2-byte and a 1-byte attribute, with `extensions` and a 1x1 section.
+    pub(crate) fn sample_file(extensions: NsdExtensions) -> NsdReader {
+        let attributes = vec![
+            NsdAttribute { name: "height".into(), format: AttributeFormat::new(AttributeType::UInt, 2).unwrap() },
+            NsdAttribute { name: "grass".into(), format: AttributeFormat::new(AttributeType::Byte, 1).unwrap() },
+        ];
+        let section = NsdSection {
+            name: "coarse".into(),
+            dimensions: LayerDimensions { width: 1, height: 1 },
+            attributes: vec![NsdAttribute { name: "biome".into(), format: AttributeFormat::default() }],
+            data: vec![7],
+        };
+        let header = NsdHeader::new("ABC", "Terrain").unwrap();
+        let data = (0..18).map(|value| value * 13).collect();
+        NsdReader::from_parts(header, LayerDimensions { width: 3, height: 2 }, attributes, data, extensions, vec![section]).unwrap()
+    }
+
+    /// Extensions using every optional chunk.
+    pub(crate) fn all_extensions() -> NsdExtensions {
+        NsdExtensions {
+            groups: vec![NsdGroup { name: "terrain".into(), attributes: vec![0, 1] }],
+            attribute_metadata: vec![NsdAttributeMetadata {
+                attribute: 0,
+                description: "Height above sea level".into(),
+                tags: BTreeMap::from([("owner".into(), "environment".into())]),
+                nodata: Some(0.0),
+                unit: "meters".into(),
+                kind: ValueKind::Continuous,
+                priority: Some(-2),
+                weight: Some(0.5),
+            }],
+            texel_order: TexelOrder::ColumnMajor,
+            row_stride: None,
+            world: Some(NsdWorldScale { origin: [10.0, -5.0], units_per_texel: [2.0, -2.0], epsg: Some(32633) }),
+        }
+    }
+
+    pub(crate) fn assert_same_file(actual: &NsdReader, expected: &NsdReader) {
+        let attributes = |attributes: &[NsdAttribute]| -> Vec<(String, AttributeFormat)> {
+            attributes.iter().map(|attribute| (attribute.name.clone(), attribute.format)).collect()
+        };
+        assert_eq!(actual.header(), expected.header());
+        assert_eq!(actual.dimensions(), expected.dimensions());
+        assert_eq!(attributes(actual.attributes()), attributes(expected.attributes()));
+        assert_eq!(actual.data(), expected.data());
+        assert_eq!(actual.extensions(), expected.extensions());
+        assert_eq!(actual.sections().len(), expected.sections().len());
+        for (actual, expected) in actual.sections().iter().zip(expected.sections()) {
+            assert_eq!(actual.name, expected.name);
+            assert_eq!(actual.dimensions, expected.dimensions);
+            assert_eq!(attributes(&actual.attributes), attributes(&expected.attributes));
+            assert_eq!(actual.data, expected.data);
+        }
+    }
+
+    fn file_bytes(file: &NsdReader) -> Vec<u8> {
+        let mut output = Cursor::new(vec![]);
+        write_parsed_file(&mut output, file).unwrap();
+        output.into_inner()
+    }
+
+    #[test]
+    fn reads_back_written_files() {
+        for extensions in [NsdExtensions::default(), all_extensions()] {
+            let file = sample_file(extensions);
+            assert_same_file(&NsdReader::from_bytes(&file_bytes(&file)).unwrap(), &file);
+        }
+    }
+
+    #[test]
+    fn samples_texels() {
+        let file = sample_file(all_extensions());
+        let grass = |x, y| file.sample(1, x, y).unwrap()[0];
+        // At the center of a texel, bilinear sampling returns the texel.
+        assert_eq!(file.sample_bilinear(1, 2.5 / 3.0, 1.5 / 2.0), Some(vec![grass(2, 1)]));
+        assert_eq!(file.sample_bilinear(1, 0.5, 0.5), Some(vec![(grass(1, 0) + grass(1, 1)) / 2.0]));
+        assert_eq!(file.sample_bilinear(1, -1.0, 9.0), Some(vec![grass(0, 1)]));
+        assert_eq!(file.sample(1, 3, 0), None);
+
+        let empty = NsdReader::from_parts(
+            NsdHeader::default(), LayerDimensions { width: 0, height: 0 }, vec![], vec![], NsdExtensions::default(), vec![]
+        ).unwrap();
+        assert_eq!(empty.sample_bilinear(0, 0.5, 0.5), None);
+    }
+
+    #[test]
+    fn reads_regions_and_texels_regardless_of_the_texel_order() {
+        let file = sample_file(all_extensions());
+        let grass = |x: u32, y: u32| file.sample(1, x, y).unwrap()[0] as u8;
+        let region = LayerRegion { x: 1, y: 0, width: 2, height: 2 };
+        assert_eq!(file.read_region(1, &region).unwrap(), [grass(1, 0), grass(2, 0), grass(1, 1), grass(2, 1)]);
+        let outside = LayerRegion { x: 2, ..region };
+        assert_eq!(file.read_region(1, &outside).unwrap_err().to_string(), "Region 2,0 2x2 is outside of the 3x2 texels");
+
+        // Column-major files are iterated column by column.
+        let texels: Vec<(u32, u32, &[u8])> = file.texels().take(3).collect();
+        assert_eq!(texels, [(0, 0, &file.data()[0..3]), (0, 1, &file.data()[3..6]), (1, 0, &file.data()[6..9])]);
+    }
+
+    #[test]
+    fn rejects_truncated_files() {
+        // Without optional chunks, every part of the file is required.
+        let mut file = sample_file(NsdExtensions::default());
+        file.sections.clear();
+        let bytes = file_bytes(&file);
+        for length in 0..bytes.len() {
+            assert!(NsdReader::from_bytes(&bytes[..length]).is_err(), "{length} of {} bytes were accepted", bytes.len());
+        }
+        let bytes = file_bytes(&sample_file(all_extensions()));
+        assert!(NsdReader::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_inconsistent_files() {
+        let bytes = file_bytes(&sample_file(all_extensions()));
+        let modified = |offset: usize, value: u8| {
+            let mut bytes = bytes.clone();
+            bytes[offset] = value;
+            NsdReader::from_bytes(&bytes).err().map(|error| error.to_string()).unwrap_or_default()
+        };
+        let width_offset = NSD_HEADER.len() + NSD_DIM_HEADER.len();
+        let size_offset = width_offset + 16 + NSD_ATTR_HEADER.len() + "height\0".len();
+        assert!(modified(width_offset, 4).contains("declares 18 bytes of texel data, but its dimensions and attributes need"));
+        assert!(modified(width_offset + 3, 0x80).contains("declares 18 bytes"));
+        assert!(modified(size_offset, 3).starts_with("Attribute height:"));
+        assert!(modified(size_offset, 0).starts_with("Attribute height:"));
+        assert!(modified(size_offset + 1, 9).contains("unknown type 9"));
+        assert!(modified(width_offset + 8, 0).contains("Unknown texel order 0"));
+    }
+
+    #[test]
+    fn records_the_texel_order_in_the_dim_chunk() {
+        let order_offset = NSD_HEADER.len() + NSD_DIM_HEADER.len() + 8;
+        let order = |extensions| {
+            let bytes = file_bytes(&sample_file(extensions));
+            u32::from_le_bytes(bytes[order_offset..order_offset + 4].try_into().unwrap())
+        };
+        // Row-major files keep the 1 which files had there before the order was recorded.
+        assert_eq!(order(NsdExtensions::default()), 1);
+        assert_eq!(order(all_extensions()), 2);
+    }
+
+    #[test]
+    fn records_the_row_stride_in_the_dim_chunk() {
+        let stride_offset = NSD_HEADER.len() + NSD_DIM_HEADER.len() + 12;
+        let row_stride = |bytes: &[u8]| u32::from_le_bytes(bytes[stride_offset..stride_offset + 4].try_into().unwrap());
+        // Tightly packed rows keep the 1 which files had there before the row stride was recorded.
+        assert_eq!(row_stride(&file_bytes(&sample_file(all_extensions()))), 1);
+
+        // Columns of 2 texels of 3 bytes each, padded to 8 bytes.
+        let file = sample_file(NsdExtensions { row_stride: Some(8), ..all_extensions() });
+        let bytes = file_bytes(&file);
+        assert_eq!(row_stride(&bytes), 8);
+        let read = NsdReader::from_bytes(&bytes).unwrap();
+        assert_same_file(&read, &file);
+
+        let mut bytes = bytes;
+        bytes[stride_offset] = 5;
+        let error = NsdReader::from_bytes(&bytes).err().unwrap().to_string();
+        assert!(error.contains("Row stride 5 of The main section is shorter than its rows"), "{error}");
+    }
+}
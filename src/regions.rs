@@ -0,0 +1,106 @@
+//! Connected-component labeling of mask layers, so individual lakes or rooms painted into one mask get IDs the
+//! runtime can tell apart.
+
+use std::io;
+
+use image::{DynamicImage, GrayImage, Luma};
+
+/// Highest ID a region can get, regions are stored as 8-bit categorical values.
+pub const MAX_REGIONS: usize = u8::MAX as usize;
+
+/// Which neighbors of a texel belong to its region.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Connectivity {
+    /// The texels sharing an edge.
+    Four,
+    /// The texels sharing an edge or a corner.
+    Eight,
+}
+
+impl Connectivity {
+    pub fn from_number(number: i64) -> Option<Connectivity> {
+        match number {
+            4 => Some(Connectivity::Four),
+            8 => Some(Connectivity::Eight),
+            _ => None,
+        }
+    }
+
+    fn offsets(&self) -> &'static [(i64, i64)] {
+        match self {
+            Connectivity::Four => &[(-1, 0), (1, 0), (0, -1), (0, 1)],
+            Connectivity::Eight => &[(-1, 0), (1, 0), (0, -1), (0, 1), (-1, -1), (1, -1), (-1, 1), (1, 1)],
+        }
+    }
+}
+
+/// IDs of the connected regions of the mask in the first channel of the image, where texels at or above half of
+/// the range (and with data) are inside. Regions are numbered from 1 in the order their first texel appears row by
+/// row, texels outside the mask are 0.
+pub fn label_regions(image: &DynamicImage, connectivity: Connectivity, layer_name: &str) -> io::Result<DynamicImage> {
+    let mask = image.to_rgba32f();
+    let (width, height) = mask.dimensions();
+    let inside: Vec<bool> = mask.pixels().map(|pixel| pixel.0[0] >= 0.5 && pixel.0[3] >= 0.5).collect();
+    let mut labels = vec![0u8; inside.len()];
+    let mut regions = 0;
+    let mut stack = Vec::new();
+    for start in 0..inside.len() {
+        if !inside[start] || labels[start] != 0 {
+            continue;
+        }
+        regions += 1;
+        if regions > MAX_REGIONS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "Layer {layer_name} has more than {MAX_REGIONS} connected regions"
+            )));
+        }
+        labels[start] = regions as u8;
+        stack.push(start);
+        while let Some(index) = stack.pop() {
+            let (x, y) = ((index % width as usize) as i64, (index / width as usize) as i64);
+            for (dx, dy) in connectivity.offsets() {
+                let (nx, ny) = (x + dx, y + dy);
+                if nx < 0 || ny < 0 || nx >= width as i64 || ny >= height as i64 {
+                    continue;
+                }
+                let neighbor = ny as usize * width as usize + nx as usize;
+                if inside[neighbor] && labels[neighbor] == 0 {
+                    labels[neighbor] = regions as u8;
+                    stack.push(neighbor);
+                }
+            }
+        }
+    }
+    Ok(DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| {
+        Luma([labels[y as usize * width as usize + x as usize]])
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mask(width: u32, height: u32, texels: &[u8]) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, texels.iter().map(|&texel| texel * 255).collect()).unwrap())
+    }
+
+    #[test]
+    fn numbers_the_connected_regions_row_by_row() {
+        let diagonal = mask(4, 3, &[
+            1, 0, 0, 1,
+            0, 1, 0, 1,
+            0, 0, 0, 0,
+        ]);
+        let labels = |connectivity| label_regions(&diagonal, connectivity, "lakes").unwrap().to_luma8().into_raw();
+        assert_eq!(labels(Connectivity::Four), [1, 0, 0, 2, 0, 3, 0, 2, 0, 0, 0, 0]);
+        assert_eq!(labels(Connectivity::Eight), [1, 0, 0, 2, 0, 1, 0, 2, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn fails_on_more_regions_than_ids() {
+        let texels: Vec<u8> = (0..MAX_REGIONS * 2 + 2).map(|index| (index % 2 == 0) as u8).collect();
+        let error = label_regions(&mask(texels.len() as u32, 1, &texels), Connectivity::Four, "lakes").unwrap_err();
+        assert_eq!(error.to_string(), "Layer lakes has more than 255 connected regions");
+        assert!(label_regions(&mask(MAX_REGIONS as u32 * 2, 1, &texels[..MAX_REGIONS * 2]), Connectivity::Four, "lakes").is_ok());
+    }
+}
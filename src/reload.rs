@@ -0,0 +1,96 @@
+//! Notifications telling a running game or editor to reload a regenerated file.
+
+use std::fs::{self, File};
+use std::io;
+use std::net::UdpSocket;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::http::post;
+use crate::json::JsonValue;
+
+/// Ways of notifying about a new file, configured in the `[reload]` table of the manifest.
+#[derive(Clone, Default, Debug, PartialEq)]
+pub struct ReloadNotifications {
+    /// File whose modification time is updated (created if needed), relative to the input directory.
+    pub touch: Option<PathBuf>,
+    /// Address (`host:port`) a UDP packet is sent to.
+    pub udp: Option<String>,
+    /// `http://` URL which is POSTed to.
+    pub webhook: Option<String>,
+}
+
+impl ReloadNotifications {
+    /// Sends every configured notification about the output file. The UDP packet and the webhook request carry
+    /// `{"event": "reload", "output": "<absolute path>"}`. Returns the errors of the notifications which failed.
+    pub fn send(&self, output: &Path, directory: &Path) -> Vec<io::Error> {
+        let output = fs::canonicalize(output).unwrap_or_else(|_| output.to_path_buf());
+        let message = JsonValue::object()
+            .with("event", "reload")
+            .with("output", output.to_string_lossy().into_owned())
+            .to_string();
+
+        let mut errors = vec![];
+        if let Some(path) = &self.touch {
+            let path = directory.join(path);
+            let touched = File::options().create(true).append(true).open(&path)
+                .and_then(|file| file.set_modified(SystemTime::now()));
+            if let Err(error) = touched {
+                errors.push(io::Error::new(error.kind(), format!("Could not touch {}: {error}", path.display())));
+            }
+        }
+        if let Some(address) = &self.udp {
+            let sent = UdpSocket::bind(if address.starts_with('[') { "[::]:0" } else { "0.0.0.0:0" })
+                .and_then(|socket| socket.send_to(message.as_bytes(), address.as_str()));
+            if let Err(error) = sent {
+                errors.push(io::Error::new(error.kind(), format!("Could not send a UDP packet to {address}: {error}")));
+            }
+        }
+        if let Some(url) = &self.webhook {
+            let status = post(url, "application/json", message.as_bytes()).and_then(|status| match status {
+                200..=299 => Ok(()),
+                _ => Err(io::Error::other(format!("the server answered with status {status}"))),
+            });
+            if let Err(error) = status {
+                errors.push(io::Error::new(error.kind(), format!("Could not call webhook {url}: {error}")));
+            }
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    #[test]
+    fn touches_the_file_and_sends_the_output_over_udp() {
+        let directory = TempDirectory::new();
+        let output = directory.join("terrain.nsd");
+        fs::write(&output, []).unwrap();
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        receiver.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let notifications = ReloadNotifications {
+            touch: Some("reload.stamp".into()),
+            udp: Some(receiver.local_addr().unwrap().to_string()),
+            webhook: None,
+        };
+        assert!(notifications.send(&output, directory.path()).is_empty());
+        assert!(directory.join("reload.stamp").is_file());
+
+        let mut packet = [0; 1024];
+        let length = receiver.recv(&mut packet).unwrap();
+        let message = JsonValue::parse(std::str::from_utf8(&packet[..length]).unwrap()).unwrap();
+        assert_eq!(message.get("event").and_then(JsonValue::as_str), Some("reload"));
+        let sent_output = fs::canonicalize(&output).unwrap();
+        assert_eq!(message.get("output").and_then(JsonValue::as_str), sent_output.to_str());
+
+        let unreachable = ReloadNotifications { touch: Some("missing/reload.stamp".into()), ..ReloadNotifications::default() };
+        let errors = unreachable.send(&output, directory.path());
+        assert!(errors[0].to_string().starts_with("Could not touch"));
+    }
+}
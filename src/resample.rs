@@ -0,0 +1,474 @@
+//! Resampling of layers to the target dimensions.
+
+use std::f64::consts::PI;
+
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageBuffer, Pixel};
+
+/// Filter used to resample a layer to the target dimensions.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ResizeFilter {
+    #[default]
+    Nearest,
+    Triangle,
+    CatmullRom,
+    Gaussian,
+    Lanczos3,
+    /// Averages all source texels covered by a target texel, weighted by the covered area.
+    Area,
+    /// Picks the value covering the largest area of a target texel, for categorical layers.
+    Majority,
+}
+
+impl ResizeFilter {
+    pub fn from_name(name: &str) -> Option<ResizeFilter> {
+        match name {
+            "nearest" => Some(ResizeFilter::Nearest),
+            "triangle" => Some(ResizeFilter::Triangle),
+            "catmull-rom" => Some(ResizeFilter::CatmullRom),
+            "gaussian" => Some(ResizeFilter::Gaussian),
+            "lanczos3" => Some(ResizeFilter::Lanczos3),
+            "area" => Some(ResizeFilter::Area),
+            "majority" => Some(ResizeFilter::Majority),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResizeFilter::Nearest => "nearest",
+            ResizeFilter::Triangle => "triangle",
+            ResizeFilter::CatmullRom => "catmull-rom",
+            ResizeFilter::Gaussian => "gaussian",
+            ResizeFilter::Lanczos3 => "lanczos3",
+            ResizeFilter::Area => "area",
+            ResizeFilter::Majority => "majority",
+        }
+    }
+
+    fn filter_type(&self) -> Option<FilterType> {
+        match self {
+            ResizeFilter::Nearest => Some(FilterType::Nearest),
+            ResizeFilter::Triangle => Some(FilterType::Triangle),
+            ResizeFilter::CatmullRom => Some(FilterType::CatmullRom),
+            ResizeFilter::Gaussian => Some(FilterType::Gaussian),
+            ResizeFilter::Lanczos3 => Some(FilterType::Lanczos3),
+            ResizeFilter::Area | ResizeFilter::Majority => None,
+        }
+    }
+
+    /// Radius of the filter kernel, in texels of the larger of the two images.
+    fn support(&self) -> f64 {
+        match self {
+            ResizeFilter::Nearest | ResizeFilter::Area | ResizeFilter::Majority => 0.0,
+            ResizeFilter::Triangle => 1.0,
+            ResizeFilter::CatmullRom => 2.0,
+            ResizeFilter::Gaussian | ResizeFilter::Lanczos3 => 3.0,
+        }
+    }
+
+    /// Unnormalized weight of a texel at the given distance from the sampled position, the kernels of the `image`
+    /// filters.
+    fn kernel(&self, distance: f64) -> f64 {
+        let sinc = |x: f64| if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+        let x = distance.abs();
+        match self {
+            ResizeFilter::Nearest | ResizeFilter::Area | ResizeFilter::Majority => if x <= 0.5 { 1.0 } else { 0.0 },
+            ResizeFilter::Triangle => (1.0 - x).max(0.0),
+            ResizeFilter::CatmullRom if x < 1.0 => (1.5 * x - 2.5) * x * x + 1.0,
+            ResizeFilter::CatmullRom if x < 2.0 => ((-0.5 * x + 2.5) * x - 4.0) * x + 2.0,
+            ResizeFilter::CatmullRom => 0.0,
+            ResizeFilter::Gaussian => (-2.0 * x * x).exp(),
+            ResizeFilter::Lanczos3 if x < 3.0 => sinc(x) * sinc(x / 3.0),
+            ResizeFilter::Lanczos3 => 0.0,
+        }
+    }
+}
+
+/// How resampling treats texels beyond the borders of a layer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum EdgeMode {
+    /// Borders are extended, the default of the `image` filters.
+    #[default]
+    Clamp,
+    /// The layer repeats, so layers tiling across world cells stay seamless.
+    Wrap,
+}
+
+/// Whether the values of a layer are measurements which can be interpolated, IDs which can't, or on/off flags.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default)]
+pub enum ValueKind {
+    #[default]
+    Continuous = 0,
+    /// Values are class IDs, e.g. biomes or surface types, so layers are resized with the nearest
+    /// and majority filters regardless of the filters chosen for other layers.
+    Categorical = 1,
+    /// Values are either set or not, e.g. playable areas, so layers are resized with the nearest and area filters
+    /// and thresholded at the middle of their range to stay binary.
+    Mask = 2,
+}
+
+impl ValueKind {
+    pub fn from_name(name: &str) -> Option<ValueKind> {
+        match name {
+            "continuous" => Some(ValueKind::Continuous),
+            "categorical" => Some(ValueKind::Categorical),
+            "mask" => Some(ValueKind::Mask),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueKind::Continuous => "continuous",
+            ValueKind::Categorical => "categorical",
+            ValueKind::Mask => "mask",
+        }
+    }
+
+    pub fn from_code(code: u8) -> Option<ValueKind> {
+        match code {
+            0 => Some(ValueKind::Continuous),
+            1 => Some(ValueKind::Categorical),
+            2 => Some(ValueKind::Mask),
+            _ => None,
+        }
+    }
+}
+
+impl EdgeMode {
+    pub fn from_name(name: &str) -> Option<EdgeMode> {
+        match name {
+            "clamp" => Some(EdgeMode::Clamp),
+            "wrap" => Some(EdgeMode::Wrap),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeMode::Clamp => "clamp",
+            EdgeMode::Wrap => "wrap",
+        }
+    }
+}
+
+/// Applies an expression to the buffer inside any `DynamicImage`, keeping its color type.
+macro_rules! map_buffer {
+    ($image:expr, $buffer:ident => $body:expr) => {
+        match $image {
+            DynamicImage::ImageLuma8($buffer) => DynamicImage::ImageLuma8($body),
+            DynamicImage::ImageLumaA8($buffer) => DynamicImage::ImageLumaA8($body),
+            DynamicImage::ImageRgb8($buffer) => DynamicImage::ImageRgb8($body),
+            DynamicImage::ImageRgba8($buffer) => DynamicImage::ImageRgba8($body),
+            DynamicImage::ImageLuma16($buffer) => DynamicImage::ImageLuma16($body),
+            DynamicImage::ImageLumaA16($buffer) => DynamicImage::ImageLumaA16($body),
+            DynamicImage::ImageRgb16($buffer) => DynamicImage::ImageRgb16($body),
+            DynamicImage::ImageRgba16($buffer) => DynamicImage::ImageRgba16($body),
+            DynamicImage::ImageRgb32F($buffer) => DynamicImage::ImageRgb32F($body),
+            DynamicImage::ImageRgba32F($buffer) => DynamicImage::ImageRgba32F($body),
+            image => {
+                let $buffer = &image.to_rgba32f();
+                DynamicImage::ImageRgba32F($body)
+            }
+        }
+    };
+}
+
+pub(crate) use map_buffer;
+
+/// Resizes the image to exactly the given dimensions, stretching it if its aspect ratio differs, so every layer
+/// covers the same area.
+pub fn resize(image: &DynamicImage, width: u32, height: u32, filter: ResizeFilter, edge: EdgeMode) -> DynamicImage {
+    if (width, height) == image.dimensions() {
+        return image.clone();
+    }
+    match filter.filter_type() {
+        // Area filters never sample beyond the borders, so the edge mode doesn't matter.
+        None if filter == ResizeFilter::Majority => map_buffer!(image, buffer => majority_resize(buffer, width, height)),
+        None => map_buffer!(image, buffer => area_resize(buffer, width, height)),
+        Some(filter_type) if edge == EdgeMode::Clamp => image.resize_exact(width, height, filter_type),
+        Some(_) => {
+            // The kernel reaches around the borders into the texels of the opposite side.
+            let columns = wrapped_weights(image.width(), width, filter);
+            let rows = wrapped_weights(image.height(), height, filter);
+            map_buffer!(image, buffer => weighted_resize(buffer, &columns, &rows))
+        }
+    }
+}
+
+/// For every target texel along one axis, the source texels under the filter kernel and their normalized weights.
+/// Positions beyond the borders wrap around, so the kernel stays centered on the target texel.
+fn wrapped_weights(source_size: u32, target_size: u32, filter: ResizeFilter) -> Vec<Vec<(usize, f64)>> {
+    let ratio = source_size as f64 / target_size as f64;
+    // When downscaling, the kernel is stretched over the source texels of a target texel.
+    let scale = ratio.max(1.0);
+    let support = filter.support() * scale;
+    (0..target_size).map(|target| {
+        let center = (target as f64 + 0.5) * ratio;
+        let left = (center - support).floor() as i64;
+        let right = ((center + support).ceil() as i64).max(left + 1);
+        let mut weights: Vec<(usize, f64)> = (left..right)
+            .map(|source| {
+                let weight = filter.kernel((source as f64 + 0.5 - center) / scale);
+                (source.rem_euclid(source_size as i64) as usize, weight)
+            })
+            .filter(|&(_, weight)| weight != 0.0)
+            .collect();
+        let sum: f64 = weights.iter().map(|(_, weight)| weight).sum();
+        for (_, weight) in &mut weights {
+            *weight /= sum;
+        }
+        weights
+    }).collect()
+}
+
+/// Resamples the rows and then the columns with the given weights of the source texels.
+fn weighted_resize<P>(
+    image: &ImageBuffer<P, Vec<P::Subpixel>>,
+    columns: &[Vec<(usize, f64)>],
+    rows: &[Vec<(usize, f64)>]
+) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel,
+    P::Subpixel: Sample,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let source_row_size = image.width() as usize * channels;
+    let row_size = columns.len() * channels;
+
+    let mut resampled_rows = Vec::with_capacity(image.height() as usize * row_size);
+    for source_row in image.as_raw().chunks_exact(source_row_size) {
+        for column in columns {
+            for channel in 0..channels {
+                resampled_rows.push(column.iter()
+                    .map(|&(x, weight)| source_row[x * channels + channel].to_f64() * weight)
+                    .sum::<f64>());
+            }
+        }
+    }
+
+    let mut samples = Vec::with_capacity(rows.len() * row_size);
+    for row in rows {
+        for index in 0..row_size {
+            let value = row.iter().map(|&(y, weight)| resampled_rows[y * row_size + index] * weight).sum();
+            samples.push(P::Subpixel::from_f64(value));
+        }
+    }
+    ImageBuffer::from_raw(columns.len() as u32, rows.len() as u32, samples).expect("The buffer has the size of the image.")
+}
+
+/// Channel value which can be averaged.
+pub(crate) trait Sample: Copy + PartialEq {
+    /// Largest value of a normalized sample.
+    const MAX: f64;
+
+    fn to_f64(self) -> f64;
+    fn from_f64(value: f64) -> Self;
+}
+
+impl Sample for u8 {
+    const MAX: f64 = u8::MAX as f64;
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value.round().clamp(0.0, u8::MAX as f64) as u8
+    }
+}
+
+impl Sample for u16 {
+    const MAX: f64 = u16::MAX as f64;
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value.round().clamp(0.0, u16::MAX as f64) as u16
+    }
+}
+
+impl Sample for f32 {
+    const MAX: f64 = 1.0;
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+}
+
+/// For every target texel along one axis, the source texels it covers and the fraction of it they cover.
+fn coverage(source_size: u32, target_size: u32) -> Vec<Vec<(usize, f64)>> {
+    let scale = source_size as f64 / target_size as f64;
+    (0..target_size).map(|target| {
+        let start = target as f64 * scale;
+        let end = start + scale;
+        let mut weights = vec![];
+        let mut source = start.floor() as usize;
+        while (source as f64) < end && source < source_size as usize {
+            let covered = end.min(source as f64 + 1.0) - start.max(source as f64);
+            if covered > 0.0 {
+                weights.push((source, covered / scale));
+            }
+            source += 1;
+        }
+        weights
+    }).collect()
+}
+
+fn area_resize<P>(image: &ImageBuffer<P, Vec<P::Subpixel>>, width: u32, height: u32) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel,
+    P::Subpixel: Sample,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let source_width = image.width() as usize;
+    let source = image.as_raw();
+    let columns = coverage(image.width(), width);
+    let rows = coverage(image.height(), height);
+
+    let mut samples = Vec::with_capacity(width as usize * height as usize * channels);
+    let mut sums = vec![0f64; channels];
+    for row in &rows {
+        for column in &columns {
+            sums.fill(0.0);
+            for &(y, row_weight) in row {
+                for &(x, column_weight) in column {
+                    let start = (y * source_width + x) * channels;
+                    for (sum, sample) in sums.iter_mut().zip(&source[start..start + channels]) {
+                        *sum += sample.to_f64() * row_weight * column_weight;
+                    }
+                }
+            }
+            samples.extend(sums.iter().map(|&sum| P::Subpixel::from_f64(sum)));
+        }
+    }
+    ImageBuffer::from_raw(width, height, samples).expect("The buffer has the size of the image.")
+}
+
+fn majority_resize<P>(image: &ImageBuffer<P, Vec<P::Subpixel>>, width: u32, height: u32) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel,
+    P::Subpixel: Sample,
+{
+    let channels = P::CHANNEL_COUNT as usize;
+    let source_width = image.width() as usize;
+    let source = image.as_raw();
+    let columns = coverage(image.width(), width);
+    let rows = coverage(image.height(), height);
+
+    let mut samples = Vec::with_capacity(width as usize * height as usize * channels);
+    let mut votes: Vec<(&[P::Subpixel], f64)> = vec![];
+    for row in &rows {
+        for column in &columns {
+            votes.clear();
+            for &(y, row_weight) in row {
+                for &(x, column_weight) in column {
+                    let start = (y * source_width + x) * channels;
+                    let value = &source[start..start + channels];
+                    match votes.iter_mut().find(|(voted, _)| *voted == value) {
+                        Some((_, weight)) => *weight += row_weight * column_weight,
+                        None => votes.push((value, row_weight * column_weight)),
+                    }
+                }
+            }
+            // Ties go to the value found first, i.e. closest to the top left corner.
+            let (value, _) = votes.iter()
+                .fold(votes[0], |best, vote| if vote.1 > best.1 { *vote } else { best });
+            samples.extend_from_slice(value);
+        }
+    }
+    ImageBuffer::from_raw(width, height, samples).expect("The buffer has the size of the image.")
+}
+
+#[cfg(test)]
+mod tests {
+    use image::GrayImage;
+
+    use super::*;
+
+    const FILTERS: [ResizeFilter; 7] = [
+        ResizeFilter::Nearest, ResizeFilter::Triangle, ResizeFilter::CatmullRom, ResizeFilter::Gaussian,
+        ResizeFilter::Lanczos3, ResizeFilter::Area, ResizeFilter::Majority,
+    ];
+
+    #[test]
+    fn resizes_to_the_exact_dimensions() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(30, 10, |x, y| image::Luma([(x * 8 + y) as u8])));
+        for filter in FILTERS {
+            for edge in [EdgeMode::Clamp, EdgeMode::Wrap] {
+                for (width, height) in [(8, 8), (64, 32), (7, 13), (30, 10)] {
+                    let resized = resize(&image, width, height, filter, edge);
+                    assert_eq!(resized.dimensions(), (width, height), "{filter:?} {edge:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn wraps_the_kernel_around_the_borders() {
+        // Only the first texel is set, so only the wrapped kernel of the last target texel reaches it.
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(7, 1, |x, _| image::Luma([if x == 0 { 200 } else { 0 }])));
+        let last = |edge| resize(&image, 5, 1, ResizeFilter::Triangle, edge).to_luma8().get_pixel(4, 0)[0];
+        assert_eq!(last(EdgeMode::Clamp), 0);
+        assert!(last(EdgeMode::Wrap) > 0);
+    }
+
+    #[test]
+    fn keeps_uniform_layers_uniform_when_wrapping() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_pixel(13, 7, image::Luma([100])));
+        for filter in FILTERS {
+            // Coprime sizes, up and down.
+            for (width, height) in [(5, 3), (30, 11)] {
+                let resized = resize(&image, width, height, filter, EdgeMode::Wrap).to_luma8();
+                assert!(resized.pixels().all(|pixel| pixel[0] == 100), "{filter:?} {width}x{height}");
+            }
+        }
+    }
+
+    #[test]
+    fn wraps_like_the_image_filters_away_from_the_borders() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(40, 40, |x, y| image::Luma([((x * 37 + y * 11) % 256) as u8])));
+        for filter in &FILTERS[..5] {
+            let clamped = resize(&image, 30, 30, *filter, EdgeMode::Clamp).to_luma8();
+            let wrapped = resize(&image, 30, 30, *filter, EdgeMode::Wrap).to_luma8();
+            // The kernels of these target texels stay within the source, even stretched by the downscaling.
+            for y in 4..26 {
+                for x in 4..26 {
+                    let (clamped, wrapped) = (clamped.get_pixel(x, y)[0], wrapped.get_pixel(x, y)[0]);
+                    assert!(clamped.abs_diff(wrapped) <= 1, "{filter:?} at ({x}, {y}): {clamped} and {wrapped}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn averages_the_covered_area() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(4, 2, vec![0, 40, 100, 100, 80, 120, 200, 0]).unwrap());
+        let resized = resize(&image, 2, 1, ResizeFilter::Area, EdgeMode::Clamp).to_luma8();
+        assert_eq!(resized.into_raw(), [60, 100]);
+
+        // The middle texel is split between both target texels.
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(3, 1, vec![0, 90, 180]).unwrap());
+        let resized = resize(&image, 2, 1, ResizeFilter::Area, EdgeMode::Clamp).to_luma8();
+        assert_eq!(resized.into_raw(), [30, 150]);
+    }
+
+    #[test]
+    fn keeps_the_value_covering_the_largest_area() {
+        // Averaging would make up IDs between 3 and 9, which none of the texels have.
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(4, 2, vec![3, 9, 9, 5, 9, 9, 5, 5]).unwrap());
+        let resized = resize(&image, 2, 1, ResizeFilter::Majority, EdgeMode::Clamp).to_luma8();
+        assert_eq!(resized.into_raw(), [9, 5]);
+
+        // Ties go to the value closest to the top left corner.
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(2, 1, vec![7, 2]).unwrap());
+        assert_eq!(resize(&image, 1, 1, ResizeFilter::Majority, EdgeMode::Clamp).to_luma8().into_raw(), [7]);
+    }
+}
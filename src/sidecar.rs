@@ -0,0 +1,176 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::format::{NsdExtensions, NsdHeader};
+use crate::json::JsonValue;
+use crate::layer::{Layer, LayerDimensions, LayerSection};
+use crate::resample::ValueKind;
+use crate::timestamp::format_rfc3339;
+
+/// Version of the descriptor layout, bumped whenever the importer needs to handle it differently.
+const UNREAL_DESCRIPTOR_VERSION: u32 = 1;
+
+/// Controls the parts of the descriptor which differ between otherwise identical runs.
+#[derive(Clone, Default)]
+pub struct DescriptorOptions {
+    /// Unix time recorded as the generation time; omitted if not set.
+    pub generated_at: Option<u64>,
+    /// Whether to record the absolute path of the source directory, which depends on the machine.
+    pub include_source_directory: bool,
+}
+
+/// Path of the Unreal Engine import descriptor written next to the given spatial data file.
+pub fn unreal_descriptor_path(spatial_data_path: &Path) -> PathBuf {
+    let mut file_name = spatial_data_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".json");
+    spatial_data_path.with_file_name(file_name)
+}
+
+/// Describes a generated file in the shape consumed by the Unreal Engine importer factory.
+pub fn make_unreal_descriptor(
+    spatial_data_path: &Path,
+    header: &NsdHeader,
+    layers: &[Layer],
+    dimensions: &LayerDimensions,
+    extensions: &NsdExtensions,
+    sections: &[LayerSection],
+    options: &DescriptorOptions
+) -> JsonValue {
+    let mut offset = 0usize;
+    let attributes = layers.iter().enumerate().map(|(index, layer)| {
+        let group = extensions.groups.iter()
+            .find(|group| group.attributes.contains(&(index as u32)))
+            .map(|group| group.name.clone());
+        let metadata = extensions.attribute_metadata.iter()
+            .find(|metadata| metadata.attribute as usize == index);
+        let tags = metadata.map_or(vec![], |metadata| {
+            metadata.tags.iter().map(|(key, value)| (key.clone(), JsonValue::from(value.as_str()))).collect()
+        });
+
+        let attribute = JsonValue::object()
+            .with("name", layer.name.as_str())
+            .with("index", index)
+            .with("type", layer.format.attribute_type.name())
+            .with("typeCode", layer.format.attribute_type as u8)
+            .with("size", layer.format.size)
+            .with("offset", offset)
+            .with("group", group)
+            .with("description", metadata.map(|metadata| metadata.description.clone()).filter(|description| !description.is_empty()))
+            .with("tags", JsonValue::Object(tags))
+            .with("nodata", metadata.and_then(|metadata| metadata.nodata))
+            .with("unit", metadata.map(|metadata| metadata.unit.clone()).filter(|unit| !unit.is_empty()))
+            .with("kind", metadata.map_or(ValueKind::Continuous, |metadata| metadata.kind).name())
+            .with("priority", metadata.and_then(|metadata| metadata.priority).map(|priority| priority as f64))
+            .with("weight", metadata.and_then(|metadata| metadata.weight))
+            .with("source", layer.source.path.file_name().unwrap_or_default().to_string_lossy().as_ref())
+            .with("tileLayer", layer.source.tile_layer.as_ref().map(|(tile_layer, _)| tile_layer.as_str()))
+            .with("alphaChannel", layer.source.alpha_channel);
+        offset += layer.format.size as usize;
+        attribute
+    }).collect::<Vec<_>>();
+
+    let sections = sections.iter().map(|section| {
+        let mut offset = 0usize;
+        let attributes = section.layers.iter().map(|layer| {
+            let attribute = JsonValue::object()
+                .with("name", layer.name.as_str())
+                .with("type", layer.format.attribute_type.name())
+                .with("typeCode", layer.format.attribute_type as u8)
+                .with("size", layer.format.size)
+                .with("offset", offset)
+                .with("source", layer.source.path.file_name().unwrap_or_default().to_string_lossy().as_ref());
+            offset += layer.format.size as usize;
+            attribute
+        }).collect::<Vec<_>>();
+        JsonValue::object()
+            .with("name", section.name.as_str())
+            .with("width", section.dimensions.width)
+            .with("height", section.dimensions.height)
+            .with("texelStride", offset)
+            .with("attributes", attributes)
+    }).collect::<Vec<_>>();
+
+    let source_directory = layers.first()
+        .and_then(|layer| layer.source.path.parent())
+        .filter(|_| options.include_source_directory)
+        .map(|directory| fs::canonicalize(directory).unwrap_or(directory.to_path_buf()));
+
+    JsonValue::object()
+        .with("version", UNREAL_DESCRIPTOR_VERSION)
+        .with("generatedAt", options.generated_at.map(format_rfc3339))
+        .with("file", spatial_data_path.file_name().unwrap_or_default().to_string_lossy().as_ref())
+        .with("header", JsonValue::object()
+            .with("vendor", header.vendor())
+            .with("identifier", header.identifier()))
+        .with("width", dimensions.width)
+        .with("height", dimensions.height)
+        .with("texelStride", offset)
+        .with("texelOrder", extensions.texel_order.name())
+        .with("rowStride", extensions.row_stride)
+        .with("world", extensions.world.map(|world| JsonValue::object()
+            .with("originX", world.origin[0])
+            .with("originY", world.origin[1])
+            .with("unitsPerTexelX", world.units_per_texel[0])
+            .with("unitsPerTexelY", world.units_per_texel[1])
+            .with("epsg", world.epsg)))
+        .with("sourceDirectory", source_directory.map(|directory| directory.display().to_string()))
+        .with("attributes", attributes)
+        .with("groups", extensions.groups.iter().map(|group| group.name.as_str()).collect::<Vec<_>>())
+        .with("sections", sections)
+}
+
+pub fn write_unreal_descriptor(
+    spatial_data_path: &Path,
+    header: &NsdHeader,
+    layers: &[Layer],
+    dimensions: &LayerDimensions,
+    extensions: &NsdExtensions,
+    sections: &[LayerSection],
+    options: &DescriptorOptions
+) -> io::Result<PathBuf> {
+    let descriptor = make_unreal_descriptor(spatial_data_path, header, layers, dimensions, extensions, sections, options);
+    let path = unreal_descriptor_path(spatial_data_path);
+    fs::write(&path, descriptor.to_pretty_string())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage};
+
+    use super::*;
+    use crate::format::{AttributeFormat, AttributeType, NsdAttributeMetadata, NsdGroup};
+    use crate::layer::tests::sample_layer;
+
+    #[test]
+    fn describes_the_attributes_and_their_offsets() {
+        let image = DynamicImage::ImageLuma8(GrayImage::new(4, 2));
+        let layers = vec![
+            sample_layer("height", &image, AttributeFormat::new(AttributeType::UInt, 2).unwrap()),
+            sample_layer("grass", &image, AttributeFormat::default()),
+        ];
+        let extensions = NsdExtensions {
+            groups: vec![NsdGroup { name: "vegetation".into(), attributes: vec![1] }],
+            attribute_metadata: vec![NsdAttributeMetadata { attribute: 0, unit: "meters".into(), ..NsdAttributeMetadata::default() }],
+            ..NsdExtensions::default()
+        };
+        let path = Path::new("Output/Terrain.nsd");
+        let descriptor = make_unreal_descriptor(
+            path, &NsdHeader::default(), &layers, &LayerDimensions { width: 4, height: 2 }, &extensions, &[],
+            &DescriptorOptions::default()
+        );
+
+        assert_eq!(unreal_descriptor_path(path), Path::new("Output/Terrain.nsd.json"));
+        assert_eq!(descriptor.get("file").and_then(JsonValue::as_str), Some("Terrain.nsd"));
+        assert_eq!(descriptor.get("generatedAt"), Some(&JsonValue::Null));
+        assert_eq!(descriptor.get("sourceDirectory"), Some(&JsonValue::Null));
+        assert_eq!(descriptor.get("texelStride").and_then(JsonValue::as_u64), Some(3));
+        let attributes = descriptor.get("attributes").and_then(JsonValue::as_array).unwrap();
+        let field = |index: usize, key: &str| attributes[index].get(key).cloned().unwrap();
+        assert_eq!((field(0, "offset"), field(1, "offset")), (JsonValue::from(0), JsonValue::from(2)));
+        assert_eq!((field(0, "group"), field(1, "group")), (JsonValue::Null, JsonValue::from("vegetation")));
+        assert_eq!(field(0, "unit"), JsonValue::from("meters"));
+        assert_eq!(field(1, "source"), JsonValue::from("grass.png"));
+    }
+}
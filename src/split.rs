@@ -0,0 +1,268 @@
+//! Splitting of files whose texel data exceeds a size limit into a grid of smaller files with an index.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cache::{fnv1a, FNV_OFFSET_BASIS};
+use crate::format::{NsdExtensions, NsdHeader, NsdWorldScale, TexelOrder};
+use crate::info;
+use crate::json::JsonValue;
+use crate::layer::{Layer, LayerDimensions, LayerSection};
+use crate::writer::{aligned_row_stride, make_attribute_bytes, make_raw_data, texel_stride, write_binary};
+
+/// Grid of equally sized tiles covering the full dimensions.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SplitGrid {
+    pub columns: u32,
+    pub rows: u32,
+    pub tile: LayerDimensions,
+    /// Row stride of the tiles, if their rows are padded.
+    pub row_stride: Option<u32>,
+}
+
+/// Size of the (uncompressed) DATA chunk payload of a file with these dimensions.
+fn data_size(layers: &[Layer], dimensions: &LayerDimensions, extensions: &NsdExtensions, row_alignment: Option<u32>) -> u64 {
+    match row_alignment {
+        Some(alignment) => {
+            let (_, row_count) = extensions.texel_order.rows(dimensions.width, dimensions.height);
+            aligned_row_stride(layers, dimensions, extensions.texel_order, alignment) as u64 * row_count as u64
+        }
+        None => texel_stride(layers) as u64 * dimensions.get_texel_count() as u64,
+    }
+}
+
+/// Grid whose tiles stay within the size limit of the texel data, halving the longer side of the tiles until they
+/// do. Returns `None` if the data fits into a single file.
+pub fn plan_split(
+    layers: &[Layer],
+    dimensions: &LayerDimensions,
+    extensions: &NsdExtensions,
+    row_alignment: Option<u32>,
+    limit: u64
+) -> io::Result<Option<SplitGrid>> {
+    let data_size = |dimensions: &LayerDimensions| data_size(layers, dimensions, extensions, row_alignment);
+    if data_size(dimensions) <= limit {
+        return Ok(None);
+    }
+    let mut tile = dimensions.clone();
+    while data_size(&tile) > limit {
+        if tile.width >= tile.height && tile.width > 1 {
+            tile.width /= 2;
+        } else if tile.height > 1 {
+            tile.height /= 2;
+        } else {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("A single texel exceeds the size limit of {limit} bytes")));
+        }
+    }
+    Ok(Some(SplitGrid {
+        columns: dimensions.width.div_ceil(tile.width),
+        rows: dimensions.height.div_ceil(tile.height),
+        row_stride: row_alignment.map(|alignment| aligned_row_stride(layers, &tile, extensions.texel_order, alignment)),
+        tile,
+    }))
+}
+
+/// Copies the texels of a tile out of the tightly packed texel data of the full dimensions.
+fn tile_raw_data(raw_data: &[u8], dimensions: &LayerDimensions, extensions: &NsdExtensions, stride: usize, grid: &SplitGrid, column: u32, row: u32) -> Vec<u8> {
+    let order = extensions.texel_order;
+    let LayerDimensions { width, height } = grid.tile;
+    let mut tile = vec![0; stride * grid.tile.get_texel_count()];
+    for y in 0..height {
+        for x in 0..width {
+            let source = order.storage_index(column * width + x, row * height + y, dimensions.width, dimensions.height) * stride;
+            let target = order.storage_index(x, y, width, height) * stride;
+            tile[target..target + stride].copy_from_slice(&raw_data[source..source + stride]);
+        }
+    }
+    tile
+}
+
+/// Tiles written so far by a resumable split, `<stem>.checkpoint.json` next to the tiles, with the fingerprint of
+/// everything stored in each of them. Tiles are only kept if their fingerprint still matches.
+struct SplitCheckpoint {
+    path: PathBuf,
+    tiles: BTreeMap<String, String>,
+}
+
+impl SplitCheckpoint {
+    /// Reads the checkpoint of an earlier run, if there is a valid one.
+    fn load(path: PathBuf) -> SplitCheckpoint {
+        let mut tiles = BTreeMap::new();
+        let json = fs::read_to_string(&path).ok().and_then(|source| JsonValue::parse(&source).ok());
+        if let Some(JsonValue::Object(entries)) = json {
+            for (file, fingerprint) in entries {
+                if let Some(fingerprint) = fingerprint.as_str() {
+                    tiles.insert(file, fingerprint.to_string());
+                }
+            }
+        }
+        SplitCheckpoint { path, tiles }
+    }
+
+    fn is_written(&self, directory: &Path, file_name: &str, fingerprint: &str) -> bool {
+        self.tiles.get(file_name).is_some_and(|written| written == fingerprint) && directory.join(file_name).is_file()
+    }
+
+    fn record(&mut self, file_name: &str, fingerprint: String) -> io::Result<()> {
+        self.tiles.insert(file_name.to_string(), fingerprint);
+        let json = JsonValue::Object(self.tiles.iter().map(|(file, fingerprint)| (file.clone(), fingerprint.as_str().into())).collect());
+        fs::write(&self.path, json.to_pretty_string())
+    }
+}
+
+/// Hash of the contents of a tile, as hex, since JSON numbers can't hold 64 bits.
+fn tile_fingerprint(header: &NsdHeader, layers: &[Layer], extensions: &NsdExtensions, tile_data: &[u8], sections: &[LayerSection]) -> String {
+    let mut hash = fnv1a(FNV_OFFSET_BASIS, format!("{header:?}{extensions:?}").as_bytes());
+    hash = fnv1a(hash, &make_attribute_bytes(layers));
+    hash = fnv1a(hash, tile_data);
+    for section in sections {
+        hash = fnv1a(hash, format!("{}{:?}", section.name, section.dimensions).as_bytes());
+        hash = fnv1a(hash, &make_attribute_bytes(&section.layers));
+        hash = fnv1a(hash, &make_raw_data(&section.layers, &section.dimensions, TexelOrder::RowMajor));
+    }
+    format!("{hash:016x}")
+}
+
+/// Writes every tile of the grid next to the output path, as `<stem>_x<column>_y<row>.nsd` counted from the top
+/// left, and an index `<stem>.index.json` describing the grid. Sections are only stored in the first tile.
+/// With `resume`, tiles recorded by the checkpoint of an interrupted run are kept if their contents didn't change.
+/// Returns the path of the index followed by the paths of the tiles.
+#[allow(clippy::too_many_arguments)]
+pub fn write_split(
+    output: &Path,
+    header: &NsdHeader,
+    layers: &[Layer],
+    extensions: &NsdExtensions,
+    raw_data: &[u8],
+    sections: &[LayerSection],
+    grid: &SplitGrid,
+    resume: bool
+) -> io::Result<Vec<PathBuf>> {
+    let directory = output.parent().unwrap_or(Path::new(""));
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let stride = texel_stride(layers);
+    let dimensions = &LayerDimensions { width: grid.columns * grid.tile.width, height: grid.rows * grid.tile.height };
+    let tile_extensions = NsdExtensions { row_stride: grid.row_stride, ..extensions.clone() };
+
+    let mut paths = vec![directory.join(format!("{stem}.index.json"))];
+    let mut checkpoint = resume.then(|| SplitCheckpoint::load(directory.join(format!("{stem}.checkpoint.json"))));
+    let mut tiles = vec![];
+    for row in 0..grid.rows {
+        for column in 0..grid.columns {
+            let file_name = format!("{stem}_x{column}_y{row}.nsd");
+            let path = directory.join(&file_name);
+            let tile_data = tile_raw_data(raw_data, dimensions, extensions, stride, grid, column, row);
+            let tile_sections = if paths.len() == 1 { sections } else { &[] };
+            // Every tile is placed at its own corner of the world.
+            let world = extensions.world.map(|world| NsdWorldScale {
+                origin: world.texel_to_world((column * grid.tile.width) as f64, (row * grid.tile.height) as f64),
+                ..world
+            });
+            let tile_extensions = NsdExtensions { world, ..tile_extensions.clone() };
+            let fingerprint = checkpoint.as_ref()
+                .map(|_| tile_fingerprint(header, layers, &tile_extensions, &tile_data, tile_sections));
+            match (&mut checkpoint, fingerprint) {
+                (Some(checkpoint), Some(fingerprint)) if checkpoint.is_written(directory, &file_name, &fingerprint) => {
+                    info!("Tile {file_name} has been written by an earlier run.");
+                }
+                (checkpoint, fingerprint) => {
+                    let mut file = BufWriter::new(fs::File::create(&path)?);
+                    write_binary(&mut file, header, layers, &grid.tile, &tile_extensions, &tile_data, tile_sections)?;
+                    file.flush()?;
+                    if let (Some(checkpoint), Some(fingerprint)) = (checkpoint, fingerprint) {
+                        checkpoint.record(&file_name, fingerprint)?;
+                    }
+                }
+            }
+
+            tiles.push(JsonValue::object()
+                .with("file", file_name)
+                .with("column", column)
+                .with("row", row)
+                .with("x", column * grid.tile.width)
+                .with("y", row * grid.tile.height));
+            paths.push(path);
+        }
+    }
+
+    let mut index = JsonValue::object()
+        .with("width", dimensions.width)
+        .with("height", dimensions.height)
+        .with("columns", grid.columns)
+        .with("rows", grid.rows)
+        .with("tile_width", grid.tile.width)
+        .with("tile_height", grid.tile.height)
+        .with("tiles", tiles);
+    if !sections.is_empty() {
+        index.insert("sections_file", format!("{stem}_x0_y0.nsd"));
+    }
+    fs::write(&paths[0], index.to_pretty_string())?;
+    if let Some(checkpoint) = checkpoint {
+        fs::remove_file(checkpoint.path)?;
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, Luma};
+
+    use super::*;
+    use crate::format::AttributeFormat;
+    use crate::layer::tests::sample_layer;
+    use crate::reader::NsdReader;
+    use crate::testing::TempDirectory;
+
+    #[test]
+    fn splits_the_texels_into_a_grid_of_files() {
+        let dimensions = LayerDimensions { width: 4, height: 2 };
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(4, 2, |x, y| Luma([(x + 10 * y) as u8])));
+        let layers = [sample_layer("grass", &image, AttributeFormat::default())];
+        let extensions = NsdExtensions::default();
+        assert_eq!(plan_split(&layers, &dimensions, &extensions, None, 8).unwrap(), None);
+        let grid = plan_split(&layers, &dimensions, &extensions, None, 4).unwrap().unwrap();
+        assert_eq!(grid, SplitGrid { columns: 2, rows: 1, tile: LayerDimensions { width: 2, height: 2 }, row_stride: None });
+
+        let directory = TempDirectory::new();
+        let raw_data = make_raw_data(&layers, &dimensions, TexelOrder::RowMajor);
+        let paths = write_split(
+            &directory.join("terrain.nsd"), &NsdHeader::default(), &layers, &extensions, &raw_data, &[], &grid, false
+        ).unwrap();
+        assert_eq!(paths, [
+            directory.join("terrain.index.json"),
+            directory.join("terrain_x0_y0.nsd"),
+            directory.join("terrain_x1_y0.nsd"),
+        ]);
+        assert_eq!(NsdReader::open(&paths[2]).unwrap().data(), [2, 3, 12, 13]);
+        let index = JsonValue::parse(&fs::read_to_string(&paths[0]).unwrap()).unwrap();
+        assert_eq!(index.get("columns").and_then(JsonValue::as_u64), Some(2));
+
+        let error = plan_split(&layers, &dimensions, &extensions, None, 0).unwrap_err();
+        assert_eq!(error.to_string(), "A single texel exceeds the size limit of 0 bytes");
+    }
+
+    #[test]
+    fn keeps_the_unchanged_tiles_of_interrupted_runs() {
+        let dimensions = LayerDimensions { width: 4, height: 2 };
+        let image = DynamicImage::ImageLuma8(GrayImage::from_fn(4, 2, |x, y| Luma([(x + 10 * y) as u8])));
+        let layers = [sample_layer("grass", &image, AttributeFormat::default())];
+        let (header, extensions) = (NsdHeader::default(), NsdExtensions::default());
+        let grid = SplitGrid { columns: 2, rows: 1, tile: LayerDimensions { width: 2, height: 2 }, row_stride: None };
+
+        // The first tile was written by an interrupted run, the second one with different texels.
+        let directory = TempDirectory::new();
+        let fingerprint = tile_fingerprint(&header, &layers, &extensions, &[0, 1, 10, 11], &[]);
+        let checkpoint = JsonValue::object().with("terrain_x0_y0.nsd", fingerprint).with("terrain_x1_y0.nsd", "0");
+        fs::write(directory.join("terrain.checkpoint.json"), checkpoint.to_pretty_string()).unwrap();
+        fs::write(directory.join("terrain_x0_y0.nsd"), "kept").unwrap();
+        fs::write(directory.join("terrain_x1_y0.nsd"), "stale").unwrap();
+
+        let raw_data = make_raw_data(&layers, &dimensions, TexelOrder::RowMajor);
+        let paths = write_split(&directory.join("terrain.nsd"), &header, &layers, &extensions, &raw_data, &[], &grid, true).unwrap();
+        assert_eq!(fs::read_to_string(&paths[1]).unwrap(), "kept");
+        assert_eq!(NsdReader::open(&paths[2]).unwrap().data(), [2, 3, 12, 13]);
+        assert!(!directory.join("terrain.checkpoint.json").exists());
+    }
+}
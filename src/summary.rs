@@ -0,0 +1,205 @@
+//! Summary tables of the outputs of a run, so changes between runs, like an output doubling in size, stand out.
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use thousands::Separable;
+
+use crate::json::JsonValue;
+
+const CSV_COLUMNS: &str = "output,size,layers,failed_layers,warnings,duration_seconds";
+
+/// An output of a run.
+#[derive(Clone, Debug)]
+pub struct OutputSummary {
+    pub output: String,
+    /// Size in bytes, the total of all files for split outputs. `None` for dry runs.
+    pub size: Option<u64>,
+    pub layers: usize,
+    /// Layers which could not be loaded and have been left out.
+    pub failed_layers: usize,
+    pub warnings: usize,
+    pub duration_seconds: f64,
+}
+
+impl OutputSummary {
+    pub fn to_json(&self) -> JsonValue {
+        JsonValue::object()
+            .with("output", self.output.as_str())
+            .with("size", self.size)
+            .with("layers", self.layers)
+            .with("failed_layers", self.failed_layers)
+            .with("warnings", self.warnings)
+            .with("duration_seconds", self.duration_seconds)
+    }
+}
+
+fn invalid_summary(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Whether the summary file is a CSV table, otherwise it's JSON.
+fn is_csv(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("csv"))
+}
+
+/// Writes the summaries as CSV if the path ends with `.csv`, or as JSON otherwise.
+pub fn write_summary(path: &Path, summaries: &[OutputSummary]) -> io::Result<()> {
+    let contents = match is_csv(path) {
+        true => to_csv(summaries),
+        false => JsonValue::object()
+            .with("outputs", summaries.iter().map(OutputSummary::to_json).collect::<Vec<_>>())
+            .to_pretty_string(),
+    };
+    fs::write(path, contents)
+}
+
+pub fn to_csv(summaries: &[OutputSummary]) -> String {
+    let mut csv = format!("{CSV_COLUMNS}\n");
+    for summary in summaries {
+        csv += &format!(
+            "{},{},{},{},{},{:.3}\n",
+            csv_field(&summary.output), summary.size.map(|size| size.to_string()).unwrap_or_default(), summary.layers,
+            summary.failed_layers, summary.warnings, summary.duration_seconds
+        );
+    }
+    csv
+}
+
+/// Quotes the field if it contains a separator, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    match field.contains([',', '"', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+/// Splits a CSV line into its fields, unquoting them.
+fn split_csv_line(line: &str) -> Vec<String> {
+    let (mut fields, mut field, mut quoted) = (vec![], String::new(), false);
+    let mut chars = line.chars().peekable();
+    while let Some(char) = chars.next() {
+        match (char, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            ('"', _) => quoted = !quoted,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (char, _) => field.push(char),
+        }
+    }
+    fields.push(field);
+    fields
+}
+
+/// Sizes of the outputs in a summary written by an earlier run, keyed by the output.
+pub fn read_sizes(path: &Path) -> io::Result<HashMap<String, u64>> {
+    let source = fs::read_to_string(path)?;
+    if is_csv(path) {
+        let mut lines = source.lines();
+        if lines.next() != Some(CSV_COLUMNS) {
+            return Err(invalid_summary(format!("{} is not a summary written by nsdgen", path.display())));
+        }
+        return Ok(lines
+            .map(split_csv_line)
+            .filter_map(|fields| Some((fields.first()?.clone(), fields.get(1)?.parse().ok()?)))
+            .collect());
+    }
+    let root = JsonValue::parse(&source).map_err(|error| invalid_summary(format!("{}: {error}", path.display())))?;
+    let outputs = root.get("outputs").and_then(JsonValue::as_array)
+        .ok_or_else(|| invalid_summary(format!("{} is not a summary written by nsdgen", path.display())))?;
+    Ok(outputs.iter()
+        .filter_map(|output| Some((
+            output.get("output")?.as_str()?.to_string(),
+            output.get("size")?.as_u64()?,
+        )))
+        .collect())
+}
+
+/// Lines of a table of the summaries, aligned for the terminal. The size of every output is compared against its
+/// size in the previous summary.
+pub fn format_table(summaries: &[OutputSummary], previous_sizes: &HashMap<String, u64>) -> Vec<String> {
+    let mut rows = vec![["Output", "Size", "Change", "Layers", "Failed", "Warnings", "Time"].map(String::from)];
+    for summary in summaries {
+        let change = match (summary.size, previous_sizes.get(&summary.output)) {
+            (Some(size), Some(&previous)) if previous > 0 => {
+                format!("{:+.1}%", (size as f64 / previous as f64 - 1.0) * 100.0)
+            }
+            (Some(_), None) if !previous_sizes.is_empty() => "new".to_string(),
+            _ => String::new(),
+        };
+        rows.push([
+            summary.output.clone(),
+            summary.size.map(|size| size.separate_with_commas()).unwrap_or_else(|| "-".to_string()),
+            change,
+            summary.layers.to_string(),
+            summary.failed_layers.to_string(),
+            summary.warnings.to_string(),
+            format!("{:.2}s", summary.duration_seconds),
+        ]);
+    }
+
+    let widths: Vec<usize> = (0..rows[0].len())
+        .map(|column| rows.iter().map(|row| row[column].chars().count()).max().unwrap_or(0))
+        .collect();
+    rows.iter()
+        .map(|row| {
+            // The output is aligned to the left, the numbers to the right.
+            let cells: Vec<String> = row.iter().zip(&widths).enumerate()
+                .map(|(column, (cell, &width))| match column {
+                    0 => format!("{cell:<width$}"),
+                    _ => format!("{cell:>width$}"),
+                })
+                .collect();
+            cells.join("  ").trim_end().to_string()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    fn summary(output: &str, size: Option<u64>) -> OutputSummary {
+        OutputSummary { output: output.to_string(), size, layers: 3, failed_layers: 1, warnings: 2, duration_seconds: 1.5 }
+    }
+
+    #[test]
+    fn reads_the_sizes_of_written_summaries() {
+        let directory = TempDirectory::new();
+        let summaries = [summary("a,\"b\".nsd", Some(1000)), summary("c.nsd", Some(2000)), summary("d.nsd", None)];
+        for name in ["summary.csv", "summary.json"] {
+            let path = directory.join(name);
+            write_summary(&path, &summaries).unwrap();
+            let sizes = read_sizes(&path).unwrap();
+            assert_eq!(sizes.len(), 2, "{name}");
+            assert_eq!(sizes["a,\"b\".nsd"], 1000, "{name}");
+            assert_eq!(sizes["c.nsd"], 2000, "{name}");
+        }
+        assert!(to_csv(&summaries).starts_with(&format!("{CSV_COLUMNS}\n\"a,\"\"b\"\".nsd\",1000,3,1,2,1.500\n")));
+
+        let foreign = directory.join("foreign.csv");
+        fs::write(&foreign, "name,size\nx,1\n").unwrap();
+        assert_eq!(read_sizes(&foreign).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn compares_the_sizes_with_the_previous_run() {
+        let summaries = [summary("a.nsd", Some(1500)), summary("b.nsd", Some(1000)), summary("c.nsd", None)];
+        let previous = HashMap::from([("a.nsd".to_string(), 1000)]);
+        let table = format_table(&summaries, &previous);
+        assert_eq!(table.len(), 4);
+        assert!(table[0].starts_with("Output "));
+        assert!(table[1].starts_with("a.nsd") && table[1].contains("1,500") && table[1].contains("+50.0%"));
+        assert!(table[2].contains("new"));
+        assert!(table[3].contains(" - ") && table[3].ends_with("1.50s"));
+        // Every row is aligned to the same width, as the last column is never empty.
+        assert!(table.iter().all(|row| row.chars().count() == table[0].chars().count()));
+
+        assert!(!format_table(&summaries, &HashMap::new())[2].contains("new"));
+    }
+}
@@ -0,0 +1,116 @@
+//! Resources of the machine the files are generated on, read where the system exposes them.
+
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Memory which can be allocated without swapping, from `MemAvailable` of `/proc/meminfo`.
+/// `None` where it's unknown.
+pub fn available_memory() -> Option<u64> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo.lines().find(|line| line.starts_with("MemAvailable:"))?;
+    let kibibytes: u64 = line.trim_start_matches("MemAvailable:").trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kibibytes * 1024)
+}
+
+/// Name of the machine, from the environment or `/proc/sys/kernel/hostname`. `None` where it's unknown.
+pub fn host_name() -> Option<String> {
+    let name = std::env::var("HOSTNAME").or_else(|_| std::env::var("COMPUTERNAME")).ok()
+        .or_else(|| fs::read_to_string("/proc/sys/kernel/hostname").ok())?;
+    Some(name.trim().to_string()).filter(|name| !name.is_empty())
+}
+
+/// Bytes which can still be written to the file system of the directory by this user.
+/// `Ok(None)` on platforms where it can't be queried.
+pub fn available_disk_space(directory: &Path) -> io::Result<Option<u64>> {
+    disk::available_space(directory)
+}
+
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+mod disk {
+    use std::ffi::{c_char, CString};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    /// `struct statvfs` of 64-bit Linux.
+    #[repr(C)]
+    #[derive(Default)]
+    struct StatVfs {
+        block_size: u64,
+        fragment_size: u64,
+        blocks: u64,
+        free_blocks: u64,
+        available_blocks: u64,
+        files: u64,
+        free_files: u64,
+        available_files: u64,
+        file_system_id: u64,
+        flags: u64,
+        max_name_length: u64,
+        spare: [i32; 6],
+    }
+
+    extern "C" {
+        fn statvfs(path: *const c_char, buffer: *mut StatVfs) -> i32;
+    }
+
+    pub fn available_space(directory: &Path) -> io::Result<Option<u64>> {
+        let path = CString::new(directory.as_os_str().as_bytes()).map_err(io::Error::other)?;
+        let mut stats = StatVfs::default();
+        if unsafe { statvfs(path.as_ptr(), &mut stats) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Blocks reserved for the superuser aren't available.
+        Ok(Some(stats.available_blocks * stats.fragment_size))
+    }
+}
+
+#[cfg(windows)]
+mod disk {
+    use std::io;
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn GetDiskFreeSpaceExW(directory: *const u16, available: *mut u64, total: *mut u64, free: *mut u64) -> i32;
+    }
+
+    pub fn available_space(directory: &Path) -> io::Result<Option<u64>> {
+        let path: Vec<u16> = directory.as_os_str().encode_wide().chain(Some(0)).collect();
+        let mut available = 0;
+        let succeeded = unsafe {
+            GetDiskFreeSpaceExW(path.as_ptr(), &mut available, std::ptr::null_mut(), std::ptr::null_mut())
+        };
+        match succeeded {
+            0 => Err(io::Error::last_os_error()),
+            _ => Ok(Some(available)),
+        }
+    }
+}
+
+#[cfg(not(any(all(target_os = "linux", target_pointer_width = "64"), windows)))]
+mod disk {
+    use std::io;
+    use std::path::Path;
+
+    pub fn available_space(_directory: &Path) -> io::Result<Option<u64>> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    #[test]
+    #[cfg(any(all(target_os = "linux", target_pointer_width = "64"), windows))]
+    fn reads_the_free_space_of_the_file_system() {
+        let directory = TempDirectory::new();
+        assert!(available_disk_space(directory.path()).unwrap().is_some_and(|available| available > 0));
+        let error = available_disk_space(&directory.join("missing")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+    }
+}
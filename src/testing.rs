@@ -0,0 +1,35 @@
+//! Helpers of the unit tests.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Empty directory of a test, removed with its contents when dropped.
+pub struct TempDirectory(PathBuf);
+
+impl TempDirectory {
+    pub fn new() -> TempDirectory {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = env::temp_dir()
+            .join(format!("nsdgen-test-{}-{}", process::id(), NEXT.fetch_add(1, Ordering::Relaxed)));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TempDirectory(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
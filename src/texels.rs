@@ -0,0 +1,266 @@
+//! Typed texel storage of a loaded layer, holding the values exactly as they're stored in its attribute.
+
+use std::ops::Range;
+
+use half::f16;
+use image::{DynamicImage, GrayImage, Luma};
+
+use crate::format::{AttributeFormat, AttributeType};
+use crate::manifest::LayerConfig;
+
+/// Values of every texel of a layer, in row-major order, in the representation of its attribute.
+pub struct TexelBuffer {
+    pub width: u32,
+    pub height: u32,
+    values: TexelValues,
+}
+
+enum TexelValues {
+    /// `size` channels per texel.
+    Byte(Vec<u8>),
+    UInt16(Vec<u16>),
+    UInt32(Vec<u32>),
+    Int8(Vec<i8>),
+    Int16(Vec<i16>),
+    Half(Vec<f16>),
+    Float(Vec<f32>),
+}
+
+impl TexelBuffer {
+    /// Converts the image into values of the layer's format.
+    /// If a nodata value is given, texels less than half opaque store it instead, as alpha marks the texels
+    /// without data then.
+    ///
+    /// Images already in the matching representation (e.g. 16-bit grayscale for 16-bit attributes)
+    /// are read directly, other ones are converted with the `image` crate.
+    pub fn from_image(image: &DynamicImage, config: &LayerConfig, nodata: Option<f64>) -> TexelBuffer {
+        let format = config.format;
+        let nodata = nodata.filter(|_| image.color().has_alpha());
+        let size = format.size as usize;
+        let values = match (format.attribute_type, size) {
+            (AttributeType::Byte, 1) if nodata.is_none() && image.as_luma8().is_some() => {
+                TexelValues::Byte(image.as_luma8().unwrap().as_raw().clone())
+            }
+            (AttributeType::Byte, _) => {
+                let nodata = nodata.map(|nodata| [nodata.round().clamp(0.0, u8::MAX as f64) as u8; 4]);
+                let mut values = Vec::with_capacity(image.width() as usize * image.height() as usize * size);
+                for pixel in image.to_rgba8().pixels() {
+                    match nodata {
+                        Some(nodata) if pixel.0[3] < 128 => values.extend_from_slice(&nodata[..size]),
+                        _ => values.extend_from_slice(&pixel.0[..size]),
+                    }
+                }
+                TexelValues::Byte(values)
+            }
+            (AttributeType::UInt, 2) if nodata.is_none() && image.as_luma16().is_some() => {
+                TexelValues::UInt16(image.as_luma16().unwrap().as_raw().clone())
+            }
+            (AttributeType::UInt, 2) => {
+                let nodata = nodata.map(|nodata| nodata.round().clamp(0.0, u16::MAX as f64) as u16);
+                TexelValues::UInt16(image.to_rgba16().pixels()
+                    .map(|pixel| match nodata {
+                        Some(nodata) if pixel.0[3] < 32768 => nodata,
+                        _ => pixel.0[0],
+                    })
+                    .collect())
+            }
+            (AttributeType::UInt, _) => {
+                let nodata = nodata.map(|nodata| nodata.round().clamp(0.0, u32::MAX as f64) as u32);
+                TexelValues::UInt32(image.to_rgba16().pixels()
+                    .map(|pixel| match nodata {
+                        Some(nodata) if pixel.0[3] < 32768 => nodata,
+                        // Spread the 16-bit range over the full 32-bit range.
+                        _ => pixel.0[0] as u32 * 0x10001,
+                    })
+                    .collect())
+            }
+            (AttributeType::Float, 2) => {
+                TexelValues::Half(image.to_rgba32f().pixels()
+                    .map(|pixel| match nodata {
+                        Some(nodata) if pixel.0[3] < 0.5 => f16::from_f64(nodata),
+                        _ => f16::from_f32(pixel.0[0]),
+                    })
+                    .collect())
+            }
+            (AttributeType::Float, _) => {
+                TexelValues::Float(image.to_rgba32f().pixels()
+                    .map(|pixel| match nodata {
+                        Some(nodata) if pixel.0[3] < 0.5 => nodata as f32,
+                        _ => pixel.0[0],
+                    })
+                    .collect())
+            }
+            (AttributeType::Int, 1) => {
+                let zero_point = config.zero_point.unwrap_or(128);
+                TexelValues::Int8(image.to_rgba8().pixels()
+                    .map(|pixel| match nodata {
+                        Some(nodata) if pixel.0[3] < 128 => nodata.round().clamp(i8::MIN as f64, i8::MAX as f64) as i8,
+                        _ => (pixel.0[0] as i64 - zero_point).clamp(i8::MIN as i64, i8::MAX as i64) as i8,
+                    })
+                    .collect())
+            }
+            (AttributeType::Int, _) => {
+                let zero_point = config.zero_point.unwrap_or(32768);
+                TexelValues::Int16(image.to_rgba16().pixels()
+                    .map(|pixel| match nodata {
+                        Some(nodata) if pixel.0[3] < 32768 => nodata.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16,
+                        _ => (pixel.0[0] as i64 - zero_point).clamp(i16::MIN as i64, i16::MAX as i64) as i16,
+                    })
+                    .collect())
+            }
+        };
+        TexelBuffer {
+            width: image.width(),
+            height: image.height(),
+            values,
+        }
+    }
+
+    /// Mask (255 inside, 0 outside) of the texels whose first component is in the upper half of its range,
+    /// for layers used as masks of other layers.
+    pub fn to_mask(&self) -> GrayImage {
+        let texel_count = self.width as usize * self.height as usize;
+        let inside: Vec<bool> = match &self.values {
+            TexelValues::Byte(values) => {
+                let size = values.len() / texel_count.max(1);
+                values.iter().step_by(size.max(1)).map(|&value| value >= 128).collect()
+            }
+            TexelValues::UInt16(values) => values.iter().map(|&value| value >= 32768).collect(),
+            TexelValues::UInt32(values) => values.iter().map(|&value| value >= 1 << 31).collect(),
+            TexelValues::Int8(values) => values.iter().map(|&value| value >= 0).collect(),
+            TexelValues::Int16(values) => values.iter().map(|&value| value >= 0).collect(),
+            TexelValues::Half(values) => values.iter().map(|&value| value.to_f32() >= 0.5).collect(),
+            TexelValues::Float(values) => values.iter().map(|&value| value >= 0.5).collect(),
+        };
+        GrayImage::from_fn(self.width, self.height, |x, y| {
+            Luma([if inside[y as usize * self.width as usize + x as usize] { u8::MAX } else { 0 }])
+        })
+    }
+
+    /// Buffer of the given format without values, like a released one, for layers which aren't loaded.
+    pub fn empty(width: u32, height: u32, format: AttributeFormat) -> TexelBuffer {
+        let values = match (format.attribute_type, format.size) {
+            (AttributeType::Byte, _) => TexelValues::Byte(vec![]),
+            (AttributeType::UInt, 2) => TexelValues::UInt16(vec![]),
+            (AttributeType::UInt, _) => TexelValues::UInt32(vec![]),
+            (AttributeType::Float, 2) => TexelValues::Half(vec![]),
+            (AttributeType::Float, _) => TexelValues::Float(vec![]),
+            (AttributeType::Int, 1) => TexelValues::Int8(vec![]),
+            (AttributeType::Int, _) => TexelValues::Int16(vec![]),
+        };
+        TexelBuffer { width, height, values }
+    }
+
+    /// Frees the values once they've been packed, keeping the dimensions. The buffer encodes to nothing afterwards.
+    pub fn release(&mut self) {
+        self.values = match self.values {
+            TexelValues::Byte(_) => TexelValues::Byte(vec![]),
+            TexelValues::UInt16(_) => TexelValues::UInt16(vec![]),
+            TexelValues::UInt32(_) => TexelValues::UInt32(vec![]),
+            TexelValues::Int8(_) => TexelValues::Int8(vec![]),
+            TexelValues::Int16(_) => TexelValues::Int16(vec![]),
+            TexelValues::Half(_) => TexelValues::Half(vec![]),
+            TexelValues::Float(_) => TexelValues::Float(vec![]),
+        };
+    }
+
+    /// Little-endian bytes of every texel, in row-major order.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = vec![];
+        self.encode_into(0..self.width as usize * self.height as usize, &mut bytes);
+        bytes
+    }
+
+    /// Appends the little-endian bytes of the texels in the range (row-major indices). Released buffers append
+    /// nothing.
+    pub fn encode_into(&self, texels: Range<usize>, bytes: &mut Vec<u8>) {
+        fn values_in<T>(values: &[T], texels: Range<usize>, size: usize) -> &[T] {
+            values.get(texels.start * size..texels.end * size).unwrap_or_default()
+        }
+        match &self.values {
+            TexelValues::Byte(values) => {
+                let size = values.len() / (self.width as usize * self.height as usize).max(1);
+                bytes.extend_from_slice(values_in(values, texels, size));
+            }
+            TexelValues::UInt16(values) => bytes.extend(values_in(values, texels, 1).iter().flat_map(|value| value.to_le_bytes())),
+            TexelValues::UInt32(values) => bytes.extend(values_in(values, texels, 1).iter().flat_map(|value| value.to_le_bytes())),
+            TexelValues::Int8(values) => bytes.extend(values_in(values, texels, 1).iter().flat_map(|value| value.to_le_bytes())),
+            TexelValues::Int16(values) => bytes.extend(values_in(values, texels, 1).iter().flat_map(|value| value.to_le_bytes())),
+            TexelValues::Half(values) => bytes.extend(values_in(values, texels, 1).iter()
+                .map(|value| if value.is_nan() { f16::NAN } else { *value })
+                .flat_map(|value| value.to_le_bytes())),
+            TexelValues::Float(values) => bytes.extend(values_in(values, texels, 1).iter()
+                // Keep the output byte-reproducible regardless of NaN payloads.
+                .map(|value| if value.is_nan() { f32::NAN } else { *value })
+                .flat_map(|value| value.to_le_bytes())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{ImageBuffer, Rgba, RgbaImage};
+
+    use super::*;
+
+    fn config(attribute_type: AttributeType, size: u8) -> LayerConfig {
+        LayerConfig { format: AttributeFormat::new(attribute_type, size).unwrap(), ..LayerConfig::default() }
+    }
+
+    #[test]
+    fn stores_the_first_channels_in_byte_vectors() {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 1, Rgba([10, 20, 30, 40])));
+        for size in 1..=4 {
+            let texels = TexelBuffer::from_image(&image, &config(AttributeType::Byte, size), None);
+            assert_eq!(texels.encode(), [&[10, 20, 30, 40][..size as usize]; 2].concat(), "size {size}");
+        }
+    }
+
+    #[test]
+    fn stores_multi_byte_scalars_little_endian() {
+        let image = DynamicImage::ImageLuma16(ImageBuffer::from_pixel(1, 1, Luma([0x1234u16])));
+        assert_eq!(TexelBuffer::from_image(&image, &config(AttributeType::UInt, 2), None).encode(), [0x34, 0x12]);
+        assert_eq!(
+            TexelBuffer::from_image(&image, &config(AttributeType::UInt, 4), None).encode(),
+            0x1234_1234u32.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn encodes_ranges_of_texels_until_released() {
+        let image = DynamicImage::ImageLuma16(ImageBuffer::from_raw(3, 1, vec![0u16, 0x8000, 0x7fff]).unwrap());
+        let mut texels = TexelBuffer::from_image(&image, &config(AttributeType::UInt, 2), None);
+        let mut bytes = vec![];
+        texels.encode_into(1..3, &mut bytes);
+        assert_eq!(bytes, [0x00, 0x80, 0xff, 0x7f]);
+        assert_eq!(texels.to_mask().into_raw(), [0, 255, 0]);
+
+        texels.release();
+        assert!(texels.encode().is_empty());
+        assert_eq!((texels.width, texels.height), (3, 1));
+    }
+
+    #[test]
+    fn subtracts_the_zero_point_from_signed_ints() {
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(3, 1, vec![0, 128, 255]).unwrap());
+        let encode = |config: &LayerConfig| TexelBuffer::from_image(&image, config, None).encode();
+        assert_eq!(encode(&config(AttributeType::Int, 1)), [-128i8, 0, 127].map(|value| value as u8));
+        let shifted = LayerConfig { zero_point: Some(0), ..config(AttributeType::Int, 1) };
+        assert_eq!(encode(&shifted), [0i8, 127, 127].map(|value| value as u8));
+
+        let image = DynamicImage::ImageLuma16(ImageBuffer::from_raw(2, 1, vec![0u16, 32769]).unwrap());
+        let texels = TexelBuffer::from_image(&image, &config(AttributeType::Int, 2), None);
+        assert_eq!(texels.encode(), [(-32768i16).to_le_bytes(), 1i16.to_le_bytes()].concat());
+    }
+
+    #[test]
+    fn stores_half_floats_with_nodata_for_transparent_texels() {
+        let image = DynamicImage::ImageRgba32F(ImageBuffer::from_fn(2, 1, |x, _| Rgba([0.25, 0.0, 0.0, x as f32])));
+        let half = config(AttributeType::Float, 2);
+        assert_eq!(TexelBuffer::from_image(&image, &half, None).encode(), [f16::from_f32(0.25).to_le_bytes(); 2].concat());
+        assert_eq!(
+            TexelBuffer::from_image(&image, &half, Some(-1.0)).encode(),
+            [f16::from_f32(-1.0).to_le_bytes(), f16::from_f32(0.25).to_le_bytes()].concat()
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use image::{DynamicImage, GrayImage, Luma};
+
+use crate::base64;
+use crate::json::JsonValue;
+use crate::layer::DecodeLimits;
+use crate::xml::{self, XmlElement};
+use crate::warning;
+
+/// Flip and rotation flags stored in the highest bits of a global tile ID.
+const TILE_FLAGS_MASK: u32 = 0xF000_0000;
+
+/// How tile layers of Tiled maps are turned into layers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TiledConfig {
+    /// Each tile becomes a square block of this many texels before resizing.
+    pub texels_per_tile: u32,
+    /// Tile layers to import; all of them if not specified.
+    pub layers: Option<Vec<String>>,
+    /// Tile property stored instead of the tile ID.
+    pub property: Option<String>,
+}
+
+impl Default for TiledConfig {
+    fn default() -> Self {
+        TiledConfig {
+            texels_per_tile: 1,
+            layers: None,
+            property: None,
+        }
+    }
+}
+
+struct TileLayer {
+    name: String,
+    /// Global tile IDs in row-major order, 0 meaning no tile.
+    tiles: Vec<u32>,
+}
+
+struct Tileset {
+    first_gid: u32,
+    /// Properties of individual tiles, keyed by their local ID.
+    tile_properties: BTreeMap<u32, BTreeMap<String, String>>,
+}
+
+pub struct TiledMap {
+    width: u32,
+    height: u32,
+    layers: Vec<TileLayer>,
+    tilesets: Vec<Tileset>,
+}
+
+fn invalid_map(path: &Path, message: impl AsRef<str>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message.as_ref()))
+}
+
+/// Whether the file is a map format which can be imported.
+pub fn is_tiled_map(path: &Path) -> bool {
+    matches!(path.extension().and_then(|extension| extension.to_str()), Some("tmx") | Some("tmj"))
+}
+
+impl TiledMap {
+    /// Loads a `.tmx` (XML) or `.tmj` (JSON) map, including its external `.tsx`/`.tsj` tilesets.
+    pub fn load(path: &Path) -> io::Result<TiledMap> {
+        let source = fs::read_to_string(path)?;
+        if path.extension().is_some_and(|extension| extension == "tmx") {
+            let root = xml::parse(&source).map_err(|error| invalid_map(path, error))?;
+            TiledMap::from_xml(path, &root)
+        } else {
+            let root = JsonValue::parse(&source).map_err(|error| invalid_map(path, error))?;
+            TiledMap::from_json(path, &root)
+        }
+    }
+
+    pub fn layer_names(&self) -> impl Iterator<Item = &str> {
+        self.layers.iter().map(|layer| layer.name.as_str())
+    }
+
+    /// Size of the images rendered by `rasterize`.
+    pub fn raster_dimensions(&self, config: &TiledConfig) -> io::Result<(u32, u32)> {
+        let texels_per_tile = config.texels_per_tile.max(1);
+        match (self.width.checked_mul(texels_per_tile), self.height.checked_mul(texels_per_tile)) {
+            (Some(width), Some(height)) => Ok((width, height)),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "A map of {}x{} tiles is too large for {texels_per_tile} texels per tile", self.width, self.height
+            ))),
+        }
+    }
+
+    /// Renders a tile layer into a grayscale image, clamping values above 255. Fails before allocating the image if
+    /// it exceeds the limits.
+    pub fn rasterize(&self, layer_name: &str, config: &TiledConfig, limits: &DecodeLimits) -> io::Result<DynamicImage> {
+        let layer = self.layers.iter().find(|layer| layer.name == layer_name)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Tile layer {layer_name} not found")))?;
+        let (width, height) = self.raster_dimensions(config)?;
+        limits.check(width, height, 1)?;
+
+        let values: Vec<u32> = layer.tiles.iter().map(|&gid| self.tile_value(gid, config)).collect();
+        if let Some(value) = values.iter().find(|&&value| value > u8::MAX as u32) {
+            warning!(None, "loading", "Tile layer {layer_name} contains value {value} which doesn't fit in a byte and will be clamped.");
+        }
+
+        let texels_per_tile = config.texels_per_tile.max(1);
+        let image = GrayImage::from_fn(width, height, |x, y| {
+            let tile = (y / texels_per_tile) as usize * self.width as usize + (x / texels_per_tile) as usize;
+            Luma([values[tile].min(u8::MAX as u32) as u8])
+        });
+        Ok(DynamicImage::ImageLuma8(image))
+    }
+
+    fn tile_value(&self, gid: u32, config: &TiledConfig) -> u32 {
+        let gid = gid & !TILE_FLAGS_MASK;
+        let Some(property) = &config.property else {
+            return gid;
+        };
+        if gid == 0 {
+            return 0;
+        }
+        self.tilesets.iter()
+            .filter(|tileset| tileset.first_gid <= gid)
+            .max_by_key(|tileset| tileset.first_gid)
+            .and_then(|tileset| tileset.tile_properties.get(&(gid - tileset.first_gid)))
+            .and_then(|properties| properties.get(property))
+            .and_then(|value| value.parse::<f64>().ok().or(match value.as_str() {
+                "true" => Some(1.0),
+                "false" => Some(0.0),
+                _ => None,
+            }))
+            .map_or(0, |value| value.clamp(0.0, u32::MAX as f64) as u32)
+    }
+
+    fn from_xml(path: &Path, root: &XmlElement) -> io::Result<TiledMap> {
+        if root.name != "map" {
+            return Err(invalid_map(path, "the root element is not <map>"));
+        }
+        if root.attribute("infinite") == Some("1") {
+            return Err(invalid_map(path, "infinite maps are not supported"));
+        }
+        let dimension = |name: &str| root.attribute(name)
+            .and_then(|value| value.parse::<u32>().ok())
+            .ok_or_else(|| invalid_map(path, format!("missing map {name}")));
+
+        let mut map = TiledMap {
+            width: dimension("width")?,
+            height: dimension("height")?,
+            layers: vec![],
+            tilesets: vec![],
+        };
+
+        for tileset in root.children_named("tileset") {
+            let first_gid = tileset.attribute("firstgid").and_then(|gid| gid.parse().ok()).unwrap_or(1);
+            let tile_properties = match tileset.attribute("source") {
+                Some(source) => load_external_tileset(&path.with_file_name(source))?,
+                None => xml_tile_properties(tileset),
+            };
+            map.tilesets.push(Tileset { first_gid, tile_properties });
+        }
+
+        map.collect_xml_layers(path, root)?;
+        Ok(map)
+    }
+
+    fn collect_xml_layers(&mut self, path: &Path, parent: &XmlElement) -> io::Result<()> {
+        for element in &parent.children {
+            match element.name.as_str() {
+                "layer" => {
+                    let name = element.attribute("name").unwrap_or_default().to_string();
+                    let data = element.child("data")
+                        .ok_or_else(|| invalid_map(path, format!("tile layer {name} has no data")))?;
+                    let tiles = match data.attribute("encoding") {
+                        Some("csv") => parse_csv(&data.text),
+                        Some("base64") => decode_tile_bytes(&data.text, data.attribute("compression")),
+                        None => Ok(data.children_named("tile")
+                            .map(|tile| tile.attribute("gid").and_then(|gid| gid.parse().ok()).unwrap_or(0))
+                            .collect()),
+                        Some(encoding) => Err(format!("unsupported encoding {encoding}")),
+                    }.map_err(|error| invalid_map(path, format!("tile layer {name}: {error}")))?;
+                    self.push_layer(path, name, tiles)?;
+                }
+                "group" => self.collect_xml_layers(path, element)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn from_json(path: &Path, root: &JsonValue) -> io::Result<TiledMap> {
+        if root.get("infinite").and_then(JsonValue::as_bool) == Some(true) {
+            return Err(invalid_map(path, "infinite maps are not supported"));
+        }
+        let dimension = |name: &str| root.get(name)
+            .and_then(JsonValue::as_u64)
+            .ok_or_else(|| invalid_map(path, format!("missing map {name}")));
+
+        let mut map = TiledMap {
+            width: dimension("width")? as u32,
+            height: dimension("height")? as u32,
+            layers: vec![],
+            tilesets: vec![],
+        };
+
+        for tileset in root.get("tilesets").and_then(JsonValue::as_array).unwrap_or_default() {
+            let first_gid = tileset.get("firstgid").and_then(JsonValue::as_u64).unwrap_or(1) as u32;
+            let tile_properties = match tileset.get("source").and_then(JsonValue::as_str) {
+                Some(source) => load_external_tileset(&path.with_file_name(source))?,
+                None => json_tile_properties(tileset),
+            };
+            map.tilesets.push(Tileset { first_gid, tile_properties });
+        }
+
+        map.collect_json_layers(path, root)?;
+        Ok(map)
+    }
+
+    fn collect_json_layers(&mut self, path: &Path, parent: &JsonValue) -> io::Result<()> {
+        for layer in parent.get("layers").and_then(JsonValue::as_array).unwrap_or_default() {
+            match layer.get("type").and_then(JsonValue::as_str) {
+                Some("tilelayer") => {
+                    let name = layer.get("name").and_then(JsonValue::as_str).unwrap_or_default().to_string();
+                    let tiles = match layer.get("data") {
+                        Some(JsonValue::Array(data)) => Ok(data.iter().map(|gid| gid.as_u64().unwrap_or(0) as u32).collect()),
+                        Some(JsonValue::String(data)) => {
+                            decode_tile_bytes(data, layer.get("compression").and_then(JsonValue::as_str).filter(|c| !c.is_empty()))
+                        }
+                        _ => Err("missing data (chunked layers are not supported)".to_string()),
+                    }.map_err(|error| invalid_map(path, format!("tile layer {name}: {error}")))?;
+                    self.push_layer(path, name, tiles)?;
+                }
+                Some("group") => self.collect_json_layers(path, layer)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn push_layer(&mut self, path: &Path, name: String, tiles: Vec<u32>) -> io::Result<()> {
+        let expected = self.width as usize * self.height as usize;
+        if tiles.len() != expected {
+            return Err(invalid_map(path, format!("tile layer {name} has {} tiles, expected {expected}", tiles.len())));
+        }
+        self.layers.push(TileLayer { name, tiles });
+        Ok(())
+    }
+}
+
+fn load_external_tileset(path: &Path) -> io::Result<BTreeMap<u32, BTreeMap<String, String>>> {
+    let source = fs::read_to_string(path)?;
+    if path.extension().is_some_and(|extension| extension == "tsx") {
+        let root = xml::parse(&source).map_err(|error| invalid_map(path, error))?;
+        Ok(xml_tile_properties(&root))
+    } else {
+        let root = JsonValue::parse(&source).map_err(|error| invalid_map(path, error))?;
+        Ok(json_tile_properties(&root))
+    }
+}
+
+fn xml_tile_properties(tileset: &XmlElement) -> BTreeMap<u32, BTreeMap<String, String>> {
+    tileset.children_named("tile")
+        .filter_map(|tile| {
+            let id = tile.attribute("id")?.parse().ok()?;
+            let properties = tile.child("properties")?.children_named("property")
+                .filter_map(|property| {
+                    let value = property.attribute("value").map_or(property.text.clone(), String::from);
+                    Some((property.attribute("name")?.to_string(), value))
+                })
+                .collect();
+            Some((id, properties))
+        })
+        .collect()
+}
+
+fn json_tile_properties(tileset: &JsonValue) -> BTreeMap<u32, BTreeMap<String, String>> {
+    tileset.get("tiles").and_then(JsonValue::as_array).unwrap_or_default().iter()
+        .filter_map(|tile| {
+            let id = tile.get("id")?.as_u64()? as u32;
+            let properties = tile.get("properties")?.as_array()?.iter()
+                .filter_map(|property| {
+                    let value = match property.get("value")? {
+                        JsonValue::String(string) => string.clone(),
+                        value => value.to_string(),
+                    };
+                    Some((property.get("name")?.as_str()?.to_string(), value))
+                })
+                .collect();
+            Some((id, properties))
+        })
+        .collect()
+}
+
+fn parse_csv(text: &str) -> Result<Vec<u32>, String> {
+    text.split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+        .map(|value| value.parse::<u32>().map_err(|_| format!("invalid tile ID `{value}`")))
+        .collect()
+}
+
+fn decode_tile_bytes(text: &str, compression: Option<&str>) -> Result<Vec<u32>, String> {
+    let bytes = base64::decode(text)?;
+    let mut decompressed = vec![];
+    match compression {
+        None => decompressed = bytes,
+        Some("zlib") => {
+            ZlibDecoder::new(bytes.as_slice()).read_to_end(&mut decompressed).map_err(|error| error.to_string())?;
+        }
+        Some("gzip") => {
+            GzDecoder::new(bytes.as_slice()).read_to_end(&mut decompressed).map_err(|error| error.to_string())?;
+        }
+        Some(compression) => return Err(format!("unsupported compression {compression}")),
+    }
+    Ok(decompressed
+        .chunks_exact(4)
+        .map(|gid| u32::from_le_bytes(gid.try_into().unwrap()))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map width="2" height="2" infinite="0">
+  <tileset firstgid="1">
+    <tile id="1"><properties><property name="cost" value="7"/></properties></tile>
+  </tileset>
+  <group name="terrain">
+    <layer name="ground" width="2" height="2">
+      <data encoding="csv">1,2,
+0,2147483650</data>
+    </layer>
+  </group>
+</map>"#;
+
+    const TMJ: &str = r#"{
+  "width": 2, "height": 2, "infinite": false,
+  "tilesets": [{"firstgid": 1, "tiles": [{"id": 1, "properties": [{"name": "cost", "type": "int", "value": 7}]}]}],
+  "layers": [{"type": "tilelayer", "name": "ground", "data": [1, 2, 0, 2147483650]}]
+}"#;
+
+    fn rasterize(map: &TiledMap, config: TiledConfig) -> Vec<u8> {
+        map.rasterize("ground", &config, &DecodeLimits::default()).unwrap().to_luma8().into_raw()
+    }
+
+    #[test]
+    fn rasterizes_tile_layers_of_both_formats() {
+        let directory = TempDirectory::new();
+        for (name, source) in [("map.tmx", TMX), ("map.tmj", TMJ)] {
+            let path = directory.join(name);
+            fs::write(&path, source).unwrap();
+            assert!(is_tiled_map(&path));
+            let map = TiledMap::load(&path).unwrap();
+            assert_eq!(map.layer_names().collect::<Vec<_>>(), ["ground"], "{name}");
+
+            // The flip flags of the last tile are ignored.
+            assert_eq!(rasterize(&map, TiledConfig::default()), [1, 2, 0, 2], "{name}");
+            assert_eq!(rasterize(&map, TiledConfig { texels_per_tile: 2, ..TiledConfig::default() }), [
+                1, 1, 2, 2,
+                1, 1, 2, 2,
+                0, 0, 2, 2,
+                0, 0, 2, 2,
+            ], "{name}");
+            let cost = TiledConfig { property: Some("cost".into()), ..TiledConfig::default() };
+            assert_eq!(rasterize(&map, cost), [0, 7, 0, 7], "{name}");
+        }
+    }
+
+    #[test]
+    fn rejects_layers_of_the_wrong_size() {
+        let directory = TempDirectory::new();
+        let path = directory.join("map.tmx");
+        fs::write(&path, TMX.replace("1,2,\n0,2147483650", "1,2,0")).unwrap();
+        let error = TiledMap::load(&path).err().unwrap();
+        assert!(error.to_string().contains("tile layer ground has 3 tiles, expected 4"), "{error}");
+    }
+}
@@ -0,0 +1,53 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Seconds since the Unix epoch, or the value of `SOURCE_DATE_EPOCH` if it's set,
+/// so builds can pin the time recorded in generated files.
+pub fn current_unix_time() -> u64 {
+    if let Some(epoch) = std::env::var("SOURCE_DATE_EPOCH").ok().and_then(|epoch| epoch.parse().ok()) {
+        return epoch;
+    }
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs())
+}
+
+/// Converts days since the Unix epoch into a (year, month, day) civil date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Formats a Unix timestamp as `YYYY-MM-DD` (UTC).
+pub fn format_date(seconds: u64) -> String {
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Formats a Unix timestamp as an RFC 3339 UTC date and time, e.g. `2023-06-18T12:00:00Z`.
+pub fn format_rfc3339(seconds: u64) -> String {
+    let time_of_day = seconds % 86400;
+    format!(
+        "{}T{:02}:{:02}:{:02}Z",
+        format_date(seconds), time_of_day / 3600, time_of_day / 60 % 60, time_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_dates_in_utc() {
+        assert_eq!(format_rfc3339(0), "1970-01-01T00:00:00Z");
+        assert_eq!(format_rfc3339(1687089600), "2023-06-18T12:00:00Z");
+        // Leap years, and the century which isn't one.
+        assert_eq!(format_date(951782400), "2000-02-29");
+        assert_eq!(format_rfc3339(4107542399), "2100-02-28T23:59:59Z");
+    }
+}
@@ -0,0 +1,464 @@
+//! Minimal parser for the subset of TOML used by manifests: tables, arrays of tables,
+//! dotted keys, strings, integers, floats, booleans, arrays and inline tables.
+
+use std::collections::BTreeMap;
+
+pub type Table = BTreeMap<String, Value>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(integer) => Some(*integer),
+            _ => None,
+        }
+    }
+
+    /// Integers are accepted wherever a float is expected.
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Float(float) => Some(*float),
+            Value::Integer(integer) => Some(*integer as f64),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(boolean) => Some(*boolean),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(array) => Some(array),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&Table> {
+        match self {
+            Value::Table(table) => Some(table),
+            _ => None,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+}
+
+/// Deepest nesting of arrays and inline tables accepted, far beyond that of any manifest.
+const MAX_NESTING: usize = 128;
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    /// Arrays and inline tables being parsed.
+    depth: usize,
+}
+
+pub fn parse(source: &str) -> Result<Table, String> {
+    let mut parser = Parser {
+        chars: source.chars().peekable(),
+        line: 1,
+        depth: 0,
+    };
+    parser.parse_document().map_err(|error| format!("line {}: {error}", parser.line))
+}
+
+impl<'a> Parser<'a> {
+    fn parse_document(&mut self) -> Result<Table, String> {
+        let mut root = Table::new();
+        let mut current_path: Vec<String> = vec![];
+
+        loop {
+            self.skip_whitespace_and_newlines();
+            match self.chars.peek() {
+                None => return Ok(root),
+                Some('[') => {
+                    self.chars.next();
+                    let is_array = self.chars.peek() == Some(&'[');
+                    if is_array {
+                        self.chars.next();
+                    }
+                    self.skip_whitespace();
+                    current_path = self.parse_key()?;
+                    self.expect(']')?;
+                    if is_array {
+                        self.expect(']')?;
+                        let (last, parents) = current_path.split_last().unwrap();
+                        let parent = descend(&mut root, parents)?;
+                        let entry = parent.entry(last.clone()).or_insert_with(|| Value::Array(vec![]));
+                        match entry {
+                            Value::Array(array) => array.push(Value::Table(Table::new())),
+                            _ => return Err(format!("`{last}` is not an array of tables")),
+                        }
+                    } else {
+                        descend(&mut root, &current_path)?;
+                    }
+                    self.expect_line_end()?;
+                }
+                Some(_) => {
+                    let key = self.parse_key()?;
+                    self.expect('=')?;
+                    self.skip_whitespace();
+                    let value = self.parse_value()?;
+                    let table = descend(&mut root, &current_path)?;
+                    insert(table, &key, value)?;
+                    self.expect_line_end()?;
+                }
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.chars.peek(), Some(' ') | Some('\t')) {
+            self.chars.next();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.chars.peek() == Some(&'#') {
+            while !matches!(self.chars.peek(), None | Some('\n')) {
+                self.chars.next();
+            }
+        }
+    }
+
+    fn skip_whitespace_and_newlines(&mut self) {
+        loop {
+            self.skip_whitespace();
+            self.skip_comment();
+            match self.chars.peek() {
+                Some('\n') => {
+                    self.line += 1;
+                    self.chars.next();
+                }
+                Some('\r') => {
+                    self.chars.next();
+                }
+                _ => return,
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        self.skip_whitespace();
+        match self.chars.next() {
+            Some(char) if char == expected => Ok(()),
+            Some(char) => Err(format!("expected `{expected}`, found `{char}`")),
+            None => Err(format!("expected `{expected}`, found end of file")),
+        }
+    }
+
+    fn expect_line_end(&mut self) -> Result<(), String> {
+        self.skip_whitespace();
+        self.skip_comment();
+        match self.chars.peek() {
+            None | Some('\n') | Some('\r') => Ok(()),
+            Some(char) => Err(format!("unexpected `{char}` after value")),
+        }
+    }
+
+    fn parse_key(&mut self) -> Result<Vec<String>, String> {
+        let mut parts = vec![];
+        loop {
+            self.skip_whitespace();
+            let part = match self.chars.peek() {
+                Some('"') => {
+                    self.chars.next();
+                    self.parse_basic_string()?
+                }
+                Some('\'') => {
+                    self.chars.next();
+                    self.parse_literal_string()?
+                }
+                _ => {
+                    let mut part = String::new();
+                    while let Some(&char) = self.chars.peek() {
+                        if !(char.is_ascii_alphanumeric() || char == '_' || char == '-') {
+                            break;
+                        }
+                        part.push(char);
+                        self.chars.next();
+                    }
+                    if part.is_empty() {
+                        return Err("expected a key".into());
+                    }
+                    part
+                }
+            };
+            parts.push(part);
+            self.skip_whitespace();
+            if self.chars.peek() != Some(&'.') {
+                return Ok(parts);
+            }
+            self.chars.next();
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, String> {
+        match self.chars.peek() {
+            Some('"') => {
+                self.chars.next();
+                Ok(Value::String(self.parse_basic_string()?))
+            }
+            Some('\'') => {
+                self.chars.next();
+                Ok(Value::String(self.parse_literal_string()?))
+            }
+            Some('[') => self.parse_nested(Self::parse_array),
+            Some('{') => self.parse_nested(Self::parse_inline_table),
+            Some(_) => self.parse_scalar(),
+            None => Err("expected a value, found end of file".into()),
+        }
+    }
+
+    /// Parses an array or inline table after its opening bracket, failing once they're nested deeper than
+    /// `MAX_NESTING` instead of overflowing the stack.
+    fn parse_nested(&mut self, parse: fn(&mut Self) -> Result<Value, String>) -> Result<Value, String> {
+        if self.depth == MAX_NESTING {
+            return Err("values are nested too deeply".into());
+        }
+        self.chars.next();
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn parse_basic_string(&mut self) -> Result<String, String> {
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                None | Some('\n') => return Err("unterminated string".into()),
+                Some('"') => return Ok(string),
+                Some('\\') => match self.chars.next() {
+                    Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('r') => string.push('\r'),
+                    Some('"') => string.push('"'),
+                    Some('\\') => string.push('\\'),
+                    Some('u') => {
+                        let code: String = (0..4).filter_map(|_| self.chars.next()).collect();
+                        let char = u32::from_str_radix(&code, 16).ok()
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| format!("invalid unicode escape `\\u{code}`"))?;
+                        string.push(char);
+                    }
+                    Some(char) => return Err(format!("invalid escape `\\{char}`")),
+                    None => return Err("unterminated string".into()),
+                },
+                Some(char) => string.push(char),
+            }
+        }
+    }
+
+    fn parse_literal_string(&mut self) -> Result<String, String> {
+        let mut string = String::new();
+        loop {
+            match self.chars.next() {
+                None | Some('\n') => return Err("unterminated string".into()),
+                Some('\'') => return Ok(string),
+                Some(char) => string.push(char),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, String> {
+        let mut array = vec![];
+        loop {
+            self.skip_whitespace_and_newlines();
+            if self.chars.peek() == Some(&']') {
+                self.chars.next();
+                return Ok(Value::Array(array));
+            }
+            array.push(self.parse_value()?);
+            self.skip_whitespace_and_newlines();
+            match self.chars.next() {
+                Some(',') => {}
+                Some(']') => return Ok(Value::Array(array)),
+                Some(char) => return Err(format!("expected `,` or `]` in array, found `{char}`")),
+                None => return Err("unterminated array".into()),
+            }
+        }
+    }
+
+    fn parse_inline_table(&mut self) -> Result<Value, String> {
+        let mut table = Table::new();
+        self.skip_whitespace();
+        if self.chars.peek() == Some(&'}') {
+            self.chars.next();
+            return Ok(Value::Table(table));
+        }
+        loop {
+            let key = self.parse_key()?;
+            self.expect('=')?;
+            self.skip_whitespace();
+            let value = self.parse_value()?;
+            insert(&mut table, &key, value)?;
+            self.skip_whitespace();
+            match self.chars.next() {
+                Some(',') => {}
+                Some('}') => return Ok(Value::Table(table)),
+                Some(char) => return Err(format!("expected `,` or `}}` in inline table, found `{char}`")),
+                None => return Err("unterminated inline table".into()),
+            }
+        }
+    }
+
+    fn parse_scalar(&mut self) -> Result<Value, String> {
+        let mut token = String::new();
+        while let Some(&char) = self.chars.peek() {
+            if matches!(char, ',' | ']' | '}' | '#' | ' ' | '\t' | '\n' | '\r') {
+                break;
+            }
+            token.push(char);
+            self.chars.next();
+        }
+        match token.as_str() {
+            "true" => return Ok(Value::Boolean(true)),
+            "false" => return Ok(Value::Boolean(false)),
+            "inf" | "+inf" => return Ok(Value::Float(f64::INFINITY)),
+            "-inf" => return Ok(Value::Float(f64::NEG_INFINITY)),
+            "nan" | "+nan" | "-nan" => return Ok(Value::Float(f64::NAN)),
+            _ => {}
+        }
+        let number = token.replace('_', "");
+        if let Some(hex) = number.strip_prefix("0x") {
+            return i64::from_str_radix(hex, 16)
+                .map(Value::Integer)
+                .map_err(|_| format!("invalid hexadecimal integer `{token}`"));
+        }
+        if let Ok(integer) = number.parse::<i64>() {
+            return Ok(Value::Integer(integer));
+        }
+        if let Ok(float) = number.parse::<f64>() {
+            return Ok(Value::Float(float));
+        }
+        Err(format!("invalid value `{token}`"))
+    }
+}
+
+fn descend<'t>(mut table: &'t mut Table, path: &[String]) -> Result<&'t mut Table, String> {
+    for key in path {
+        let entry = table.entry(key.clone()).or_insert_with(|| Value::Table(Table::new()));
+        table = match entry {
+            Value::Table(table) => table,
+            // Headers following an array of tables refer to its last element.
+            Value::Array(array) => match array.last_mut() {
+                Some(Value::Table(table)) => table,
+                _ => return Err(format!("`{key}` is not a table")),
+            },
+            _ => return Err(format!("`{key}` is not a table")),
+        };
+    }
+    Ok(table)
+}
+
+fn insert(table: &mut Table, key: &[String], value: Value) -> Result<(), String> {
+    let (last, parents) = key.split_last().unwrap();
+    let table = descend(table, parents)?;
+    if table.contains_key(last) {
+        return Err(format!("duplicate key `{last}`"));
+    }
+    table.insert(last.clone(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(entries: &[(&str, Value)]) -> Value {
+        Value::Table(entries.iter().map(|(key, value)| (key.to_string(), value.clone())).collect())
+    }
+
+    #[test]
+    fn parses_tables_arrays_of_tables_and_values() {
+        let root = parse(r#"
+            # Comment
+            mask = 'playable area' # Trailing comment
+            [layers.height]
+            type = "uint"
+            size = 0x2
+            scale = 1_000.5
+            wrap = true
+            tags = { owner = "environment", "quoted key" = "a\tb \u00e9" }
+            pipeline = [
+                { op = "blur", radius = 2 },
+                { op = "invert" },
+            ]
+            [[outputs.tiles]]
+            bounds = [-inf, 0, 1e3]
+            [[outputs.tiles]]
+            world.origin = [[1, 2], []]
+        "#).unwrap();
+
+        assert_eq!(root["mask"], Value::String("playable area".into()));
+        assert_eq!(root["layers"], table(&[("height", table(&[
+            ("type", Value::String("uint".into())),
+            ("size", Value::Integer(2)),
+            ("scale", Value::Float(1000.5)),
+            ("wrap", Value::Boolean(true)),
+            ("tags", table(&[("owner", Value::String("environment".into())), ("quoted key", Value::String("a\tb é".into()))])),
+            ("pipeline", Value::Array(vec![
+                table(&[("op", Value::String("blur".into())), ("radius", Value::Integer(2))]),
+                table(&[("op", Value::String("invert".into()))]),
+            ])),
+        ]))]));
+        assert_eq!(root["outputs"], table(&[("tiles", Value::Array(vec![
+            table(&[("bounds", Value::Array(vec![Value::Float(f64::NEG_INFINITY), Value::Integer(0), Value::Float(1000.0)]))]),
+            table(&[("world", table(&[("origin", Value::Array(vec![
+                Value::Array(vec![Value::Integer(1), Value::Integer(2)]),
+                Value::Array(vec![]),
+            ]))]))]),
+        ]))]));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let malformed = [
+            "a =", "a = 1 2", "a = 1\na = 2", "a.b = 1\na = 2", "a = 1\n[a]", "[a", "[[a]\n", "= 1", "a = \"x",
+            "a = \"x\ny\"", "a = 'x", "a = \"\\q\"", "a = \"\\u12\"", "a = [1, 2", "a = [1 2]", "a = {b = 1", "a = {b = 1 c = 2}",
+            "a = 1.2.3", "a = 0xZ", "a = yes",
+        ];
+        for source in malformed {
+            assert!(parse(source).is_err(), "{source:?} was accepted");
+        }
+        assert!(parse("a = 1\nb = [\n1,\n2 3]").unwrap_err().starts_with("line 4:"));
+    }
+
+    #[test]
+    fn limits_the_nesting() {
+        let nested = |depth: usize| format!("a = {}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse(&nested(MAX_NESTING)).is_ok());
+        assert!(parse(&nested(MAX_NESTING + 1)).unwrap_err().contains("nested too deeply"));
+        assert!(parse(&format!("a = {}", "{b = ".repeat(100_000))).is_err());
+    }
+}
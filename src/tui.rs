@@ -0,0 +1,244 @@
+//! Interactive session of `nsdgen tui`, choosing which discovered layers are stored, in which order and format, before
+//! generating, as a quicker way of trying things out than editing the manifest. Commands are read line by line.
+
+use std::io;
+
+use image::GenericImageView;
+
+use crate::format::{AttributeFormat, AttributeType};
+use crate::layer::{sort_layer_sources, LayerSource};
+use crate::manifest::Manifest;
+
+const HISTOGRAM_BINS: usize = 16;
+const HISTOGRAM_WIDTH: usize = 40;
+
+pub const TUI_HELP: &str = "\
+Commands (layers are given by their number):
+  list                  Show the layers
+  toggle <n>            Include or leave out a layer
+  move <n> <position>   Move a layer to another position
+  type <n> <type>       Set the attribute type: byte, uint, int or float
+  size <n> <bytes>      Set the bytes per texel, e.g. the number of channels of byte attributes
+  histogram <n>         Show the distribution of the source values
+  generate [args]       Generate with the layers as shown, followed by more arguments of `generate`
+  help                  Show this help
+  quit                  Leave without generating";
+
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+pub enum TuiCommand {
+    List,
+    Toggle(usize),
+    Move { from: usize, to: usize },
+    Type(usize, AttributeType),
+    Size(usize, u8),
+    Histogram(usize),
+    /// Generates with the additional arguments of `generate`.
+    Generate(Vec<String>),
+    Help,
+    Quit,
+}
+
+impl TuiCommand {
+    /// Parses a command line, with layers and positions counted from 1.
+    pub fn parse(line: &str) -> io::Result<TuiCommand> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |index: usize| -> io::Result<usize> {
+            let word = words.get(index).ok_or_else(|| invalid_input(format!("`{}` requires a layer number", words[0])))?;
+            word.parse::<usize>().ok().filter(|&number| number > 0).map(|number| number - 1)
+                .ok_or_else(|| invalid_input(format!("Invalid layer number `{word}`")))
+        };
+        let argument = |index: usize, name: &str| -> io::Result<&str> {
+            words.get(index).copied().ok_or_else(|| invalid_input(format!("`{}` requires the {name}", words[0])))
+        };
+        Ok(match words.first().copied().unwrap_or("list") {
+            "list" | "ls" => TuiCommand::List,
+            "toggle" | "t" => TuiCommand::Toggle(number(1)?),
+            "move" | "mv" => TuiCommand::Move { from: number(1)?, to: number(2)? },
+            "type" => {
+                let name = argument(2, "attribute type")?;
+                let attribute_type = AttributeType::from_name(name)
+                    .ok_or_else(|| invalid_input(format!("Unknown attribute type `{name}`")))?;
+                TuiCommand::Type(number(1)?, attribute_type)
+            }
+            "size" => {
+                let size = argument(2, "size")?;
+                TuiCommand::Size(number(1)?, size.parse().map_err(|_| invalid_input(format!("Invalid size `{size}`")))?)
+            }
+            "histogram" | "hist" => TuiCommand::Histogram(number(1)?),
+            "generate" | "gen" => TuiCommand::Generate(words[1..].iter().map(|word| word.to_string()).collect()),
+            "help" | "?" => TuiCommand::Help,
+            "quit" | "exit" | "q" => TuiCommand::Quit,
+            command => return Err(invalid_input(format!("Unknown command `{command}`, see `help`"))),
+        })
+    }
+}
+
+pub struct TuiLayer {
+    pub source: LayerSource,
+    pub included: bool,
+}
+
+/// Discovered layers with the changes made so far, which are applied to the manifest.
+pub struct TuiSession {
+    pub manifest: Manifest,
+    pub layers: Vec<TuiLayer>,
+}
+
+impl TuiSession {
+    pub fn new(manifest: Manifest, mut sources: Vec<LayerSource>) -> TuiSession {
+        sort_layer_sources(&mut sources, &manifest);
+        let layers = sources.into_iter().map(|source| TuiLayer { source, included: true }).collect();
+        TuiSession { manifest, layers }
+    }
+
+    fn layer(&self, index: usize) -> io::Result<&TuiLayer> {
+        self.layers.get(index).ok_or_else(|| invalid_input(format!("There is no layer {}", index + 1)))
+    }
+
+    fn set_format(&mut self, index: usize, attribute_type: AttributeType, size: u8) -> io::Result<()> {
+        let stem = self.layer(index)?.source.stem.clone();
+        let format = AttributeFormat::new(attribute_type, size)?;
+        self.manifest.layers.entry(stem).or_default().format = format;
+        Ok(())
+    }
+
+    /// Applies a command which changes the layers, ignoring the others.
+    pub fn apply(&mut self, command: &TuiCommand) -> io::Result<()> {
+        match *command {
+            TuiCommand::Toggle(index) => {
+                self.layer(index)?;
+                self.layers[index].included ^= true;
+            }
+            TuiCommand::Move { from, to } => {
+                self.layer(from)?;
+                self.layer(to)?;
+                let layer = self.layers.remove(from);
+                self.layers.insert(to, layer);
+                // Every layer gets its position, as the order of the others would depend on their names.
+                for (order, layer) in self.layers.iter().enumerate() {
+                    self.manifest.layers.entry(layer.source.stem.clone()).or_default().order = order as i64;
+                }
+            }
+            TuiCommand::Type(index, attribute_type) => {
+                self.set_format(index, attribute_type, attribute_type.default_size())?;
+            }
+            TuiCommand::Size(index, size) => {
+                let attribute_type = self.manifest.layer(&self.layer(index)?.source.stem).format.attribute_type;
+                self.set_format(index, attribute_type, size)?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// A line per layer with its number, whether it's included, its attribute name, source and format.
+    pub fn list(&self) -> String {
+        let names: Vec<String> = self.layers.iter().map(|layer| self.manifest.attribute_name(&layer.source)).collect();
+        let name_width = names.iter().map(String::len).max().unwrap_or(0);
+        let lines: Vec<String> = self.layers.iter().zip(&names).enumerate()
+            .map(|(index, (layer, name))| {
+                let config = self.manifest.layer(&layer.source.stem);
+                format!(
+                    "{:>3} [{}] {name:<name_width$}  {:<5} {}  {:<11} {}",
+                    index + 1,
+                    if layer.included { "x" } else { " " },
+                    config.format.attribute_type.name(),
+                    config.format.size,
+                    config.kind.name(),
+                    layer.source
+                )
+            })
+            .collect();
+        lines.join("\n")
+    }
+
+    /// Attribute names of the layers which have been left out.
+    pub fn excluded_names(&self) -> Vec<String> {
+        self.layers.iter()
+            .filter(|layer| !layer.included)
+            .map(|layer| self.manifest.attribute_name(&layer.source))
+            .collect()
+    }
+
+    /// Bars of the distribution of the first channel of the source, from its lowest to its highest possible value.
+    pub fn histogram(&self, index: usize) -> io::Result<String> {
+        let source = &self.layer(index)?.source;
+        if source.tile_layer.is_some() || source.mosaic.is_some() {
+            return Err(invalid_input(format!("Histograms of {source} aren't supported, only of image files")));
+        }
+        let image = image::open(&source.path).map_err(io::Error::other)?;
+        let mut counts = [0usize; HISTOGRAM_BINS];
+        for pixel in image.to_rgba32f().pixels() {
+            let bin = (pixel[0].clamp(0.0, 1.0) * HISTOGRAM_BINS as f32) as usize;
+            counts[bin.min(HISTOGRAM_BINS - 1)] += 1;
+        }
+        let max_count = counts.iter().copied().max().unwrap_or(0).max(1);
+        let lines: Vec<String> = counts.iter().enumerate()
+            .map(|(bin, &count)| {
+                let low = bin as f64 / HISTOGRAM_BINS as f64;
+                let bar = "#".repeat((count * HISTOGRAM_WIDTH).div_ceil(max_count));
+                format!("{low:>6.3} {bar:<HISTOGRAM_WIDTH$} {count}")
+            })
+            .collect();
+        let (width, height) = image.dimensions();
+        Ok(format!("{source} ({width}x{height}):\n{}", lines.join("\n")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDirectory;
+
+    fn session(directory: &TempDirectory, stems: &[&str]) -> TuiSession {
+        let sources = stems.iter().map(|stem| LayerSource::image(directory.join(format!("{stem}.png")))).collect();
+        TuiSession::new(Manifest::default(), sources)
+    }
+
+    fn stems(session: &TuiSession) -> Vec<&str> {
+        session.layers.iter().map(|layer| layer.source.stem.as_str()).collect()
+    }
+
+    #[test]
+    fn changes_the_layers_with_commands() {
+        let directory = TempDirectory::new();
+        let mut session = session(&directory, &["height", "biomes", "grass"]);
+        assert_eq!(stems(&session), ["biomes", "grass", "height"]);
+
+        for line in ["move 3 1", "toggle 2", "type 1 float", "size 3 2"] {
+            session.apply(&TuiCommand::parse(line).unwrap()).unwrap();
+        }
+        assert_eq!(stems(&session), ["height", "biomes", "grass"]);
+        assert_eq!(session.manifest.layer("height").order, 0);
+        assert_eq!(session.manifest.layer("grass").order, 2);
+        assert_eq!(session.excluded_names(), ["biomes"]);
+        assert_eq!(session.manifest.layer("height").format, AttributeFormat::new(AttributeType::Float, 4).unwrap());
+        assert_eq!(session.manifest.layer("grass").format, AttributeFormat::new(AttributeType::Byte, 2).unwrap());
+        let list = session.list();
+        assert!(list.lines().nth(1).unwrap().starts_with("  2 [ ] biomes"), "{list}");
+
+        assert!(session.apply(&TuiCommand::Toggle(3)).err().unwrap().to_string().contains("There is no layer 4"));
+        assert!(session.apply(&TuiCommand::parse("size 1 3").unwrap()).is_err());
+        for line in ["toggle", "toggle 0", "type 1 double", "size 1 x", "jump"] {
+            assert!(TuiCommand::parse(line).is_err(), "{line:?} was accepted");
+        }
+        assert!(matches!(TuiCommand::parse("").unwrap(), TuiCommand::List));
+        assert!(matches!(TuiCommand::parse("gen -w 5").unwrap(), TuiCommand::Generate(args) if args == ["-w", "5"]));
+    }
+
+    #[test]
+    fn shows_the_distribution_of_the_source_values() {
+        let directory = TempDirectory::new();
+        image::GrayImage::from_raw(4, 1, vec![0, 0, 128, 255]).unwrap().save(directory.join("height.png")).unwrap();
+        let histogram = session(&directory, &["height"]).histogram(0).unwrap();
+        let lines: Vec<&str> = histogram.lines().collect();
+        assert_eq!(lines.len(), 1 + HISTOGRAM_BINS);
+        assert!(lines[0].ends_with("(4x1):"), "{histogram}");
+        assert_eq!(lines[1], format!(" 0.000 {} 2", "#".repeat(HISTOGRAM_WIDTH)));
+        assert_eq!(lines[9], format!(" 0.500 {:<HISTOGRAM_WIDTH$} 1", "#".repeat(HISTOGRAM_WIDTH / 2)));
+        assert_eq!(lines[16].split_whitespace().last(), Some("1"));
+    }
+}
@@ -0,0 +1,317 @@
+use std::io;
+use std::path::Path;
+
+use crate::format::{AttributeFormat, NsdExtensions, NsdHeader, TexelOrder};
+use crate::layer::{Layer, LayerDimensions, LayerSection};
+use crate::reader::{NsdReader, NsdSection};
+use crate::writer::{make_raw_data, texel_stride};
+
+fn mismatch(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Re-opens a generated file and compares it against the layers it was generated from.
+pub fn verify_file(
+    path: &Path,
+    header: &NsdHeader,
+    layers: &[Layer],
+    dimensions: &LayerDimensions,
+    extensions: &NsdExtensions,
+    sections: &[LayerSection]
+) -> io::Result<()> {
+    let reader = NsdReader::open(path)?;
+
+    if reader.header() != header {
+        return Err(mismatch(format!(
+            "Header mismatch: expected {}/{}, found {}/{}",
+            header.vendor(), header.identifier(), reader.header().vendor(), reader.header().identifier()
+        )));
+    }
+
+    if reader.dimensions() != dimensions {
+        return Err(mismatch(format!(
+            "Dimensions mismatch: expected {}x{}, found {}x{}",
+            dimensions.width, dimensions.height, reader.dimensions().width, reader.dimensions().height
+        )));
+    }
+
+    if reader.attributes().len() != layers.len() {
+        return Err(mismatch(format!(
+            "Attribute count mismatch: expected {}, found {}", layers.len(), reader.attributes().len()
+        )));
+    }
+    for (attribute, layer) in reader.attributes().iter().zip(layers) {
+        if attribute.name != layer.name || attribute.format != layer.format {
+            return Err(mismatch(format!(
+                "Attribute mismatch: expected {} ({:?}), found {} ({:?})",
+                layer.name, layer.format, attribute.name, attribute.format
+            )));
+        }
+    }
+
+    if reader.texel_order() != extensions.texel_order {
+        return Err(mismatch(format!(
+            "Texel order mismatch: expected {}, found {}", extensions.texel_order.name(), reader.texel_order().name()
+        )));
+    }
+
+    if reader.extensions().row_stride != extensions.row_stride {
+        return Err(mismatch(format!(
+            "Row stride mismatch: expected {:?}, found {:?}", extensions.row_stride, reader.extensions().row_stride
+        )));
+    }
+
+    let expected_data = make_raw_data(layers, dimensions, extensions.texel_order);
+    if let Some(offset) = expected_data.iter().zip(reader.data()).position(|(lhs, rhs)| lhs != rhs) {
+        let stride = texel_stride(layers);
+        let texel = offset / stride;
+        let mut attribute_offset = offset % stride;
+        let layer = layers.iter()
+            .find(|layer| {
+                let size = layer.format.size as usize;
+                if attribute_offset < size {
+                    return true;
+                }
+                attribute_offset -= size;
+                false
+            })
+            .unwrap();
+        let (x, y) = extensions.texel_order.coordinates(texel, dimensions.width, dimensions.height);
+        return Err(mismatch(format!("Texel data mismatch at ({x}, {y}) in attribute {}", layer.name)));
+    }
+    if expected_data.len() != reader.data().len() {
+        return Err(mismatch(format!(
+            "Data size mismatch: expected {} bytes, found {}", expected_data.len(), reader.data().len()
+        )));
+    }
+
+    if reader.groups() != extensions.groups.as_slice() {
+        return Err(mismatch("Attribute groups mismatch".into()));
+    }
+    if reader.extensions().attribute_metadata != extensions.attribute_metadata {
+        return Err(mismatch("Attribute metadata mismatch".into()));
+    }
+    if reader.world() != extensions.world.as_ref() {
+        return Err(mismatch(format!("World scale mismatch: expected {:?}, found {:?}", extensions.world, reader.world())));
+    }
+
+    if reader.sections().len() != sections.len() {
+        return Err(mismatch(format!(
+            "Section count mismatch: expected {}, found {}", sections.len(), reader.sections().len()
+        )));
+    }
+    for (found, section) in reader.sections().iter().zip(sections) {
+        let attributes_match = found.attributes.len() == section.layers.len() && found.attributes.iter()
+            .zip(&section.layers)
+            .all(|(attribute, layer)| attribute.name == layer.name && attribute.format == layer.format);
+        if found.name != section.name || found.dimensions != section.dimensions || !attributes_match {
+            return Err(mismatch(format!("Structure of section {} doesn't match", section.name)));
+        }
+        if found.data != make_raw_data(&section.layers, &section.dimensions, TexelOrder::RowMajor) {
+            return Err(mismatch(format!("Texel data mismatch in section {}", section.name)));
+        }
+    }
+
+    Ok(())
+}
+
+/// Texels of an attribute which differ between two files by more than the tolerance.
+struct AttributeDifference {
+    count: usize,
+    max_difference: f64,
+    /// Row-major index of the texel with the largest difference.
+    max_texel: usize,
+}
+
+fn compare_attribute<'a>(
+    format: &AttributeFormat,
+    texel_count: usize,
+    expected: impl Fn(usize) -> &'a [u8],
+    found: impl Fn(usize) -> &'a [u8],
+    tolerance: f64
+) -> Option<AttributeDifference> {
+    let mut difference = AttributeDifference { count: 0, max_difference: 0.0, max_texel: 0 };
+    for texel in 0..texel_count {
+        let (expected, found) = (expected(texel), found(texel));
+        let texel_difference = (0..format.component_count())
+            .map(|component| {
+                let (expected, found) = (format.decode_component(expected, component), format.decode_component(found, component));
+                // Matching NaNs (e.g. float nodata values) aren't a difference.
+                if expected.is_nan() && found.is_nan() { 0.0 } else { (expected - found).abs() }
+            })
+            .fold(0.0, |max: f64, difference| if difference.is_nan() { f64::INFINITY } else { max.max(difference) });
+        if texel_difference > tolerance {
+            difference.count += 1;
+            if texel_difference > difference.max_difference {
+                difference.max_difference = texel_difference;
+                difference.max_texel = texel;
+            }
+        }
+    }
+    (difference.count > 0).then_some(difference)
+}
+
+fn describe_difference(context: &str, difference: &AttributeDifference, width: u32, tolerance: f64) -> String {
+    let (x, y) = (difference.max_texel % width as usize, difference.max_texel / width as usize);
+    format!(
+        "{context}: {} texels differ by more than {tolerance} (at most {} at ({x}, {y}))",
+        difference.count, difference.max_difference
+    )
+}
+
+fn section_texel(section: &NsdSection, attribute: usize, texel: usize) -> &[u8] {
+    let stride: usize = section.attributes.iter().map(|attribute| attribute.format.size as usize).sum();
+    let offset: usize = section.attributes[..attribute].iter().map(|attribute| attribute.format.size as usize).sum();
+    let start = texel * stride + offset;
+    &section.data[start..start + section.attributes[attribute].format.size as usize]
+}
+
+/// Compares a regenerated file against a golden one and describes every difference. Texel values of attributes
+/// may differ by up to `tolerance`, except for the `exact_attributes` (e.g. categorical ones) which must match.
+/// The texel order and row padding aren't compared, only the values.
+pub fn compare_files(golden: &NsdReader, regenerated: &NsdReader, tolerance: f64, exact_attributes: &[String]) -> Vec<String> {
+    let mut differences = vec![];
+    let tolerance_of = |name: &str| if exact_attributes.iter().any(|exact| exact == name) { 0.0 } else { tolerance };
+
+    if golden.header() != regenerated.header() {
+        differences.push(format!(
+            "Header: expected {}/{}, found {}/{}",
+            golden.header().vendor(), golden.header().identifier(), regenerated.header().vendor(), regenerated.header().identifier()
+        ));
+    }
+    if golden.dimensions() != regenerated.dimensions() {
+        differences.push(format!(
+            "Dimensions: expected {}x{}, found {}x{}",
+            golden.dimensions().width, golden.dimensions().height, regenerated.dimensions().width, regenerated.dimensions().height
+        ));
+    }
+
+    for attribute in golden.attributes() {
+        if !regenerated.attributes().iter().any(|found| found.name == attribute.name) {
+            differences.push(format!("Attribute {} is missing", attribute.name));
+        }
+    }
+    for (index, attribute) in regenerated.attributes().iter().enumerate() {
+        let Some(golden_index) = golden.attributes().iter().position(|expected| expected.name == attribute.name) else {
+            differences.push(format!("Attribute {} is new", attribute.name));
+            continue;
+        };
+        let expected = &golden.attributes()[golden_index];
+        if expected.format != attribute.format {
+            differences.push(format!(
+                "Attribute {}: expected {} ({} bytes), found {} ({} bytes)",
+                attribute.name, expected.format.attribute_type.name(), expected.format.size,
+                attribute.format.attribute_type.name(), attribute.format.size
+            ));
+            continue;
+        }
+        if golden.dimensions() != regenerated.dimensions() {
+            continue;
+        }
+        if golden_index != index {
+            differences.push(format!("Attribute {}: moved from index {golden_index} to {index}", attribute.name));
+        }
+        let tolerance = tolerance_of(&attribute.name);
+        let texel_count = (golden.dimensions().width * golden.dimensions().height) as usize;
+        let difference = compare_attribute(
+            &attribute.format,
+            texel_count,
+            |texel| golden.attribute_bytes(golden_index, texel),
+            |texel| regenerated.attribute_bytes(index, texel),
+            tolerance
+        );
+        if let Some(difference) = difference {
+            let context = format!("Attribute {}", attribute.name);
+            differences.push(describe_difference(&context, &difference, golden.dimensions().width, tolerance));
+        }
+    }
+
+    if golden.groups() != regenerated.groups() {
+        differences.push("Attribute groups differ".into());
+    }
+    for (index, attribute) in regenerated.attributes().iter().enumerate() {
+        let Some(golden_index) = golden.attributes().iter().position(|expected| expected.name == attribute.name) else {
+            continue;
+        };
+        let (expected, found) = (golden.attribute_metadata(golden_index), regenerated.attribute_metadata(index));
+        let metadata_matches = match (expected, found) {
+            (Some(expected), Some(found)) => {
+                expected.description == found.description && expected.tags == found.tags && expected.nodata == found.nodata
+                    && expected.priority == found.priority && expected.weight == found.weight
+            }
+            (expected, found) => expected.is_none() && found.is_none(),
+        };
+        if !metadata_matches {
+            differences.push(format!("Attribute {}: metadata differs", attribute.name));
+        }
+    }
+
+    for section in golden.sections() {
+        if !regenerated.sections().iter().any(|found| found.name == section.name) {
+            differences.push(format!("Section {} is missing", section.name));
+        }
+    }
+    for found in regenerated.sections() {
+        let Some(expected) = golden.sections().iter().find(|expected| expected.name == found.name) else {
+            differences.push(format!("Section {} is new", found.name));
+            continue;
+        };
+        let structure_matches = expected.dimensions == found.dimensions
+            && expected.attributes.len() == found.attributes.len()
+            && expected.attributes.iter().zip(&found.attributes)
+                .all(|(expected, found)| expected.name == found.name && expected.format == found.format);
+        if !structure_matches {
+            differences.push(format!("Section {}: structure differs", found.name));
+            continue;
+        }
+        let texel_count = (found.dimensions.width * found.dimensions.height) as usize;
+        for (index, attribute) in found.attributes.iter().enumerate() {
+            let tolerance = tolerance_of(&attribute.name);
+            let difference = compare_attribute(
+                &attribute.format,
+                texel_count,
+                |texel| section_texel(expected, index, texel),
+                |texel| section_texel(found, index, texel),
+                tolerance
+            );
+            if let Some(difference) = difference {
+                let context = format!("Section {}, attribute {}", found.name, attribute.name);
+                differences.push(describe_difference(&context, &difference, found.dimensions.width, tolerance));
+            }
+        }
+    }
+
+    differences
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::builder::NsdBuilder;
+    use crate::format::AttributeType;
+    use crate::layer::LayerDimensions;
+
+    fn file(height: &[u8], biomes: &[u8]) -> NsdReader {
+        let mut bytes = Cursor::new(vec![]);
+        NsdBuilder::new(LayerDimensions { width: 2, height: 1 })
+            .add_layer("height", AttributeFormat::new(AttributeType::UInt, 2).unwrap(), height).unwrap()
+            .add_layer("biomes", AttributeFormat::default(), biomes).unwrap()
+            .write(&mut bytes).unwrap();
+        NsdReader::from_bytes(bytes.get_ref()).unwrap()
+    }
+
+    #[test]
+    fn compares_the_values_within_the_tolerance() {
+        let golden = file(&[100, 0, 200, 0], &[1, 2]);
+        assert!(compare_files(&golden, &file(&[100, 0, 200, 0], &[1, 2]), 0.0, &[]).is_empty());
+        assert!(compare_files(&golden, &file(&[102, 0, 199, 0], &[1, 3]), 2.0, &[]).is_empty());
+
+        let exact = ["biomes".to_string()];
+        assert_eq!(compare_files(&golden, &file(&[105, 0, 200, 0], &[1, 3]), 2.0, &exact), [
+            "Attribute height: 1 texels differ by more than 2 (at most 5 at (0, 0))",
+            "Attribute biomes: 1 texels differ by more than 0 (at most 1 at (1, 0))",
+        ]);
+    }
+}
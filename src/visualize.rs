@@ -0,0 +1,378 @@
+//! False-color images of single attributes, for design reviews and documentation.
+
+use std::io;
+
+use image::{Rgba, RgbaImage};
+
+use crate::layer::LayerDimensions;
+use crate::reader::{NsdAttribute, NsdReader};
+use crate::resample::ValueKind;
+
+/// How values are mapped to colors.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Colormap {
+    Viridis,
+    Magma,
+    Grayscale,
+    /// A distinct color per value, for IDs.
+    Categorical,
+}
+
+impl Colormap {
+    /// Default colormap of an attribute of the given kind.
+    pub fn for_kind(kind: ValueKind) -> Colormap {
+        match kind {
+            ValueKind::Continuous => Colormap::Viridis,
+            ValueKind::Categorical => Colormap::Categorical,
+            ValueKind::Mask => Colormap::Grayscale,
+        }
+    }
+
+    /// Control points, evenly spread from the low to the high end of the range.
+    fn gradient(&self) -> &'static [[u8; 3]] {
+        match self {
+            Colormap::Viridis => &[
+                [0x44, 0x01, 0x54], [0x47, 0x2d, 0x7b], [0x3b, 0x52, 0x8b], [0x2c, 0x72, 0x8e], [0x21, 0x91, 0x8c],
+                [0x28, 0xae, 0x80], [0x5e, 0xc9, 0x62], [0xad, 0xdc, 0x30], [0xfd, 0xe7, 0x25],
+            ],
+            Colormap::Magma => &[
+                [0x00, 0x00, 0x04], [0x1c, 0x10, 0x44], [0x4f, 0x12, 0x7b], [0x81, 0x25, 0x81], [0xb5, 0x36, 0x7a],
+                [0xe5, 0x50, 0x64], [0xfb, 0x87, 0x61], [0xfe, 0xc2, 0x87], [0xfc, 0xfd, 0xbf],
+            ],
+            Colormap::Grayscale | Colormap::Categorical => &[[0x00, 0x00, 0x00], [0xff, 0xff, 0xff]],
+        }
+    }
+
+    /// Color of a value, normalized to 0-1 for gradients.
+    fn color(&self, value: f64, range: &ValueRange) -> [u8; 3] {
+        if *self == Colormap::Categorical {
+            return category_color(value);
+        }
+        let gradient = self.gradient();
+        let position = ((value - range.low) / (range.high - range.low)).clamp(0.0, 1.0);
+        let position = if position.is_nan() { 0.0 } else { position } * (gradient.len() - 1) as f64;
+        let index = (position as usize).min(gradient.len() - 2);
+        let fraction = position - index as f64;
+        let [from, to] = [gradient[index], gradient[index + 1]];
+        [0, 1, 2].map(|channel| (from[channel] as f64 + (to[channel] as f64 - from[channel] as f64) * fraction).round() as u8)
+    }
+}
+
+/// Hues spread by the golden angle, so neighboring IDs get clearly different colors.
+fn category_color(value: f64) -> [u8; 3] {
+    let hue = (value.round() * 0.618_033_988_75).rem_euclid(1.0) * 6.0;
+    let (saturation, brightness) = (0.65, 0.95);
+    let chroma = brightness * saturation;
+    let secondary = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (red, green, blue) = match hue as u32 {
+        0 => (chroma, secondary, 0.0),
+        1 => (secondary, chroma, 0.0),
+        2 => (0.0, chroma, secondary),
+        3 => (0.0, secondary, chroma),
+        4 => (secondary, 0.0, chroma),
+        _ => (chroma, 0.0, secondary),
+    };
+    let base = brightness - chroma;
+    [red, green, blue].map(|channel| ((channel + base) * 255.0).round() as u8)
+}
+
+/// Values mapped to the ends of a gradient.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ValueRange {
+    pub low: f64,
+    pub high: f64,
+}
+
+impl std::str::FromStr for ValueRange {
+    type Err = String;
+
+    /// Parses `low,high`.
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        let values: Vec<f64> = string.split(',')
+            .map(|value| value.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("Invalid range {string}, expected low,high"))?;
+        let [low, high] = values[..] else {
+            return Err(format!("Invalid range {string}, expected low,high"));
+        };
+        if !low.is_finite() || !high.is_finite() || low >= high {
+            return Err("The range must satisfy low < high".into());
+        }
+        Ok(ValueRange { low, high })
+    }
+}
+
+pub struct VisualizeOptions {
+    /// Defaults to the colormap of the attribute's kind.
+    pub colormap: Option<Colormap>,
+    /// Component of vector attributes to show.
+    pub component: usize,
+    /// Defaults to the smallest and largest value with data.
+    pub range: Option<ValueRange>,
+    /// Whether to add the range (or the IDs with their colors) below the image.
+    pub legend: bool,
+}
+
+/// Renders an attribute of the main section or of any other section. Texels without data are transparent.
+pub fn visualize(reader: &NsdReader, attribute_name: &str, options: &VisualizeOptions) -> io::Result<RgbaImage> {
+    let not_found = || {
+        let names: Vec<&str> = reader.attributes().iter()
+            .chain(reader.sections().iter().flat_map(|section| &section.attributes))
+            .map(|attribute| attribute.name.as_str())
+            .collect();
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("The file has no attribute {attribute_name}, it has {}", names.join(", "))
+        )
+    };
+
+    let (dimensions, values, metadata) = match reader.attributes().iter().position(|attribute| attribute.name == attribute_name) {
+        Some(index) => {
+            let LayerDimensions { width, height } = *reader.dimensions();
+            check_component(&reader.attributes()[index], options.component)?;
+            let values: Vec<f64> = (0..height).flat_map(|y| (0..width).map(move |x| (x, y)))
+                .map(|(x, y)| reader.sample(index, x, y).map_or(0.0, |values| values[options.component]))
+                .collect();
+            (reader.dimensions().clone(), values, reader.attribute_metadata(index))
+        }
+        None => {
+            let (section, index) = reader.sections().iter()
+                .find_map(|section| {
+                    let index = section.attributes.iter().position(|attribute| attribute.name == attribute_name)?;
+                    Some((section, index))
+                })
+                .ok_or_else(not_found)?;
+            let attribute = &section.attributes[index];
+            check_component(attribute, options.component)?;
+            let stride: usize = section.attributes.iter().map(|attribute| attribute.format.size as usize).sum();
+            let offset: usize = section.attributes[..index].iter().map(|attribute| attribute.format.size as usize).sum();
+            let values = section.data.chunks_exact(stride)
+                .map(|texel| attribute.format.decode_component(&texel[offset..], options.component))
+                .collect();
+            // Sections have no metadata.
+            (section.dimensions.clone(), values, None)
+        }
+    };
+
+    let nodata = metadata.and_then(|metadata| metadata.nodata);
+    let kind = metadata.map_or(ValueKind::Continuous, |metadata| metadata.kind);
+    let colormap = options.colormap.unwrap_or(Colormap::for_kind(kind));
+    let has_data = |value: &f64| nodata != Some(*value);
+    let range = options.range.unwrap_or_else(|| {
+        let low = values.iter().copied().filter(has_data).fold(f64::INFINITY, f64::min);
+        let high = values.iter().copied().filter(has_data).fold(f64::NEG_INFINITY, f64::max);
+        match low <= high {
+            true => ValueRange { low, high },
+            false => ValueRange { low: 0.0, high: 0.0 },
+        }
+    });
+
+    let mut image = RgbaImage::from_fn(dimensions.width, dimensions.height, |x, y| {
+        let value = values[y as usize * dimensions.width as usize + x as usize];
+        match has_data(&value) {
+            true => {
+                let [red, green, blue] = colormap.color(value, &range);
+                Rgba([red, green, blue, 255])
+            }
+            false => Rgba([0, 0, 0, 0]),
+        }
+    });
+    if options.legend {
+        image = match colormap {
+            Colormap::Categorical => {
+                let mut categories: Vec<f64> = values.iter().copied().filter(has_data).map(f64::round).collect();
+                categories.sort_by(f64::total_cmp);
+                categories.dedup();
+                with_category_legend(&image, &categories)
+            }
+            _ => with_gradient_legend(&image, colormap, &range),
+        };
+    }
+    Ok(image)
+}
+
+fn check_component(attribute: &NsdAttribute, component: usize) -> io::Result<()> {
+    let count = attribute.format.component_count();
+    if component >= count {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Attribute {} has {count} components, component {component} doesn't exist", attribute.name)
+        ));
+    }
+    Ok(())
+}
+
+/// Rows of 3-pixel-wide glyphs, from the top.
+fn glyph(character: char) -> [u8; 5] {
+    match character {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => [0b000; 5],
+    }
+}
+
+const GLYPH_SCALE: u32 = 2;
+const GLYPH_ADVANCE: u32 = 4 * GLYPH_SCALE;
+const TEXT_HEIGHT: u32 = 5 * GLYPH_SCALE;
+const MARGIN: u32 = 4;
+const SWATCH_SIZE: u32 = 12;
+const LEGEND_MIN_WIDTH: u32 = 96;
+const LEGEND_BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+fn format_label(value: f64) -> String {
+    let label = format!("{value:.3}");
+    let label = label.trim_end_matches('0').trim_end_matches('.');
+    match label {
+        "-0" => "0".to_string(),
+        label => label.to_string(),
+    }
+}
+
+fn text_width(text: &str) -> u32 {
+    (text.chars().count() as u32 * GLYPH_ADVANCE).saturating_sub(GLYPH_SCALE)
+}
+
+fn draw_text(image: &mut RgbaImage, text: &str, left: u32, top: u32) {
+    for (index, character) in text.chars().enumerate() {
+        for (row, bits) in glyph(character).iter().enumerate() {
+            for column in 0..3 {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+                for (dx, dy) in (0..GLYPH_SCALE).flat_map(|dx| (0..GLYPH_SCALE).map(move |dy| (dx, dy))) {
+                    let x = left + index as u32 * GLYPH_ADVANCE + column * GLYPH_SCALE + dx;
+                    let y = top + row as u32 * GLYPH_SCALE + dy;
+                    if x < image.width() && y < image.height() {
+                        image.put_pixel(x, y, Rgba([0, 0, 0, 255]));
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The image on top of a legend of the given height, at least `LEGEND_MIN_WIDTH` wide.
+fn extend_for_legend(image: &RgbaImage, legend_height: u32) -> RgbaImage {
+    let width = image.width().max(LEGEND_MIN_WIDTH);
+    let mut extended = RgbaImage::from_fn(width, image.height() + legend_height, |_, y| match y < image.height() {
+        true => Rgba([0, 0, 0, 0]),
+        false => LEGEND_BACKGROUND,
+    });
+    image::imageops::replace(&mut extended, image, 0, 0);
+    extended
+}
+
+/// A gradient bar with the low end of the range labeled on the left and the high end on the right.
+fn with_gradient_legend(image: &RgbaImage, colormap: Colormap, range: &ValueRange) -> RgbaImage {
+    let mut extended = extend_for_legend(image, MARGIN * 3 + SWATCH_SIZE + TEXT_HEIGHT);
+    let top = image.height() + MARGIN;
+    let bar_width = extended.width() - 2 * MARGIN;
+    for x in 0..bar_width {
+        let value = range.low + (range.high - range.low) * x as f64 / (bar_width - 1).max(1) as f64;
+        let [red, green, blue] = colormap.color(value, range);
+        for y in top..top + SWATCH_SIZE {
+            extended.put_pixel(MARGIN + x, y, Rgba([red, green, blue, 255]));
+        }
+    }
+    let (low, high) = (format_label(range.low), format_label(range.high));
+    let label_top = top + SWATCH_SIZE + MARGIN;
+    draw_text(&mut extended, &low, MARGIN, label_top);
+    let high_left = (extended.width() - MARGIN).saturating_sub(text_width(&high)).max(MARGIN + text_width(&low) + GLYPH_ADVANCE);
+    draw_text(&mut extended, &high, high_left, label_top);
+    extended
+}
+
+/// A swatch with the ID next to it for every category, wrapping into as many rows as needed.
+fn with_category_legend(image: &RgbaImage, categories: &[f64]) -> RgbaImage {
+    let width = image.width().max(LEGEND_MIN_WIDTH);
+    let entries: Vec<(f64, String, u32)> = categories.iter()
+        .map(|&category| {
+            let label = format_label(category);
+            let entry_width = SWATCH_SIZE + MARGIN + text_width(&label) + 2 * MARGIN;
+            (category, label, entry_width)
+        })
+        .collect();
+
+    let mut positions = vec![];
+    let (mut x, mut row) = (MARGIN, 0);
+    for (_, _, entry_width) in &entries {
+        if x > MARGIN && x + entry_width > width {
+            x = MARGIN;
+            row += 1;
+        }
+        positions.push((x, row));
+        x += entry_width;
+    }
+    let rows = positions.last().map_or(0, |(_, row)| row + 1);
+    let row_height = SWATCH_SIZE + MARGIN;
+    let mut extended = extend_for_legend(image, MARGIN + rows * row_height);
+
+    for ((category, label, _), (left, row)) in entries.iter().zip(positions) {
+        let top = image.height() + MARGIN + row * row_height;
+        let [red, green, blue] = category_color(*category);
+        for (dx, dy) in (0..SWATCH_SIZE).flat_map(|dx| (0..SWATCH_SIZE).map(move |dy| (dx, dy))) {
+            if left + dx < extended.width() {
+                extended.put_pixel(left + dx, top + dy, Rgba([red, green, blue, 255]));
+            }
+        }
+        draw_text(&mut extended, label, left + SWATCH_SIZE + MARGIN, top + (SWATCH_SIZE - TEXT_HEIGHT) / 2);
+    }
+    extended
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{NsdAttributeMetadata, NsdExtensions};
+    use crate::reader::tests::sample_file;
+
+    fn options(colormap: Colormap) -> VisualizeOptions {
+        VisualizeOptions { colormap: Some(colormap), component: 0, range: None, legend: false }
+    }
+
+    #[test]
+    fn maps_the_values_with_data_to_the_colormap() {
+        let nodata = NsdAttributeMetadata { attribute: 1, nodata: Some(26.0), ..NsdAttributeMetadata::default() };
+        let file = sample_file(NsdExtensions { attribute_metadata: vec![nodata], ..NsdExtensions::default() });
+
+        // The grass values are 26, 65, 104, 143, 182 and 221, the first of them without data.
+        let image = visualize(&file, "grass", &options(Colormap::Grayscale)).unwrap();
+        assert_eq!(image.dimensions(), (3, 2));
+        assert_eq!(image.get_pixel(0, 0), &Rgba([0, 0, 0, 0]));
+        assert_eq!(image.get_pixel(1, 0), &Rgba([0, 0, 0, 255]));
+        assert_eq!(image.get_pixel(2, 1), &Rgba([255, 255, 255, 255]));
+
+        let range = Some("0,442".parse().unwrap());
+        let image = visualize(&file, "grass", &VisualizeOptions { range, ..options(Colormap::Grayscale) }).unwrap();
+        assert_eq!(image.get_pixel(2, 1), &Rgba([128, 128, 128, 255]));
+
+        let image = visualize(&file, "biome", &options(Colormap::Categorical)).unwrap();
+        let [red, green, blue] = category_color(7.0);
+        assert_eq!(image.into_raw(), [red, green, blue, 255]);
+
+        let image = visualize(&file, "grass", &VisualizeOptions { legend: true, ..options(Colormap::Viridis) }).unwrap();
+        assert_eq!(image.width(), LEGEND_MIN_WIDTH);
+        assert_eq!(image.height(), 2 + MARGIN * 3 + SWATCH_SIZE + TEXT_HEIGHT);
+    }
+
+    #[test]
+    fn rejects_missing_attributes_and_components() {
+        let file = sample_file(NsdExtensions::default());
+        let error = visualize(&file, "rivers", &options(Colormap::Viridis)).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        assert!(error.to_string().ends_with("it has height, grass, biome"), "{error}");
+        let error = visualize(&file, "height", &VisualizeOptions { component: 1, ..options(Colormap::Viridis) }).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!("2,1".parse::<ValueRange>().is_err());
+    }
+}
@@ -0,0 +1,659 @@
+use std::io;
+use std::io::{Cursor, Seek, SeekFrom, Write};
+use std::ops::Range;
+
+use flate2::Compression;
+use flate2::write::ZlibEncoder;
+
+use crate::format::*;
+use crate::layer::{Layer, LayerDimensions, LayerSection};
+use crate::progress::{ProgressCallback, ProgressEvent};
+use crate::reader::{NsdAttribute, NsdReader};
+
+fn layer_attributes(layers: &[Layer]) -> Vec<NsdAttribute> {
+    layers.iter().map(|layer| NsdAttribute { name: layer.name.clone(), format: layer.format }).collect()
+}
+
+pub fn make_attribute_bytes(layers: &[Layer]) -> Box<[u8]> {
+    make_attribute_table_bytes(&layer_attributes(layers))
+}
+
+pub(crate) fn make_attribute_table_bytes(attributes: &[NsdAttribute]) -> Box<[u8]> {
+    let mut attribute_bytes: Vec<u8> = vec![];
+    for attribute in attributes {
+        attribute_bytes.extend_from_slice(NSD_ATTR_HEADER.as_slice());
+        attribute_bytes.extend_from_slice(attribute.name.as_ref());
+        // string termination
+        attribute_bytes.push(0);
+        // attribute size
+        attribute_bytes.push(attribute.format.size);
+        // attribute type (ESpatialDataTexelAttributeType)
+        attribute_bytes.push(attribute.format.attribute_type as u8);
+    }
+    attribute_bytes.into_boxed_slice()
+}
+
+/// The DIM chunk, with the texel order and the row stride in the two words which used to be reserved and always 1.
+/// Tightly packed rows keep the 1, as padded rows take at least 2 bytes.
+pub fn make_dimensions_bytes(dimensions: &LayerDimensions, order: TexelOrder, row_stride: Option<u32>) -> Box<[u8]> {
+    let mut bytes: Vec<u8> = vec![];
+    bytes.extend_from_slice(NSD_DIM_HEADER.as_slice());
+    bytes.extend_from_slice(dimensions.width.to_le_bytes().as_slice());
+    bytes.extend_from_slice(dimensions.height.to_le_bytes().as_slice());
+    bytes.extend_from_slice((order as u32).to_le_bytes().as_slice());
+    bytes.extend_from_slice(row_stride.unwrap_or(1).to_le_bytes().as_slice());
+    bytes.into_boxed_slice()
+}
+
+/// Combined size of all attributes of a single texel.
+pub fn texel_stride(layers: &[Layer]) -> usize {
+    layers.iter().map(|layer| layer.format.size as usize).sum()
+}
+
+/// Interleaves the layers into the uncompressed texel data stored in the DATA chunk.
+pub fn make_raw_data(layers: &[Layer], dimensions: &LayerDimensions, order: TexelOrder) -> Vec<u8> {
+    let formats = layers.iter().map(|layer| layer.format).collect();
+    let mut packer = DataPacker::new(formats, dimensions, order);
+    packer.pack_all(layers);
+    packer.finish()
+}
+
+/// Rows of a layer transposed together when packing column-major texel data.
+const SCATTER_BAND_ROWS: usize = 64;
+/// Texel data `DataPacker::pack_all` interleaves every layer into before moving on, small enough to stay in the cache.
+const PACK_BLOCK_BYTES: usize = 256 * 1024;
+
+/// Interleaves layers into the texel data one at a time, in whatever order they become available,
+/// so packing can overlap with loading the remaining layers.
+pub struct DataPacker {
+    formats: Vec<AttributeFormat>,
+    dimensions: LayerDimensions,
+    order: TexelOrder,
+    stride: usize,
+    raw_data: Vec<u8>,
+    /// Combined size of the attributes packed so far.
+    packed: usize,
+    progress: Option<ProgressCallback>,
+}
+
+impl DataPacker {
+    /// Allocates the texel data for attributes of the given formats, in their final order.
+    pub fn new(formats: Vec<AttributeFormat>, dimensions: &LayerDimensions, order: TexelOrder) -> DataPacker {
+        let stride = formats.iter().map(|format| format.size as usize).sum();
+        DataPacker {
+            raw_data: vec![0; stride * dimensions.get_texel_count()],
+            formats,
+            dimensions: dimensions.clone(),
+            order,
+            stride,
+            packed: 0,
+            progress: None,
+        }
+    }
+
+    /// Reports the fraction of the texel data packed after every layer.
+    pub fn with_progress(self, progress: ProgressCallback) -> DataPacker {
+        DataPacker { progress: Some(progress), ..self }
+    }
+
+    /// Writes the texels of the layer at the given attribute index.
+    pub fn pack(&mut self, index: usize, layer: &Layer) {
+        assert_eq!(self.formats[index], layer.format, "Layer {} has an unexpected format", layer.name);
+        let rows = 0..self.dimensions.height as usize;
+        let mut encoded = vec![];
+        self.encode_rows(layer, rows.clone(), &mut encoded);
+        self.scatter(index, &encoded, rows);
+        self.packed += layer.format.size as usize;
+        if let Some(progress) = &self.progress {
+            progress(&ProgressEvent::Packed { fraction: self.packed as f64 / self.stride as f64 });
+        }
+    }
+
+    /// Writes the texels of every layer (in attribute order), a block of rows at a time, so each block of the texel
+    /// data is filled while it's in the cache instead of going over all of it once per layer.
+    pub fn pack_all(&mut self, layers: &[Layer]) {
+        let LayerDimensions { width, height } = self.dimensions;
+        let (width, height) = (width as usize, height as usize);
+        let rows_per_block = match self.order {
+            TexelOrder::RowMajor => (PACK_BLOCK_BYTES / (width * self.stride).max(1)).max(1),
+            TexelOrder::ColumnMajor => SCATTER_BAND_ROWS,
+        };
+        let mut encoded = vec![];
+        for first_row in (0..height).step_by(rows_per_block) {
+            let rows = first_row..(first_row + rows_per_block).min(height);
+            for (index, layer) in layers.iter().enumerate() {
+                assert_eq!(self.formats[index], layer.format, "Layer {} has an unexpected format", layer.name);
+                encoded.clear();
+                self.encode_rows(layer, rows.clone(), &mut encoded);
+                self.scatter(index, &encoded, rows.clone());
+            }
+        }
+        self.packed = self.stride;
+    }
+
+    /// Appends the encoded texels of the rows of the output. Layers are resized to the exact dimensions of the
+    /// output, anything else would shift their texels against the other attributes.
+    fn encode_rows(&self, layer: &Layer, rows: Range<usize>, encoded: &mut Vec<u8>) {
+        let LayerDimensions { width, height } = self.dimensions;
+        assert!(
+            (layer.texels.width, layer.texels.height) == (width, height),
+            "Layer {} is {}x{}, not {width}x{height} like the output", layer.name, layer.texels.width, layer.texels.height
+        );
+        layer.texels.encode_into(rows.start * width as usize..rows.end * width as usize, encoded);
+    }
+
+    /// Copies the encoded texels of the rows (in row-major order) into the attribute at the given index.
+    fn scatter(&mut self, index: usize, encoded: &[u8], rows: Range<usize>) {
+        let offset: usize = self.formats[..index].iter().map(|format| format.size as usize).sum();
+        match self.formats[index].size {
+            1 => self.scatter_sized::<1>(offset, encoded, rows),
+            2 => self.scatter_sized::<2>(offset, encoded, rows),
+            3 => self.scatter_sized::<3>(offset, encoded, rows),
+            4 => self.scatter_sized::<4>(offset, encoded, rows),
+            size => unreachable!("Attributes are 1 to 4 bytes large, not {size}."),
+        }
+    }
+
+    /// `scatter` for attributes of `SIZE` bytes. With the size known at compile time every copy is a single move,
+    /// and the loops walk the texels in order instead of computing the storage index of each of them. The strided
+    /// writes are bound by memory bandwidth rather than by instructions, which is why this isn't explicitly
+    /// vectorized; `cargo bench --bench interleave` compares it against packing per texel.
+    fn scatter_sized<const SIZE: usize>(&mut self, offset: usize, encoded: &[u8], rows: Range<usize>) {
+        let LayerDimensions { width, height } = self.dimensions;
+        let (width, height, stride) = (width as usize, height as usize, self.stride);
+        assert_eq!(encoded.len(), rows.len() * width * SIZE, "The encoded texels don't cover the rows");
+        match self.order {
+            TexelOrder::RowMajor => {
+                let texels = self.raw_data[rows.start * width * stride..].chunks_exact_mut(stride);
+                for (texel, value) in texels.zip(encoded.chunks_exact(SIZE)) {
+                    texel[offset..offset + SIZE].copy_from_slice(value);
+                }
+            }
+            TexelOrder::ColumnMajor => {
+                // Transposed in bands of rows, so both the rows read and the columns written stay in the cache.
+                for band_start in rows.clone().step_by(SCATTER_BAND_ROWS) {
+                    let band = band_start..(band_start + SCATTER_BAND_ROWS).min(rows.end);
+                    for x in 0..width {
+                        let column = (x * height + band.start) * stride + offset;
+                        let texels = self.raw_data[column..].chunks_mut(stride);
+                        for (y, texel) in band.clone().zip(texels) {
+                            let value = ((y - rows.start) * width + x) * SIZE;
+                            texel[..SIZE].copy_from_slice(&encoded[value..value + SIZE]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn finish(self) -> Vec<u8> {
+        self.raw_data
+    }
+
+    /// Texel data without the attributes at the given indices, e.g. of layers which failed to load.
+    pub fn finish_without(self, skipped: &[usize]) -> Vec<u8> {
+        if skipped.is_empty() {
+            return self.raw_data;
+        }
+        let mut kept = vec![];
+        let mut offset = 0;
+        for (index, format) in self.formats.iter().enumerate() {
+            if !skipped.contains(&index) {
+                kept.push(offset..offset + format.size as usize);
+            }
+            offset += format.size as usize;
+        }
+        self.raw_data.chunks_exact(self.stride)
+            .flat_map(|texel| kept.iter().flat_map(|range| &texel[range.clone()]))
+            .copied()
+            .collect()
+    }
+}
+
+/// Size of texel data as recorded in the DATA chunk, which can't hold 4 GiB or more.
+fn data_chunk_size(size: u64, description: &str) -> io::Result<u32> {
+    u32::try_from(size).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!(
+        "The {description} is {size} bytes, more than a data chunk can hold (4 GiB), split the output into smaller \
+         files with --max-data-size"
+    )))
+}
+
+/// Writes the DATA chunk, compressing straight into the output.
+/// The compressed size isn't known up front, so it's patched in once compression is done.
+fn write_data_chunk<W: Write + Seek>(output: &mut W, raw_data: &[u8]) -> io::Result<()> {
+    output.write_all(NSD_DATA_HEADER.as_slice())?;
+    let raw_size = data_chunk_size(raw_data.len() as u64, "texel data")?;
+    output.write_all(raw_size.to_le_bytes().as_slice())?;
+
+    let compressed_size_position = output.stream_position()?;
+    output.write_all(0u32.to_le_bytes().as_slice())?;
+
+    let mut encoder = ZlibEncoder::new(&mut *output, Compression::default());
+    encoder.write_all(raw_data)?;
+    encoder.finish()?;
+
+    let end_position = output.stream_position()?;
+    let compressed_size = data_chunk_size(end_position - compressed_size_position - 4, "compressed texel data")?;
+    output.seek(SeekFrom::Start(compressed_size_position))?;
+    output.write_all(compressed_size.to_le_bytes().as_slice())?;
+    output.seek(SeekFrom::Start(end_position))?;
+    Ok(())
+}
+
+pub fn make_data_bytes(raw_data: &[u8]) -> io::Result<Box<[u8]>> {
+    let mut cursor = Cursor::new(vec![]);
+    write_data_chunk(&mut cursor, raw_data)?;
+    Ok(cursor.into_inner().into_boxed_slice())
+}
+
+fn push_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend_from_slice(string.as_bytes());
+    // string termination
+    bytes.push(0);
+}
+
+/// Frames the payload of an optional chunk with its magic and size.
+fn make_optional_chunk(magic: &[u8; 4], payload: Vec<u8>) -> Box<[u8]> {
+    let mut bytes: Vec<u8> = Vec::with_capacity(8 + payload.len());
+    bytes.extend_from_slice(magic.as_slice());
+    bytes.extend_from_slice((payload.len() as u32).to_le_bytes().as_slice());
+    bytes.extend(payload);
+    bytes.into_boxed_slice()
+}
+
+pub fn make_groups_bytes(groups: &[NsdGroup]) -> Box<[u8]> {
+    let mut payload: Vec<u8> = vec![];
+    payload.extend_from_slice((groups.len() as u32).to_le_bytes().as_slice());
+    for group in groups {
+        push_string(&mut payload, &group.name);
+        payload.extend_from_slice((group.attributes.len() as u32).to_le_bytes().as_slice());
+        for attribute in &group.attributes {
+            payload.extend_from_slice(attribute.to_le_bytes().as_slice());
+        }
+    }
+    make_optional_chunk(&NSD_GROUP_HEADER, payload)
+}
+
+pub fn make_metadata_bytes(attribute_metadata: &[NsdAttributeMetadata]) -> Box<[u8]> {
+    let mut payload: Vec<u8> = vec![];
+    payload.extend_from_slice((attribute_metadata.len() as u32).to_le_bytes().as_slice());
+    for metadata in attribute_metadata {
+        payload.extend_from_slice(metadata.attribute.to_le_bytes().as_slice());
+        push_string(&mut payload, &metadata.description);
+        payload.extend_from_slice((metadata.tags.len() as u32).to_le_bytes().as_slice());
+        for (key, value) in &metadata.tags {
+            push_string(&mut payload, key);
+            push_string(&mut payload, value);
+        }
+    }
+    // Nodata values follow the entries, so readers which don't know about them can stop before.
+    for metadata in attribute_metadata {
+        match metadata.nodata {
+            Some(nodata) => {
+                payload.push(1);
+                payload.extend_from_slice(nodata.to_le_bytes().as_slice());
+            }
+            None => payload.push(0),
+        }
+    }
+    // Followed by the units and kinds the same way.
+    for metadata in attribute_metadata {
+        push_string(&mut payload, &metadata.unit);
+        payload.push(metadata.kind as u8);
+    }
+    // And by the priorities and blend weights, each with a byte telling whether it's set.
+    for metadata in attribute_metadata {
+        match metadata.priority {
+            Some(priority) => {
+                payload.push(1);
+                payload.extend_from_slice(priority.to_le_bytes().as_slice());
+            }
+            None => payload.push(0),
+        }
+        match metadata.weight {
+            Some(weight) => {
+                payload.push(1);
+                payload.extend_from_slice(weight.to_le_bytes().as_slice());
+            }
+            None => payload.push(0),
+        }
+    }
+    make_optional_chunk(&NSD_METADATA_HEADER, payload)
+}
+
+pub fn make_world_bytes(world: &NsdWorldScale) -> Box<[u8]> {
+    let mut payload: Vec<u8> = vec![];
+    for value in world.origin.iter().chain(&world.units_per_texel) {
+        payload.extend_from_slice(value.to_le_bytes().as_slice());
+    }
+    // Only georeferenced files have a coordinate reference system, so readers can stop before.
+    if let Some(epsg) = world.epsg {
+        payload.extend_from_slice(epsg.to_le_bytes().as_slice());
+    }
+    make_optional_chunk(&NSD_WORLD_HEADER, payload)
+}
+
+/// Wraps an additional section (its name, DIM, ATR and DATA chunks) into an optional chunk.
+/// Sections are always row-major and tightly packed.
+pub fn make_section_bytes(section: &LayerSection) -> io::Result<Box<[u8]>> {
+    let raw_data = make_raw_data(&section.layers, &section.dimensions, TexelOrder::RowMajor);
+    make_raw_section_bytes(&section.name, &section.dimensions, &layer_attributes(&section.layers), &raw_data)
+}
+
+/// Same as `make_section_bytes`, with the texel data already interleaved.
+fn make_raw_section_bytes(name: &str, dimensions: &LayerDimensions, attributes: &[NsdAttribute], raw_data: &[u8]) -> io::Result<Box<[u8]>> {
+    let mut payload: Vec<u8> = vec![];
+    push_string(&mut payload, name);
+    payload.extend_from_slice(&make_dimensions_bytes(dimensions, TexelOrder::RowMajor, None));
+    payload.extend_from_slice(&make_attribute_table_bytes(attributes));
+    payload.extend_from_slice(&make_data_bytes(raw_data)?);
+    Ok(make_optional_chunk(&NSD_SECTION_HEADER, payload))
+}
+
+/// Smallest row stride which is a multiple of the alignment.
+pub fn aligned_row_stride(layers: &[Layer], dimensions: &LayerDimensions, order: TexelOrder, alignment: u32) -> u32 {
+    let (row_length, _) = order.rows(dimensions.width, dimensions.height);
+    let row_size = (row_length * texel_stride(layers)) as u32;
+    row_size.div_ceil(alignment) * alignment
+}
+
+/// Pads every row of tightly packed texel data to the row stride.
+fn pad_rows(raw_data: &[u8], row_count: usize, row_stride: usize) -> Vec<u8> {
+    let row_size = raw_data.len() / row_count;
+    let mut padded = vec![0; row_stride * row_count];
+    for (row, padded_row) in raw_data.chunks_exact(row_size).zip(padded.chunks_exact_mut(row_stride)) {
+        padded_row[..row_size].copy_from_slice(row);
+    }
+    padded
+}
+
+pub fn make_binary(
+    header: &NsdHeader,
+    layers: &[Layer],
+    dimensions: &LayerDimensions,
+    extensions: &NsdExtensions
+) -> io::Result<Vec<u8>> {
+    let raw_data = make_raw_data(layers, dimensions, extensions.texel_order);
+    make_binary_from_raw_data(header, layers, dimensions, extensions, &raw_data, &[])
+}
+
+/// Same as `make_binary`, with the texel data already interleaved (e.g. by a `DataPacker`).
+pub fn make_binary_from_raw_data(
+    header: &NsdHeader,
+    layers: &[Layer],
+    dimensions: &LayerDimensions,
+    extensions: &NsdExtensions,
+    raw_data: &[u8],
+    sections: &[LayerSection]
+) -> io::Result<Vec<u8>> {
+    let mut cursor = Cursor::new(vec![]);
+    write_binary(&mut cursor, header, layers, dimensions, extensions, raw_data, sections)?;
+    Ok(cursor.into_inner())
+}
+
+/// Writes the file directly to the output, without building it in memory first.
+pub fn write_binary<W: Write + Seek>(
+    output: &mut W,
+    header: &NsdHeader,
+    layers: &[Layer],
+    dimensions: &LayerDimensions,
+    extensions: &NsdExtensions,
+    raw_data: &[u8],
+    sections: &[LayerSection]
+) -> io::Result<()> {
+    let sections = sections.iter().map(make_section_bytes).collect::<io::Result<Vec<_>>>()?;
+    write_attributes_binary(output, header, &layer_attributes(layers), dimensions, extensions, raw_data, &sections)
+}
+
+/// Writes a parsed (or assembled) file back out, e.g. after editing it.
+pub fn write_parsed_file<W: Write + Seek>(output: &mut W, file: &NsdReader) -> io::Result<()> {
+    let sections = file.sections().iter()
+        .map(|section| make_raw_section_bytes(&section.name, &section.dimensions, &section.attributes, &section.data))
+        .collect::<io::Result<Vec<_>>>()?;
+    write_attributes_binary(output, file.header(), file.attributes(), file.dimensions(), file.extensions(), file.data(), &sections)
+}
+
+/// Same as `write_binary`, with only the names and formats of the attributes and the section chunks already made.
+pub(crate) fn write_attributes_binary<W: Write + Seek>(
+    output: &mut W,
+    header: &NsdHeader,
+    attributes: &[NsdAttribute],
+    dimensions: &LayerDimensions,
+    extensions: &NsdExtensions,
+    raw_data: &[u8],
+    sections: &[Box<[u8]>]
+) -> io::Result<()> {
+    output.write_all(header.to_bytes().as_slice())?;
+    output.write_all(&make_dimensions_bytes(dimensions, extensions.texel_order, extensions.row_stride))?;
+    output.write_all(&make_attribute_table_bytes(attributes))?;
+    match extensions.row_stride {
+        Some(row_stride) => {
+            let (_, row_count) = extensions.texel_order.rows(dimensions.width, dimensions.height);
+            write_data_chunk(output, &pad_rows(raw_data, row_count, row_stride as usize))?;
+        }
+        None => write_data_chunk(output, raw_data)?,
+    }
+
+    for chunk in make_extension_chunks(extensions) {
+        output.write_all(&chunk)?;
+    }
+    for section in sections {
+        output.write_all(section)?;
+    }
+    Ok(())
+}
+
+/// Optional chunks of the extensions which are set, in the order they're written.
+fn make_extension_chunks(extensions: &NsdExtensions) -> Vec<Box<[u8]>> {
+    let mut chunks = vec![];
+    if !extensions.groups.is_empty() {
+        chunks.push(make_groups_bytes(&extensions.groups));
+    }
+    if !extensions.attribute_metadata.is_empty() {
+        chunks.push(make_metadata_bytes(&extensions.attribute_metadata));
+    }
+    if let Some(world) = &extensions.world {
+        chunks.push(make_world_bytes(world));
+    }
+    chunks
+}
+
+/// Size of a file planned before its layers are loaded. Every chunk is known exactly except the compressed texel
+/// data, which depends on the values and is bounded by the worst case of zlib.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct FileSizeEstimate {
+    /// Bytes of everything but the compressed texel data of the main section and the sections.
+    pub fixed: u64,
+    /// Texel data of the main section (with its row padding) and the sections before compression.
+    pub raw_data: u64,
+    /// Largest possible size of the compressed texel data.
+    pub max_compressed_data: u64,
+}
+
+impl FileSizeEstimate {
+    pub fn max_file_size(&self) -> u64 {
+        self.fixed + self.max_compressed_data
+    }
+}
+
+/// Largest size of zlib-compressed data, like `compressBound` of zlib.
+fn max_compressed_size(size: u64) -> u64 {
+    size + (size >> 12) + (size >> 14) + (size >> 25) + 13
+}
+
+/// Plans the size of the file the layers will be written to, which only needs their names and formats.
+pub fn estimate_file_size(
+    header: &NsdHeader,
+    layers: &[Layer],
+    dimensions: &LayerDimensions,
+    extensions: &NsdExtensions,
+    sections: &[LayerSection]
+) -> FileSizeEstimate {
+    // Magic, uncompressed and compressed size.
+    const DATA_CHUNK_HEADER_SIZE: u64 = 12;
+    let texel_count = |dimensions: &LayerDimensions| dimensions.width as u64 * dimensions.height as u64;
+
+    let main_data = match extensions.row_stride {
+        Some(row_stride) => {
+            let (_, row_count) = extensions.texel_order.rows(dimensions.width, dimensions.height);
+            row_stride as u64 * row_count as u64
+        }
+        None => texel_stride(layers) as u64 * texel_count(dimensions),
+    };
+    let mut estimate = FileSizeEstimate {
+        fixed: (header.to_bytes().len() + make_dimensions_bytes(dimensions, extensions.texel_order, extensions.row_stride).len()) as u64
+            + make_attribute_table_bytes(&layer_attributes(layers)).len() as u64
+            + DATA_CHUNK_HEADER_SIZE
+            + make_extension_chunks(extensions).iter().map(|chunk| chunk.len() as u64).sum::<u64>(),
+        raw_data: main_data,
+        max_compressed_data: max_compressed_size(main_data),
+    };
+    for section in sections {
+        let data = texel_stride(&section.layers) as u64 * texel_count(&section.dimensions);
+        // Magic and size of the optional chunk, and the terminated name.
+        estimate.fixed += 8 + section.name.len() as u64 + 1
+            + make_dimensions_bytes(&section.dimensions, TexelOrder::RowMajor, None).len() as u64
+            + make_attribute_table_bytes(&layer_attributes(&section.layers)).len() as u64
+            + DATA_CHUNK_HEADER_SIZE;
+        estimate.raw_data += data;
+        estimate.max_compressed_data += max_compressed_size(data);
+    }
+    estimate
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{DynamicImage, GrayImage, ImageBuffer, Luma, Rgb, RgbImage};
+
+    use super::*;
+    use crate::layer::tests::sample_layer;
+    use crate::reader::tests::all_extensions;
+
+    #[test]
+    fn records_the_sizes_of_the_data_chunk() {
+        let raw_data: Vec<u8> = (0..10_000u32).map(|value| (value % 251) as u8).collect();
+        let bytes = make_data_bytes(&raw_data).unwrap();
+        assert_eq!(bytes[..4], NSD_DATA_HEADER);
+        assert_eq!(u32::from_le_bytes(bytes[4..8].try_into().unwrap()), 10_000);
+        assert_eq!(u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize, bytes.len() - 12);
+    }
+
+    #[test]
+    fn rejects_data_chunks_of_4_gib() {
+        assert_eq!(data_chunk_size(u32::MAX as u64, "texel data").unwrap(), u32::MAX);
+        let error = data_chunk_size(u32::MAX as u64 + 1, "compressed texel data").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("--max-data-size"), "{error}");
+    }
+
+    /// Byte, 16-bit and 3-byte vector layers of distinct values.
+    fn sample_layers(dimensions: &LayerDimensions) -> Vec<Layer> {
+        let LayerDimensions { width, height } = *dimensions;
+        let bytes = DynamicImage::ImageLuma8(GrayImage::from_fn(width, height, |x, y| Luma([(x * 7 + y) as u8])));
+        let words = DynamicImage::ImageLuma16(ImageBuffer::from_fn(width, height, |x, y| Luma([(x * 1000 + y) as u16])));
+        let colors = DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| Rgb([x as u8, y as u8, 9])));
+        vec![
+            sample_layer("grass", &bytes, AttributeFormat::default()),
+            sample_layer("height", &words, AttributeFormat::new(AttributeType::UInt, 2).unwrap()),
+            sample_layer("color", &colors, AttributeFormat::new(AttributeType::Byte, 3).unwrap()),
+        ]
+    }
+
+    #[test]
+    fn packs_layers_in_the_order_they_finish_loading() {
+        let dimensions = LayerDimensions { width: 5, height: 3 };
+        let mut layers = sample_layers(&dimensions);
+        let formats = layers.iter().map(|layer| layer.format).collect::<Vec<_>>();
+        let mut packer = DataPacker::new(formats.clone(), &dimensions, TexelOrder::RowMajor);
+        for index in [2, 0, 1] {
+            packer.pack(index, &layers[index]);
+        }
+        assert_eq!(packer.finish(), make_raw_data(&layers, &dimensions, TexelOrder::RowMajor));
+
+        // Without the 16-bit layer, as if it failed to load.
+        let mut packer = DataPacker::new(formats, &dimensions, TexelOrder::RowMajor);
+        packer.pack(0, &layers[0]);
+        packer.pack(2, &layers[2]);
+        layers.remove(1);
+        assert_eq!(packer.finish_without(&[1]), make_raw_data(&layers, &dimensions, TexelOrder::RowMajor));
+    }
+
+    #[test]
+    fn packs_blocks_of_rows_like_every_texel_at_its_storage_index() {
+        // Taller than a band of rows, and not a multiple of it.
+        let dimensions = LayerDimensions { width: 3, height: SCATTER_BAND_ROWS as u32 * 2 + 5 };
+        let layers = sample_layers(&dimensions);
+        let encoded: Vec<Vec<u8>> = layers.iter().map(|layer| layer.texels.encode()).collect();
+        let (width, height) = (dimensions.width as usize, dimensions.height as usize);
+        for order in [TexelOrder::RowMajor, TexelOrder::ColumnMajor] {
+            let mut expected = vec![];
+            for index in 0..width * height {
+                let texel = match order {
+                    TexelOrder::RowMajor => index,
+                    TexelOrder::ColumnMajor => index % height * width + index / height,
+                };
+                for (layer, bytes) in layers.iter().zip(&encoded) {
+                    let size = layer.format.size as usize;
+                    expected.extend_from_slice(&bytes[texel * size..(texel + 1) * size]);
+                }
+            }
+            assert_eq!(make_raw_data(&layers, &dimensions, order), expected, "{order:?}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "not 4x2 like the output")]
+    fn fails_on_layers_not_resized_to_the_output() {
+        let layers = sample_layers(&LayerDimensions { width: 2, height: 2 });
+        make_raw_data(&layers, &LayerDimensions { width: 4, height: 2 }, TexelOrder::RowMajor);
+    }
+
+    #[test]
+    fn stores_the_columns_after_each_other_in_column_major_order() {
+        let dimensions = LayerDimensions { width: 3, height: 2 };
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(3, 2, vec![1, 2, 3, 4, 5, 6]).unwrap());
+        let layers = [sample_layer("grass", &image, AttributeFormat::default())];
+        assert_eq!(make_raw_data(&layers, &dimensions, TexelOrder::RowMajor), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(make_raw_data(&layers, &dimensions, TexelOrder::ColumnMajor), [1, 4, 2, 5, 3, 6]);
+    }
+
+    #[test]
+    fn pads_the_rows_to_the_aligned_row_stride() {
+        let dimensions = LayerDimensions { width: 3, height: 2 };
+        let layers = sample_layers(&dimensions);
+        // Rows of 3 texels of 6 bytes.
+        assert_eq!(aligned_row_stride(&layers, &dimensions, TexelOrder::RowMajor, 16), 32);
+        assert_eq!(aligned_row_stride(&layers, &dimensions, TexelOrder::RowMajor, 9), 18);
+        assert_eq!(aligned_row_stride(&layers, &dimensions, TexelOrder::ColumnMajor, 16), 16);
+
+        let raw_data = make_raw_data(&layers, &dimensions, TexelOrder::RowMajor);
+        let padded = pad_rows(&raw_data, 2, 32);
+        assert_eq!(padded.len(), 64);
+        assert_eq!((&padded[..18], &padded[32..50]), (&raw_data[..18], &raw_data[18..]));
+        assert!(padded[18..32].iter().chain(&padded[50..]).all(|&byte| byte == 0));
+    }
+
+    #[test]
+    fn estimates_the_size_of_the_written_file() {
+        let dimensions = LayerDimensions { width: 5, height: 3 };
+        let layers = sample_layers(&dimensions);
+        let extensions = all_extensions();
+        let biomes = DynamicImage::ImageLuma8(GrayImage::from_raw(2, 1, vec![3, 4]).unwrap());
+        let section = LayerSection {
+            name: "coarse".into(),
+            dimensions: LayerDimensions { width: 2, height: 1 },
+            layers: vec![sample_layer("biome", &biomes, AttributeFormat::default())],
+        };
+        let header = NsdHeader::default();
+        let raw_data = make_raw_data(&layers, &dimensions, extensions.texel_order);
+        let mut output = Cursor::new(vec![]);
+        write_binary(&mut output, &header, &layers, &dimensions, &extensions, &raw_data, std::slice::from_ref(&section)).unwrap();
+
+        let estimate = estimate_file_size(&header, &layers, &dimensions, &extensions, &[section]);
+        assert_eq!(estimate.raw_data, 15 * 6 + 2);
+        let compressed_size = |data: &[u8]| make_data_bytes(data).unwrap().len() as u64 - 12;
+        let file_size = output.get_ref().len() as u64;
+        assert_eq!(estimate.fixed + compressed_size(&raw_data) + compressed_size(&[3, 4]), file_size);
+        assert!(estimate.max_file_size() >= file_size);
+    }
+}
@@ -0,0 +1,266 @@
+//! Minimal XML parser for reading Tiled maps: elements, attributes and text content.
+//! Processing instructions, comments and doctypes are skipped; namespaces are not interpreted.
+
+#[derive(Clone, Debug, Default)]
+pub struct XmlElement {
+    pub name: String,
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<XmlElement>,
+    /// Concatenated text content directly inside the element.
+    pub text: String,
+}
+
+impl XmlElement {
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn child(&self, name: &str) -> Option<&XmlElement> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children_named<'e>(&'e self, name: &'e str) -> impl Iterator<Item = &'e XmlElement> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+/// Deepest nesting of elements accepted, far beyond that of any Tiled map.
+const MAX_NESTING: usize = 128;
+
+struct XmlParser<'a> {
+    source: &'a str,
+    position: usize,
+    /// Elements being parsed.
+    depth: usize,
+}
+
+/// Parses a document and returns its root element.
+pub fn parse(source: &str) -> Result<XmlElement, String> {
+    let mut parser = XmlParser { source, position: 0, depth: 0 };
+    parser.skip_misc()?;
+    let root = parser.parse_element()?;
+    parser.skip_misc()?;
+    if parser.position != source.len() {
+        return Err(parser.error("content after the root element"));
+    }
+    Ok(root)
+}
+
+impl<'a> XmlParser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.source[self.position..]
+    }
+
+    fn error(&self, message: &str) -> String {
+        let line = self.source[..self.position].matches('\n').count() + 1;
+        format!("line {line}: {message}")
+    }
+
+    fn skip_whitespace(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.position = self.source.len() - trimmed.len();
+    }
+
+    fn skip_past(&mut self, terminator: &str) -> Result<(), String> {
+        match self.rest().find(terminator) {
+            Some(index) => {
+                self.position += index + terminator.len();
+                Ok(())
+            }
+            None => Err(self.error(&format!("missing `{terminator}`"))),
+        }
+    }
+
+    /// Skips whitespace, comments, processing instructions and doctypes.
+    fn skip_misc(&mut self) -> Result<(), String> {
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("<?") {
+                self.skip_past("?>")?;
+            } else if self.rest().starts_with("<!--") {
+                self.skip_past("-->")?;
+            } else if self.rest().starts_with("<!") {
+                self.skip_past(">")?;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    fn parse_name(&mut self) -> Result<String, String> {
+        let length = self.rest()
+            .find(|char: char| char.is_whitespace() || matches!(char, '=' | '>' | '/'))
+            .unwrap_or(self.rest().len());
+        if length == 0 {
+            return Err(self.error("expected a name"));
+        }
+        let name = self.rest()[..length].to_string();
+        self.position += length;
+        Ok(name)
+    }
+
+    /// Parses an element, failing once they're nested deeper than `MAX_NESTING` instead of overflowing the stack.
+    fn parse_element(&mut self) -> Result<XmlElement, String> {
+        if self.depth == MAX_NESTING {
+            return Err(self.error("elements are nested too deeply"));
+        }
+        self.depth += 1;
+        let element = self.parse_element_contents();
+        self.depth -= 1;
+        element
+    }
+
+    fn parse_element_contents(&mut self) -> Result<XmlElement, String> {
+        if !self.rest().starts_with('<') {
+            return Err(self.error("expected an element"));
+        }
+        self.position += 1;
+
+        let mut element = XmlElement {
+            name: self.parse_name()?,
+            ..XmlElement::default()
+        };
+
+        loop {
+            self.skip_whitespace();
+            if self.rest().starts_with("/>") {
+                self.position += 2;
+                return Ok(element);
+            }
+            if self.rest().starts_with('>') {
+                self.position += 1;
+                break;
+            }
+            let key = self.parse_name()?;
+            self.skip_whitespace();
+            if !self.rest().starts_with('=') {
+                return Err(self.error(&format!("expected `=` after attribute {key}")));
+            }
+            self.position += 1;
+            self.skip_whitespace();
+            let quote = self.rest().chars().next()
+                .filter(|char| *char == '"' || *char == '\'')
+                .ok_or_else(|| self.error("expected a quoted attribute value"))?;
+            self.position += 1;
+            let length = self.rest().find(quote).ok_or_else(|| self.error("unterminated attribute value"))?;
+            let value = unescape(&self.rest()[..length]);
+            self.position += length + 1;
+            element.attributes.push((key, value));
+        }
+
+        loop {
+            let text_length = self.rest().find('<').ok_or_else(|| self.error(&format!("unclosed element {}", element.name)))?;
+            element.text.push_str(&unescape(&self.rest()[..text_length]));
+            self.position += text_length;
+
+            if self.rest().starts_with("</") {
+                self.position += 2;
+                let name = self.parse_name()?;
+                if name != element.name {
+                    return Err(self.error(&format!("expected </{}>, found </{name}>", element.name)));
+                }
+                self.skip_whitespace();
+                self.skip_past(">")?;
+                return Ok(element);
+            } else if self.rest().starts_with("<![CDATA[") {
+                self.position += "<![CDATA[".len();
+                let length = self.rest().find("]]>").ok_or_else(|| self.error("unterminated CDATA section"))?;
+                element.text.push_str(&self.rest()[..length]);
+                self.position += length + 3;
+            } else if self.rest().starts_with("<!--") || self.rest().starts_with("<?") {
+                self.skip_misc()?;
+            } else {
+                element.children.push(self.parse_element()?);
+            }
+        }
+    }
+}
+
+fn unescape(text: &str) -> String {
+    if !text.contains('&') {
+        return text.to_string();
+    }
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let Some(end) = rest.find(';') else { break };
+        let entity = &rest[1..end];
+        let replacement = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity.strip_prefix("#x").map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(|decimal| decimal.parse::<u32>()))
+                .and_then(|code| code.ok())
+                .and_then(char::from_u32),
+        };
+        match replacement {
+            Some(char) => {
+                result.push(char);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                result.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_elements_attributes_and_text() {
+        let root = parse(r#"<?xml version="1.0" encoding="UTF-8"?>
+            <!DOCTYPE map>
+            <!-- Exported by Tiled -->
+            <map width="2" height='1' name="a &amp; b &lt;&#x41;&#66;&unknown;">
+              <tileset firstgid="1" source="terrain.tsx"/>
+              <layer name="ground">
+                <data encoding="csv"><![CDATA[1,<2>]]><!-- comment -->&gt;</data>
+              </layer>
+            </map>
+            <!-- Trailing comment -->
+        "#).unwrap();
+
+        assert_eq!(root.name, "map");
+        assert_eq!(root.attribute("height"), Some("1"));
+        assert_eq!(root.attribute("name"), Some("a & b <AB&unknown;"));
+        assert_eq!(root.attribute("missing"), None);
+        assert_eq!(root.children.len(), 2);
+        assert_eq!(root.child("tileset").and_then(|tileset| tileset.attribute("source")), Some("terrain.tsx"));
+        let layers: Vec<&XmlElement> = root.children_named("layer").collect();
+        assert_eq!(layers.len(), 1);
+        let data = layers[0].child("data").unwrap();
+        assert_eq!(data.attribute("encoding"), Some("csv"));
+        assert_eq!(data.text, "1,<2>>");
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        let malformed = [
+            "", "map", "<map>", "<map></layer>", "<map><layer></map>", "<map width=2/>", "<map width/>",
+            "<map width=\"2/>", "<map/><map/>", "<map/>text", "<!-- unterminated", "<map><![CDATA[x</map>", "</>",
+        ];
+        for source in malformed {
+            assert!(parse(source).is_err(), "{source:?} was accepted");
+        }
+        assert!(parse("<map>\n<layer>\n</data>").unwrap_err().starts_with("line 3:"));
+    }
+
+    #[test]
+    fn limits_the_nesting() {
+        let nested = |depth: usize| "<a>".repeat(depth) + &"</a>".repeat(depth);
+        assert!(parse(&nested(MAX_NESTING)).is_ok());
+        assert!(parse(&nested(MAX_NESTING + 1)).unwrap_err().contains("nested too deeply"));
+        assert!(parse(&"<a>".repeat(100_000)).is_err());
+    }
+}